@@ -0,0 +1,370 @@
+//! AllDebrid client.
+//!
+//! AllDebrid always fetches every file in a magnet and has no selection
+//! step, so selection is emulated client-side (see [`EmulatedSelection`]).
+
+use crate::error::{Error, Result};
+use crate::http::{self, Auth, Token};
+use crate::provider::{
+    Account, DebridProvider, EmulatedSelection, TorrentFile, TorrentState, TorrentStatus,
+    UnrestrictedLink,
+};
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+pub const AD_BASE_URL: &str = "https://api.alldebrid.com";
+
+const AGENT: &str = "lj";
+
+/// Every response is wrapped in `{"status": ..., "data" | "error": ...}`,
+/// usually with HTTP 200 even on failure.
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    status: String,
+    data: Option<T>,
+    error: Option<ApiError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserData {
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct User {
+    username: String,
+    #[serde(default)]
+    is_premium: bool,
+    premium_until: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadData {
+    magnets: Vec<UploadedMagnet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadedMagnet {
+    id: Option<u64>,
+    error: Option<ApiError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusData {
+    magnets: MagnetStatus,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MagnetStatus {
+    filename: String,
+    size: u64,
+    status: String,
+    status_code: u32,
+    downloaded: Option<u64>,
+    download_speed: Option<u64>,
+    seeders: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FilesData {
+    magnets: Vec<MagnetFiles>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MagnetFiles {
+    files: Vec<FileNode>,
+}
+
+/// Node of AllDebrid's file tree: a folder has `e` entries, a file has a
+/// size `s` and link `l`.
+#[derive(Debug, Deserialize)]
+struct FileNode {
+    n: String,
+    s: Option<u64>,
+    l: Option<String>,
+    e: Option<Vec<FileNode>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnlockData {
+    link: String,
+    filename: String,
+    filesize: Option<u64>,
+}
+
+/// Flattens the file tree into numbered files (ids start at 1) with links.
+fn flatten(nodes: &[FileNode], prefix: &str, out: &mut Vec<(TorrentFile, String)>) {
+    for node in nodes {
+        let path = if prefix.is_empty() {
+            node.n.clone()
+        } else {
+            format!("{}/{}", prefix, node.n)
+        };
+        if let Some(children) = &node.e {
+            flatten(children, &path, out);
+        } else if let Some(link) = &node.l {
+            let id = out.len() as u32 + 1;
+            out.push((
+                TorrentFile {
+                    id,
+                    path,
+                    bytes: node.s.unwrap_or(0),
+                    selected: 1,
+                },
+                link.clone(),
+            ));
+        }
+    }
+}
+
+pub struct AllDebrid {
+    client: Client,
+    api_key: Token,
+    base_url: String,
+    selection: EmulatedSelection,
+}
+
+impl AllDebrid {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(Client::new(), api_key)
+    }
+
+    pub fn with_client(client: Client, api_key: &str) -> Self {
+        Self {
+            client,
+            api_key: Token::new(api_key),
+            base_url: AD_BASE_URL.to_string(),
+            selection: EmulatedSelection::default(),
+        }
+    }
+
+    /// Points the client at another API root, e.g. a proxy or mirror.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        http::request(
+            &self.client,
+            method,
+            format!("{}{}", self.base_url, path),
+            &self.api_key,
+            Auth::Bearer,
+        )
+        .query(&[("agent", AGENT)])
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        req: RequestBuilder,
+        context: &'static str,
+    ) -> Result<T> {
+        let resp = req.send().await.map_err(Error::http(context))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(Error::http(context))?;
+
+        let envelope: Envelope<T> = serde_json::from_str(&text).map_err(|e| {
+            if status.is_success() {
+                Error::Parse {
+                    context,
+                    message: e.to_string(),
+                }
+            } else {
+                Error::Api {
+                    context,
+                    status,
+                    code: None,
+                    message: text.clone(),
+                }
+            }
+        })?;
+
+        if envelope.status == "success"
+            && let Some(data) = envelope.data
+        {
+            return Ok(data);
+        }
+        Err(api_error(context, status, envelope.error))
+    }
+
+    async fn files(&self, magnet_id: &str) -> Result<Vec<(TorrentFile, String)>> {
+        let data: FilesData = self
+            .call(
+                self.get("/v4/magnet/files").query(&[("id[]", magnet_id)]),
+                "Failed to list magnet files",
+            )
+            .await?;
+
+        let mut files = Vec::new();
+        for magnet in &data.magnets {
+            flatten(&magnet.files, "", &mut files);
+        }
+        Ok(files)
+    }
+}
+
+fn api_error(context: &'static str, status: StatusCode, error: Option<ApiError>) -> Error {
+    let (code, message) = error
+        .map(|e| (e.code, e.message))
+        .unwrap_or_else(|| ("UNKNOWN".to_string(), "Unknown error".to_string()));
+    // Surface auth failures the same way RD's 401s are, so callers can
+    // treat every provider alike
+    let status = if code.starts_with("AUTH_") {
+        StatusCode::UNAUTHORIZED
+    } else {
+        status
+    };
+    Error::Api {
+        context,
+        status,
+        code: None,
+        message: format!("{} ({})", message, code),
+    }
+}
+
+/// Maps AllDebrid's numeric status codes onto [`TorrentState`].
+fn torrent_state(code: u32, status: &str) -> TorrentState {
+    match code {
+        0..=3 => TorrentState::Processing,
+        4 => TorrentState::Ready,
+        _ => TorrentState::Failed(status.to_string()),
+    }
+}
+
+#[async_trait]
+impl DebridProvider for AllDebrid {
+    fn name(&self) -> &'static str {
+        "alldebrid"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "AllDebrid"
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        let data: UploadData = self
+            .call(
+                self.post("/v4/magnet/upload")
+                    .form(&[("magnets[]", magnet)]),
+                "Failed to add magnet",
+            )
+            .await?;
+
+        let uploaded = data
+            .magnets
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Torrent("magnet rejected".to_string()))?;
+        if let Some(error) = uploaded.error {
+            return Err(api_error(
+                "Failed to add magnet",
+                StatusCode::OK,
+                Some(error),
+            ));
+        }
+        uploaded
+            .id
+            .map(|id| id.to_string())
+            .ok_or_else(|| Error::Torrent("magnet rejected".to_string()))
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus> {
+        let data: StatusData = self
+            .call(
+                self.get("/v4.1/magnet/status").query(&[("id", torrent_id)]),
+                "Failed to get magnet status",
+            )
+            .await?;
+        let magnet = data.magnets;
+        let state = torrent_state(magnet.status_code, &magnet.status);
+        let ready = state == TorrentState::Ready;
+
+        let all_files = if ready {
+            Some(self.files(torrent_id).await?)
+        } else {
+            None
+        };
+        let (state, files, links) =
+            self.selection
+                .resolve(torrent_id, state, all_files, &magnet.filename, magnet.size);
+
+        let progress = match (magnet.downloaded, magnet.size) {
+            _ if ready => 100.0,
+            (Some(done), size) if size > 0 => done as f64 / size as f64 * 100.0,
+            _ => 0.0,
+        };
+
+        Ok(TorrentStatus {
+            state,
+            name: Some(magnet.filename),
+            status: magnet.status,
+            progress,
+            speed: magnet.download_speed.unwrap_or(0),
+            seeders: magnet.seeders.unwrap_or(0),
+            files,
+            links,
+        })
+    }
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        self.selection.select(torrent_id, file_ids);
+        Ok(())
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        let data: UnlockData = self
+            .call(
+                self.get("/v4/link/unlock").query(&[("link", link)]),
+                "Failed to unlock link",
+            )
+            .await?;
+        Ok(UnrestrictedLink {
+            filename: data.filename,
+            download: data.link,
+            filesize: data.filesize,
+        })
+    }
+
+    async fn delete(&self, torrent_id: &str) -> Result<()> {
+        self.selection.forget(torrent_id);
+        let _: serde_json::Value = self
+            .call(
+                self.get("/v4/magnet/delete").query(&[("id", torrent_id)]),
+                "Failed to delete magnet",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn account(&self) -> Result<Option<Account>> {
+        let data: UserData = self
+            .call(self.get("/v4/user"), "Failed to get account")
+            .await?;
+        Ok(Some(Account {
+            username: data.user.username,
+            premium_until: data
+                .user
+                .premium_until
+                .filter(|&t| data.user.is_premium && t > 0),
+        }))
+    }
+}
@@ -0,0 +1,163 @@
+//! Sonarr/Radarr "Torrent Blackhole" download client support.
+//!
+//! The *arr apps drop `.torrent` and `.magnet` files into a folder and
+//! import whatever appears in another one. [`serve`](crate::serve) watches
+//! the first (`[blackhole] watch`), runs each drop through the provider and
+//! downloads it into `<completed>/.incomplete/<release name>/`, which the
+//! importers skip as a hidden folder. Once every file of the release has
+//! completed, and any [extraction](crate::extract) is done, the folder is
+//! renamed to `<completed>/<release name>/` in one step, so the *arr never
+//! sees a half-finished release. Drops that fail are renamed to
+//! `<file>.failed` and left for the user.
+
+use crate::config::{BlackholeConfig, ExtractConfig};
+use crate::error::{Error, Result};
+use crate::extract;
+use crate::logging::{self, Level};
+use crate::magnet::Magnet;
+use crate::platform::{self, sanitize_filename};
+use crate::store::{self, Download, DownloadStatus};
+use crate::torrent;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Folder under `completed` that releases are downloaded into.
+pub const INCOMPLETE_DIR: &str = ".incomplete";
+
+/// A configured pair of folders.
+#[derive(Debug, Clone)]
+pub struct Blackhole {
+    watch: PathBuf,
+    completed: PathBuf,
+}
+
+impl Blackhole {
+    /// The blackhole described by `config`, if it names both folders.
+    pub fn from_config(config: &BlackholeConfig) -> Option<Self> {
+        Some(Blackhole {
+            watch: config.watch.clone()?,
+            completed: config.completed.clone()?,
+        })
+    }
+
+    /// The folder drops are picked up from.
+    pub fn watch_dir(&self) -> &Path {
+        &self.watch
+    }
+
+    /// The `.torrent` and `.magnet` files waiting in the watch folder.
+    pub fn dropped(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.watch) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path.extension().is_some_and(|ext| {
+                        ext.eq_ignore_ascii_case("torrent") || ext.eq_ignore_ascii_case("magnet")
+                    })
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Where the downloads of a release go until it is complete.
+    pub fn staging_dir(&self, magnet: &str) -> PathBuf {
+        self.completed
+            .join(INCOMPLETE_DIR)
+            .join(sanitize_filename(&release_name(magnet)))
+    }
+
+    /// Sets a drop that could not be fetched aside as `<file>.failed`.
+    pub fn reject(&self, path: &Path, error: &Error) {
+        let mut failed = path.as_os_str().to_owned();
+        failed.push(".failed");
+        let _ = fs::rename(path, &failed);
+        logging::log(
+            Level::Error,
+            &format!("Blackhole: {} failed: {}", path.display(), error),
+            &[("LJ_EVENT", "blackhole_failed")],
+        );
+    }
+
+    /// Moves every release whose downloads have all completed out of
+    /// [`INCOMPLETE_DIR`], so the *arr can import it. Releases with failed
+    /// files stay there until the files are retried.
+    pub fn finish_releases(&self, extract: &ExtractConfig) {
+        let staging = self.completed.join(INCOMPLETE_DIR);
+        let mut releases: BTreeMap<String, Vec<Download>> = BTreeMap::new();
+        for dl in store::load_all_downloads() {
+            if Path::new(&dl.target_dir).parent() == Some(staging.as_path()) {
+                releases.entry(dl.target_dir.clone()).or_default().push(dl);
+            }
+        }
+
+        for (dir, downloads) in releases {
+            if !is_ready(&downloads, extract) {
+                continue;
+            }
+            let dir = PathBuf::from(dir);
+            let Some(name) = dir.file_name() else {
+                continue;
+            };
+            let target = self.completed.join(name);
+            let result = if target.exists() {
+                Err(format!("{} already exists", target.display()))
+            } else {
+                platform::move_dir(&dir, &target, &mut |_, _| {}).map_err(|e| e.to_string())
+            };
+            match result {
+                Ok(()) => {
+                    let target_dir = target.to_string_lossy().to_string();
+                    for dl in &downloads {
+                        let _ =
+                            store::update_download(&dl.id, |dl| dl.target_dir = target_dir.clone());
+                    }
+                    logging::log(
+                        Level::Info,
+                        &format!("Blackhole: {} is complete", target.display()),
+                        &[("LJ_EVENT", "blackhole_completed")],
+                    );
+                }
+                Err(e) => logging::log(
+                    Level::Error,
+                    &format!("Blackhole: moving {} failed: {}", dir.display(), e),
+                    &[("LJ_EVENT", "blackhole_failed")],
+                ),
+            }
+        }
+    }
+}
+
+/// The magnet link a dropped file stands for.
+pub fn read_drop(path: &Path) -> Result<String> {
+    let data = fs::read(path)?;
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("torrent"))
+    {
+        return torrent::to_magnet(&data);
+    }
+    let magnet = String::from_utf8_lossy(&data).trim().to_string();
+    Magnet::parse(&magnet)?;
+    Ok(magnet)
+}
+
+/// The release's name, which the *arr matches against what it grabbed,
+/// or its info hash if the magnet has no name.
+fn release_name(magnet: &str) -> String {
+    Magnet::parse(magnet)
+        .map(|magnet| magnet.display_name().to_string())
+        .unwrap_or_else(|_| store::new_batch_id())
+}
+
+fn is_ready(downloads: &[Download], extract: &ExtractConfig) -> bool {
+    downloads
+        .iter()
+        .all(|dl| dl.status == DownloadStatus::Completed)
+        && !extract::is_pending(extract, downloads)
+}
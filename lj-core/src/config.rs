@@ -0,0 +1,928 @@
+//! Configuration directory layout, `config.toml` and API key storage.
+
+use crate::crypt::{self, Encryption};
+use crate::error::{Error, Result};
+use crate::hooks;
+use crate::keystore;
+use crate::media::Episodes;
+use crate::provider::{DEFAULT_PROVIDER, ProviderInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Environment variable naming the active profile. Also how the profile is
+/// handed to background workers.
+pub const PROFILE_ENV: &str = "LJ_PROFILE";
+
+/// Environment variable overriding the active provider's API root, e.g. to
+/// go through a caching proxy or hit a local mock.
+pub const API_URL_ENV: &str = "LJ_API_URL";
+
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
+
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Switches the process to a named profile, which gets its own config,
+/// keys and state under `profiles/<name>`. `None` or `"default"` keeps the
+/// top-level directory. Must run before anything reads the config dir.
+pub fn set_profile(name: Option<&str>) -> Result<()> {
+    let name = name.filter(|n| *n != "default");
+    if let Some(name) = name
+        && (name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    {
+        return Err(Error::Config(format!(
+            "invalid profile name '{}' (use letters, digits, '-' and '_')",
+            name
+        )));
+    }
+    PROFILE
+        .set(name.map(str::to_string))
+        .map_err(|_| Error::Other("profile already selected".to_string()))
+}
+
+/// The active profile, if one was selected.
+pub fn profile() -> Option<&'static str> {
+    PROFILE.get().and_then(|p| p.as_deref())
+}
+
+/// Sets the passphrase for [encrypted key files](crate::crypt), e.g. once
+/// the user typed it. `LJ_PASSPHRASE` takes precedence.
+pub fn set_passphrase(passphrase: String) {
+    let _ = PASSPHRASE.set(passphrase);
+}
+
+fn passphrase() -> Option<String> {
+    env::var(crypt::PASSPHRASE_ENV)
+        .ok()
+        .filter(|p| !p.is_empty())
+        .or_else(|| PASSPHRASE.get().cloned())
+}
+
+/// `base/lj`, or the active profile's directory under it.
+fn profile_dir(base: Option<PathBuf>) -> PathBuf {
+    let base = base.unwrap_or_else(|| PathBuf::from(".")).join("lj");
+    match profile() {
+        Some(name) => base.join("profiles").join(name),
+        None => base,
+    }
+}
+
+/// Directory holding `config.toml` and the API keys for the active profile.
+pub fn config_dir() -> PathBuf {
+    profile_dir(dirs::config_dir())
+}
+
+/// Directory holding download records and other mutable state for the
+/// active profile: `$XDG_STATE_HOME/lj` on Linux, the local data directory
+/// elsewhere. Kept apart from [`config_dir`] so dotfile backups stay small.
+pub fn state_dir() -> PathBuf {
+    profile_dir(dirs::state_dir().or_else(dirs::data_local_dir))
+}
+
+/// Directory for files that only matter while lj runs, such as live
+/// [progress](crate::progress): under `$XDG_RUNTIME_DIR`, in memory, where
+/// there is one, and [`state_dir`] elsewhere.
+pub fn runtime_dir() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => profile_dir(Some(dir)),
+        None => state_dir(),
+    }
+}
+
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Settings from `config.toml`. Every field is optional so an empty or
+/// missing file means "use the defaults".
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Debrid service to use (see [`PROVIDERS`](crate::provider::PROVIDERS)).
+    pub provider: Option<String>,
+    /// Where new downloads are saved; the current directory if unset.
+    pub download_dir: Option<PathBuf>,
+    /// Whether files take the name the host's `Content-Disposition` header
+    /// gives them over the one the debrid service reported.
+    pub host_filenames: bool,
+    /// Whether to download links that are plain HTTP or point at a local or
+    /// private host (see [`untrusted_link`](crate::http::untrusted_link)),
+    /// with a warning, instead of refusing them.
+    pub insecure_links: bool,
+    /// Whether downloads are written with `O_DIRECT` on Linux, so they
+    /// don't crowd other files out of the page cache (see
+    /// [`FileSink`](crate::sink::FileSink)).
+    pub direct_io: bool,
+    /// Trackers added to magnets that name none, including those made
+    /// from a bare info hash.
+    pub trackers: Vec<String>,
+    /// Per-provider settings, from `[providers.<name>]` sections.
+    pub providers: BTreeMap<String, ProviderConfig>,
+    /// How long to wait on the debrid service, from `[wait]`.
+    pub wait: WaitConfig,
+    /// Certificate settings for every HTTPS connection, from `[tls]`.
+    pub tls: TlsConfig,
+    /// Timeouts and connection settings for every request, from `[http]`.
+    pub http: HttpConfig,
+    /// Settings for `lj serve`, from `[serve]`.
+    pub serve: ServeConfig,
+    /// Which files of a torrent to download, from `[select]`.
+    pub select: SelectConfig,
+    /// Where to send lifecycle notifications, from `[webhook]`.
+    pub webhook: WebhookConfig,
+    /// Where to send push notifications, from `[ntfy]`.
+    pub ntfy: NtfyConfig,
+    /// Where to post Discord messages, from `[discord]`.
+    pub discord: DiscordConfig,
+    /// Telegram bot settings, from `[telegram]`.
+    pub telegram: TelegramConfig,
+    /// Commands to run after downloads, from `[hooks]`.
+    pub hooks: HooksConfig,
+    /// Archive extraction, from `[extract]`.
+    pub extract: ExtractConfig,
+    /// Uploads to cloud storage once batches finish, from `[upload]`.
+    pub upload: UploadConfig,
+    /// Library layout for movies and shows, from `[organize]`.
+    pub organize: OrganizeConfig,
+    /// Directories checked for files a torrent would download again, from
+    /// `[library]`.
+    pub library: LibraryConfig,
+    /// Jellyfin library refreshes, from `[jellyfin]`.
+    pub jellyfin: JellyfinConfig,
+    /// Plex library refreshes, from `[plex]`.
+    pub plex: PlexConfig,
+    /// Kodi notifications and library updates, from `[kodi]`.
+    pub kodi: KodiConfig,
+    /// Dead man's switch pings, from `[healthchecks]`.
+    pub healthchecks: HealthchecksConfig,
+    /// Home Assistant sensors over MQTT, from `[mqtt]`.
+    pub mqtt: MqttConfig,
+    /// Sonarr/Radarr blackhole folders for `lj serve`, from `[blackhole]`.
+    pub blackhole: BlackholeConfig,
+    /// qBittorrent API emulation for `lj serve`, from `[qbittorrent]`.
+    pub qbittorrent: QbittorrentConfig,
+    /// Browser extension and bookmarklet listener for `lj serve`, from
+    /// `[companion]`.
+    pub companion: CompanionConfig,
+    /// Torznab endpoints for `lj search`, from `[indexers.<name>]` sections.
+    pub indexers: BTreeMap<String, IndexerConfig>,
+    /// Media player for `lj play`, from `[player]`.
+    pub player: PlayerConfig,
+    /// Feed subscriptions for `lj rss`, from `[rss]`.
+    pub rss: RssConfig,
+}
+
+/// Settings from the `[select]` section. Patterns are matched as
+/// case-insensitive substrings of file names.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelectConfig {
+    /// Only download files matching one of these, if any are given.
+    pub include: Vec<String>,
+    /// Never download files matching any of these.
+    pub exclude: Vec<String>,
+    /// Only download these episodes; set by `--episodes`, not the file.
+    #[serde(skip)]
+    pub episodes: Option<Episodes>,
+}
+
+impl SelectConfig {
+    /// Whether a file called `name` passes the filters.
+    pub fn matches(&self, name: &str) -> bool {
+        self.matcher()(name)
+    }
+
+    /// [`matches`](Self::matches) with the patterns lowercased once, for
+    /// checking many names.
+    pub fn matcher(&self) -> impl Fn(&str) -> bool + '_ {
+        let lower = |patterns: &[String]| -> Vec<String> {
+            patterns.iter().map(|p| p.to_lowercase()).collect()
+        };
+        let (include, exclude) = (lower(&self.include), lower(&self.exclude));
+        move |name| {
+            let lower = name.to_lowercase();
+            let hit = |pattern: &String| lower.contains(pattern.as_str());
+            (include.is_empty() || include.iter().any(hit))
+                && !exclude.iter().any(hit)
+                && self.episodes.as_ref().is_none_or(|e| e.contains(name))
+        }
+    }
+}
+
+/// Name of the per-directory settings file.
+pub const LOCAL_CONFIG_FILE: &str = ".ljrc";
+
+/// Settings from a `.ljrc` file, which override `config.toml` when lj runs
+/// in its directory or below it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalConfig {
+    pub provider: Option<String>,
+    /// Relative paths are taken from the directory holding the `.ljrc`.
+    pub download_dir: Option<PathBuf>,
+    /// Replaces the global `[select]` section as a whole.
+    pub select: Option<SelectConfig>,
+}
+
+/// The nearest `.ljrc` in `dir` or one of its ancestors.
+pub fn find_local_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(LOCAL_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Settings from the `[serve]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServeConfig {
+    /// Address the HTTP API listens on; `127.0.0.1:7878` if unset.
+    pub bind: Option<String>,
+    /// Bearer token every request must carry. Without one the API is open
+    /// to anyone who can reach `bind`.
+    pub token: Option<String>,
+}
+
+/// Settings from the `[webhook]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// URL to POST events to; no webhook if unset.
+    pub url: Option<String>,
+    /// Key for the HMAC-SHA256 signature in `X-Lj-Signature`.
+    pub secret: Option<String>,
+    /// Events to send, by name; `started`, `completed` and `failed` if
+    /// empty.
+    pub events: Vec<String>,
+}
+
+/// Settings from the `[ntfy]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NtfyConfig {
+    /// ntfy server; `https://ntfy.sh` if unset.
+    pub server: Option<String>,
+    /// Topic to publish to; no notifications if unset.
+    pub topic: Option<String>,
+    /// Access token for protected topics.
+    pub token: Option<String>,
+    /// Events to send, by name; `completed` and `failed` if empty.
+    pub events: Vec<String>,
+}
+
+/// Settings from the `[mqtt]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Broker, e.g. `mqtt://homeassistant.local:1883`; nothing is
+    /// published if unset.
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of lj's own topics; `lj` if unset.
+    pub topic: Option<String>,
+    /// Home Assistant's discovery prefix; `homeassistant` if unset.
+    pub discovery_prefix: Option<String>,
+}
+
+/// Settings from the `[discord]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    /// Discord webhook URL; no notifications if unset.
+    pub url: Option<String>,
+    /// Name the messages are posted under; `lj` if unset.
+    pub username: Option<String>,
+    /// Events to send, by name; `completed` and `failed` if empty.
+    pub events: Vec<String>,
+}
+
+/// Settings from the `[telegram]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    /// Bot token from @BotFather.
+    pub token: Option<String>,
+    /// Chat to notify and, with `accept_magnets`, take magnets from.
+    pub chat_id: Option<String>,
+    /// Bot API root to use instead of Telegram's, e.g. a local Bot API
+    /// server.
+    pub api_url: Option<String>,
+    /// Events to send, by name; `completed` and `failed` if empty.
+    pub events: Vec<String>,
+    /// Have `lj serve` download magnet links sent from `chat_id`.
+    pub accept_magnets: bool,
+}
+
+/// Settings from the `[hooks]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Command run before each download starts, which may move or veto it.
+    pub before_download: Option<String>,
+    /// Command run after each download that completes or fails.
+    pub on_download: Option<String>,
+    /// Command run once every download from a magnet has finished.
+    pub on_batch: Option<String>,
+}
+
+/// Settings from the `[extract]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractConfig {
+    /// Extract archives once all of a magnet's downloads have finished.
+    pub enabled: bool,
+    /// Delete the archives after a clean extraction.
+    pub delete_archives: bool,
+    /// 7-Zip executable, e.g. `7zz`; `7z` if unset.
+    pub program: Option<String>,
+}
+
+/// Settings from the `[upload]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// rclone destination, e.g. `gdrive:Media`, or an absolute local
+    /// directory to move files into without rclone.
+    pub remote: Option<String>,
+    /// Copy files instead of moving them, leaving the local ones in place.
+    pub keep_local: bool,
+    /// rclone executable; `rclone` if unset.
+    pub program: Option<String>,
+    /// Extra rclone flags, e.g. `["--bwlimit", "10M"]`.
+    pub args: Vec<String>,
+}
+
+/// Settings from the `[organize]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OrganizeConfig {
+    /// Sort movies and episodes into a library layout (also `--organize`).
+    pub enabled: bool,
+    /// Root of the library; the download directory if unset.
+    pub library: Option<PathBuf>,
+    /// Folder for movies under `library`; `Movies` if unset.
+    pub movies: Option<String>,
+    /// Folder for shows under `library`; `TV` if unset.
+    pub tv: Option<String>,
+}
+
+/// Settings from the `[library]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    /// Directories searched, with their subdirectories, for files that are
+    /// already there; none by default, which turns the check off.
+    pub dirs: Vec<PathBuf>,
+    /// Only warn about such files instead of leaving them out.
+    pub warn_only: bool,
+}
+
+/// Settings from the `[jellyfin]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JellyfinConfig {
+    /// Server root, e.g. `http://jellyfin:8096`; no refreshes if unset.
+    pub url: Option<String>,
+    /// API key from the dashboard's API Keys page.
+    pub api_key: Option<String>,
+    /// Local path prefixes and what the server calls them, for servers that
+    /// see the library under a different mount.
+    pub paths: BTreeMap<String, String>,
+}
+
+/// Settings from the `[plex]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlexConfig {
+    /// Server root, e.g. `http://plex:32400`; no refreshes if unset.
+    pub url: Option<String>,
+    /// `X-Plex-Token` of an account that can manage the server.
+    pub token: Option<String>,
+    /// Local directories and the ID of the library section they belong to.
+    pub sections: BTreeMap<String, u32>,
+    /// Local path prefixes and what the server calls them, as for Jellyfin.
+    pub paths: BTreeMap<String, String>,
+}
+
+/// Settings from the `[kodi]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KodiConfig {
+    /// Web server root, e.g. `http://localhost:8080`; nothing is sent if
+    /// unset.
+    pub url: Option<String>,
+    /// Web server login, if Kodi asks for one.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Local path prefixes and what Kodi calls them, as for Jellyfin.
+    pub paths: BTreeMap<String, String>,
+}
+
+/// Settings from the `[healthchecks]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthchecksConfig {
+    /// Ping URL of the check, e.g. `https://hc-ping.com/<uuid>`; no pings
+    /// if unset.
+    pub url: Option<String>,
+}
+
+/// Settings from the `[blackhole]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlackholeConfig {
+    /// Folder the *arr drops `.torrent` and `.magnet` files into (its
+    /// "Torrent Folder").
+    pub watch: Option<PathBuf>,
+    /// Folder the *arr imports finished downloads from (its "Watch Folder").
+    pub completed: Option<PathBuf>,
+}
+
+/// Settings from the `[qbittorrent]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QbittorrentConfig {
+    /// Answer the qBittorrent WebUI API in `lj serve`.
+    pub enabled: bool,
+    /// Login clients must use; any login is accepted if neither is set.
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Settings from the `[companion]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompanionConfig {
+    /// Take magnets from the browser in `lj serve`.
+    pub enabled: bool,
+    /// Loopback address to listen on; `127.0.0.1:7879` if unset.
+    pub bind: Option<String>,
+    /// Token the extension or bookmarklet must send; required.
+    pub token: Option<String>,
+}
+
+/// Settings from the `[player]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerConfig {
+    /// Player executable, e.g. `vlc`; mpv, then VLC, if unset.
+    pub program: Option<String>,
+    /// Arguments passed before the URL, e.g. `["--fs"]`.
+    pub args: Vec<String>,
+}
+
+/// Settings from the `[rss]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RssConfig {
+    /// Minutes between polls in `lj daemon`; feeds are only polled by
+    /// `lj rss poll` if unset.
+    pub interval: Option<u64>,
+    /// Subscriptions, from `[rss.feeds.<name>]` sections.
+    pub feeds: BTreeMap<String, FeedConfig>,
+}
+
+/// Settings for one RSS or Atom feed.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    pub url: String,
+    /// Regexes an item's title must match one of; every item if empty.
+    pub include: Vec<String>,
+    /// Regexes that rule an item out, even if it is included.
+    pub exclude: Vec<String>,
+    /// Where this feed's downloads go instead of `download_dir`.
+    pub download_dir: Option<PathBuf>,
+}
+
+/// Settings from the `[tls]` section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Extra PEM CA certificate(s) to trust, e.g. for an intercepting proxy.
+    pub ca_cert: Option<PathBuf>,
+    /// PEM client certificate for mutual TLS; needs `client_key`.
+    pub client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key for `client_cert`.
+    pub client_key: Option<PathBuf>,
+    /// Accept any server certificate. For debugging only: it makes every
+    /// connection, API token included, open to interception.
+    pub insecure_skip_verify: bool,
+}
+
+/// Settings from the `[http]` section, for the API and the downloads alike.
+/// Durations are in seconds; 0 turns a limit off.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// For establishing a connection, TLS handshake included; 30 if unset.
+    pub connect_timeout: Option<u64>,
+    /// Between two reads; 60 if unset.
+    pub read_timeout: Option<u64>,
+    /// How long an unused connection is kept open for reuse; 90 if unset.
+    pub pool_idle_timeout: Option<u64>,
+    /// Interval of TCP keepalive probes; 60 if unset.
+    pub tcp_keepalive: Option<u64>,
+    /// Use HTTP/2 with servers that offer it; true if unset.
+    pub http2: Option<bool>,
+    /// Sent instead of `lj/<version>`.
+    pub user_agent: Option<String>,
+}
+
+/// Settings for one provider.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderConfig {
+    /// API root to use instead of the service's own.
+    pub api_url: Option<String>,
+    /// API token; takes precedence over the key file.
+    pub token: Option<String>,
+    /// Command printing the API token on its first line, e.g.
+    /// `pass show rd/token`; used when `token` is unset.
+    #[serde(alias = "api_key_cmd")]
+    pub token_cmd: Option<String>,
+    /// Copied from the `[wait]` section, which applies to every provider.
+    #[serde(skip)]
+    pub wait: WaitConfig,
+}
+
+/// Settings from the `[wait]` section, in seconds. 0 waits as long as it
+/// takes, for big torrents the service has to download first.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaitConfig {
+    /// For the torrent's file list; 60 if unset.
+    pub files: Option<u64>,
+    /// For the service to have the selected files ready; 600 if unset.
+    pub processing: Option<u64>,
+    /// At 0% with no seeders before the torrent counts as dead; 300 if
+    /// unset.
+    pub stalled: Option<u64>,
+    /// Without new data before a download gives up; 600 if unset.
+    pub download: Option<u64>,
+}
+
+impl WaitConfig {
+    /// How long to wait for the file list, or `None` to wait for good.
+    pub fn files_timeout(&self) -> Option<Duration> {
+        timeout(self.files.unwrap_or(60))
+    }
+
+    /// How long to wait for the files to be ready, or `None` to wait for
+    /// good.
+    pub fn processing_timeout(&self) -> Option<Duration> {
+        timeout(self.processing.unwrap_or(600))
+    }
+
+    /// How long a torrent may sit at 0% with no seeders before it counts as
+    /// dead, or `None` to never give up on it.
+    pub fn stalled_timeout(&self) -> Option<Duration> {
+        timeout(self.stalled.unwrap_or(300))
+    }
+
+    /// How long a download may go without new data, across reconnects,
+    /// before it fails, or `None` to never give up on it.
+    pub fn download_timeout(&self) -> Option<Duration> {
+        timeout(self.download.unwrap_or(600))
+    }
+}
+
+fn timeout(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Settings for one Torznab indexer, e.g. from Jackett or Prowlarr.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexerConfig {
+    /// Torznab feed root, the part before `/api`.
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+impl Config {
+    /// Loads `config.toml`, returning the defaults if it does not exist.
+    pub fn load() -> Result<Config> {
+        let path = config_file();
+        match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data)
+                .map_err(|e| Error::Config(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Merges the nearest `.ljrc` above `dir` over these settings and
+    /// returns its path, if there is one.
+    pub fn apply_local(&mut self, dir: &Path) -> Result<Option<PathBuf>> {
+        let Some(path) = find_local_config(dir) else {
+            return Ok(None);
+        };
+        let data = fs::read_to_string(&path)?;
+        let local: LocalConfig = toml::from_str(&data)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+
+        if let Some(provider) = local.provider {
+            self.provider = Some(provider);
+        }
+        if let Some(download_dir) = local.download_dir {
+            let base = path.parent().unwrap_or(Path::new("."));
+            self.download_dir = Some(base.join(download_dir));
+        }
+        if let Some(select) = local.select {
+            self.select = select;
+        }
+        Ok(Some(path))
+    }
+
+    /// Points `provider` at `url` instead of its configured or built-in API
+    /// root.
+    pub fn set_api_url(&mut self, provider: &ProviderInfo, url: &str) {
+        self.providers
+            .entry(provider.name.to_string())
+            .or_default()
+            .api_url = Some(url.trim_end_matches('/').to_string());
+    }
+
+    /// Settings for `provider`, or the defaults if it has no section.
+    pub fn provider_config(&self, provider: &ProviderInfo) -> ProviderConfig {
+        ProviderConfig {
+            wait: self.wait,
+            ..self
+                .providers
+                .get(provider.name)
+                .cloned()
+                .unwrap_or_default()
+        }
+    }
+}
+
+/// Key file for a provider. Real-Debrid keeps the original `api_key` name.
+pub fn api_key_file(provider: &ProviderInfo) -> PathBuf {
+    if provider.name == DEFAULT_PROVIDER {
+        config_dir().join("api_key")
+    } else {
+        config_dir().join(format!("api_key.{}", provider.name))
+    }
+}
+
+/// Encrypted key file for a provider: its key file's name plus `.age`.
+pub fn encrypted_key_file(provider: &ProviderInfo) -> PathBuf {
+    let mut name = api_key_file(provider).into_os_string();
+    name.push(".age");
+    PathBuf::from(name)
+}
+
+/// Whether the provider's key is in a passphrase-encrypted file and no
+/// passphrase was given yet.
+pub fn key_needs_passphrase(provider: &ProviderInfo) -> bool {
+    passphrase().is_none()
+        && fs::read_to_string(encrypted_key_file(provider))
+            .is_ok_and(|armored| crypt::needs_passphrase(&armored))
+}
+
+/// The key in the provider's encrypted key file, if it has one.
+pub fn encrypted_api_key(provider: &ProviderInfo) -> Result<Option<String>> {
+    let armored = match fs::read_to_string(encrypted_key_file(provider)) {
+        Ok(armored) => armored,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    crypt::decrypt(&armored, passphrase().as_deref()).map(Some)
+}
+
+/// The key in the file named by the provider's token variable plus
+/// `_FILE` (e.g. `RD_API_TOKEN_FILE`), such as a systemd credential.
+fn key_from_file(provider: &ProviderInfo) -> Result<Option<String>> {
+    let var = format!("{}_FILE", provider.token_env);
+    let Some(path) = env::var_os(&var).filter(|path| !path.is_empty()) else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    let key = fs::read_to_string(&path)
+        .map_err(|e| Error::Config(format!("{} {}: {}", var, path.display(), e)))?;
+    match key.trim() {
+        "" => Err(Error::Config(format!(
+            "{} {} is empty",
+            var,
+            path.display()
+        ))),
+        key => Ok(Some(key.to_string())),
+    }
+}
+
+/// The first line `command` prints, e.g. from `pass` or `sops`. It may ask
+/// for a passphrase on the terminal.
+fn key_from_command(command: &str) -> Result<String> {
+    let output = hooks::shell(command)
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| Error::Config(format!("token_cmd `{}`: {}", command, e)))?;
+    if !output.status.success() {
+        return Err(Error::Config(format!(
+            "token_cmd `{}` exited with {}",
+            command, output.status
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next().map(str::trim) {
+        Some(key) if !key.is_empty() => Ok(key.to_string()),
+        _ => Err(Error::Config(format!(
+            "token_cmd `{}` printed no token",
+            command
+        ))),
+    }
+}
+
+/// Returns the provider's API key from its environment variable or the file
+/// its `_FILE` variant names, falling back to its `config.toml` section
+/// (`token`, then `token_cmd`) and then to where `set-key` stored it.
+/// Fails if a key file or command that is set fails, or the key is in an
+/// encrypted file that can't be decrypted.
+pub fn load_api_key(provider: &ProviderInfo, config: &Config) -> Result<Option<String>> {
+    if let Ok(key) = env::var(provider.token_env)
+        && !key.is_empty()
+    {
+        return Ok(Some(key));
+    }
+    if let Some(key) = key_from_file(provider)? {
+        return Ok(Some(key));
+    }
+
+    let provider_config = config.provider_config(provider);
+    if let Some(key) = provider_config.token
+        && !key.trim().is_empty()
+    {
+        return Ok(Some(key.trim().to_string()));
+    }
+    if let Some(command) = provider_config.token_cmd
+        && !command.trim().is_empty()
+    {
+        return key_from_command(&command).map(Some);
+    }
+
+    if let Some(key) = encrypted_api_key(provider)? {
+        return Ok(Some(key));
+    }
+    Ok(stored_api_key(provider))
+}
+
+/// The key `set-key` stored for the provider: its encrypted key file, its
+/// key file, or else the system keyring. The files are read first, so
+/// hosts without a keyring never wait on one.
+pub fn stored_api_key(provider: &ProviderInfo) -> Option<String> {
+    if let Ok(Some(key)) = encrypted_api_key(provider) {
+        return Some(key);
+    }
+    let key_file = api_key_file(provider);
+    if key_file.exists()
+        && let Ok(key) = fs::read_to_string(&key_file)
+    {
+        let key = key.trim().to_string();
+        if !key.is_empty() {
+            return Some(key);
+        }
+    }
+    keystore::load(provider)
+}
+
+/// Creates `dir` if needed and makes it accessible only to its owner.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Why the provider's key file is exposed to other users, if it is: the
+/// file or the config directory holding it has group or world permissions.
+/// Always `None` where there are no Unix permissions.
+pub fn key_file_exposure(provider: &ProviderInfo) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let file = api_key_file(provider);
+        let mode = fs::metadata(&file).ok()?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Some(format!(
+                "API key file {} is accessible by other users (mode {:o}); run `chmod 600 {}`",
+                file.display(),
+                mode,
+                file.display()
+            ));
+        }
+        let dir = config_dir();
+        let mode = fs::metadata(&dir).ok()?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Some(format!(
+                "config directory {} is accessible by other users (mode {:o}); run `chmod 700 {}`",
+                dir.display(),
+                mode,
+                dir.display()
+            ));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = provider;
+    None
+}
+
+/// Where [`save_api_key`] put a key.
+#[derive(Debug)]
+pub enum KeyStorage {
+    Keyring,
+    File(PathBuf),
+    Encrypted(PathBuf),
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Stores the provider's key in the system keyring, removing any key file
+/// it replaces. Without a keyring, or with `plaintext`, the key goes to the
+/// key file instead.
+pub fn save_api_key(provider: &ProviderInfo, key: &str, plaintext: bool) -> io::Result<KeyStorage> {
+    let key_file = api_key_file(provider);
+    remove_if_exists(&encrypted_key_file(provider))?;
+    if !plaintext && keystore::save(provider, key).is_ok() {
+        remove_if_exists(&key_file)?;
+        return Ok(KeyStorage::Keyring);
+    }
+    create_private_dir(&config_dir())?;
+    write_private(&key_file, key.as_bytes())?;
+    if plaintext {
+        keystore::delete(provider);
+    }
+    Ok(KeyStorage::File(key_file))
+}
+
+/// Stores the provider's key in its encrypted key file, removing the key
+/// file and keyring entry it replaces.
+pub fn save_encrypted_api_key(
+    provider: &ProviderInfo,
+    key: &str,
+    encryption: &Encryption,
+) -> Result<KeyStorage> {
+    let armored = crypt::encrypt(key, encryption)?;
+    let path = encrypted_key_file(provider);
+    create_private_dir(&config_dir())?;
+    write_private(&path, armored.as_bytes())?;
+    remove_if_exists(&api_key_file(provider))?;
+    keystore::delete(provider);
+    Ok(KeyStorage::Encrypted(path))
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it into
+/// place, so a crash mid-write never leaves a truncated file behind.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomic_mode(path, data, 0o666)
+}
+
+/// Like [`write_atomic`], but the file is only ever readable by its owner.
+pub fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomic_mode(path, data, 0o600)
+}
+
+/// `mode` is the Unix permissions the file is created with, less the
+/// umask.
+fn write_atomic_mode(path: &Path, data: &[u8], mode: u32) -> io::Result<()> {
+    #[cfg(not(unix))]
+    let _ = mode;
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".tmp.{}", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
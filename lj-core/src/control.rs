@@ -0,0 +1,210 @@
+//! Front-end operations on downloads.
+//!
+//! Each call goes through the [`daemon`](crate::ipc) when one is running, and
+//! otherwise works on the store and worker processes directly, so the CLI
+//! and `lj serve` behave the same either way.
+
+use crate::error::{Error, Result};
+use crate::ipc::{self, Request, Response};
+use crate::platform;
+use crate::progress;
+use crate::store::{self, Download, DownloadStatus};
+use crate::worker;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+/// Lists downloads through the daemon when one is running, with the live
+/// [progress](crate::progress) of the running ones.
+pub fn list_downloads() -> Vec<Download> {
+    if ipc::daemon_running()
+        && let Ok(Response::Downloads(downloads)) = ipc::request(&Request::List)
+    {
+        return downloads;
+    }
+
+    // Relaunch or clean up dead processes
+    worker::supervise();
+    let mut downloads = store::load_all_downloads();
+    progress::overlay(&mut downloads);
+    downloads
+}
+
+pub fn cancel_download(id: &str) -> bool {
+    if ipc::daemon_running() {
+        return matches!(
+            ipc::request(&Request::Cancel { id: id.to_string() }),
+            Ok(Response::Cancelled(true))
+        );
+    }
+    worker::cancel_download(id)
+}
+
+/// Hands the download to the daemon, or spawns a worker process for it.
+pub fn start_download(download: &Download) -> Result<()> {
+    start_downloads(std::slice::from_ref(download))
+}
+
+/// Hands the downloads to the daemon, or spawns one worker process that
+/// runs them all, as for the files of a torrent.
+pub fn start_downloads(downloads: &[Download]) -> Result<()> {
+    let mut checked = Vec::new();
+    for download in downloads {
+        if !checked.contains(&&download.target_dir) {
+            check_target_dir(&download.target_dir)?;
+            checked.push(&download.target_dir);
+        }
+    }
+    if ipc::daemon_running() {
+        for download in downloads {
+            match ipc::request(&Request::Add {
+                download: Box::new(download.clone()),
+            })? {
+                Response::Ok => {}
+                Response::Error(e) => return Err(Error::Other(e)),
+                _ => return Err(Error::Other("Unexpected daemon response".to_string())),
+            }
+        }
+        return Ok(());
+    }
+    if downloads.is_empty() {
+        return Ok(());
+    }
+
+    // Save downloads first, then spawn
+    for download in downloads {
+        store::save_download(download)?;
+    }
+    worker::spawn_worker(downloads)?;
+    Ok(())
+}
+
+/// Fails unless files can be written in `dir`, or in the nearest directory
+/// above it that exists, as the worker creates the rest. A worker that can't
+/// write only dies in the background, long after the command has returned.
+pub fn check_target_dir(dir: &str) -> Result<()> {
+    let unusable = |kind: io::ErrorKind, message: String| {
+        Error::Io(io::Error::new(
+            kind,
+            format!("Can't download to {}: {}", dir, message),
+        ))
+    };
+    let existing = Path::new(dir)
+        .ancestors()
+        .find(|path| fs::symlink_metadata(path).is_ok())
+        .unwrap_or(Path::new("."));
+    if !existing.is_dir() {
+        return Err(unusable(
+            io::ErrorKind::NotADirectory,
+            format!("{} is not a directory", existing.display()),
+        ));
+    }
+    let probe = existing.join(format!(".lj-write-test-{}", process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|e| unusable(e.kind(), e.to_string()))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Restarts a failed or cancelled download from the beginning.
+pub fn retry_download(id: &str) -> Result<Download> {
+    let download = store::load_download(id)
+        .ok_or_else(|| Error::Other(format!("Download not found: {}", id)))?;
+    if !matches!(
+        download.status,
+        DownloadStatus::Failed(_) | DownloadStatus::Cancelled
+    ) {
+        return Err(Error::Other(format!(
+            "Only failed or cancelled downloads can be retried ({} is {})",
+            id,
+            download.status.kind()
+        )));
+    }
+
+    let download = Download {
+        status: DownloadStatus::Pending,
+        downloaded_bytes: 0,
+        speed: 0.0,
+        average_speed: 0.0,
+        pid: None,
+        pid_started: None,
+        restarts: 0,
+        ..download
+    };
+    start_download(&download)?;
+    Ok(download)
+}
+
+/// Points a download that isn't running at `dir`, taking its partial file
+/// along, without starting it. A download being started by another process
+/// counts as running. `progress` follows the partial file's copy when it
+/// has to be copied to another filesystem (see [`platform::move_file`]).
+pub fn set_download_dir(
+    id: &str,
+    dir: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<Download> {
+    let download = store::load_download(id)
+        .ok_or_else(|| Error::Other(format!("Download not found: {}", id)))?;
+    let idle = match download.status {
+        DownloadStatus::Pending => !download.process_alive(),
+        DownloadStatus::Failed(_) | DownloadStatus::Cancelled => true,
+        _ => false,
+    };
+    if !idle {
+        return Err(Error::Other(format!(
+            "Only pending, failed or cancelled downloads can be moved ({} is {})",
+            id,
+            download.status.kind()
+        )));
+    }
+    check_target_dir(dir)?;
+
+    let from = download.target_path();
+    let moved = Download {
+        target_dir: dir.to_string(),
+        ..download
+    };
+    let to = moved.target_path();
+    if from != to && from.is_file() {
+        fs::create_dir_all(dir)?;
+        platform::move_file(&from, &to, progress)?;
+    }
+    store::update_download(id, |dl| dl.target_dir = dir.to_string())?;
+    Ok(moved)
+}
+
+/// Starts a download over in `dir`, for one whose folder has gone missing.
+/// A worker still writing into the lost folder is stopped first.
+pub fn move_download(id: &str, dir: &str) -> Result<Download> {
+    let download = store::load_download(id)
+        .ok_or_else(|| Error::Other(format!("Download not found: {}", id)))?;
+    if download.status == DownloadStatus::Completed {
+        return Err(Error::Other(format!(
+            "Completed downloads can't be moved ({})",
+            id
+        )));
+    }
+    check_target_dir(dir)?;
+    if !download.status.is_finished() {
+        cancel_download(id);
+    }
+
+    let download = Download {
+        target_dir: dir.to_string(),
+        status: DownloadStatus::Pending,
+        downloaded_bytes: 0,
+        speed: 0.0,
+        average_speed: 0.0,
+        pid: None,
+        pid_started: None,
+        restarts: 0,
+        ..download
+    };
+    start_download(&download)?;
+    Ok(download)
+}
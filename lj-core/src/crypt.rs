@@ -0,0 +1,122 @@
+//! Encrypted API key files, for config directories synced to cloud storage.
+//!
+//! `lj set-key --encrypt` stores the key as an ASCII-armored
+//! [age](https://age-encryption.org) file, `api_key.age` next to where the
+//! plaintext key would go, instead of in the keyring or the key file.
+//! It is encrypted either with a passphrase, taken from `LJ_PASSPHRASE` or
+//! asked for when the key is needed, or to an age identity generated on
+//! first use and kept in the system keyring, so a synced copy is useless
+//! without the machine it was made on.
+
+use crate::error::{Error, Result};
+use crate::keystore;
+use age::armor::ArmoredReader;
+use age::secrecy::{ExposeSecret, SecretString};
+use age::{DecryptError, Decryptor, Identity, scrypt, x25519};
+use std::io::{BufReader, Read};
+use std::str::FromStr;
+
+/// Environment variable holding the passphrase for encrypted key files.
+pub const PASSPHRASE_ENV: &str = "LJ_PASSPHRASE";
+
+/// What a key file is encrypted with.
+#[derive(Debug, Clone)]
+pub enum Encryption {
+    Passphrase(String),
+    /// The age identity in the system keyring.
+    Keyring,
+}
+
+/// The keyring's identity, generating and storing one if there is none.
+fn keyring_identity() -> Result<x25519::Identity> {
+    if let Some(identity) = keystore::load_identity() {
+        return x25519::Identity::from_str(&identity).map_err(|e| {
+            Error::Other(format!(
+                "Invalid encryption key in the system keyring: {}",
+                e
+            ))
+        });
+    }
+    let identity = x25519::Identity::generate();
+    keystore::save_identity(identity.to_string().expose_secret()).map_err(|e| {
+        Error::Other(format!(
+            "No system keyring to keep the encryption key in ({}); use a passphrase",
+            e
+        ))
+    })?;
+    Ok(identity)
+}
+
+/// `key` as an armored age file.
+pub fn encrypt(key: &str, encryption: &Encryption) -> Result<String> {
+    let armored = match encryption {
+        Encryption::Passphrase(passphrase) => {
+            let recipient = scrypt::Recipient::new(SecretString::from(passphrase.clone()));
+            age::encrypt_and_armor(&recipient, key.as_bytes())
+        }
+        Encryption::Keyring => {
+            age::encrypt_and_armor(&keyring_identity()?.to_public(), key.as_bytes())
+        }
+    };
+    armored.map_err(|e| Error::Other(format!("Could not encrypt the API key: {}", e)))
+}
+
+fn decryptor(armored: &str) -> Result<Decryptor<ArmoredReader<BufReader<&[u8]>>>> {
+    Decryptor::new_buffered(ArmoredReader::new(armored.as_bytes()))
+        .map_err(|e| Error::Other(format!("Invalid encrypted API key: {}", e)))
+}
+
+/// Whether `armored` is encrypted with a passphrase rather than the
+/// keyring's identity.
+pub fn needs_passphrase(armored: &str) -> bool {
+    decryptor(armored).is_ok_and(|decryptor| decryptor.is_scrypt())
+}
+
+/// The key in `armored`, decrypted with `passphrase` or the keyring's
+/// identity, whichever it was encrypted with.
+pub fn decrypt(armored: &str, passphrase: Option<&str>) -> Result<String> {
+    let decryptor = decryptor(armored)?;
+    let scrypt = decryptor.is_scrypt();
+    let identity: Box<dyn Identity> = if scrypt {
+        let passphrase = passphrase.ok_or_else(|| {
+            Error::Config(format!(
+                "the API key is encrypted with a passphrase; set {}",
+                PASSPHRASE_ENV
+            ))
+        })?;
+        Box::new(scrypt::Identity::new(SecretString::from(
+            passphrase.to_string(),
+        )))
+    } else {
+        let identity = keystore::load_identity().ok_or_else(|| {
+            Error::Config(
+                "the API key is encrypted to a key that is not in this system's keyring"
+                    .to_string(),
+            )
+        })?;
+        Box::new(x25519::Identity::from_str(&identity).map_err(|e| {
+            Error::Other(format!(
+                "Invalid encryption key in the system keyring: {}",
+                e
+            ))
+        })?)
+    };
+
+    let mut reader = decryptor
+        .decrypt(std::iter::once(identity.as_ref()))
+        .map_err(|e| match e {
+            DecryptError::DecryptionFailed | DecryptError::NoMatchingKeys if scrypt => {
+                Error::Config("wrong passphrase for the encrypted API key".to_string())
+            }
+            DecryptError::NoMatchingKeys => Error::Config(
+                "the API key is encrypted to a different key than the one in this system's keyring"
+                    .to_string(),
+            ),
+            e => Error::Other(format!("Could not decrypt the API key: {}", e)),
+        })?;
+    let mut key = String::new();
+    reader
+        .read_to_string(&mut key)
+        .map_err(|e| Error::Other(format!("Could not decrypt the API key: {}", e)))?;
+    Ok(key.trim().to_string())
+}
@@ -0,0 +1,306 @@
+//! Long-running daemon that owns all transfers.
+//!
+//! Instead of one detached process per file, the daemon runs every download
+//! as a task in a single process, shares one HTTP client and an optional
+//! global bandwidth limit between them, and answers [`ipc`](crate::ipc)
+//! requests on a Unix socket.
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::events::{self, DownloadEvent};
+use crate::ipc::{self, Request, Response};
+use crate::logging::{self, Level};
+use crate::notify;
+use crate::progress;
+use crate::ratelimit::RateLimiter;
+use crate::rss::Poller;
+use crate::store::{self, Download, DownloadStatus};
+use crate::systemd;
+use crate::worker::{self, Claim, TransferOptions};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Notify, Semaphore};
+use tokio::task::AbortHandle;
+
+/// How often the daemon looks for dead workers to restart.
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone)]
+pub struct DaemonOptions {
+    /// Global download limit in bytes per second across all transfers.
+    pub rate_limit: Option<u64>,
+    /// Client shared by every transfer (see [`http::client`](crate::http::client)).
+    pub client: Client,
+    /// Settings the [notifications](notify) are read from.
+    pub config: Config,
+    /// Feeds to poll, and how often (see [`rss`](crate::rss)).
+    pub rss: Option<(Arc<Poller>, Duration)>,
+}
+
+struct Daemon {
+    client: Client,
+    limiter: Option<Arc<RateLimiter>>,
+    transfers: TransferOptions,
+    tasks: Mutex<HashMap<String, AbortHandle>>,
+    /// Transfers that may run at once, as many as a worker process runs.
+    slots: Semaphore,
+    shutdown: Notify,
+}
+
+/// Runs the daemon until it receives SIGINT, SIGTERM or a shutdown request.
+pub async fn run_daemon(opts: DaemonOptions) -> io::Result<()> {
+    logging::init();
+    let path = ipc::socket_path();
+    if ipc::daemon_running() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "a daemon is already running",
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Left over from a daemon that did not shut down cleanly
+    let _ = fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    notify::start(&opts.config, &opts.client);
+    let daemon = Arc::new(Daemon {
+        client: opts.client,
+        limiter: opts.rate_limit.map(|bps| Arc::new(RateLimiter::new(bps))),
+        transfers: TransferOptions::from_config(&opts.config),
+        tasks: Mutex::new(HashMap::new()),
+        slots: Semaphore::new(worker::FILES_AT_ONCE),
+        shutdown: Notify::new(),
+    });
+
+    if let Some((poller, interval)) = opts.rss {
+        tokio::spawn(poll_feeds(daemon.clone(), poller, interval));
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut watchdog = systemd::watchdog_interval().map(tokio::time::interval);
+    let mut supervision = tokio::time::interval(SUPERVISE_INTERVAL);
+    let _ = systemd::notify("READY=1");
+    logging::log(
+        Level::Info,
+        &format!("Daemon listening on {}", path.display()),
+        &[("LJ_EVENT", "daemon_started")],
+    );
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((stream, _)) = accepted {
+                    let daemon = daemon.clone();
+                    tokio::spawn(async move {
+                        let _ = handle_connection(&daemon, stream).await;
+                    });
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+            _ = daemon.shutdown.notified() => break,
+            _ = supervision.tick() => daemon.supervise(),
+            // Pinging from the accept loop means a wedged daemon gets restarted
+            _ = async {
+                match &mut watchdog {
+                    Some(interval) => interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let _ = systemd::notify("WATCHDOG=1");
+            }
+        }
+    }
+
+    let _ = systemd::notify("STOPPING=1");
+    logging::log(
+        Level::Info,
+        "Daemon stopping",
+        &[("LJ_EVENT", "daemon_stopped")],
+    );
+    daemon.stop_all();
+    let _ = fs::remove_file(&path);
+    progress::close();
+    notify::finish().await;
+    Ok(())
+}
+
+/// Polls the feeds every `interval`, starting their downloads here.
+async fn poll_feeds(daemon: Arc<Daemon>, poller: Arc<Poller>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        poller
+            .poll(false, &|download| match daemon.start(download.clone()) {
+                Response::Error(e) => Err(Error::Other(e)),
+                _ => Ok(()),
+            })
+            .await;
+    }
+}
+
+async fn handle_connection(daemon: &Arc<Daemon>, stream: UnixStream) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+
+    let (response, subscribe) = match serde_json::from_str::<Request>(&line) {
+        Ok(req) => {
+            let subscribe = matches!(req, Request::Subscribe);
+            (daemon.handle(req), subscribe)
+        }
+        Err(e) => (Response::Error(format!("Invalid request: {}", e)), false),
+    };
+
+    let mut reply = serde_json::to_string(&response)?;
+    reply.push('\n');
+    write.write_all(reply.as_bytes()).await?;
+    if !subscribe {
+        return Ok(());
+    }
+
+    // Stream events until the client hangs up
+    let mut events = events::subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let mut line = serde_json::to_string(&event)?;
+        line.push('\n');
+        write.write_all(line.as_bytes()).await?;
+    }
+}
+
+impl Daemon {
+    fn handle(self: &Arc<Self>, req: Request) -> Response {
+        match req {
+            Request::Add { download } => self.start(*download),
+            Request::List => {
+                self.supervise();
+                let mut downloads = store::load_all_downloads();
+                progress::overlay(&mut downloads);
+                Response::Downloads(downloads)
+            }
+            Request::Cancel { id } => {
+                let handle = self.tasks.lock().unwrap().remove(&id);
+                let running = handle.is_some();
+                if let Some(handle) = handle {
+                    handle.abort();
+                    progress::clear(&id);
+                }
+                let mut target = None;
+                let _ = store::update_download(&id, |dl| {
+                    // Pending, it was ours and waiting for a slot
+                    if dl.status == DownloadStatus::Downloading
+                        || (running && dl.status == DownloadStatus::Pending)
+                    {
+                        dl.status = DownloadStatus::Cancelled;
+                        dl.speed = 0.0;
+                        dl.pid = None;
+                        dl.pid_started = None;
+                        target = Some(dl.target_path());
+                    }
+                });
+                if let Some(target) = target {
+                    let _ = fs::remove_file(target);
+                    events::emit(DownloadEvent::Cancelled { id });
+                }
+                Response::Cancelled(running)
+            }
+            // handle_connection streams the events after this reply
+            Request::Subscribe => Response::Ok,
+            Request::Shutdown => {
+                self.shutdown.notify_one();
+                Response::Ok
+            }
+        }
+    }
+
+    fn start(self: &Arc<Self>, mut download: Download) -> Response {
+        let claim = match Claim::acquire(&download.id) {
+            Ok(claim) => claim,
+            Err(e) => return Response::Error(e.to_string()),
+        };
+        // Pending until one of the slots is free
+        download.status = DownloadStatus::Pending;
+        download.set_process(std::process::id());
+        if let Err(e) = store::save_download(&download) {
+            return Response::Error(format!("Failed to save download: {}", e));
+        }
+
+        let id = download.id.clone();
+        let daemon = self.clone();
+        let task_id = id.clone();
+        // Held until the handle is in, so a task that ends at once finds
+        // it to remove
+        let mut tasks = self.tasks.lock().unwrap();
+        let handle = tokio::spawn(async move {
+            let Ok(_slot) = daemon.slots.acquire().await else {
+                return;
+            };
+            let started =
+                store::update_download(&task_id, |dl| dl.status = DownloadStatus::Downloading);
+            if let Ok(Some(download)) = started {
+                let limiter = daemon.limiter.as_deref();
+                worker::run_download(download, &daemon.client, limiter, daemon.transfers, claim)
+                    .await;
+            }
+            let mut tasks = daemon.tasks.lock().unwrap();
+            // Unless the download was started again since
+            if tasks
+                .get(&task_id)
+                .is_some_and(|handle| handle.id() == tokio::task::id())
+            {
+                tasks.remove(&task_id);
+            }
+        });
+        tasks.insert(id, handle.abort_handle());
+        Response::Ok
+    }
+
+    /// Takes over downloads whose worker process or previous daemon died,
+    /// resuming them as tasks here.
+    fn supervise(self: &Arc<Self>) {
+        for download in worker::reap_dead_workers() {
+            self.start(download);
+        }
+    }
+
+    fn stop_all(&self) {
+        for (id, handle) in self.tasks.lock().unwrap().drain() {
+            handle.abort();
+            let stopped = store::update_download(&id, |dl| {
+                // One still waiting for a slot stays this process's, to be
+                // started by whoever supervises once it is gone
+                if dl.status == DownloadStatus::Pending {
+                    return;
+                }
+                if dl.status == DownloadStatus::Downloading {
+                    dl.status = DownloadStatus::Failed("Daemon stopped".to_string());
+                }
+                dl.speed = 0.0;
+                dl.pid = None;
+                dl.pid_started = None;
+            });
+            if let Ok(Some(dl)) = stopped
+                && let Some(event) = worker::finished_event(&dl)
+            {
+                events::emit(event);
+            }
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Debrid-Link seedbox client.
+//!
+//! A Debrid-Link seedbox always fetches every file in a torrent, so
+//! selection is emulated client-side (see [`EmulatedSelection`]). Finished
+//! files carry their own direct URLs; links handed out by
+//! [`DebridProvider::poll`] are `dl:<torrent id>:<file id>` so
+//! [`DebridProvider::unrestrict`] can report the file's name and size too.
+
+use crate::error::{Error, Result};
+use crate::http::{self, Auth, Token};
+use crate::provider::{
+    Account, DebridProvider, EmulatedSelection, TorrentFile, TorrentState, TorrentStatus,
+    UnrestrictedLink,
+};
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DL_BASE_URL: &str = "https://debrid-link.com/api/v2";
+
+/// Prefix of the links handed out by [`DebridProvider::poll`].
+const FILE_LINK: &str = "dl:";
+
+/// Every response is `{"success": ..., "value" | "error": ...}`.
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    value: Option<T>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountInfo {
+    pseudo: String,
+    /// 0 for free accounts.
+    #[serde(default)]
+    account_type: u32,
+    /// Seconds of premium left.
+    premium_left: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Torrent {
+    id: String,
+    name: Option<String>,
+    total_size: Option<u64>,
+    /// 0–100.
+    download_percent: Option<f64>,
+    download_speed: Option<u64>,
+    peers_connected: Option<u32>,
+    error: Option<i64>,
+    error_string: Option<String>,
+    files: Option<Vec<File>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    id: String,
+    name: String,
+    size: Option<u64>,
+    download_percent: Option<f64>,
+    download_url: Option<String>,
+}
+
+pub struct DebridLink {
+    client: Client,
+    api_key: Token,
+    base_url: String,
+    selection: EmulatedSelection,
+}
+
+impl DebridLink {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(Client::new(), api_key)
+    }
+
+    pub fn with_client(client: Client, api_key: &str) -> Self {
+        Self {
+            client,
+            api_key: Token::new(api_key),
+            base_url: DL_BASE_URL.to_string(),
+            selection: EmulatedSelection::default(),
+        }
+    }
+
+    /// Points the client at another API root, e.g. a proxy or mirror.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        http::request(
+            &self.client,
+            method,
+            format!("{}{}", self.base_url, path),
+            &self.api_key,
+            Auth::Bearer,
+        )
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        req: RequestBuilder,
+        context: &'static str,
+    ) -> Result<T> {
+        let resp = req.send().await.map_err(Error::http(context))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(Error::http(context))?;
+
+        let envelope: Envelope<T> = serde_json::from_str(&text).map_err(|e| {
+            if status.is_success() {
+                Error::Parse {
+                    context,
+                    message: e.to_string(),
+                }
+            } else {
+                Error::Api {
+                    context,
+                    status,
+                    code: None,
+                    message: text.clone(),
+                }
+            }
+        })?;
+
+        if envelope.success
+            && let Some(value) = envelope.value
+        {
+            return Ok(value);
+        }
+        Err(api_error(context, status, envelope.error))
+    }
+
+    async fn torrent(&self, torrent_id: &str) -> Result<Torrent> {
+        let torrents: Vec<Torrent> = self
+            .call(
+                self.request(Method::GET, "/seedbox/list")
+                    .query(&[("ids", torrent_id)]),
+                "Failed to get torrent info",
+            )
+            .await?;
+        torrents
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Torrent(format!("torrent {} not found", torrent_id)))
+    }
+}
+
+fn api_error(context: &'static str, status: StatusCode, error: Option<String>) -> Error {
+    let code = error.unwrap_or_else(|| "unknown".to_string());
+    // Surface auth failures the same way RD's 401s are, so callers can
+    // treat every provider alike
+    let status = if matches!(code.as_str(), "badToken" | "hidedToken" | "notDebrid") {
+        StatusCode::UNAUTHORIZED
+    } else {
+        status
+    };
+    Error::Api {
+        context,
+        status,
+        code: None,
+        message: code,
+    }
+}
+
+/// Debrid-Link reports progress rather than a status name, so the state is
+/// derived from the error flag and the files' completion.
+fn torrent_state(torrent: &Torrent) -> TorrentState {
+    if torrent.error.unwrap_or(0) != 0 {
+        let message = torrent.error_string.as_deref().unwrap_or("error");
+        return TorrentState::Failed(message.to_string());
+    }
+    let files = torrent.files.as_deref().unwrap_or_default();
+    let done = !files.is_empty()
+        && files
+            .iter()
+            .all(|f| f.download_percent.unwrap_or(0.0) >= 100.0 && f.download_url.is_some());
+    if done {
+        TorrentState::Ready
+    } else if files.is_empty() {
+        TorrentState::Resolving
+    } else {
+        TorrentState::Processing
+    }
+}
+
+#[async_trait]
+impl DebridProvider for DebridLink {
+    fn name(&self) -> &'static str {
+        "debrid-link"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Debrid-Link"
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        let torrent: Torrent = self
+            .call(
+                self.request(Method::POST, "/seedbox/add")
+                    .json(&serde_json::json!({ "url": magnet, "async": true })),
+                "Failed to add magnet",
+            )
+            .await?;
+        Ok(torrent.id)
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus> {
+        let torrent = self.torrent(torrent_id).await?;
+        let state = torrent_state(&torrent);
+        let ready = state == TorrentState::Ready;
+        let status = match &state {
+            TorrentState::Ready => "downloaded",
+            TorrentState::Failed(_) => "error",
+            TorrentState::Resolving => "resolving",
+            _ => "downloading",
+        };
+
+        let all_files = ready.then(|| {
+            torrent
+                .files
+                .iter()
+                .flatten()
+                .enumerate()
+                .map(|(i, f)| {
+                    let file = TorrentFile {
+                        id: i as u32 + 1,
+                        path: f.name.clone(),
+                        bytes: f.size.unwrap_or(0),
+                        selected: 1,
+                    };
+                    (file, format!("{}{}:{}", FILE_LINK, torrent.id, f.id))
+                })
+                .collect()
+        });
+        let name = torrent.name.as_deref().unwrap_or(torrent_id);
+        let (state, files, links) = self.selection.resolve(
+            torrent_id,
+            state,
+            all_files,
+            name,
+            torrent.total_size.unwrap_or(0),
+        );
+
+        Ok(TorrentStatus {
+            state,
+            name: torrent.name,
+            status: status.to_string(),
+            progress: torrent.download_percent.unwrap_or(0.0),
+            speed: torrent.download_speed.unwrap_or(0),
+            seeders: torrent.peers_connected.unwrap_or(0),
+            files,
+            links,
+        })
+    }
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        self.selection.select(torrent_id, file_ids);
+        Ok(())
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        let (torrent_id, file_id) = link
+            .strip_prefix(FILE_LINK)
+            .and_then(|ids| ids.split_once(':'))
+            .ok_or_else(|| Error::Other(format!("Not a Debrid-Link link: {}", link)))?;
+
+        let torrent = self.torrent(torrent_id).await?;
+        let file = torrent
+            .files
+            .into_iter()
+            .flatten()
+            .find(|f| f.id == file_id)
+            .ok_or_else(|| Error::Torrent(format!("file {} not found", file_id)))?;
+        let download = file.download_url.ok_or_else(|| Error::Parse {
+            context: "Failed to get torrent info",
+            message: "file has no download URL".to_string(),
+        })?;
+
+        Ok(UnrestrictedLink {
+            filename: file
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or(&file.name)
+                .to_string(),
+            download,
+            filesize: file.size,
+        })
+    }
+
+    async fn delete(&self, torrent_id: &str) -> Result<()> {
+        self.selection.forget(torrent_id);
+        let _: serde_json::Value = self
+            .call(
+                self.request(Method::DELETE, &format!("/seedbox/{}/remove", torrent_id)),
+                "Failed to delete torrent",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn account(&self) -> Result<Option<Account>> {
+        let info: AccountInfo = self
+            .call(
+                self.request(Method::GET, "/account/infos"),
+                "Failed to get account",
+            )
+            .await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Ok(Some(Account {
+            username: info.pseudo,
+            premium_until: info
+                .premium_left
+                .filter(|&left| info.account_type > 0 && left > 0)
+                .map(|left| now + left),
+        }))
+    }
+}
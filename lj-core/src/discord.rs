@@ -0,0 +1,124 @@
+//! Discord notifications.
+//!
+//! Each event is posted to the `[discord]` section's webhook URL as an
+//! embed listing the file, its size, how long the download took and how it
+//! ended, coloured by outcome.
+
+use crate::config::DiscordConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::format::{format_duration, format_size};
+use crate::notify::{self, Notifier};
+use crate::store;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Events sent when the section lists none.
+const DEFAULT_EVENTS: &[&str] = &["completed", "failed"];
+
+const GREEN: u32 = 0x2ecc71;
+const RED: u32 = 0xe74c3c;
+const GREY: u32 = 0x95a5a6;
+const BLUE: u32 = 0x3498db;
+
+pub struct Discord {
+    url: String,
+    username: Option<String>,
+    events: Vec<String>,
+}
+
+impl Discord {
+    /// The Discord webhook described by `config`, if it has a URL.
+    pub fn from_config(config: &DiscordConfig) -> Option<Self> {
+        Some(Discord {
+            url: config.url.clone()?,
+            username: config.username.clone(),
+            events: config.events.clone(),
+        })
+    }
+}
+
+fn embed(event: &DownloadEvent) -> serde_json::Value {
+    let (title, _) = notify::describe(event);
+    let download = store::load_download(event.id());
+    let filename = download
+        .as_ref()
+        .map(|dl| dl.filename.clone())
+        .unwrap_or_else(|| event.id().to_string());
+
+    let (status, color, size) = match event {
+        DownloadEvent::Started { total_bytes, .. } => ("Started".to_string(), BLUE, *total_bytes),
+        DownloadEvent::Progress { total_bytes, .. } => {
+            ("Downloading".to_string(), BLUE, *total_bytes)
+        }
+        DownloadEvent::Completed { bytes, .. } => ("Completed".to_string(), GREEN, *bytes),
+        DownloadEvent::Failed { error, .. } => (
+            format!("Failed: {}", error),
+            RED,
+            download.as_ref().map_or(0, |dl| dl.total_bytes),
+        ),
+        DownloadEvent::Cancelled { .. } => (
+            "Cancelled".to_string(),
+            GREY,
+            download.as_ref().map_or(0, |dl| dl.total_bytes),
+        ),
+    };
+
+    let mut fields = vec![
+        json!({"name": "File", "value": filename}),
+        json!({"name": "Size", "value": format_size(size), "inline": true}),
+    ];
+    if let Some(dl) = &download
+        && !matches!(event, DownloadEvent::Started { .. })
+    {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        fields.push(json!({
+            "name": "Duration",
+            "value": format_duration(now.saturating_sub(dl.started_at)),
+            "inline": true,
+        }));
+    }
+    fields.push(json!({"name": "Status", "value": status, "inline": true}));
+
+    json!({"title": title, "color": color, "fields": fields})
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        notify::selects(&self.events, DEFAULT_EVENTS, event)
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let context = "Discord request failed";
+        let body = json!({
+            "username": self.username.as_deref().unwrap_or("lj"),
+            "embeds": [embed(event)],
+        });
+
+        let resp = client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(Error::http(context))?;
+        if !resp.status().is_success() {
+            return Err(Error::Api {
+                context,
+                status: resp.status(),
+                code: None,
+                message: resp.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Error type shared by the client, pipeline and workers.
+
+use reqwest::StatusCode;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The API answered with a non-success status.
+    #[error("{context}: {status} - {message}")]
+    Api {
+        context: &'static str,
+        status: StatusCode,
+        /// Provider-specific error code from the response body, if any.
+        code: Option<i64>,
+        message: String,
+    },
+    /// The request never got a response (DNS, TLS, connection reset, ...).
+    #[error("{context}: {source}")]
+    Http {
+        context: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// A download host answered with a non-success status.
+    #[error("HTTP error: {0}")]
+    Status(StatusCode),
+    /// The response could not be decoded.
+    #[error("{context}: {message}")]
+    Parse {
+        context: &'static str,
+        message: String,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Timeout waiting for {0}")]
+    Timeout(&'static str),
+    /// The torrent sat at 0% with no seeders for this many seconds.
+    #[error("Torrent appears dead: no progress and no seeders for {0}s")]
+    Stalled(u64),
+    /// RD gave up on the torrent (`magnet_error`, `dead`, `error`).
+    #[error("Torrent error: {0}")]
+    Torrent(String),
+    #[error("No files in torrent")]
+    NoFiles,
+    #[error("No files selected")]
+    NothingSelected,
+    #[error("No download links obtained")]
+    NoLinks,
+    #[error("Cancelled")]
+    Cancelled,
+    /// Some or all of a batch failed, e.g. a torrent's downloads or the
+    /// queued magnets.
+    #[error("{failed} of {total} {what} failed")]
+    Batch {
+        failed: usize,
+        total: usize,
+        what: &'static str,
+    },
+    #[error("Invalid config: {0}")]
+    Config(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Process exit codes for each class of failure, so scripts can tell them
+/// apart. 0 is success.
+pub mod exit {
+    /// Anything not covered below, e.g. a bad config.
+    pub const FAILURE: i32 = 1;
+    /// An invalid magnet, torrent file or command line.
+    pub const INPUT: i32 = 2;
+    /// The service rejected the API key.
+    pub const AUTH: i32 = 3;
+    /// The service answered with an error, or failed the torrent.
+    pub const API: i32 = 4;
+    pub const TIMEOUT: i32 = 5;
+    pub const NETWORK: i32 = 6;
+    pub const IO: i32 = 7;
+    /// Every download of the batch failed.
+    pub const DOWNLOAD: i32 = 8;
+    /// Some of the batch failed, the rest went through.
+    pub const PARTIAL: i32 = 9;
+    pub const CANCELLED: i32 = 130;
+}
+
+impl Error {
+    /// Wraps a failed request, masking any credentials in its URL.
+    pub(crate) fn http(context: &'static str) -> impl FnOnce(reqwest::Error) -> Error {
+        move |mut source| {
+            if let Some(url) = source.url_mut() {
+                crate::http::redact_url(url);
+            }
+            Error::Http { context, source }
+        }
+    }
+
+    /// Whether the API rejected the token.
+    pub fn is_auth(&self) -> bool {
+        matches!(
+            self,
+            Error::Api { status, code, .. }
+                if *status == StatusCode::UNAUTHORIZED || *code == Some(8)
+        )
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            _ if self.is_auth() => exit::AUTH,
+            Error::Parse {
+                context: "Invalid magnet link" | "Invalid torrent file",
+                ..
+            } => exit::INPUT,
+            Error::Api { .. } | Error::Parse { .. } | Error::Torrent(_) | Error::NoLinks => {
+                exit::API
+            }
+            Error::Http { .. } | Error::Status(_) => exit::NETWORK,
+            Error::Io(_) => exit::IO,
+            Error::Timeout(_) | Error::Stalled(_) => exit::TIMEOUT,
+            Error::Cancelled => exit::CANCELLED,
+            Error::Batch { failed, total, .. } if failed < total => exit::PARTIAL,
+            Error::Batch { .. } => exit::DOWNLOAD,
+            Error::NoFiles | Error::NothingSelected | Error::Config(_) | Error::Other(_) => {
+                exit::FAILURE
+            }
+        }
+    }
+
+    /// A short suggestion for the user, where there is an obvious next step.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            _ if self.is_auth() => Some("Run `lj set-key` to update your API key"),
+            Error::Api { status, .. } if *status == StatusCode::FORBIDDEN => {
+                Some("Check that your account is premium and not locked")
+            }
+            Error::Api { status, .. } if status.is_server_error() => {
+                Some("Real-Debrid may be under maintenance; try again shortly")
+            }
+            Error::Http { .. } => Some("Check your network connection"),
+            Error::Timeout("file list") => {
+                Some("The torrent may have no seeders; wait longer with --files-timeout")
+            }
+            Error::Timeout("the torrent to be processed") => Some(
+                "The torrent may not be cached; try again later, or wait longer with \
+                 --processing-timeout",
+            ),
+            Error::Timeout("data from the host") => {
+                Some("The host stopped sending; retry it, or raise `download` under [wait]")
+            }
+            Error::Batch {
+                what: "queued item(s)",
+                ..
+            } => Some("See `lj queue list` for what went wrong"),
+            Error::Batch { .. } => Some("See `lj dl` for what went wrong"),
+            Error::Stalled(_) => {
+                Some("Pick a better seeded release, or keep waiting with --stalled-timeout 0")
+            }
+            _ => None,
+        }
+    }
+}
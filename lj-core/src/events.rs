@@ -0,0 +1,88 @@
+//! Download lifecycle events.
+//!
+//! [`worker::run_download`](crate::worker::run_download) reports each
+//! transfer's progress here as it goes, so frontends in the same process can
+//! [`subscribe`] instead of polling the store. Other processes get the
+//! daemon's events through [`ipc::subscribe`](crate::ipc::subscribe).
+
+use crate::{logging, notify};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+
+/// How many events a slow subscriber may fall behind before it starts
+/// missing them.
+const CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DownloadEvent {
+    /// The host answered and bytes are about to flow.
+    Started {
+        id: String,
+        filename: String,
+        total_bytes: u64,
+    },
+    /// Sent about twice a second while the transfer runs.
+    Progress {
+        id: String,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+        /// Bytes per second, smoothed.
+        speed: f64,
+    },
+    Completed {
+        id: String,
+        bytes: u64,
+    },
+    Failed {
+        id: String,
+        error: String,
+    },
+    Cancelled {
+        id: String,
+    },
+}
+
+impl DownloadEvent {
+    /// The download this event is about.
+    pub fn id(&self) -> &str {
+        match self {
+            DownloadEvent::Started { id, .. }
+            | DownloadEvent::Progress { id, .. }
+            | DownloadEvent::Completed { id, .. }
+            | DownloadEvent::Failed { id, .. }
+            | DownloadEvent::Cancelled { id } => id,
+        }
+    }
+
+    /// The event's name, as in its serialized `event` field.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadEvent::Started { .. } => "started",
+            DownloadEvent::Progress { .. } => "progress",
+            DownloadEvent::Completed { .. } => "completed",
+            DownloadEvent::Failed { .. } => "failed",
+            DownloadEvent::Cancelled { .. } => "cancelled",
+        }
+    }
+}
+
+fn sender() -> &'static broadcast::Sender<DownloadEvent> {
+    static SENDER: OnceLock<broadcast::Sender<DownloadEvent>> = OnceLock::new();
+    SENDER.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+/// Receives every event emitted in this process from now on. A receiver
+/// that falls behind gets [`RecvError::Lagged`](broadcast::error::RecvError::Lagged)
+/// and skips ahead.
+pub fn subscribe() -> broadcast::Receiver<DownloadEvent> {
+    sender().subscribe()
+}
+
+pub(crate) fn emit(event: DownloadEvent) {
+    logging::log_event(&event);
+    notify::enqueue(&event);
+    // Nobody listening is fine
+    let _ = sender().send(event);
+}
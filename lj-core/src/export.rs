@@ -0,0 +1,129 @@
+//! Download history export for `lj export`.
+//!
+//! Each record becomes one CSV row or JSON object, with times as RFC 3339
+//! UTC and sizes and speeds in bytes, so spreadsheets and `jq` can take
+//! them as they are.
+
+use crate::error::{Error, Result};
+use crate::format::format_timestamp;
+use crate::store::{Download, DownloadStatus};
+use serde::Serialize;
+
+/// One exported download.
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub id: String,
+    pub filename: String,
+    pub status: &'static str,
+    /// Why the download failed, for failed ones.
+    pub error: Option<String>,
+    pub size: u64,
+    pub downloaded: u64,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    /// Seconds from start to finish.
+    pub duration: Option<u64>,
+    /// Bytes per second over the whole download, once it has finished.
+    pub average_speed: Option<u64>,
+    /// Last measured speed, in bytes per second.
+    pub speed: u64,
+    pub target: String,
+    pub torrent: Option<String>,
+    pub category: Option<String>,
+    /// Where the file was uploaded to, if it was.
+    pub remote: Option<String>,
+}
+
+impl From<&Download> for Record {
+    fn from(dl: &Download) -> Self {
+        let duration = dl
+            .finished_at
+            .map(|finished| finished.saturating_sub(dl.started_at));
+        let average_speed = duration
+            .filter(|_| dl.status == DownloadStatus::Completed)
+            .map(|secs| dl.downloaded_bytes / secs.max(1));
+        Record {
+            id: dl.id.clone(),
+            filename: dl.filename.clone(),
+            status: dl.status.kind(),
+            error: match &dl.status {
+                DownloadStatus::Failed(e) => Some(e.clone()),
+                _ => None,
+            },
+            size: dl.total_bytes,
+            downloaded: dl.downloaded_bytes,
+            started_at: format_timestamp(dl.started_at),
+            finished_at: dl.finished_at.map(format_timestamp),
+            duration,
+            average_speed,
+            speed: dl.speed as u64,
+            target: dl.target_path().to_string_lossy().to_string(),
+            torrent: dl.torrent.clone(),
+            category: dl.category.clone(),
+            remote: dl.remote.clone(),
+        }
+    }
+}
+
+/// Which records to export.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    /// Only downloads started at or after this Unix time.
+    pub since: Option<u64>,
+    /// Only downloads with one of these [statuses](DownloadStatus::kind);
+    /// all if empty.
+    pub statuses: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, dl: &Download) -> bool {
+        self.since.is_none_or(|since| dl.started_at >= since)
+            && (self.statuses.is_empty() || self.statuses.iter().any(|s| s == dl.status.kind()))
+    }
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The records as CSV, with a header row.
+pub fn to_csv(records: &[Record]) -> String {
+    let mut out = String::from(
+        "id,filename,status,error,size,downloaded,started_at,finished_at,duration,\
+         average_speed,speed,target,torrent,category,remote\n",
+    );
+    let opt = |value: Option<String>| value.unwrap_or_default();
+    for r in records {
+        let fields = [
+            r.id.clone(),
+            r.filename.clone(),
+            r.status.to_string(),
+            opt(r.error.clone()),
+            r.size.to_string(),
+            r.downloaded.to_string(),
+            r.started_at.clone(),
+            opt(r.finished_at.clone()),
+            opt(r.duration.map(|d| d.to_string())),
+            opt(r.average_speed.map(|s| s.to_string())),
+            r.speed.to_string(),
+            r.target.clone(),
+            opt(r.torrent.clone()),
+            opt(r.category.clone()),
+            opt(r.remote.clone()),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// The records as a JSON array.
+pub fn to_json(records: &[Record]) -> Result<String> {
+    serde_json::to_string_pretty(records).map_err(|e| Error::Other(e.to_string()))
+}
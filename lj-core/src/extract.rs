@@ -0,0 +1,202 @@
+//! Archive extraction once a magnet's downloads have finished.
+//!
+//! With `[extract] enabled`, the [`hooks`](crate::hooks) step that ends a
+//! batch looks for rar, zip and 7z sets among its files (including
+//! multi-volume `.partNN.rar`, `.rNN`, `.zNN` and `.7z.NNN` sets) and
+//! extracts each one whose volumes all completed into their directory with
+//! 7-Zip, before `on_batch` runs. 7-Zip checks every file's CRC as it goes,
+//! so archives are only deleted (with `delete_archives`) after a clean
+//! extraction. Progress is recorded on the set's first volume as
+//! [`Extraction`], for `lj dl` to show.
+
+use crate::config::ExtractConfig;
+use crate::error::{Error, Result};
+use crate::logging::{self, Level};
+use crate::store::{self, Download, DownloadStatus, Extraction};
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+/// 7-Zip executable used when `program` is unset.
+pub const DEFAULT_PROGRAM: &str = "7z";
+
+/// The volumes of one archive, in the order they were downloaded.
+#[derive(Debug, Clone)]
+pub struct ArchiveSet {
+    /// The volume 7-Zip is pointed at.
+    pub first: Download,
+    pub volumes: Vec<Download>,
+}
+
+fn digits(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Which archive `name` is a volume of, and whether it is the one to open.
+fn volume(name: &str) -> Option<(String, bool)> {
+    let lower = name.to_lowercase();
+    if let Some(stem) = lower.strip_suffix(".rar") {
+        if let Some((base, part)) = stem.rsplit_once(".part")
+            && digits(part)
+        {
+            return Some((format!("{}.rar", base), part.parse::<u32>().ok()? == 1));
+        }
+        return Some((lower, true));
+    }
+    let (base, ext) = lower.rsplit_once('.')?;
+    match ext {
+        "zip" | "7z" => Some((lower.clone(), true)),
+        _ if ext.len() == 3 && ext.starts_with('r') && digits(&ext[1..]) => {
+            Some((format!("{}.rar", base), false))
+        }
+        _ if ext.len() == 3 && ext.starts_with('z') && digits(&ext[1..]) => {
+            Some((format!("{}.zip", base), false))
+        }
+        _ if digits(ext) && (base.ends_with(".7z") || base.ends_with(".zip")) => {
+            Some((base.to_string(), ext.parse::<u32>().ok()? == 1))
+        }
+        _ => None,
+    }
+}
+
+/// The archive sets among `downloads` that can be extracted: every volume
+/// that was downloaded completed, and the first one is there.
+pub fn archive_sets(downloads: &[Download]) -> Vec<ArchiveSet> {
+    let mut sets: BTreeMap<(String, String), Vec<(Download, bool)>> = BTreeMap::new();
+    for dl in downloads {
+        if let Some((key, first)) = volume(&dl.filename) {
+            sets.entry((dl.target_dir.clone(), key))
+                .or_default()
+                .push((dl.clone(), first));
+        }
+    }
+
+    sets.into_values()
+        .filter(|volumes| {
+            volumes
+                .iter()
+                .all(|(dl, _)| dl.status == DownloadStatus::Completed)
+        })
+        .filter_map(|volumes| {
+            let first = volumes.iter().find(|(_, first)| *first)?.0.clone();
+            Some(ArchiveSet {
+                first,
+                volumes: volumes.into_iter().map(|(dl, _)| dl).collect(),
+            })
+        })
+        .collect()
+}
+
+fn set_state(set: &ArchiveSet, state: Extraction) {
+    let _ = store::update_download(&set.first.id, |dl| dl.extraction = Some(state));
+}
+
+/// The last percentage 7-Zip printed in `output`, which redraws its
+/// progress with backspaces.
+fn last_percent(output: &str) -> Option<u8> {
+    output
+        .split(['\x08', '\r', '\n'])
+        .filter_map(|segment| segment.split_whitespace().next()?.strip_suffix('%'))
+        .filter_map(|pct| pct.parse().ok())
+        .next_back()
+}
+
+/// Extracts one set into its directory.
+pub async fn extract_set(config: &ExtractConfig, set: &ArchiveSet) -> Result<()> {
+    let program = config.program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    set_state(set, Extraction::Extracting { progress: 0 });
+
+    let mut child = Command::new(program)
+        .arg("x")
+        .arg("-y")
+        .arg("-bso0")
+        .arg("-bsp1")
+        .arg(format!("-o{}", set.first.target_dir))
+        .arg(set.first.target_path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Other(format!("Failed to run {}: {}", program, e)))?;
+
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let errors = tokio::spawn(async move {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text).await;
+        text
+    });
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut buf = [0u8; 1024];
+    let mut shown = 0;
+    while let Ok(n) = stdout.read(&mut buf).await
+        && n > 0
+    {
+        if let Some(pct) = last_percent(&String::from_utf8_lossy(&buf[..n]))
+            && pct != shown
+        {
+            shown = pct;
+            set_state(set, Extraction::Extracting { progress: pct });
+        }
+    }
+
+    let status = child.wait().await?;
+    if !status.success() {
+        let errors = errors.await.unwrap_or_default();
+        let reason = errors
+            .lines()
+            .map(str::trim)
+            .rfind(|l| !l.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{} exited with {}", program, status));
+        return Err(Error::Other(reason));
+    }
+
+    if config.delete_archives {
+        for volume in &set.volumes {
+            fs::remove_file(volume.target_path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether extraction still has to run, or finish, on some archive set
+/// among a batch's `downloads`. It starts after the last download
+/// completes, so this waits for each set's outcome.
+pub fn is_pending(config: &ExtractConfig, downloads: &[Download]) -> bool {
+    config.enabled
+        && archive_sets(downloads).iter().any(|set| {
+            !matches!(
+                set.first.extraction,
+                Some(Extraction::Extracted | Extraction::Failed(_))
+            )
+        })
+}
+
+/// Extracts every complete archive set among a finished batch's
+/// `downloads`, recording how each went.
+pub async fn extract_batch(config: &ExtractConfig, downloads: &[Download]) {
+    for set in archive_sets(downloads) {
+        let id = set.first.id.as_str();
+        match extract_set(config, &set).await {
+            Ok(()) => {
+                set_state(&set, Extraction::Extracted);
+                logging::log(
+                    Level::Info,
+                    &format!("{}: Extracted {}", id, set.first.filename),
+                    &[("LJ_DOWNLOAD_ID", id), ("LJ_EVENT", "extracted")],
+                );
+            }
+            Err(e) => {
+                set_state(&set, Extraction::Failed(e.to_string()));
+                logging::log(
+                    Level::Error,
+                    &format!("{}: Extracting {} failed: {}", id, set.first.filename, e),
+                    &[("LJ_DOWNLOAD_ID", id), ("LJ_EVENT", "extract_failed")],
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Human-readable formatting helpers.
+
+pub fn format_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    if bytes >= GB {
+        format!("{:.2} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.2} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.2} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// [`format_bytes`], or "size unknown" for a download whose host didn't
+/// say how big it is.
+///
+/// ```
+/// use lj_core::format::format_size;
+///
+/// assert_eq!(format_size(2048), "2.00 KB");
+/// assert_eq!(format_size(0), "size unknown");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    if bytes == 0 {
+        return "size unknown".to_string();
+    }
+    format_bytes(bytes)
+}
+
+pub fn format_speed(bytes_per_sec: f64) -> String {
+    const KB: f64 = 1024.0;
+    const MB: f64 = KB * 1024.0;
+
+    if bytes_per_sec >= MB {
+        format!("{:.2} MB/s", bytes_per_sec / MB)
+    } else if bytes_per_sec >= KB {
+        format!("{:.2} KB/s", bytes_per_sec / KB)
+    } else {
+        format!("{:.0} B/s", bytes_per_sec)
+    }
+}
+
+/// Formats a duration as e.g. `42s`, `3m 05s` or `1h 02m`.
+pub fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Parses a byte count such as `500K`, `10M` or `1.5G` (binary units).
+pub fn parse_bytes(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, mult) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1024.0),
+        'M' => (&s[..s.len() - 1], 1024.0 * 1024.0),
+        'G' => (&s[..s.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (s, 1.0),
+    };
+    let value: f64 = num.trim().parse().ok()?;
+    if value < 0.0 {
+        return None;
+    }
+    Some((value * mult) as u64)
+}
+
+/// Formats a Unix timestamp as an RFC 3339 UTC time.
+///
+/// ```
+/// use lj_core::format::format_timestamp;
+///
+/// assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+/// assert_eq!(format_timestamp(1_709_210_096), "2024-02-29T12:34:56Z");
+/// ```
+pub fn format_timestamp(secs: u64) -> String {
+    let (days, time) = (secs / 86400, secs % 86400);
+    // Civil-from-days, after Howard Hinnant's date algorithms
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Parses a UTC date (`2024-02-29`) or date and time
+/// (`2024-02-29T12:34:56Z`) into a Unix timestamp.
+///
+/// ```
+/// use lj_core::format::parse_timestamp;
+///
+/// assert_eq!(parse_timestamp("2024-02-29"), Some(1_709_164_800));
+/// assert_eq!(parse_timestamp("2024-02-29T12:34:56Z"), Some(1_709_210_096));
+/// assert_eq!(parse_timestamp("2024-13-01"), None);
+/// ```
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time.trim_end_matches('Z'))),
+        None => (s, None),
+    };
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let seconds = match time {
+        Some(time) => {
+            let mut parts = time.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+            let (h, m) = (parts.next()??, parts.next()??);
+            let s = parts.next().unwrap_or(Some(0))?;
+            if !(0..24).contains(&h) || !(0..60).contains(&m) || !(0..=60).contains(&s) {
+                return None;
+            }
+            h * 3600 + m * 60 + s
+        }
+        None => 0,
+    };
+
+    // Days-from-civil, the inverse of `format_timestamp`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86400 + seconds).ok()
+}
+
+/// Parses an age such as `90m`, `12h`, `7d` or `2w` into seconds.
+///
+/// ```
+/// use lj_core::format::parse_age;
+///
+/// assert_eq!(parse_age("7d"), Some(7 * 86400));
+/// assert_eq!(parse_age("7"), None);
+/// assert_eq!(parse_age("99999999999999999w"), None);
+/// ```
+pub fn parse_age(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let unit = match s.chars().last()?.to_ascii_lowercase() {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return None,
+    };
+    let value: u64 = s[..s.len() - 1].trim().parse().ok()?;
+    value.checked_mul(unit)
+}
@@ -0,0 +1,261 @@
+//! Registering lj as the desktop's `magnet:` link handler.
+//!
+//! On Linux and the BSDs this is a freedesktop `.desktop` entry, made the
+//! default for `x-scheme-handler/magnet` with `xdg-mime`; on Windows, the
+//! `magnet` URL protocol under `HKEY_CURRENT_USER`. The browser then runs
+//! `lj --yes <magnet>`, with no terminal to ask anything in. macOS only
+//! hands links to app bundles, so it isn't supported there.
+
+use crate::config::{self, Config};
+use crate::error::{Error, Result};
+use std::path::Path;
+
+/// Where a handler was registered.
+#[derive(Debug)]
+pub struct Registration {
+    /// The desktop entry's path or the registry key.
+    pub location: String,
+    /// Whether it was also made the default; if not, the browser or the
+    /// desktop's settings have to pick it.
+    pub is_default: bool,
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use super::Registration;
+    use crate::config;
+    use crate::error::Result;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+
+    const MIME_TYPE: &str = "x-scheme-handler/magnet";
+
+    /// `lj-magnet.desktop`, or `lj-<profile>-magnet.desktop`.
+    fn desktop_id() -> String {
+        match config::profile() {
+            Some(profile) => format!("lj-{}-magnet.desktop", profile),
+            None => "lj-magnet.desktop".to_string(),
+        }
+    }
+
+    fn applications_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("applications")
+    }
+
+    /// Quotes an `Exec` argument as the desktop entry spec asks, then
+    /// escapes it again as a string value.
+    fn exec_arg(arg: &str) -> String {
+        let plain = !arg.is_empty()
+            && !arg.contains(|c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c));
+        let arg = arg.replace('%', "%%");
+        if plain {
+            return arg;
+        }
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted.replace('\\', "\\\\")
+    }
+
+    fn desktop_entry(exe: &Path, args: &[String]) -> String {
+        let mut exec = exec_arg(&exe.to_string_lossy());
+        for arg in args {
+            exec.push(' ');
+            exec.push_str(&exec_arg(arg));
+        }
+        format!(
+            "[Desktop Entry]
+Type=Application
+Name=lj
+Comment=Download magnet links through a debrid service
+Exec={exec} %u
+Terminal=false
+NoDisplay=true
+MimeType={MIME_TYPE};
+"
+        )
+    }
+
+    /// Runs a desktop tool, if it is installed.
+    fn run(program: &str, args: &[&str]) -> bool {
+        Command::new(program)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    pub fn register(exe: &Path, args: &[String]) -> Result<Registration> {
+        let dir = applications_dir();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(desktop_id());
+        config::write_atomic(&path, desktop_entry(exe, args).as_bytes())?;
+        run("update-desktop-database", &[&dir.to_string_lossy()]);
+        Ok(Registration {
+            location: path.display().to_string(),
+            is_default: run("xdg-mime", &["default", &desktop_id(), MIME_TYPE]),
+        })
+    }
+
+    /// Drops the entry from `mimeapps.list`, where `xdg-mime` made it the
+    /// default. Other handlers listed with it are kept.
+    fn forget_default(id: &str) -> std::io::Result<()> {
+        let Some(path) = dirs::config_dir().map(|dir| dir.join("mimeapps.list")) else {
+            return Ok(());
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Ok(());
+        };
+        let mut changed = false;
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let Some(value) = line
+                .strip_prefix(MIME_TYPE)
+                .and_then(|v| v.strip_prefix('='))
+            else {
+                lines.push(line.to_string());
+                continue;
+            };
+            let others: Vec<&str> = value
+                .split(';')
+                .filter(|entry| !entry.is_empty() && *entry != id)
+                .collect();
+            changed |= others.len() != value.split(';').filter(|e| !e.is_empty()).count();
+            if !others.is_empty() {
+                lines.push(format!("{}={};", MIME_TYPE, others.join(";")));
+            }
+        }
+        if changed {
+            let mut text = lines.join("\n");
+            text.push('\n');
+            config::write_atomic(&path, text.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn unregister() -> Result<Option<String>> {
+        let dir = applications_dir();
+        let path = dir.join(desktop_id());
+        forget_default(&desktop_id())?;
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                run("update-desktop-database", &[&dir.to_string_lossy()]);
+                Ok(Some(path.display().to_string()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::Registration;
+    use crate::error::{Error, Result};
+    use std::path::Path;
+    use std::process::Command;
+
+    const KEY: &str = r"HKCU\Software\Classes\magnet";
+
+    /// Runs `reg` and returns what it printed.
+    fn reg(args: &[&str]) -> Result<String> {
+        let output = Command::new("reg").args(args).output()?;
+        if !output.status.success() {
+            return Err(Error::Other(format!(
+                "reg {} failed: {}",
+                args[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn command_key() -> String {
+        format!(r"{}\shell\open\command", KEY)
+    }
+
+    pub fn register(exe: &Path, args: &[String]) -> Result<Registration> {
+        let mut command = format!("\"{}\"", exe.display());
+        for arg in args {
+            command.push_str(&format!(" \"{}\"", arg));
+        }
+        command.push_str(" \"%1\"");
+        reg(&["add", KEY, "/ve", "/d", "URL:Magnet link", "/f"])?;
+        reg(&["add", KEY, "/v", "URL Protocol", "/d", "", "/f"])?;
+        reg(&["add", &command_key(), "/ve", "/d", &command, "/f"])?;
+        Ok(Registration {
+            location: KEY.to_string(),
+            is_default: true,
+        })
+    }
+
+    pub fn unregister() -> Result<Option<String>> {
+        // Leave another client's registration alone
+        let exe = std::env::current_exe()?;
+        let ours = reg(&["query", &command_key(), "/ve"])
+            .is_ok_and(|out| out.contains(&exe.display().to_string()));
+        if !ours {
+            return Ok(None);
+        }
+        reg(&["delete", KEY, "/f"])?;
+        Ok(Some(KEY.to_string()))
+    }
+}
+
+#[cfg(not(any(all(unix, not(target_os = "macos")), windows)))]
+mod imp {
+    use super::Registration;
+    use crate::error::{Error, Result};
+    use std::path::Path;
+
+    fn unsupported() -> Error {
+        Error::Other("Registering a magnet handler is not supported on this platform".to_string())
+    }
+
+    pub fn register(_exe: &Path, _args: &[String]) -> Result<Registration> {
+        Err(unsupported())
+    }
+
+    pub fn unregister() -> Result<Option<String>> {
+        Err(unsupported())
+    }
+}
+
+/// Arguments lj is run with, before the magnet: no prompts, and the
+/// active profile. Without a `download_dir`, downloads go to the user's
+/// downloads folder, since the browser starts lj in no particular directory.
+fn handler_args(config: &Config) -> Result<Vec<String>> {
+    let mut args = vec!["--yes".to_string()];
+    if config.download_dir.is_none() {
+        let dir = dirs::download_dir()
+            .or_else(|| dirs::home_dir().map(|home| home.join("Downloads")))
+            .ok_or_else(|| Error::Config("set download_dir for the magnet handler".to_string()))?;
+        args.push("--download-dir".to_string());
+        args.push(dir.to_string_lossy().to_string());
+    }
+    if let Some(profile) = config::profile() {
+        args.push("--profile".to_string());
+        args.push(profile.to_string());
+    }
+    Ok(args)
+}
+
+/// Makes `exe` the handler for `magnet:` links.
+pub fn register(exe: &Path, config: &Config) -> Result<Registration> {
+    imp::register(exe, &handler_args(config)?)
+}
+
+/// Removes the handler [`register`] installed, returning where it was, or
+/// `None` if there was none.
+pub fn unregister() -> Result<Option<String>> {
+    imp::unregister()
+}
@@ -0,0 +1,145 @@
+//! Health check pings, for healthchecks.io or any compatible dead man's
+//! switch.
+//!
+//! Each magnet's batch of downloads is one run of the `[healthchecks]`
+//! check: its first download starting pings `<url>/start`, and once every
+//! download has finished lj pings `<url>` if none failed or `<url>/fail`
+//! if any did, with a summary as the body. Runs are told apart by a run id
+//! derived from the batch, so batches that overlap are timed separately.
+//! If the pings stop, or a fail arrives, the service raises the alarm like
+//! for any other cron job.
+
+use crate::config::HealthchecksConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::notify::Notifier;
+use crate::store::{self, Download, DownloadStatus};
+use async_trait::async_trait;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+
+/// Name batches are claimed under.
+const CLAIMANT: &str = "healthchecks";
+
+pub struct Healthchecks {
+    url: String,
+}
+
+impl Healthchecks {
+    /// The check described by `config`, if it has a URL.
+    pub fn from_config(config: &HealthchecksConfig) -> Option<Self> {
+        Some(Healthchecks {
+            url: config.url.clone()?.trim_end_matches('/').to_string(),
+        })
+    }
+
+    async fn ping(&self, client: &Client, path: &str, run: &str, body: String) -> Result<()> {
+        let resp = client
+            .post(format!("{}{}", self.url, path))
+            .query(&[("rid", run_id(run))])
+            .body(body)
+            .send()
+            .await
+            .map_err(Error::http("Health check ping failed"))?;
+        if !resp.status().is_success() {
+            return Err(Error::Status(resp.status()));
+        }
+        Ok(())
+    }
+}
+
+/// A UUID made from `run`, as the service wants run ids.
+///
+/// ```
+/// use lj_core::healthchecks::run_id;
+///
+/// let id = run_id("1718000000000-Some.Show");
+/// assert_eq!(id.len(), 36);
+/// assert_eq!(id, run_id("1718000000000-Some.Show"));
+/// ```
+pub fn run_id(run: &str) -> String {
+    let hex: String = Sha256::digest(run.as_bytes())
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Whether the run failed, and a summary for the ping's body.
+fn summarize(downloads: &[Download]) -> (bool, String) {
+    let completed = downloads
+        .iter()
+        .filter(|dl| dl.status == DownloadStatus::Completed)
+        .count();
+    let mut body = format!("{} of {} files completed\n", completed, downloads.len());
+    let mut failed = false;
+    for dl in downloads {
+        match &dl.status {
+            DownloadStatus::Failed(e) => {
+                failed = true;
+                body.push_str(&format!("failed: {}: {}\n", dl.filename, e));
+            }
+            DownloadStatus::Cancelled => body.push_str(&format!("cancelled: {}\n", dl.filename)),
+            _ => {}
+        }
+    }
+    (failed, body)
+}
+
+#[async_trait]
+impl Notifier for Healthchecks {
+    fn name(&self) -> &'static str {
+        "healthchecks"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        !matches!(event, DownloadEvent::Progress { .. })
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let Some(dl) = store::load_download(event.id()) else {
+            return Ok(());
+        };
+        // Records from before batches are runs of their own
+        let Some(batch) = dl.batch.clone() else {
+            return match event {
+                DownloadEvent::Started { .. } => {
+                    self.ping(client, "/start", &dl.id, dl.filename.clone())
+                        .await
+                }
+                _ if dl.status.is_finished() => {
+                    let (failed, body) = summarize(std::slice::from_ref(&dl));
+                    let path = if failed { "/fail" } else { "" };
+                    self.ping(client, path, &dl.id, body).await
+                }
+                _ => Ok(()),
+            };
+        };
+
+        if let DownloadEvent::Started { .. } = event {
+            // Restarts and later files of the batch don't start a new run
+            if store::claim_batch(&batch, "healthchecks-start")? {
+                let body = dl.torrent.clone().unwrap_or(dl.filename);
+                self.ping(client, "/start", &batch, body).await?;
+            }
+            return Ok(());
+        }
+        // Claimed only once the ping is through, so a retry sends it again
+        let Some(downloads) = store::unclaimed_finished_batch(&batch, CLAIMANT) else {
+            return Ok(());
+        };
+        let (failed, body) = summarize(&downloads);
+        self.ping(client, if failed { "/fail" } else { "" }, &batch, body)
+            .await?;
+        store::claim_batch(&batch, CLAIMANT)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! User commands run around downloads.
+//!
+//! `[hooks] before_download` runs before each download is started and may
+//! move or veto it (see [`before_download`]). `on_download` runs after each
+//! download that completes or fails, with its path as the argument.
+//! `on_batch` runs once every download from the same magnet has finished,
+//! with the completed files' paths as arguments, after any
+//! [archive extraction](crate::extract) and before any
+//! [upload](crate::upload). All get the details in
+//! `LJ_*` environment variables. Commands go through `sh -c`, which makes
+//! the arguments `"$1"`, `"$@"` and so on (`cmd /C` on Windows, which
+//! appends them); the last two run one at a time, in the worker or daemon
+//! that finished the download.
+
+use crate::config::{ExtractConfig, HooksConfig};
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::extract;
+use crate::notify::Notifier;
+use crate::store::{self, Download, DownloadStatus};
+use crate::upload::Upload;
+use async_trait::async_trait;
+use reqwest::Client;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub struct Hooks {
+    on_download: Option<String>,
+    on_batch: Option<String>,
+    extract: ExtractConfig,
+    upload: Option<Upload>,
+}
+
+impl Hooks {
+    /// The hooks described by `config`, with archive extraction from
+    /// `extract` and `upload`, if anything needs doing.
+    pub fn from_config(
+        config: &HooksConfig,
+        extract: &ExtractConfig,
+        upload: Option<Upload>,
+    ) -> Option<Self> {
+        let hooks = Hooks {
+            on_download: config.on_download.clone(),
+            on_batch: config.on_batch.clone(),
+            extract: extract.clone(),
+            upload,
+        };
+        (hooks.on_download.is_some()
+            || hooks.on_batch.is_some()
+            || hooks.extract.enabled
+            || hooks.upload.is_some())
+        .then_some(hooks)
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn shell(command: &str) -> Command {
+    // Arguments become the command's "$1", "$2", ...
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command).arg("lj");
+    cmd
+}
+
+#[cfg(windows)]
+pub(crate) fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+async fn run(command: &str, args: &[String], env: &[(&str, String)]) -> Result<()> {
+    let mut cmd = shell(command);
+    cmd.args(args)
+        .envs(env.iter().map(|(key, value)| (key, value)));
+    let status = tokio::process::Command::from(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(Error::Other(format!(
+            "`{}` exited with {}",
+            command, status
+        )));
+    }
+    Ok(())
+}
+
+/// Runs `before_download` for a download about to start, with the same
+/// arguments and variables as `on_download` (`LJ_SIZE` being the expected
+/// size). A non-zero exit vetoes the download, with the command's stderr as
+/// the reason. A path printed on stdout becomes the download's new target,
+/// relative to its original directory.
+pub fn before_download(config: &HooksConfig, download: &mut Download) -> Result<()> {
+    let Some(command) = &config.before_download else {
+        return Ok(());
+    };
+    let mut env = download_env(download);
+    env.retain(|(key, _)| !matches!(*key, "LJ_SIZE" | "LJ_STATUS" | "LJ_ERROR"));
+    env.push(("LJ_SIZE", download.total_bytes.to_string()));
+    env.push(("LJ_DIR", download.target_dir.clone()));
+
+    let path = download.target_path().to_string_lossy().to_string();
+    let output = shell(command)
+        .arg(&path)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(Error::Other(if reason.is_empty() {
+            format!("{} vetoed by before_download hook", download.filename)
+        } else {
+            format!("{} vetoed: {}", download.filename, reason)
+        }));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if let Some(line) = stdout.lines().map(str::trim).find(|l| !l.is_empty()) {
+        let target = Path::new(&download.target_dir).join(line);
+        let filename = target.file_name().ok_or_else(|| {
+            Error::Other(format!("before_download hook gave no file name: {}", line))
+        })?;
+        download.filename = filename.to_string_lossy().to_string();
+        download.target_dir = target
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_string_lossy()
+            .to_string();
+    }
+    Ok(())
+}
+
+fn download_env(dl: &Download) -> Vec<(&'static str, String)> {
+    let error = match &dl.status {
+        DownloadStatus::Failed(e) => e.clone(),
+        _ => String::new(),
+    };
+    vec![
+        ("LJ_DOWNLOAD_ID", dl.id.clone()),
+        ("LJ_FILENAME", dl.filename.clone()),
+        ("LJ_PATH", dl.target_path().to_string_lossy().to_string()),
+        ("LJ_SIZE", dl.downloaded_bytes.to_string()),
+        ("LJ_STATUS", dl.status.kind().to_string()),
+        ("LJ_ERROR", error),
+        ("LJ_TORRENT", dl.torrent.clone().unwrap_or_default()),
+        ("LJ_BATCH_ID", dl.batch.clone().unwrap_or_default()),
+    ]
+}
+
+fn batch_env(batch: &str, downloads: &[Download]) -> Vec<(&'static str, String)> {
+    let completed: Vec<&Download> = downloads
+        .iter()
+        .filter(|dl| dl.status == DownloadStatus::Completed)
+        .collect();
+    let status = if completed.len() == downloads.len() {
+        "completed"
+    } else if completed.is_empty() {
+        "failed"
+    } else {
+        "partial"
+    };
+    let first = &downloads[0];
+    vec![
+        ("LJ_BATCH_ID", batch.to_string()),
+        ("LJ_TORRENT", first.torrent.clone().unwrap_or_default()),
+        ("LJ_DIR", first.target_dir.clone()),
+        ("LJ_STATUS", status.to_string()),
+        ("LJ_COUNT", downloads.len().to_string()),
+        ("LJ_COMPLETED", completed.len().to_string()),
+        (
+            "LJ_SIZE",
+            completed
+                .iter()
+                .map(|dl| dl.downloaded_bytes)
+                .sum::<u64>()
+                .to_string(),
+        ),
+    ]
+}
+
+#[async_trait]
+impl Notifier for Hooks {
+    fn name(&self) -> &'static str {
+        "hook"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        matches!(
+            event,
+            DownloadEvent::Completed { .. }
+                | DownloadEvent::Failed { .. }
+                | DownloadEvent::Cancelled { .. }
+        )
+    }
+
+    async fn send(&self, _client: &Client, event: &DownloadEvent) -> Result<()> {
+        let Some(dl) = store::load_download(event.id()) else {
+            return Ok(());
+        };
+
+        // A cancelled download was stopped on purpose, but it still ends
+        // its batch
+        let mut result = Ok(());
+        if let Some(command) = &self.on_download
+            && !matches!(event, DownloadEvent::Cancelled { .. })
+        {
+            let path = dl.target_path().to_string_lossy().to_string();
+            result = run(command, &[path], &download_env(&dl)).await;
+        }
+
+        // Records from before batches are uploaded on their own
+        if let Some(upload) = &self.upload
+            && dl.batch.is_none()
+        {
+            upload.upload_batch(std::slice::from_ref(&dl)).await;
+        }
+
+        if (self.on_batch.is_some() || self.extract.enabled || self.upload.is_some())
+            && let Some(batch) = &dl.batch
+            && let Some(downloads) = store::claim_finished_batch(batch, "hooks")?
+        {
+            if self.extract.enabled {
+                extract::extract_batch(&self.extract, &downloads).await;
+            }
+            if let Some(command) = &self.on_batch {
+                let paths: Vec<String> = downloads
+                    .iter()
+                    .filter(|dl| dl.status == DownloadStatus::Completed)
+                    .map(|dl| dl.target_path().to_string_lossy().to_string())
+                    .collect();
+                result = result.and(run(command, &paths, &batch_env(batch, &downloads)).await);
+            }
+            if let Some(upload) = &self.upload {
+                // Reloaded, as extraction may have deleted archives
+                let downloads: Vec<Download> = downloads
+                    .iter()
+                    .filter_map(|dl| store::load_download(&dl.id))
+                    .collect();
+                upload.upload_batch(&downloads).await;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,363 @@
+//! HTTP client construction from the TLS settings in `config.toml`, and
+//! authenticated requests to the providers' APIs.
+//!
+//! Each process builds one client and hands it to the provider, the link
+//! probes and every transfer, so they share its connection pool.
+//!
+//! Providers build every API request through [`request`], which is the only
+//! place a [`Token`] is read. Creating a token registers it with
+//! [`logging::redact`], so it is masked in log lines and error messages
+//! even where a server or library echoes it back.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::logging;
+use crate::platform::sanitize_filename;
+use reqwest::{Certificate, Client, Identity, Method, RequestBuilder, Response, Url, header};
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::Duration;
+
+/// Sent with every request, so services can tell lj's traffic apart.
+pub const USER_AGENT: &str = concat!("lj/", env!("CARGO_PKG_VERSION"));
+
+/// Limit for establishing a connection, TLS handshake included.
+const CONNECT_TIMEOUT: u64 = 30;
+
+/// Limit between two reads. There is no overall timeout, since a large
+/// transfer may legitimately take hours.
+const READ_TIMEOUT: u64 = 60;
+
+/// How long an unused connection stays in the pool.
+const POOL_IDLE_TIMEOUT: u64 = 90;
+
+const TCP_KEEPALIVE: u64 = 60;
+
+/// Query parameters whose values are credentials.
+const SECRET_PARAMS: &[&str] = &[
+    "apikey",
+    "api_key",
+    "token",
+    "access_token",
+    "auth",
+    "passkey",
+];
+
+/// A provider's API token. It never shows in `Debug` output.
+#[derive(Clone)]
+pub struct Token(String);
+
+impl Token {
+    pub fn new(token: &str) -> Self {
+        logging::add_secret(token);
+        Token(token.to_string())
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Token([REDACTED])")
+    }
+}
+
+/// How an API expects its token.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Auth {
+    /// In an `Authorization: Bearer` header.
+    Bearer,
+    /// As the named query parameter.
+    Query(&'static str),
+}
+
+/// A `method` request to `url` carrying `token` the way `auth` says.
+pub(crate) fn request(
+    client: &Client,
+    method: Method,
+    url: String,
+    token: &Token,
+    auth: Auth,
+) -> RequestBuilder {
+    let request = client.request(method, url);
+    match auth {
+        Auth::Bearer => request.bearer_auth(&token.0),
+        Auth::Query(name) => request.query(&[(name, &token.0)]),
+    }
+}
+
+/// The token as a query parameter, for the odd endpoint that wants it in
+/// addition to the header.
+pub(crate) fn token_param<'a>(name: &'static str, token: &'a Token) -> (&'static str, &'a str) {
+    (name, &token.0)
+}
+
+/// Masks the values of credential query parameters in `url`.
+pub(crate) fn redact_url(url: &mut Url) {
+    if !url
+        .query_pairs()
+        .any(|(name, _)| SECRET_PARAMS.contains(&name.to_ascii_lowercase().as_str()))
+    {
+        return;
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if SECRET_PARAMS.contains(&name.to_ascii_lowercase().as_str()) {
+                logging::REDACTED.to_string()
+            } else {
+                value.to_string()
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+}
+
+/// `secs`, or `default` if unset, as a limit; 0 means none.
+fn limit(secs: Option<u64>, default: u64) -> Option<Duration> {
+    let secs = secs.unwrap_or(default);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+fn read(path: &Path, what: &str) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| Error::Config(format!("{} {}: {}", what, path.display(), e)))
+}
+
+/// Builds a client with the timeouts and connection settings of
+/// `config.http` that trusts `tls.ca_cert` in addition to the system roots
+/// and presents `tls.client_cert` when the server asks for one.
+///
+/// Connections are kept alive and reused, and HTTP/2 is used with servers
+/// that offer it unless turned off. Responses are never decompressed, even
+/// if a dependency turns reqwest's decoders on: byte counts, ranges and
+/// sizes must refer to the bytes written to disk.
+pub fn client(config: &Config) -> Result<Client> {
+    let (tls, http) = (&config.tls, &config.http);
+    let mut builder = Client::builder()
+        .user_agent(http.user_agent.as_deref().unwrap_or(USER_AGENT))
+        .no_gzip()
+        .no_brotli()
+        .no_deflate()
+        .no_zstd()
+        .pool_idle_timeout(limit(http.pool_idle_timeout, POOL_IDLE_TIMEOUT))
+        .pool_max_idle_per_host(8)
+        .tcp_keepalive(limit(http.tcp_keepalive, TCP_KEEPALIVE));
+    if let Some(timeout) = limit(http.connect_timeout, CONNECT_TIMEOUT) {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = limit(http.read_timeout, READ_TIMEOUT) {
+        builder = builder.read_timeout(timeout);
+    }
+    if http.http2 == Some(false) {
+        builder = builder.http1_only();
+    }
+
+    if let Some(path) = &tls.ca_cert {
+        let pem = read(path, "CA certificate")?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| Error::Config(format!("CA certificate {}: {}", path.display(), e)))?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = read(cert_path, "client certificate")?;
+            let key = read(key_path, "client key")?;
+            let identity = Identity::from_pkcs8_pem(&cert, &key).map_err(|e| {
+                Error::Config(format!("client certificate {}: {}", cert_path.display(), e))
+            })?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::Config(
+                "tls.client_cert and tls.client_key must be set together".to_string(),
+            ));
+        }
+    }
+
+    if tls.insecure_skip_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder
+        .build()
+        .map_err(|e| Error::Config(format!("HTTP client: {}", e)))
+}
+
+/// Why a download link should not be fetched unattended, if it shouldn't:
+/// it isn't HTTPS, or it points at this machine or a private network, where
+/// a debrid service has no business sending anyone.
+///
+/// ```
+/// use lj_core::http::untrusted_link;
+///
+/// assert_eq!(untrusted_link("https://host.example/d/abc/file.mkv"), None);
+/// assert!(untrusted_link("http://host.example/d/abc/file.mkv").is_some());
+/// assert!(untrusted_link("https://192.168.1.1/admin").is_some());
+/// assert!(untrusted_link("https://[::1]:8080/file.mkv").is_some());
+/// assert!(untrusted_link("https://printer.local/file.mkv").is_some());
+/// ```
+pub fn untrusted_link(url: &str) -> Option<String> {
+    let Ok(url) = Url::parse(url) else {
+        return Some("the link is not a valid URL".to_string());
+    };
+    let host = url.host_str().unwrap_or_default();
+    let private = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => private_v4(ip),
+        Ok(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => private_v4(ip),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+        Err(_) => {
+            let name = host.trim_end_matches('.').to_ascii_lowercase();
+            !name.contains('.')
+                || [".localhost", ".local", ".internal", ".lan", ".home.arpa"]
+                    .iter()
+                    .any(|suffix| name.ends_with(suffix))
+        }
+    };
+    if private {
+        Some(format!(
+            "the link points at a local or private host ({})",
+            host
+        ))
+    } else if url.scheme() != "https" {
+        Some(format!("the link to {} is not HTTPS", host))
+    } else {
+        None
+    }
+}
+
+fn private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // Carrier-grade NAT, which Tailscale also uses
+        || (a == 100 && (64..128).contains(&b))
+}
+
+/// The content coding of `resp`, such as gzip, if it has one. Its bytes and
+/// length are then those of the compressed body rather than the file.
+pub fn content_coding(resp: &Response) -> Option<&str> {
+    resp.headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|enc| !enc.is_empty() && !enc.eq_ignore_ascii_case("identity"))
+}
+
+/// The file name a `Content-Disposition` header gives, made safe to create
+/// in the download folder. `filename*` (RFC 6266) wins over `filename`, and
+/// any directories in the name are dropped.
+///
+/// ```
+/// use lj_core::http::disposition_filename;
+///
+/// assert_eq!(
+///     disposition_filename(r#"attachment; filename="Some Show; S01E01.mkv""#).as_deref(),
+///     Some("Some Show; S01E01.mkv")
+/// );
+/// assert_eq!(
+///     disposition_filename("attachment; filename=x.mkv; filename*=UTF-8''Caf%C3%A9.mkv")
+///         .as_deref(),
+///     Some("Café.mkv")
+/// );
+/// assert_eq!(
+///     disposition_filename(r#"attachment; filename="../../.bashrc""#).as_deref(),
+///     Some(".bashrc")
+/// );
+/// assert_eq!(disposition_filename("inline"), None);
+/// ```
+pub fn disposition_filename(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    for param in disposition_params(value).into_iter().skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename" => plain = Some(unquote(value.trim())),
+            "filename*" => extended = decode_extended(value.trim()),
+            _ => {}
+        }
+    }
+    let name = extended.or(plain)?;
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    (!name.is_empty() && name != "." && name != "..").then(|| sanitize_filename(name))
+}
+
+/// Splits a header value at the semicolons outside quoted strings.
+fn disposition_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => {
+            let mut out = String::new();
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                out.push(if c == '\\' {
+                    chars.next().unwrap_or(c)
+                } else {
+                    c
+                });
+            }
+            out
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Decodes an RFC 5987 value such as `UTF-8''Caf%C3%A9.mkv`.
+fn decode_extended(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.to_ascii_lowercase();
+    let encoded = parts.nth(1)?;
+
+    let mut bytes = Vec::new();
+    let mut rest = encoded.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
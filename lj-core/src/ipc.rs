@@ -0,0 +1,106 @@
+//! Control protocol between the daemon and its clients.
+//!
+//! Each connection to the daemon's Unix socket carries one request and one
+//! response, both encoded as a single line of JSON. A [`Request::Subscribe`]
+//! connection is followed by a line per event until the client hangs up. The daemon is Unix-only;
+//! elsewhere [`daemon_running`] is always false.
+
+use crate::config::config_dir;
+use crate::events::DownloadEvent;
+use crate::store::Download;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    /// Start transferring a new download record.
+    Add { download: Box<Download> },
+    /// List all download records.
+    List,
+    /// Cancel a running download.
+    Cancel { id: String },
+    /// Keep the connection open and receive every
+    /// [`DownloadEvent`](crate::events::DownloadEvent) as a line of JSON.
+    Subscribe,
+    /// Stop the daemon.
+    Shutdown,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", content = "data", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Downloads(Vec<Download>),
+    /// Whether the download was running when the cancel arrived.
+    Cancelled(bool),
+    Error(String),
+}
+
+pub fn socket_path() -> PathBuf {
+    config_dir().join("lj.sock")
+}
+
+/// Whether a daemon is accepting connections on the control socket.
+#[cfg(unix)]
+pub fn daemon_running() -> bool {
+    std::os::unix::net::UnixStream::connect(socket_path()).is_ok()
+}
+
+#[cfg(not(unix))]
+pub fn daemon_running() -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+pub fn request(_req: &Request) -> io::Result<Response> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the daemon is not supported on this platform",
+    ))
+}
+
+/// Sends one request to the daemon and waits for its response.
+#[cfg(unix)]
+pub fn request(req: &Request) -> io::Result<Response> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut stream = std::os::unix::net::UnixStream::connect(socket_path())?;
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    serde_json::from_str(&reply).map_err(io::Error::other)
+}
+
+#[cfg(not(unix))]
+pub fn subscribe() -> io::Result<impl Iterator<Item = io::Result<DownloadEvent>>> {
+    Err::<std::iter::Empty<_>, _>(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the daemon is not supported on this platform",
+    ))
+}
+
+/// Follows the daemon's download events. The iterator blocks for each
+/// event and ends when the daemon goes away.
+#[cfg(unix)]
+pub fn subscribe() -> io::Result<impl Iterator<Item = io::Result<DownloadEvent>>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut stream = std::os::unix::net::UnixStream::connect(socket_path())?;
+    let mut line = serde_json::to_string(&Request::Subscribe)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let ack = lines.next().transpose()?.unwrap_or_default();
+    match serde_json::from_str(&ack).map_err(io::Error::other)? {
+        Response::Ok => {}
+        Response::Error(e) => return Err(io::Error::other(e)),
+        _ => return Err(io::Error::other("unexpected daemon response")),
+    }
+    Ok(lines.map(|line| serde_json::from_str(&line?).map_err(io::Error::other)))
+}
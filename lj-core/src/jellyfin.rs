@@ -0,0 +1,74 @@
+//! Jellyfin library refreshes.
+//!
+//! When a download completes, the `[jellyfin]` server is told that the
+//! file's directory changed, so it scans just that folder instead of
+//! waiting for its next scheduled library scan.
+
+use crate::config::JellyfinConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::media;
+use crate::notify::Notifier;
+use crate::store;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+pub struct Jellyfin {
+    url: String,
+    api_key: String,
+    paths: BTreeMap<String, String>,
+}
+
+impl Jellyfin {
+    /// The server described by `config`, if it has a URL and API key.
+    pub fn from_config(config: &JellyfinConfig) -> Option<Self> {
+        Some(Jellyfin {
+            url: config.url.clone()?.trim_end_matches('/').to_string(),
+            api_key: config.api_key.clone()?,
+            paths: config.paths.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Jellyfin {
+    fn name(&self) -> &'static str {
+        "jellyfin"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        matches!(event, DownloadEvent::Completed { .. })
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let context = "Jellyfin request failed";
+        let Some(dl) = store::load_download(event.id()) else {
+            return Ok(());
+        };
+        let body = json!({
+            "Updates": [{
+                "Path": media::server_path(&dl.target_dir, &self.paths),
+                "UpdateType": "Created",
+            }]
+        });
+
+        let resp = client
+            .post(format!("{}/Library/Media/Updated", self.url))
+            .header("X-Emby-Token", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(Error::http(context))?;
+        if !resp.status().is_success() {
+            return Err(Error::Api {
+                context,
+                status: resp.status(),
+                code: None,
+                message: resp.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
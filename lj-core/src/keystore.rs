@@ -0,0 +1,68 @@
+//! API keys in the operating system's credential store.
+//!
+//! `lj set-key` keeps keys in the Secret Service (GNOME Keyring, KWallet)
+//! on Linux and the BSDs, the Keychain on macOS and the Credential Manager
+//! on Windows, under the service `lj` and an account named after the
+//! provider (`<provider>@<profile>` for a profile). Where none is running,
+//! e.g. on a headless server, [`config::save_api_key`] falls back to the
+//! plaintext key file. The identity that [encrypted key files](crate::crypt)
+//! can be encrypted to is kept here too, as `age-identity`.
+
+use crate::config;
+use crate::provider::ProviderInfo;
+use keyring::Entry;
+use std::io;
+
+const SERVICE: &str = "lj";
+
+/// Account the age identity is stored under.
+const IDENTITY: &str = "age-identity";
+
+fn account(name: &str) -> String {
+    match config::profile() {
+        Some(profile) => format!("{}@{}", name, profile),
+        None => name.to_string(),
+    }
+}
+
+fn entry(name: &str) -> io::Result<Entry> {
+    Entry::new(SERVICE, &account(name)).map_err(io::Error::other)
+}
+
+fn load_secret(name: &str) -> Option<String> {
+    let secret = entry(name).ok()?.get_password().ok()?;
+    let secret = secret.trim().to_string();
+    (!secret.is_empty()).then_some(secret)
+}
+
+/// The provider's key from the credential store, if it has one.
+pub fn load(provider: &ProviderInfo) -> Option<String> {
+    load_secret(provider.name)
+}
+
+/// Stores the provider's key, failing if there is no credential store.
+pub fn save(provider: &ProviderInfo, key: &str) -> io::Result<()> {
+    entry(provider.name)?
+        .set_password(key)
+        .map_err(io::Error::other)
+}
+
+/// Removes the provider's key, if one is stored.
+pub fn delete(provider: &ProviderInfo) {
+    if let Ok(entry) = entry(provider.name) {
+        let _ = entry.delete_credential();
+    }
+}
+
+/// The age identity encrypted key files may be encrypted to, if one is
+/// stored.
+pub fn load_identity() -> Option<String> {
+    load_secret(IDENTITY)
+}
+
+/// Stores the age identity, failing if there is no credential store.
+pub fn save_identity(identity: &str) -> io::Result<()> {
+    entry(IDENTITY)?
+        .set_password(identity)
+        .map_err(io::Error::other)
+}
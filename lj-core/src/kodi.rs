@@ -0,0 +1,112 @@
+//! Kodi notifications and library updates over JSON-RPC.
+//!
+//! When a download completes, the `[kodi]` instance pops up a notification
+//! and scans the file's directory into its video library, so the new file
+//! is in the library by the time the user picks up the remote. Kodi's
+//! web server ("Allow remote control via HTTP") has to be on.
+
+use crate::config::KodiConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::media;
+use crate::notify::Notifier;
+use crate::store;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+pub struct Kodi {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    paths: BTreeMap<String, String>,
+}
+
+impl Kodi {
+    /// The instance described by `config`, if it has a URL.
+    pub fn from_config(config: &KodiConfig) -> Option<Self> {
+        Some(Kodi {
+            url: config.url.clone()?.trim_end_matches('/').to_string(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            paths: config.paths.clone(),
+        })
+    }
+
+    /// Calls `method`. Kodi reports failures in the response body, with a
+    /// 200 status.
+    async fn call(&self, client: &Client, method: &str, params: Value) -> Result<()> {
+        let context = "Kodi request failed";
+        let mut req = client.post(format!("{}/jsonrpc", self.url)).json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }));
+        if let Some(username) = &self.username {
+            req = req.basic_auth(username, self.password.as_deref());
+        }
+
+        let resp = req.send().await.map_err(Error::http(context))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(Error::http(context))?;
+        if !status.is_success() {
+            return Err(Error::Api {
+                context,
+                status,
+                code: None,
+                message: text,
+            });
+        }
+        let body: Value = serde_json::from_str(&text).map_err(|e| Error::Parse {
+            context,
+            message: e.to_string(),
+        })?;
+        match body.get("error") {
+            Some(error) => Err(Error::Api {
+                context,
+                status,
+                code: error["code"].as_i64(),
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Kodi {
+    fn name(&self) -> &'static str {
+        "kodi"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        matches!(event, DownloadEvent::Completed { .. })
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let Some(dl) = store::load_download(event.id()) else {
+            return Ok(());
+        };
+        // Kodi matches the directory against its sources only with the
+        // trailing separator
+        let mut directory = media::server_path(&dl.target_dir, &self.paths);
+        if !directory.ends_with(['/', '\\']) {
+            directory.push('/');
+        }
+        self.call(
+            client,
+            "VideoLibrary.Scan",
+            json!({ "directory": directory, "showdialogs": false }),
+        )
+        .await?;
+        // Last, so a retried scan doesn't pop up the notification twice
+        self.call(
+            client,
+            "GUI.ShowNotification",
+            json!({ "title": "Download complete", "message": dl.filename }),
+        )
+        .await
+    }
+}
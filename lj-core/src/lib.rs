@@ -0,0 +1,87 @@
+//! Core of lj: debrid service clients, the background download worker and
+//! the on-disk state store.
+//!
+//! The `lj` binary is a thin frontend over this crate. A typical embedding
+//! looks like:
+//!
+//! ```no_run
+//! # async fn run() -> lj_core::error::Result<()> {
+//! use lj_core::provider::{self, DebridProvider};
+//!
+//! let provider = provider::from_name("real-debrid", "API_TOKEN")?;
+//! let torrent_id = provider.add_magnet("magnet:?xt=urn:btih:...").await?;
+//! let files = provider.wait_for_files(&torrent_id).await?;
+//! let ids: Vec<u32> = files.iter().map(|f| f.id).collect();
+//! provider.select_files(&torrent_id, &ids).await?;
+//! let links = provider.wait_for_download(&torrent_id, &mut |_| {}).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything above works against any [`provider::DebridProvider`];
+//! [`mock::MockProvider`] serves canned responses for tests.
+//!
+//! Downloads are persisted as [`store::Download`] records and fetched by
+//! [`worker::run_worker`], usually inside a detached process per torrent
+//! started with [`worker::spawn_worker`], or as tasks inside a long-running
+//! [`daemon`] that clients talk to over [`ipc`]. Their progress is
+//! published as [`events`]. [`serve`] puts the same operations behind an
+//! HTTP API. Torrents the service is still downloading can be waited on by
+//! a detached process too (see [`processing`]).
+
+pub mod alldebrid;
+pub mod blackhole;
+pub mod config;
+pub mod control;
+pub mod crypt;
+#[cfg(unix)]
+pub mod daemon;
+pub mod debridlink;
+pub mod discord;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod extract;
+pub mod format;
+pub mod handler;
+pub mod healthchecks;
+pub mod hooks;
+pub mod http;
+pub mod ipc;
+pub mod jellyfin;
+pub mod keystore;
+pub mod kodi;
+pub mod library;
+pub mod logging;
+pub mod magnet;
+pub mod media;
+pub mod mock;
+pub mod mqtt;
+pub mod notify;
+pub mod ntfy;
+pub mod pipeline;
+pub mod platform;
+pub mod player;
+pub mod plex;
+pub mod premiumize;
+pub mod processing;
+pub mod progress;
+pub mod provider;
+pub mod ratelimit;
+pub mod rd;
+pub mod rss;
+pub mod search;
+pub mod serve;
+pub mod sink;
+pub mod speed;
+pub mod state;
+pub mod store;
+#[cfg(unix)]
+pub mod systemd;
+pub mod telegram;
+pub mod torbox;
+pub mod torrent;
+pub mod update;
+pub mod upload;
+pub mod webhook;
+pub mod worker;
@@ -0,0 +1,62 @@
+//! Files already in the user's library, so re-grabbing a season pack for
+//! one missing episode doesn't download the rest of it again.
+//!
+//! The `[library]` directories are walked and their files indexed by size.
+//! A file of a torrent counts as present when a library file has exactly
+//! its size and either the same name, ignoring case, or the same movie or
+//! episode (see [`media::parse`]), which survives renaming by Sonarr and
+//! Radarr.
+
+use crate::media;
+use crate::provider::TorrentFile;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Library {
+    by_size: HashMap<u64, Vec<PathBuf>>,
+}
+
+impl Library {
+    /// Indexes every file under `dirs`. Directories that can't be read are
+    /// skipped, and symlinks aren't followed.
+    pub fn scan(dirs: &[PathBuf]) -> Library {
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        let mut pending: Vec<PathBuf> = dirs.to_vec();
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(kind) = entry.file_type() else {
+                    continue;
+                };
+                if kind.is_dir() {
+                    pending.push(entry.path());
+                } else if kind.is_file()
+                    && let Ok(meta) = entry.metadata()
+                {
+                    by_size.entry(meta.len()).or_default().push(entry.path());
+                }
+            }
+        }
+        Library { by_size }
+    }
+
+    /// Where `file` already is in the library, if anywhere.
+    pub fn find(&self, file: &TorrentFile) -> Option<&Path> {
+        let name = file.name();
+        let parsed = media::parse(name);
+        self.by_size
+            .get(&file.bytes)?
+            .iter()
+            .find(|path| {
+                let Some(other) = path.file_name().and_then(|n| n.to_str()) else {
+                    return false;
+                };
+                other.eq_ignore_ascii_case(name)
+                    || parsed.is_some() && media::parse(other) == parsed
+            })
+            .map(PathBuf::as_path)
+    }
+}
@@ -0,0 +1,259 @@
+//! Logging for the daemon and background workers.
+//!
+//! Workers run with their output discarded, so once [`init`] is called the
+//! download lifecycle is logged to journald, with `LJ_DOWNLOAD_ID` and
+//! `LJ_EVENT` fields for filtering. Without journald it goes to syslog, and
+//! failing that (and on Windows) to `lj.log` in the
+//! [state directory](crate::config::state_dir). Like [`systemd`](crate::systemd)
+//! this speaks the wire protocols directly rather than linking libsystemd.
+//! Before [`init`] every call is a no-op, so the CLI stays quiet.
+//!
+//! Everything logged goes through [`redact`] first, as do the CLI's error
+//! messages and, once [`redact_panics`] is called, panic messages.
+
+use crate::config::state_dir;
+use crate::events::DownloadEvent;
+use regex::Regex;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Syslog severities, which journald uses as `PRIORITY` too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+enum Backend {
+    #[cfg(unix)]
+    Journald(std::os::unix::net::UnixDatagram),
+    #[cfg(unix)]
+    Syslog(std::os::unix::net::UnixDatagram),
+    File(PathBuf),
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// What a masked secret is replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Secrets shorter than this are not masked, as they would mangle
+/// unrelated text.
+const MIN_SECRET_LEN: usize = 8;
+
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Bearer headers and credential query parameters, for tokens that were
+/// never registered.
+static SECRET_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(bearer\s+|[?&](?:apikey|api_key|token|access_token|auth|passkey)=)[^\s&\x22')]+",
+    )
+    .unwrap()
+});
+
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Turns logging on for this process, picking the first backend that
+/// accepts a connection.
+pub fn init() {
+    BACKEND.get_or_init(open_backend);
+}
+
+#[cfg(unix)]
+fn open_backend() -> Backend {
+    use std::os::unix::net::UnixDatagram;
+
+    let connect = |path: &str| {
+        let sock = UnixDatagram::unbound()?;
+        sock.connect(path)?;
+        Ok::<_, io::Error>(sock)
+    };
+    if let Ok(sock) = connect(JOURNALD_SOCKET) {
+        return Backend::Journald(sock);
+    }
+    if let Ok(sock) = connect(SYSLOG_SOCKET) {
+        return Backend::Syslog(sock);
+    }
+    Backend::File(log_path())
+}
+
+#[cfg(not(unix))]
+fn open_backend() -> Backend {
+    Backend::File(log_path())
+}
+
+/// The fallback log file.
+pub fn log_path() -> PathBuf {
+    state_dir().join("lj.log")
+}
+
+/// Masks `secret` wherever [`redact`] finds it from now on.
+pub fn add_secret(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// `text` with every secret given to [`add_secret`], and anything that
+/// looks like a bearer token or a credential in a URL, masked.
+///
+/// ```
+/// use lj_core::logging::redact;
+///
+/// assert_eq!(
+///     redact("GET https://api.example/info?apikey=s3cr3t&id=1"),
+///     "GET https://api.example/info?apikey=[REDACTED]&id=1"
+/// );
+/// ```
+pub fn redact(text: &str) -> String {
+    let mut text = SECRET_PATTERN
+        .replace_all(text, format!("${{1}}{}", REDACTED))
+        .into_owned();
+    for secret in SECRETS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+    text
+}
+
+/// Prints panics through [`redact`], and logs them, instead of Rust's
+/// default message.
+pub fn redact_panics() {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        let location = info
+            .location()
+            .map(|l| format!(" at {}", l))
+            .unwrap_or_default();
+        let thread = std::thread::current();
+        let message = redact(&format!(
+            "thread '{}' panicked{}:\n{}",
+            thread.name().unwrap_or("<unnamed>"),
+            location,
+            message
+        ));
+        eprintln!("{}", message);
+        let backtrace = std::backtrace::Backtrace::capture();
+        if backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+            eprintln!("{}", redact(&backtrace.to_string()));
+        }
+        log(Level::Error, &message, &[("LJ_EVENT", "panic")]);
+    }));
+}
+
+/// Logs `message` with extra `fields` (upper-case journald field names),
+/// both [redacted](redact).
+pub fn log(level: Level, message: &str, fields: &[(&str, &str)]) {
+    if let Some(backend) = BACKEND.get() {
+        let message = redact(message);
+        let fields: Vec<(&str, String)> = fields
+            .iter()
+            .map(|(key, value)| (*key, redact(value)))
+            .collect();
+        let _ = backend.write(level, &message, &fields);
+    }
+}
+
+/// Logs a lifecycle event. Progress ticks are too frequent to be worth it.
+pub fn log_event(event: &DownloadEvent) {
+    let (level, name, message) = match event {
+        DownloadEvent::Started {
+            filename,
+            total_bytes,
+            ..
+        } => (
+            Level::Info,
+            "started",
+            match total_bytes {
+                0 => format!("Started {} (size unknown)", filename),
+                bytes => format!("Started {} ({} bytes)", filename, bytes),
+            },
+        ),
+        DownloadEvent::Progress { .. } => return,
+        DownloadEvent::Completed { bytes, .. } => (
+            Level::Info,
+            "completed",
+            format!("Completed ({} bytes)", bytes),
+        ),
+        DownloadEvent::Failed { error, .. } => {
+            (Level::Error, "failed", format!("Failed: {}", error))
+        }
+        DownloadEvent::Cancelled { .. } => (Level::Info, "cancelled", "Cancelled".to_string()),
+    };
+    let message = format!("{}: {}", event.id(), message);
+    log(
+        level,
+        &message,
+        &[("LJ_DOWNLOAD_ID", event.id()), ("LJ_EVENT", name)],
+    );
+}
+
+/// Newlines would start a new field or record in every format below.
+fn one_line(s: &str) -> String {
+    s.replace(['\n', '\r'], " ")
+}
+
+impl Backend {
+    fn write(&self, level: Level, message: &str, fields: &[(&str, String)]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Backend::Journald(sock) => {
+                let mut datagram = format!(
+                    "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER=lj\n",
+                    one_line(message),
+                    level as u8
+                );
+                for (key, value) in fields {
+                    datagram.push_str(&format!("{}={}\n", key, one_line(value)));
+                }
+                sock.send(datagram.as_bytes()).map(|_| ())
+            }
+            #[cfg(unix)]
+            Backend::Syslog(sock) => {
+                // Facility "user" (1)
+                let datagram = format!(
+                    "<{}>lj[{}]: {}",
+                    8 + level as u8,
+                    std::process::id(),
+                    one_line(message)
+                );
+                sock.send(datagram.as_bytes()).map(|_| ())
+            }
+            Backend::File(path) => {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(
+                    file,
+                    "{} lj[{}] {:?}: {}",
+                    secs,
+                    std::process::id(),
+                    level,
+                    one_line(message)
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Magnet links, parsed.
+//!
+//! A magnet names its torrent by info hash in one or more `xt` parameters:
+//! `urn:btih:` with BitTorrent v1's SHA-1 hash, in hex or base32, and
+//! `urn:btmh:` with v2's SHA-256 multihash. `dn` is a display name and `tr`
+//! a tracker. [`Magnet::parse`] checks the hashes are well formed, so a
+//! mangled link is refused before it reaches the provider.
+
+use crate::error::{Error, Result};
+use reqwest::Url;
+use std::fmt;
+use std::str::FromStr;
+
+/// Multihash prefix of a SHA-256 digest: code 0x12, length 32.
+const SHA256_MULTIHASH: &str = "1220";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magnet {
+    /// BitTorrent v1 info hash as lowercase hex, converted from base32 if
+    /// need be.
+    pub info_hash: Option<String>,
+    /// BitTorrent v2 info hash (the SHA-256 digest) as lowercase hex.
+    pub info_hash_v2: Option<String>,
+    /// The torrent's name from `dn`.
+    pub name: Option<String>,
+    pub trackers: Vec<String>,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Parse {
+        context: "Invalid magnet link",
+        message: message.into(),
+    }
+}
+
+/// Percent-encodes everything but unreserved characters.
+pub(crate) fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A v1 info hash as lowercase hex, from 40 hex digits or 32 base32 ones.
+fn btih(hash: &str) -> Option<String> {
+    match hash.len() {
+        40 if hash.chars().all(|c| c.is_ascii_hexdigit()) => Some(hash.to_lowercase()),
+        32 => {
+            let mut bits: u64 = 0;
+            let mut count = 0;
+            let mut hex = String::with_capacity(40);
+            for c in hash.chars() {
+                let value = match c.to_ascii_uppercase() {
+                    c @ 'A'..='Z' => c as u64 - 'A' as u64,
+                    c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+                    _ => return None,
+                };
+                bits = bits << 5 | value;
+                count += 5;
+                while count >= 4 {
+                    count -= 4;
+                    hex.push(char::from_digit(((bits >> count) & 0xf) as u32, 16)?);
+                }
+            }
+            Some(hex)
+        }
+        _ => None,
+    }
+}
+
+/// A v2 info hash's digest as lowercase hex, from its hex multihash.
+fn btmh(hash: &str) -> Option<String> {
+    let digest = hash.strip_prefix(SHA256_MULTIHASH)?;
+    (digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| digest.to_lowercase())
+}
+
+impl Magnet {
+    /// Parses `uri`, failing unless it is a magnet link with at least one
+    /// well-formed BitTorrent info hash.
+    ///
+    /// ```
+    /// use lj_core::magnet::Magnet;
+    ///
+    /// let magnet = Magnet::parse(
+    ///     "magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK&dn=Some+Show%20S01\
+    ///      &tr=udp%3A%2F%2Ftracker.example%3A1337",
+    /// )
+    /// .unwrap();
+    /// assert_eq!(
+    ///     magnet.info_hash.as_deref(),
+    ///     Some("c12fe1c06bba254a9dc9f519b335aa7c1367a88a")
+    /// );
+    /// assert_eq!(magnet.name.as_deref(), Some("Some Show S01"));
+    /// assert_eq!(magnet.trackers, ["udp://tracker.example:1337"]);
+    ///
+    /// assert!(Magnet::parse("magnet:?xt=urn:btih:1234").is_err());
+    /// assert!(Magnet::parse("https://example.com/x.torrent").is_err());
+    /// ```
+    pub fn parse(uri: &str) -> Result<Magnet> {
+        let url = Url::parse(uri.trim())
+            .ok()
+            .filter(|url| url.scheme() == "magnet")
+            .ok_or_else(|| invalid("not a magnet: URI"))?;
+
+        let mut magnet = Magnet {
+            info_hash: None,
+            info_hash_v2: None,
+            name: None,
+            trackers: Vec::new(),
+        };
+        // Parameters may be numbered when repeated, e.g. `xt.1`, `tr.2`
+        for (key, value) in url.query_pairs() {
+            match key.split('.').next().unwrap_or_default() {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        let hash = btih(hash)
+                            .ok_or_else(|| invalid(format!("bad info hash '{}'", hash)))?;
+                        magnet.info_hash.get_or_insert(hash);
+                    } else if let Some(hash) = value.strip_prefix("urn:btmh:") {
+                        let hash = btmh(hash)
+                            .ok_or_else(|| invalid(format!("bad v2 info hash '{}'", hash)))?;
+                        magnet.info_hash_v2.get_or_insert(hash);
+                    }
+                }
+                "dn" if !value.trim().is_empty() => {
+                    magnet.name.get_or_insert_with(|| value.trim().to_string());
+                }
+                "tr" if !value.is_empty() && !magnet.trackers.iter().any(|t| *t == value) => {
+                    magnet.trackers.push(value.into_owned());
+                }
+                _ => {}
+            }
+        }
+        if magnet.info_hash.is_none() && magnet.info_hash_v2.is_none() {
+            return Err(invalid(
+                "no BitTorrent info hash (xt=urn:btih: or urn:btmh:)",
+            ));
+        }
+        Ok(magnet)
+    }
+
+    /// The magnet for a bare info hash, as many sites list only that: 40
+    /// hex or 32 base32 characters for BitTorrent v1, 64 hex for v2. It gets
+    /// `trackers`, since there's nothing else to find peers with.
+    ///
+    /// ```
+    /// use lj_core::magnet::Magnet;
+    ///
+    /// let trackers = vec!["udp://tracker.example:1337".to_string()];
+    /// let magnet =
+    ///     Magnet::from_info_hash("C12FE1C06BBA254A9DC9F519B335AA7C1367A88A", &trackers).unwrap();
+    /// assert_eq!(
+    ///     magnet.to_string(),
+    ///     "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a\
+    ///      &tr=udp%3A%2F%2Ftracker.example%3A1337"
+    /// );
+    /// assert!(Magnet::from_info_hash("Some.Show.S01", &trackers).is_none());
+    /// ```
+    pub fn from_info_hash(hash: &str, trackers: &[String]) -> Option<Magnet> {
+        let hash = hash.trim();
+        let (info_hash, info_hash_v2) = match btih(hash) {
+            Some(hash) => (Some(hash), None),
+            None => (None, Some(btmh(&format!("{}{}", SHA256_MULTIHASH, hash))?)),
+        };
+        Some(Magnet {
+            info_hash,
+            info_hash_v2,
+            name: None,
+            trackers: trackers.to_vec(),
+        })
+    }
+
+    /// The torrent's name, or else its info hash.
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.info_hash.as_deref())
+            .or(self.info_hash_v2.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// `uri` with `trackers` added if it names none of its own, so the
+/// provider has somewhere to find peers for a rare, trackerless hash.
+/// Anything but a magnet link comes back as it is.
+///
+/// ```
+/// use lj_core::magnet::with_trackers;
+///
+/// let trackers = vec!["udp://tracker.example:1337".to_string()];
+/// let magnet = "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+/// assert_eq!(
+///     with_trackers(magnet, &trackers),
+///     format!("{}&tr=udp%3A%2F%2Ftracker.example%3A1337", magnet)
+/// );
+/// let own = format!("{}&tr=udp%3A%2F%2Fother.example%3A80", magnet);
+/// assert_eq!(with_trackers(&own, &trackers), own);
+/// ```
+pub fn with_trackers(uri: &str, trackers: &[String]) -> String {
+    match Magnet::parse(uri) {
+        Ok(magnet) if magnet.trackers.is_empty() => {
+            let mut uri = uri.trim().to_string();
+            for tracker in trackers {
+                uri.push_str(&format!("&tr={}", encode(tracker)));
+            }
+            uri
+        }
+        _ => uri.to_string(),
+    }
+}
+
+/// The magnet link, with its hashes, name and trackers.
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut params = Vec::new();
+        if let Some(hash) = &self.info_hash {
+            params.push(format!("xt=urn:btih:{}", hash));
+        }
+        if let Some(hash) = &self.info_hash_v2 {
+            params.push(format!("xt=urn:btmh:{}{}", SHA256_MULTIHASH, hash));
+        }
+        if let Some(name) = &self.name {
+            params.push(format!("dn={}", encode(name)));
+        }
+        for tracker in &self.trackers {
+            params.push(format!("tr={}", encode(tracker)));
+        }
+        write!(f, "magnet:?{}", params.join("&"))
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Magnet> {
+        Magnet::parse(uri)
+    }
+}
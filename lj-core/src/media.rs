@@ -0,0 +1,271 @@
+//! Media-aware placement of downloads into a library layout.
+//!
+//! Release names are parsed into a movie (title and year) or a TV episode
+//! (show, season and episode), and with `[organize]` enabled each video or
+//! subtitle file is downloaded straight into
+//! `<library>/Movies/Title (Year)/` or `<library>/TV/Show/Season NN/`, the
+//! layout Jellyfin and Plex expect. Anything else, or anything that does not
+//! parse, stays where it would have gone.
+
+use crate::config::OrganizeConfig;
+use crate::error::{Error, Result};
+use crate::platform::sanitize_filename;
+use crate::store::Download;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Extensions of the files that are organized.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mkv", "mp4", "avi", "m4v", "mov", "wmv", "ts", "webm", "srt", "sub", "ass", "ssa", "idx",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Media {
+    Movie {
+        title: String,
+        /// Without a year, a video is not taken for a movie.
+        year: u16,
+    },
+    Episode {
+        show: String,
+        season: u32,
+        episode: u32,
+    },
+}
+
+/// `S01E02` or `1x02`, as season and episode.
+fn episode_marker(token: &str) -> Option<(u32, u32)> {
+    let lower = token.to_lowercase();
+    let (season, episode) = if let Some(rest) = lower.strip_prefix('s') {
+        rest.split_once('e')?
+    } else {
+        lower.split_once('x')?
+    };
+    // `S01E02E03` is a double episode; the first one is enough
+    let episode = episode.split('e').next()?;
+    let all_digits =
+        |s: &str| !s.is_empty() && s.len() <= 3 && s.chars().all(|c| c.is_ascii_digit());
+    if !all_digits(season) || !all_digits(episode) {
+        return None;
+    }
+    Some((season.parse().ok()?, episode.parse().ok()?))
+}
+
+fn year(token: &str) -> Option<u16> {
+    let year: u16 = token.parse().ok().filter(|_| token.len() == 4)?;
+    (1900..=2099).contains(&year).then_some(year)
+}
+
+/// Parses a release file name.
+///
+/// ```
+/// use lj_core::media::{Media, parse};
+///
+/// assert_eq!(
+///     parse("The.Expanse.S02E05.1080p.WEB.x264.mkv"),
+///     Some(Media::Episode { show: "The Expanse".into(), season: 2, episode: 5 })
+/// );
+/// assert_eq!(
+///     parse("Blade.Runner.2049.2017.2160p.UHD.mkv"),
+///     Some(Media::Movie { title: "Blade Runner 2049".into(), year: 2017 })
+/// );
+/// assert_eq!(parse("Setup.exe"), None);
+/// ```
+pub fn parse(filename: &str) -> Option<Media> {
+    let (stem, ext) = filename.rsplit_once('.')?;
+    if !MEDIA_EXTENSIONS.contains(&ext.to_lowercase().as_str()) {
+        return None;
+    }
+    let tokens: Vec<&str> = stem
+        .split(['.', '_', ' '])
+        .map(|t| t.trim_matches(|c| matches!(c, '(' | ')' | '[' | ']' | '-')))
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    if let Some(pos) = tokens.iter().position(|t| episode_marker(t).is_some()) {
+        let (season, episode) = episode_marker(tokens[pos])?;
+        // A year right before the marker belongs to the show's name in
+        // the filename but not in the folder
+        let mut show = &tokens[..pos];
+        if let Some(last) = show.last()
+            && show.len() > 1
+            && year(last).is_some()
+        {
+            show = &show[..show.len() - 1];
+        }
+        if show.is_empty() {
+            return None;
+        }
+        return Some(Media::Episode {
+            show: show.join(" "),
+            season,
+            episode,
+        });
+    }
+
+    // The last year-like token that is not the whole title, so
+    // "Blade Runner 2049 2017" and "1917 2019" both work
+    let pos = tokens
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, t)| year(t).is_some())
+        .map(|(i, _)| i)
+        .next_back()?;
+    Some(Media::Movie {
+        title: tokens[..pos].join(" "),
+        year: year(tokens[pos])?,
+    })
+}
+
+impl Media {
+    /// Directory for this media under `library`.
+    pub fn directory(&self, library: &Path, config: &OrganizeConfig) -> PathBuf {
+        match self {
+            Media::Movie { title, year } => library
+                .join(config.movies.as_deref().unwrap_or("Movies"))
+                .join(sanitize_filename(&format!("{} ({})", title, year))),
+            Media::Episode { show, season, .. } => library
+                .join(config.tv.as_deref().unwrap_or("TV"))
+                .join(sanitize_filename(show))
+                .join(format!("Season {:02}", season)),
+        }
+    }
+}
+
+/// A list of episodes such as `S01E03-S01E06,S02E01`, for picking files
+/// out of a season pack. A bare season (`S02`) means all of it, and the
+/// end of a range may leave out its season (`S01E03-E06`, or `-06`).
+///
+/// ```
+/// use lj_core::media::Episodes;
+///
+/// let episodes: Episodes = "s01e03-e05,S02".parse().unwrap();
+/// assert!(episodes.contains("Show.S01E04.1080p.mkv"));
+/// assert!(episodes.contains("Show.2x10.srt"));
+/// assert!(!episodes.contains("Show.S01E06.1080p.mkv"));
+/// assert!(!episodes.contains("Show.S01.Extras.mkv"));
+/// assert!("S01E06-S01E03".parse::<Episodes>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Episodes {
+    /// Inclusive ranges of (season, episode).
+    ranges: Vec<((u32, u32), (u32, u32))>,
+}
+
+fn invalid_episodes(message: String) -> Error {
+    Error::Parse {
+        context: "Invalid episode list",
+        message,
+    }
+}
+
+/// `S01E03` or `S01` (as episodes 0 to the last); an end like `E06` or `06`
+/// takes the season of `start`.
+fn episode_bound(text: &str, start: Option<(u32, u32)>) -> Option<((u32, u32), (u32, u32))> {
+    let lower = text.trim().to_lowercase();
+    let number = |s: &str| -> Option<u32> {
+        (!s.is_empty() && s.len() <= 3 && s.chars().all(|c| c.is_ascii_digit()))
+            .then(|| s.parse().ok())?
+    };
+    if let Some((season, episode)) = episode_marker(&lower) {
+        return Some(((season, episode), (season, episode)));
+    }
+    if let Some(season) = lower.strip_prefix('s').and_then(number) {
+        return Some(((season, 0), (season, u32::MAX)));
+    }
+    let (season, _) = start?;
+    let episode = number(lower.strip_prefix('e').unwrap_or(&lower))?;
+    Some(((season, episode), (season, episode)))
+}
+
+impl FromStr for Episodes {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Episodes> {
+        let mut ranges = Vec::new();
+        for part in spec.split(',').filter(|p| !p.trim().is_empty()) {
+            let bad = || invalid_episodes(format!("can't read '{}'", part.trim()));
+            let range = match part.split_once('-') {
+                Some((from, to)) => {
+                    let (start, _) = episode_bound(from, None).ok_or_else(bad)?;
+                    let (_, end) = episode_bound(to, Some(start)).ok_or_else(bad)?;
+                    (start, end)
+                }
+                None => episode_bound(part, None).ok_or_else(bad)?,
+            };
+            if range.0 > range.1 {
+                return Err(invalid_episodes(format!(
+                    "'{}' ends before it starts",
+                    part.trim()
+                )));
+            }
+            ranges.push(range);
+        }
+        if ranges.is_empty() {
+            return Err(invalid_episodes("no episodes given".to_string()));
+        }
+        Ok(Episodes { ranges })
+    }
+}
+
+impl Episodes {
+    /// Whether `filename` is one of the episodes.
+    pub fn contains(&self, filename: &str) -> bool {
+        let Some(Media::Episode {
+            season, episode, ..
+        }) = parse(filename)
+        else {
+            return false;
+        };
+        self.ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&(season, episode)))
+    }
+}
+
+/// Moves `download` into its place in the library if organizing is on and
+/// its name parses. The library defaults to the download's own directory.
+pub fn organize(config: &OrganizeConfig, download: &mut Download) {
+    if !config.enabled {
+        return;
+    }
+    let Some(media) = parse(&download.filename) else {
+        return;
+    };
+    let library = config
+        .library
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(&download.target_dir));
+    download.target_dir = media
+        .directory(&library, config)
+        .to_string_lossy()
+        .to_string();
+}
+
+/// The entry of `map` whose key is the longest directory prefix of `path`.
+pub(crate) fn longest_prefix<'a, V>(
+    path: &str,
+    map: &'a BTreeMap<String, V>,
+) -> Option<(&'a str, &'a V)> {
+    map.iter()
+        .map(|(prefix, value)| (prefix.trim_end_matches('/'), value))
+        .filter(|(prefix, _)| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+}
+
+/// `path` as a media server sees it: the longest matching prefix in
+/// `paths` is swapped for its counterpart, e.g. when the server runs in a
+/// container with the library mounted elsewhere.
+pub(crate) fn server_path(path: &str, paths: &BTreeMap<String, String>) -> String {
+    match longest_prefix(path, paths) {
+        Some((local, remote)) => {
+            format!("{}{}", remote.trim_end_matches('/'), &path[local.len()..])
+        }
+        None => path.to_string(),
+    }
+}
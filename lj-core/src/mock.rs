@@ -0,0 +1,229 @@
+//! In-memory provider with canned responses.
+//!
+//! [`MockProvider`] implements [`DebridProvider`] without any network access,
+//! so the add/wait/select/unrestrict sequence can be exercised in tests and
+//! examples without a debrid account:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> lj_core::error::Result<()> {
+//! use lj_core::config::SelectConfig;
+//! use lj_core::mock::MockProvider;
+//! use lj_core::pipeline;
+//!
+//! let provider = MockProvider::new()
+//!     .with_file("Show/S01E01.mkv", 700_000_000)
+//!     .with_file("Show/sample.mkv", 20_000_000)
+//!     .with_download_base("http://127.0.0.1:9/files");
+//! let client = reqwest::Client::new();
+//!
+//! let magnet = "magnet:?xt=urn:btih:abc";
+//! let select = SelectConfig::default();
+//! let fetched =
+//!     pipeline::fetch_magnet(&provider, &client, magnet, &select, None, &mut |_| {}).await?;
+//! assert_eq!(fetched.links.len(), 1);
+//! assert_eq!(fetched.links[0].filename, "S01E01.mkv");
+//! assert_eq!(provider.selected(), vec![1]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::{Error, Result};
+use crate::provider::{DebridProvider, TorrentFile, TorrentState, TorrentStatus, UnrestrictedLink};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use std::sync::Mutex;
+
+/// Prefix of the links handed out by [`DebridProvider::poll`].
+const FILE_LINK: &str = "mock:";
+
+/// A provider that serves one scripted torrent.
+///
+/// The torrent's file list is available on the first poll. Once files are
+/// selected it reports [`TorrentState::Processing`] for the configured
+/// number of polls, then [`TorrentState::Ready`] with one link per selected
+/// file, unless it was told to fail.
+#[derive(Default)]
+pub struct MockProvider {
+    files: Vec<TorrentFile>,
+    download_base: String,
+    processing_polls: u32,
+    failure: Option<String>,
+    /// Links unrestricted before the API key is rejected.
+    key_rejected_after: Option<u32>,
+    state: Mutex<MockState>,
+}
+
+#[derive(Default)]
+struct MockState {
+    added: Vec<String>,
+    selected: Option<Vec<u32>>,
+    polls_after_select: u32,
+    unrestricted: u32,
+    deleted: bool,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self {
+            download_base: "http://localhost".to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds a file to the torrent. Ids are assigned from 1 in order.
+    pub fn with_file(mut self, path: &str, bytes: u64) -> Self {
+        self.files.push(TorrentFile {
+            id: self.files.len() as u32 + 1,
+            path: path.to_string(),
+            bytes,
+            selected: 0,
+        });
+        self
+    }
+
+    /// Where unrestricted links point: `<base>/<file name>`.
+    pub fn with_download_base(mut self, base: &str) -> Self {
+        self.download_base = base.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// How many polls after selection report the torrent as processing.
+    pub fn with_processing_polls(mut self, polls: u32) -> Self {
+        self.processing_polls = polls;
+        self
+    }
+
+    /// Makes the torrent fail with `message` on the first poll.
+    pub fn with_failure(mut self, message: &str) -> Self {
+        self.failure = Some(message.to_string());
+        self
+    }
+
+    /// Rejects the API key once `links` links have been unrestricted, as a
+    /// service does with a key revoked midway.
+    pub fn with_key_rejected_after(mut self, links: u32) -> Self {
+        self.key_rejected_after = Some(links);
+        self
+    }
+
+    /// Magnets passed to [`DebridProvider::add_magnet`], in order.
+    pub fn added(&self) -> Vec<String> {
+        self.state.lock().unwrap().added.clone()
+    }
+
+    /// File ids passed to the last [`DebridProvider::select_files`].
+    pub fn selected(&self) -> Vec<u32> {
+        self.state
+            .lock()
+            .unwrap()
+            .selected
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Whether the torrent was deleted.
+    pub fn deleted(&self) -> bool {
+        self.state.lock().unwrap().deleted
+    }
+
+    fn file(&self, id: u32) -> Result<&TorrentFile> {
+        self.files
+            .iter()
+            .find(|f| f.id == id)
+            .ok_or_else(|| Error::Other(format!("No such file: {}", id)))
+    }
+}
+
+#[async_trait]
+impl DebridProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Mock"
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        state.added.push(magnet.to_string());
+        Ok(state.added.len().to_string())
+    }
+
+    async fn poll(&self, _torrent_id: &str) -> Result<TorrentStatus> {
+        let mut state = self.state.lock().unwrap();
+        let (torrent_state, links) = match (&self.failure, &state.selected) {
+            (Some(message), _) => (TorrentState::Failed(message.clone()), Vec::new()),
+            (None, None) => (TorrentState::AwaitingSelection, Vec::new()),
+            (None, Some(_)) if state.polls_after_select < self.processing_polls => {
+                state.polls_after_select += 1;
+                (TorrentState::Processing, Vec::new())
+            }
+            (None, Some(ids)) => {
+                let links = ids
+                    .iter()
+                    .map(|id| format!("{}{}", FILE_LINK, id))
+                    .collect();
+                (TorrentState::Ready, links)
+            }
+        };
+
+        let progress = match torrent_state {
+            TorrentState::Ready => 100.0,
+            TorrentState::Processing => {
+                state.polls_after_select as f64 / (self.processing_polls + 1) as f64 * 100.0
+            }
+            _ => 0.0,
+        };
+        Ok(TorrentStatus {
+            status: format!("{:?}", torrent_state).to_lowercase(),
+            state: torrent_state,
+            name: None,
+            progress,
+            speed: 0,
+            seeders: 0,
+            files: self.files.clone(),
+            links,
+        })
+    }
+
+    async fn select_files(&self, _torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        for &id in file_ids {
+            self.file(id)?;
+        }
+        self.state.lock().unwrap().selected = Some(file_ids.to_vec());
+        Ok(())
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        let id = link
+            .strip_prefix(FILE_LINK)
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| Error::Other(format!("Unknown link: {}", link)))?;
+        let file = self.file(id)?;
+        let mut state = self.state.lock().unwrap();
+        if self
+            .key_rejected_after
+            .is_some_and(|links| state.unrestricted >= links)
+        {
+            return Err(Error::Api {
+                context: "Failed to unrestrict link",
+                status: StatusCode::UNAUTHORIZED,
+                code: None,
+                message: "bad token".to_string(),
+            });
+        }
+        state.unrestricted += 1;
+        Ok(UnrestrictedLink {
+            filename: file.name().to_string(),
+            download: format!("{}/{}", self.download_base, file.name()),
+            filesize: Some(file.bytes),
+        })
+    }
+
+    async fn delete(&self, _torrent_id: &str) -> Result<()> {
+        self.state.lock().unwrap().deleted = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+//! Download status over MQTT, for Home Assistant.
+//!
+//! With a broker `url` in `[mqtt]`, each download gets a sensor through
+//! Home Assistant's MQTT discovery while it runs: its state is the percent
+//! done, with the status, sizes and speed as attributes, updated every few
+//! seconds. Once the download finishes its last state is published and the
+//! sensor removed again. An `lj active downloads` sensor counts the
+//! downloads still pending or running, and every lifecycle event is also
+//! published as JSON to `<topic>/events` for automations.
+//!
+//! Only the little of MQTT 3.1.1 this needs is spoken here: a connection
+//! per process with no keep-alive, and QoS 0 publishes, retained for
+//! discovery and state so Home Assistant picks them up after a restart.
+
+use crate::config::MqttConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::notify::Notifier;
+use crate::store::{self, DownloadStatus};
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Port used when the broker URL has none.
+const DEFAULT_PORT: u16 = 1883;
+
+/// How often a running download's sensor is updated.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Mqtt {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    topic: String,
+    discovery_prefix: String,
+    connection: tokio::sync::Mutex<Option<TcpStream>>,
+    /// When each running download's sensor was last updated.
+    last_progress: Mutex<HashMap<String, Instant>>,
+}
+
+/// One message to publish.
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+}
+
+impl Message {
+    fn new(topic: String, payload: impl Into<Vec<u8>>, retain: bool) -> Self {
+        Message {
+            topic,
+            payload: payload.into(),
+            retain,
+        }
+    }
+}
+
+/// Appends an MQTT "remaining length".
+fn put_length(buf: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+/// Appends a length-prefixed string.
+fn put_str(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s);
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![kind];
+    put_length(&mut buf, body.len());
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// `messages` as QoS 0 PUBLISH packets.
+fn encode(messages: &[Message]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for message in messages {
+        let mut body = Vec::new();
+        put_str(&mut body, message.topic.as_bytes());
+        body.extend_from_slice(&message.payload);
+        bytes.extend(packet(0x30 | message.retain as u8, &body));
+    }
+    bytes
+}
+
+/// Characters Home Assistant allows in discovery topics and ids.
+fn object_id(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl Mqtt {
+    /// The publisher described by `config`, if it has a broker URL.
+    pub fn from_config(config: &MqttConfig) -> Option<Self> {
+        Some(Mqtt {
+            url: config.url.clone()?,
+            username: config.username.clone(),
+            password: config.password.clone(),
+            topic: config
+                .topic
+                .as_deref()
+                .unwrap_or("lj")
+                .trim_end_matches('/')
+                .to_string(),
+            discovery_prefix: config
+                .discovery_prefix
+                .as_deref()
+                .unwrap_or("homeassistant")
+                .trim_end_matches('/')
+                .to_string(),
+            connection: tokio::sync::Mutex::new(None),
+            last_progress: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn connect(&self) -> Result<TcpStream> {
+        let url = Url::parse(&self.url)
+            .ok()
+            .filter(|url| url.scheme() == "mqtt")
+            .ok_or_else(|| Error::Config(format!("invalid MQTT broker '{}'", self.url)))?;
+        let host = url.host_str().unwrap_or("localhost");
+        let port = url.port().unwrap_or(DEFAULT_PORT);
+        let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| Error::Timeout("the MQTT broker"))??;
+
+        // Clean session, no keep-alive
+        let mut flags = 0x02;
+        let mut body = Vec::new();
+        put_str(&mut body, b"MQTT");
+        body.push(4);
+        let flags_at = body.len();
+        body.push(0);
+        body.extend_from_slice(&0u16.to_be_bytes());
+        put_str(&mut body, format!("lj-{}", std::process::id()).as_bytes());
+        if let Some(username) = &self.username {
+            flags |= 0x80;
+            put_str(&mut body, username.as_bytes());
+            if let Some(password) = &self.password {
+                flags |= 0x40;
+                put_str(&mut body, password.as_bytes());
+            }
+        }
+        body[flags_at] = flags;
+        stream.write_all(&packet(0x10, &body)).await?;
+
+        let mut ack = [0u8; 4];
+        tokio::time::timeout(CONNECT_TIMEOUT, stream.read_exact(&mut ack))
+            .await
+            .map_err(|_| Error::Timeout("the MQTT broker"))??;
+        if ack[0] != 0x20 {
+            return Err(Error::Other("MQTT broker sent no CONNACK".to_string()));
+        }
+        let reason = match ack[3] {
+            0 => return Ok(stream),
+            1 => "unsupported protocol version",
+            2 => "client id rejected",
+            3 => "server unavailable",
+            4 => "bad username or password",
+            5 => "not authorized",
+            _ => "unknown error",
+        };
+        Err(Error::Other(format!(
+            "MQTT broker refused the connection: {}",
+            reason
+        )))
+    }
+
+    /// Publishes `messages`, reconnecting once if the connection dropped.
+    async fn publish(&self, messages: &[Message]) -> Result<()> {
+        let bytes = encode(messages);
+        let mut connection = self.connection.lock().await;
+        if let Some(stream) = connection.as_mut()
+            && stream.write_all(&bytes).await.is_ok()
+        {
+            return Ok(());
+        }
+        *connection = None;
+        let mut stream = self.connect().await?;
+        stream.write_all(&encode(&[self.active_sensor()])).await?;
+        stream.write_all(&bytes).await?;
+        *connection = Some(stream);
+        Ok(())
+    }
+
+    fn device(&self) -> Value {
+        json!({ "identifiers": [self.topic], "name": "lj", "model": "lj" })
+    }
+
+    /// Discovery of the sensor counting active downloads, sent on each
+    /// connection.
+    fn active_sensor(&self) -> Message {
+        let config = json!({
+            "name": "Active downloads",
+            "unique_id": format!("{}_active", object_id(&self.topic)),
+            "state_topic": format!("{}/active", self.topic),
+            "icon": "mdi:download-multiple",
+            "device": self.device(),
+        });
+        Message::new(self.discovery_topic("active"), config.to_string(), true)
+    }
+
+    fn discovery_topic(&self, object: &str) -> String {
+        format!(
+            "{}/sensor/{}/{}/config",
+            self.discovery_prefix,
+            object_id(&self.topic),
+            object
+        )
+    }
+
+    fn state_topic(&self, object: &str) -> String {
+        format!("{}/downloads/{}", self.topic, object)
+    }
+
+    /// The messages that report `event`.
+    fn messages(&self, event: &DownloadEvent) -> Vec<Message> {
+        let object = object_id(event.id());
+        let Some(dl) = store::load_download(event.id()) else {
+            return Vec::new();
+        };
+        let (downloaded, total, speed) = match event {
+            DownloadEvent::Progress {
+                downloaded_bytes,
+                total_bytes,
+                speed,
+                ..
+            } => (*downloaded_bytes, *total_bytes, *speed),
+            _ => (dl.downloaded_bytes, dl.total_bytes, dl.speed),
+        };
+        let progress = match (&dl.status, total) {
+            (DownloadStatus::Completed, _) => 100,
+            (_, 0) => 0,
+            _ => (downloaded * 100 / total).min(100),
+        };
+        let error = match &dl.status {
+            DownloadStatus::Failed(e) => Some(e.clone()),
+            _ => None,
+        };
+        let state = json!({
+            "progress": progress,
+            "status": event.kind(),
+            "filename": dl.filename,
+            "downloaded_bytes": downloaded,
+            "total_bytes": total,
+            "speed": speed as u64,
+            "torrent": dl.torrent,
+            "error": error,
+        });
+
+        let mut messages = Vec::new();
+        if let DownloadEvent::Started { .. } = event {
+            let config = json!({
+                "name": dl.filename,
+                "unique_id": format!("{}_{}", object_id(&self.topic), object),
+                "state_topic": self.state_topic(&object),
+                "value_template": "{{ value_json.progress }}",
+                "json_attributes_topic": self.state_topic(&object),
+                "unit_of_measurement": "%",
+                "icon": "mdi:download",
+                "device": self.device(),
+            });
+            messages.push(Message::new(
+                self.discovery_topic(&object),
+                config.to_string(),
+                true,
+            ));
+        }
+        let finished = dl.status.is_finished() && !matches!(event, DownloadEvent::Progress { .. });
+        messages.push(Message::new(
+            self.state_topic(&object),
+            state.to_string(),
+            !finished,
+        ));
+        if finished {
+            // Empty retained payloads remove the sensor and its state
+            messages.push(Message::new(self.discovery_topic(&object), "", true));
+            messages.push(Message::new(self.state_topic(&object), "", true));
+        }
+        if !matches!(event, DownloadEvent::Progress { .. }) {
+            let active = store::load_downloads_with_status(&["pending", "downloading"]).len();
+            messages.push(Message::new(
+                format!("{}/active", self.topic),
+                active.to_string(),
+                true,
+            ));
+            let payload = serde_json::to_string(event).unwrap_or_default();
+            messages.push(Message::new(
+                format!("{}/events", self.topic),
+                payload,
+                false,
+            ));
+        }
+        messages
+    }
+
+    /// Whether a progress update for `id` is due, noting it if so.
+    fn progress_due(&self, id: &str) -> bool {
+        let mut last = self.last_progress.lock().unwrap();
+        let now = Instant::now();
+        if last
+            .get(id)
+            .is_some_and(|at| now.duration_since(*at) < PROGRESS_INTERVAL)
+        {
+            return false;
+        }
+        last.insert(id.to_string(), now);
+        true
+    }
+}
+
+#[async_trait]
+impl Notifier for Mqtt {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn wants(&self, _event: &DownloadEvent) -> bool {
+        true
+    }
+
+    fn wants_progress(&self) -> bool {
+        true
+    }
+
+    async fn send(&self, _client: &Client, event: &DownloadEvent) -> Result<()> {
+        match event {
+            DownloadEvent::Progress { id, .. } if !self.progress_due(id) => return Ok(()),
+            DownloadEvent::Started { id, .. } => {
+                self.last_progress
+                    .lock()
+                    .unwrap()
+                    .insert(id.clone(), Instant::now());
+            }
+            DownloadEvent::Progress { .. } => {}
+            _ => {
+                self.last_progress.lock().unwrap().remove(event.id());
+            }
+        }
+        let messages = self.messages(event);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        self.publish(&messages).await
+    }
+}
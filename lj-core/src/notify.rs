@@ -0,0 +1,228 @@
+//! Notifications for download lifecycle events.
+//!
+//! Once [`start`]ed, every event except progress ticks is queued for the
+//! notifiers configured in `config.toml` ([`webhook`](crate::webhook),
+//! [`ntfy`](crate::ntfy), [`discord`](crate::discord),
+//! [`telegram`](crate::telegram)), so emitting one never waits on the
+//! network. [`hooks`](crate::hooks), media server refreshes
+//! ([`jellyfin`](crate::jellyfin), [`plex`](crate::plex),
+//! [`kodi`](crate::kodi)), [health check pings](crate::healthchecks) and
+//! [MQTT](crate::mqtt) ride the same queue, the last also getting progress
+//! ticks. Each notifier only gets the events it asks for;
+//! deliveries are retried with backoff on network errors, 429 and 5xx
+//! responses and logged if they still fail.
+
+use crate::config::Config;
+use crate::discord::Discord;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::format::{format_bytes, format_size};
+use crate::healthchecks::Healthchecks;
+use crate::hooks::Hooks;
+use crate::jellyfin::Jellyfin;
+use crate::kodi::Kodi;
+use crate::logging::{self, Level};
+use crate::mqtt::Mqtt;
+use crate::ntfy::Ntfy;
+use crate::plex::Plex;
+use crate::store;
+use crate::telegram::Telegram;
+use crate::upload::Upload;
+use crate::webhook::Webhook;
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use reqwest::{Client, StatusCode};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// Delivery attempts per event and notifier, the first included.
+const ATTEMPTS: u32 = 4;
+
+/// A destination for lifecycle events.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Whether this notifier wants events like `event`.
+    fn wants(&self, event: &DownloadEvent) -> bool;
+
+    /// Whether this notifier also wants progress ticks, which are only
+    /// queued if one does.
+    fn wants_progress(&self) -> bool {
+        false
+    }
+
+    /// Delivers one event. Called again on retryable errors.
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()>;
+}
+
+/// The notifiers configured in `config`.
+pub fn notifiers(config: &Config) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Some(webhook) = Webhook::from_config(&config.webhook) {
+        notifiers.push(Box::new(webhook));
+    }
+    if let Some(ntfy) = Ntfy::from_config(&config.ntfy) {
+        notifiers.push(Box::new(ntfy));
+    }
+    if let Some(discord) = Discord::from_config(&config.discord) {
+        notifiers.push(Box::new(discord));
+    }
+    if let Some(telegram) = Telegram::from_config(&config.telegram) {
+        notifiers.push(Box::new(telegram));
+    }
+    if let Some(hooks) = Hooks::from_config(
+        &config.hooks,
+        &config.extract,
+        Upload::from_config(&config.upload, config.download_dir.as_deref()),
+    ) {
+        notifiers.push(Box::new(hooks));
+    }
+    if let Some(jellyfin) = Jellyfin::from_config(&config.jellyfin) {
+        notifiers.push(Box::new(jellyfin));
+    }
+    if let Some(plex) = Plex::from_config(&config.plex) {
+        notifiers.push(Box::new(plex));
+    }
+    if let Some(kodi) = Kodi::from_config(&config.kodi) {
+        notifiers.push(Box::new(kodi));
+    }
+    if let Some(healthchecks) = Healthchecks::from_config(&config.healthchecks) {
+        notifiers.push(Box::new(healthchecks));
+    }
+    if let Some(mqtt) = Mqtt::from_config(&config.mqtt) {
+        notifiers.push(Box::new(mqtt));
+    }
+    notifiers
+}
+
+struct Queue {
+    sender: UnboundedSender<DownloadEvent>,
+    task: JoinHandle<()>,
+    /// Whether any notifier wants progress ticks.
+    progress: bool,
+}
+
+static QUEUE: Mutex<Option<Queue>> = Mutex::new(None);
+
+/// Starts delivering events to the notifiers configured in `config`, if
+/// any. Must be called inside a Tokio runtime.
+pub fn start(config: &Config, client: &Client) {
+    let notifiers = notifiers(config);
+    if notifiers.is_empty() {
+        return;
+    }
+    let progress = notifiers.iter().any(|n| n.wants_progress());
+    let (sender, mut receiver) = mpsc::unbounded_channel::<DownloadEvent>();
+    let client = client.clone();
+    let task = tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            let is_progress = matches!(event, DownloadEvent::Progress { .. });
+            let deliveries: Vec<_> = notifiers
+                .iter()
+                .filter(|n| n.wants(&event) && (!is_progress || n.wants_progress()))
+                .map(|n| deliver(n.as_ref(), &client, &event))
+                .collect();
+            join_all(deliveries).await;
+        }
+    });
+    *QUEUE.lock().unwrap() = Some(Queue {
+        sender,
+        task,
+        progress,
+    });
+}
+
+/// Queues `event` if notifications are running.
+pub(crate) fn enqueue(event: &DownloadEvent) {
+    if let Some(queue) = QUEUE.lock().unwrap().as_ref()
+        && (queue.progress || !matches!(event, DownloadEvent::Progress { .. }))
+    {
+        let _ = queue.sender.send(event.clone());
+    }
+}
+
+/// Stops accepting events and waits until the queued ones are delivered or
+/// have used up their retries. Short-lived processes call this before
+/// exiting.
+pub async fn finish() {
+    let queue = QUEUE.lock().unwrap().take();
+    if let Some(queue) = queue {
+        drop(queue.sender);
+        let _ = queue.task.await;
+    }
+}
+
+/// Whether `events` (from a config section) selects `event`, with
+/// `default` standing in for an empty list.
+pub(crate) fn selects(events: &[String], default: &[&str], event: &DownloadEvent) -> bool {
+    if events.is_empty() {
+        default.contains(&event.kind())
+    } else {
+        events.iter().any(|e| e == event.kind())
+    }
+}
+
+/// A one-line title and message for chat-style notifiers.
+pub(crate) fn describe(event: &DownloadEvent) -> (&'static str, String) {
+    let name = store::load_download(event.id())
+        .map(|dl| dl.filename)
+        .unwrap_or_else(|| event.id().to_string());
+    match event {
+        DownloadEvent::Started { total_bytes, .. } => (
+            "Download started",
+            format!("{} ({})", name, format_size(*total_bytes)),
+        ),
+        DownloadEvent::Progress { .. } => ("Download progress", name),
+        DownloadEvent::Completed { bytes, .. } => (
+            "Download completed",
+            format!("{} ({})", name, format_bytes(*bytes)),
+        ),
+        DownloadEvent::Failed { error, .. } => ("Download failed", format!("{}: {}", name, error)),
+        DownloadEvent::Cancelled { .. } => ("Download cancelled", name),
+    }
+}
+
+fn retryable(error: &Error) -> bool {
+    match error {
+        Error::Http { .. } => true,
+        Error::Status(status) | Error::Api { status, .. } => {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        _ => false,
+    }
+}
+
+async fn deliver(notifier: &dyn Notifier, client: &Client, event: &DownloadEvent) {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=ATTEMPTS {
+        let error = match notifier.send(client, event).await {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        if retryable(&error) && attempt < ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            continue;
+        }
+
+        logging::log(
+            Level::Warning,
+            &format!(
+                "{}: {} notification for {} failed: {}",
+                event.id(),
+                notifier.name(),
+                event.kind(),
+                error
+            ),
+            &[
+                ("LJ_DOWNLOAD_ID", event.id()),
+                ("LJ_EVENT", "notification_failed"),
+            ],
+        );
+        return;
+    }
+}
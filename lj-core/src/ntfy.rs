@@ -0,0 +1,79 @@
+//! Push notifications through [ntfy](https://ntfy.sh).
+//!
+//! Each event becomes a message on the `[ntfy]` section's `topic`, titled
+//! after what happened, so any device subscribed to the topic gets it.
+//! Failures are sent with high priority.
+
+use crate::config::NtfyConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::notify::{self, Notifier};
+use async_trait::async_trait;
+use reqwest::Client;
+
+/// Server used when the section names none.
+pub const DEFAULT_SERVER: &str = "https://ntfy.sh";
+
+/// Events sent when the section lists none.
+const DEFAULT_EVENTS: &[&str] = &["completed", "failed"];
+
+pub struct Ntfy {
+    url: String,
+    token: Option<String>,
+    events: Vec<String>,
+}
+
+impl Ntfy {
+    /// The ntfy topic described by `config`, if it names one.
+    pub fn from_config(config: &NtfyConfig) -> Option<Self> {
+        let topic = config.topic.as_deref()?;
+        let server = config.server.as_deref().unwrap_or(DEFAULT_SERVER);
+        Some(Ntfy {
+            url: format!("{}/{}", server.trim_end_matches('/'), topic),
+            token: config.token.clone(),
+            events: config.events.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        notify::selects(&self.events, DEFAULT_EVENTS, event)
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let context = "ntfy request failed";
+        let (title, message) = notify::describe(event);
+        let (priority, tags) = match event {
+            DownloadEvent::Completed { .. } => ("default", "white_check_mark"),
+            DownloadEvent::Failed { .. } => ("high", "x"),
+            _ => ("default", "arrow_down"),
+        };
+
+        let mut request = client
+            .post(&self.url)
+            .header("Title", title)
+            .header("Priority", priority)
+            .header("Tags", tags)
+            .body(message);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let resp = request.send().await.map_err(Error::http(context))?;
+        if !resp.status().is_success() {
+            return Err(Error::Api {
+                context,
+                status: resp.status(),
+                code: None,
+                message: resp.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,460 @@
+//! Helpers for the magnet → files → links pipeline.
+
+use crate::config::SelectConfig;
+use crate::error::{Error, Result};
+use crate::http;
+use crate::magnet::Magnet;
+use crate::platform::sanitize_filename;
+use crate::provider::{DebridProvider, TorrentFile, TorrentStatus};
+use crate::store::{self, Download};
+use crate::torrent::{self, Source};
+use futures_util::{StreamExt, stream};
+use reqwest::{Client, StatusCode, header};
+use std::collections::BTreeMap;
+use std::process;
+
+/// An unrestricted, directly downloadable file.
+#[derive(Debug, Clone)]
+pub struct ResolvedLink {
+    pub filename: String,
+    pub url: String,
+    pub size: u64,
+    /// Id of the record [`resolve_links`] saved for it, if it was staged.
+    pub staged: Option<String>,
+}
+
+impl ResolvedLink {
+    /// A new pending record for this link in `target_dir`, taking over the
+    /// one saved for it when it was staged.
+    pub fn download(&self, target_dir: &str) -> Download {
+        let mut download = Download::new(&self.filename, &self.url, target_dir, self.size);
+        if let Some(id) = &self.staged {
+            download.id = id.clone();
+            download.set_process(process::id());
+        }
+        download
+    }
+}
+
+/// Where [`resolve_links`] saves each link as soon as it is unrestricted: a
+/// pending record held by this process until a worker takes it over, so the
+/// links outlive a crash before the downloads start, when the torrent may
+/// already be gone from the service. Records whose process died holding
+/// them are started by [`supervise`](crate::worker::supervise).
+#[derive(Debug, Clone)]
+pub struct Staging {
+    pub target_dir: String,
+    pub batch: String,
+    pub torrent: Option<String>,
+    pub info_hash: Option<String>,
+}
+
+impl Staging {
+    /// Staging for the files of the torrent `magnet` adds, named `torrent`.
+    pub fn new(target_dir: &str, torrent: Option<&str>, magnet: &str) -> Self {
+        Staging {
+            target_dir: target_dir.to_string(),
+            batch: store::new_batch_id(),
+            torrent: torrent.map(str::to_string),
+            info_hash: torrent::info_hash(magnet),
+        }
+    }
+
+    fn save(&self, link: &ResolvedLink) -> Result<String> {
+        let mut download = link
+            .download(&self.target_dir)
+            .in_batch(&self.batch, self.torrent.as_deref())
+            .of_torrent(self.info_hash.clone());
+        download.set_process(process::id());
+        store::save_download(&download)?;
+        Ok(download.id)
+    }
+}
+
+/// What [`fetch_magnet`] got out of a torrent.
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    /// The torrent's name, if the magnet or the service had one.
+    pub name: Option<String>,
+    pub links: Vec<ResolvedLink>,
+}
+
+impl Fetched {
+    /// Deletes the records staged for the links, which won't be downloaded
+    /// after all.
+    pub fn discard(&self) {
+        discard(&self.links);
+    }
+}
+
+/// Deletes the records staged for `links`.
+fn discard(links: &[ResolvedLink]) {
+    for id in links.iter().filter_map(|link| link.staged.as_ref()) {
+        store::delete_download(id);
+    }
+}
+
+/// Files worth offering for selection: drops samples and anything under 1 MB.
+pub fn candidate_files(files: &[TorrentFile]) -> Vec<TorrentFile> {
+    files.iter().filter(|f| is_candidate(f)).cloned().collect()
+}
+
+fn is_candidate(file: &TorrentFile) -> bool {
+    !file.path.to_lowercase().contains("sample") && file.bytes > 1_000_000
+}
+
+/// [`candidate_files`] that pass the `[select]` filters. When the filters
+/// leave nothing, all candidates are kept: before a whole-torrent service
+/// is done, its only entry is the torrent itself, so the filters are applied
+/// again to the resolved links (see [`filter_links`]). Episodes asked for
+/// with `--episodes` are the exception: a file must be one of them, however
+/// few files there are.
+///
+/// Both are applied in one pass over the list, copying only the files that
+/// make it, which matters for torrents of tens of thousands of files.
+///
+/// ```
+/// use lj_core::config::SelectConfig;
+/// use lj_core::pipeline::selectable_files;
+/// use lj_core::provider::TorrentFile;
+///
+/// let file = |path: &str| TorrentFile {
+///     id: 1,
+///     path: path.to_string(),
+///     bytes: 500_000_000,
+///     selected: 0,
+/// };
+/// let select = SelectConfig {
+///     episodes: Some("S01E02".parse().unwrap()),
+///     ..SelectConfig::default()
+/// };
+///
+/// // A single file that isn't one of the episodes isn't taken anyway
+/// assert!(selectable_files(&[file("Show.S01E01.mkv")], &select).is_empty());
+/// assert_eq!(selectable_files(&[file("Show.S01E02.mkv")], &select).len(), 1);
+/// // Nor is the whole torrent when nothing is left to choose from
+/// assert!(selectable_files(&[], &select).is_empty());
+/// assert!(selectable_files(&[file("Show.S01E01.sample.mkv")], &select).is_empty());
+///
+/// // Other filters fall back to every candidate
+/// let select = SelectConfig {
+///     include: vec![".mp4".to_string()],
+///     ..SelectConfig::default()
+/// };
+/// assert_eq!(selectable_files(&[file("Show.S01E01.mkv")], &select).len(), 1);
+/// ```
+pub fn selectable_files(files: &[TorrentFile], select: &SelectConfig) -> Vec<TorrentFile> {
+    let matches = select.matcher();
+    let filtered: Vec<TorrentFile> = files
+        .iter()
+        .filter(|f| is_candidate(f) && matches(f.name()))
+        .cloned()
+        .collect();
+    if filtered.is_empty() && select.episodes.is_none() {
+        candidate_files(files)
+    } else {
+        filtered
+    }
+}
+
+/// What [`group_files`] puts files together by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Folder,
+    Extension,
+}
+
+/// Files sharing a folder or an extension, to be picked all at once.
+#[derive(Debug, Clone, Default)]
+pub struct FileGroup {
+    /// The folder, `/` for the top of the torrent, or the lowercased
+    /// extension, empty for files without one.
+    pub label: String,
+    /// Indices into the list the group was made from.
+    pub files: Vec<usize>,
+    pub bytes: u64,
+}
+
+/// Groups `files` by the folder they are in or their extension, sorted by
+/// label.
+///
+/// ```
+/// use lj_core::pipeline::{Grouping, group_files};
+/// use lj_core::provider::TorrentFile;
+///
+/// let file = |id, path: &str| TorrentFile {
+///     id,
+///     path: path.to_string(),
+///     bytes: 10,
+///     selected: 0,
+/// };
+/// let files = [
+///     file(1, "/Show/S01/E01.mkv"),
+///     file(2, "/Show/S01/E01.srt"),
+///     file(3, "/Show/S02/E01.MKV"),
+/// ];
+///
+/// let folders = group_files(&files, Grouping::Folder);
+/// assert_eq!(folders[0].label, "Show/S01");
+/// assert_eq!(folders[0].files, [0, 1]);
+///
+/// let extensions = group_files(&files, Grouping::Extension);
+/// assert_eq!(extensions[0].label, "mkv");
+/// assert_eq!(extensions[0].bytes, 20);
+/// ```
+pub fn group_files(files: &[TorrentFile], by: Grouping) -> Vec<FileGroup> {
+    let mut groups: BTreeMap<String, FileGroup> = BTreeMap::new();
+    for (i, file) in files.iter().enumerate() {
+        let label = match by {
+            Grouping::Folder => match file.path.trim_start_matches('/').rsplit_once('/') {
+                Some((folder, _)) => folder.to_string(),
+                None => "/".to_string(),
+            },
+            Grouping::Extension => match file.name().rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+                _ => String::new(),
+            },
+        };
+        let group = groups.entry(label).or_default();
+        group.files.push(i);
+        group.bytes += file.bytes;
+    }
+    groups
+        .into_iter()
+        .map(|(label, group)| FileGroup { label, ..group })
+        .collect()
+}
+
+/// Drops links whose file name fails the `[select]` filters, with the
+/// records staged for them.
+pub fn filter_links(links: Vec<ResolvedLink>, select: &SelectConfig) -> Vec<ResolvedLink> {
+    let matches = select.matcher();
+    links
+        .into_iter()
+        .filter(|link| {
+            let keep = matches(&link.filename);
+            if !keep && let Some(id) = &link.staged {
+                store::delete_download(id);
+            }
+            keep
+        })
+        .collect()
+}
+
+/// Size of a download as reported by a HEAD request, or 0.
+pub async fn content_length(client: &Client, url: &str) -> u64 {
+    let head = client
+        .head(url)
+        .header(header::ACCEPT_ENCODING, "identity")
+        .send()
+        .await;
+    match head {
+        Ok(resp) if resp.status().is_success() && http::content_coding(&resp).is_none() => resp
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Size of a download from a GET for its first byte, for hosts that refuse
+/// HEAD: the total in `Content-Range`, or the length of the whole file if
+/// the host ignores the range. 0 if neither says.
+pub async fn probe_length(client: &Client, url: &str) -> u64 {
+    let resp = match client
+        .get(url)
+        .header(header::RANGE, "bytes=0-0")
+        .header(header::ACCEPT_ENCODING, "identity")
+        .send()
+        .await
+    {
+        Ok(resp) if http::content_coding(&resp).is_none() => resp,
+        _ => return 0,
+    };
+    match resp.status() {
+        StatusCode::PARTIAL_CONTENT => resp
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit_once('/'))
+            .and_then(|(_, total)| total.parse().ok())
+            .unwrap_or(0),
+        status if status.is_success() => resp.content_length().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+async fn resolve_link(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    link: &str,
+) -> Result<ResolvedLink> {
+    let unrestricted = provider.unrestrict(link).await?;
+    // Some hosts reject HEAD; the service's own figure saves a request
+    let mut size = content_length(client, &unrestricted.download).await;
+    if size == 0 {
+        size = unrestricted.filesize.unwrap_or(0);
+    }
+    if size == 0 {
+        size = probe_length(client, &unrestricted.download).await;
+    }
+    Ok(ResolvedLink {
+        filename: unrestricted.filename,
+        url: unrestricted.download,
+        size,
+        staged: None,
+    })
+}
+
+/// How many links are unrestricted and probed at once.
+const RESOLVE_CONCURRENCY: usize = 4;
+
+/// Unrestricts each hoster link and probes its size, a few at a time. Links
+/// that fail are returned as errors alongside the successful ones; both keep
+/// the order of `links`. A rejected API key stops it with that error. With
+/// `staging`, each link is saved as a pending download the moment it is
+/// unrestricted; if it stops with an error, those saved are deleted again.
+pub async fn resolve_links(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    links: &[String],
+    staging: Option<&Staging>,
+) -> Result<(Vec<ResolvedLink>, Vec<Error>)> {
+    // Collected first: a lazy `map` adapter here makes the future not `Send`
+    // for callers that spawn it (rust-lang/rust#102211)
+    let pending: Vec<_> = links
+        .iter()
+        .map(|link| resolve_link(provider, client, link))
+        .collect();
+    let mut results = stream::iter(pending).buffered(RESOLVE_CONCURRENCY);
+
+    let mut resolved = Vec::new();
+    let mut errors = Vec::new();
+    let failed = loop {
+        match results.next().await {
+            None => break None,
+            Some(Ok(mut link)) => {
+                if let Some(staging) = staging {
+                    match staging.save(&link) {
+                        Ok(id) => link.staged = Some(id),
+                        Err(e) => break Some(e),
+                    }
+                }
+                resolved.push(link)
+            }
+            // The other links would be refused the same way
+            Some(Err(e)) if e.is_auth() => break Some(e),
+            Some(Err(e)) => errors.push(e),
+        }
+    };
+    if let Some(e) = failed {
+        discard(&resolved);
+        return Err(e);
+    }
+
+    Ok((resolved, errors))
+}
+
+/// The torrent's name: the magnet's `dn` or, failing that, the name the
+/// service gave it. Asks the service, so call it before the torrent is
+/// deleted.
+pub async fn torrent_name(
+    provider: &dyn DebridProvider,
+    torrent_id: &str,
+    magnet: &str,
+) -> Option<String> {
+    match Magnet::parse(magnet).ok().and_then(|magnet| magnet.name) {
+        Some(name) => Some(name),
+        None => provider.poll(torrent_id).await.ok()?.name,
+    }
+}
+
+/// Where the files of a torrent go under `target_dir`: a folder named after
+/// the torrent when there are several of them, so they aren't left loose,
+/// or `target_dir` itself.
+///
+/// ```
+/// use lj_core::pipeline::torrent_dir;
+///
+/// assert_eq!(torrent_dir("/dl", Some("Show/S01"), 8), "/dl/Show_S01");
+/// assert_eq!(torrent_dir("/dl", Some("Show/S01"), 1), "/dl");
+/// assert_eq!(torrent_dir("/dl", None, 8), "/dl");
+/// ```
+pub fn torrent_dir(target_dir: &str, name: Option<&str>, files: usize) -> String {
+    match name.map(sanitize_filename) {
+        Some(name) if files > 1 => std::path::Path::new(target_dir)
+            .join(name)
+            .to_string_lossy()
+            .to_string(),
+        _ => target_dir.to_string(),
+    }
+}
+
+/// Runs a magnet through the provider without asking the user anything:
+/// every [selectable](selectable_files) file is selected, or the whole
+/// torrent if there are none. `on_progress` sees each poll while the service works.
+/// Links that fail to resolve are dropped; with a `target_dir` the others
+/// are [staged](Staging) there. The remote torrent is deleted once its
+/// links are out.
+pub async fn fetch_magnet(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    magnet: &str,
+    select: &SelectConfig,
+    target_dir: Option<&str>,
+    on_progress: &mut (dyn for<'a> FnMut(&'a TorrentStatus) + Send),
+) -> Result<Fetched> {
+    let source = Source::Magnet(magnet.to_string());
+    fetch_source(provider, client, &source, select, target_dir, on_progress).await
+}
+
+/// [`fetch_magnet`] for a magnet or an uploaded `.torrent` file.
+pub async fn fetch_source(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    source: &Source,
+    select: &SelectConfig,
+    target_dir: Option<&str>,
+    on_progress: &mut (dyn for<'a> FnMut(&'a TorrentStatus) + Send),
+) -> Result<Fetched> {
+    let torrent_id = source.add(provider).await?;
+    let files = provider.wait_for_files(&torrent_id).await?;
+
+    let candidates = selectable_files(&files, select);
+    let selected: Vec<u32> = if candidates.is_empty() {
+        files.iter().map(|f| f.id).collect()
+    } else {
+        candidates.iter().map(|f| f.id).collect()
+    };
+    if selected.is_empty() {
+        let _ = provider.delete(&torrent_id).await;
+        return Err(Error::NoFiles);
+    }
+
+    provider.select_files(&torrent_id, &selected).await?;
+    let links = match provider.wait_for_download(&torrent_id, on_progress).await {
+        Ok(links) => links,
+        // Nobody to ask whether to keep waiting
+        Err(e @ Error::Stalled(_)) => {
+            let _ = provider.delete(&torrent_id).await;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    let name = torrent_name(provider, &torrent_id, source.magnet()).await;
+    let staging = target_dir.map(|dir| Staging::new(dir, name.as_deref(), source.magnet()));
+    let resolved = resolve_links(provider, client, &links, staging.as_ref()).await;
+    let _ = provider.delete(&torrent_id).await;
+    let (resolved, _) = resolved?;
+
+    if resolved.is_empty() {
+        return Err(Error::NoLinks);
+    }
+    let links = filter_links(resolved, select);
+    if links.is_empty() {
+        return Err(Error::NothingSelected);
+    }
+    Ok(Fetched { name, links })
+}
@@ -0,0 +1,323 @@
+//! Platform-specific process management and path handling.
+
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(unix)]
+mod imp {
+    use nix::sys::signal::{self, Signal};
+    use nix::unistd::Pid;
+    use std::io;
+    use std::process::Command;
+
+    pub fn process_alive(pid: u32) -> bool {
+        signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+    }
+
+    /// Field 22 of `/proc/<pid>/stat`, in clock ticks since boot.
+    #[cfg(target_os = "linux")]
+    pub fn process_start_time(pid: u32) -> Option<u64> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name in field 2 may contain spaces and parentheses
+        let rest = &stat[stat.rfind(')')? + 1..];
+        rest.split_whitespace().nth(19)?.parse().ok()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn process_start_time(_pid: u32) -> Option<u64> {
+        None
+    }
+
+    pub fn terminate(pid: u32) -> io::Result<()> {
+        signal::kill(Pid::from_raw(pid as i32), Signal::SIGTERM).map_err(io::Error::from)
+    }
+
+    pub fn detach(_cmd: &mut Command) {}
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+    use std::os::windows::process::CommandExt;
+    use std::process::Command;
+    use windows_sys::Win32::Foundation::{CloseHandle, FILETIME, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{
+        CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW, DETACHED_PROCESS, GetExitCodeProcess,
+        GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE,
+        TerminateProcess,
+    };
+
+    pub fn process_alive(pid: u32) -> bool {
+        unsafe {
+            let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if handle.is_null() {
+                return false;
+            }
+            let mut code = 0u32;
+            let ok = GetExitCodeProcess(handle, &mut code) != 0;
+            CloseHandle(handle);
+            ok && code == STILL_ACTIVE as u32
+        }
+    }
+
+    /// Creation time as a `FILETIME`, in 100 ns units since 1601.
+    pub fn process_start_time(pid: u32) -> Option<u64> {
+        unsafe {
+            let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if handle.is_null() {
+                return None;
+            }
+            let zero = FILETIME {
+                dwLowDateTime: 0,
+                dwHighDateTime: 0,
+            };
+            let (mut created, mut exited, mut kernel, mut user) = (zero, zero, zero, zero);
+            let ok = GetProcessTimes(handle, &mut created, &mut exited, &mut kernel, &mut user);
+            CloseHandle(handle);
+            (ok != 0).then(|| (created.dwHighDateTime as u64) << 32 | created.dwLowDateTime as u64)
+        }
+    }
+
+    pub fn terminate(pid: u32) -> io::Result<()> {
+        unsafe {
+            let handle = OpenProcess(PROCESS_TERMINATE, 0, pid);
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let ok = TerminateProcess(handle, 1) != 0;
+            CloseHandle(handle);
+            if ok {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+    }
+
+    pub fn detach(cmd: &mut Command) {
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP | CREATE_NO_WINDOW);
+    }
+}
+
+/// Whether a process with this id is still running.
+pub fn process_alive(pid: u32) -> bool {
+    imp::process_alive(pid)
+}
+
+/// An opaque token for when the process started, or `None` where the
+/// platform cannot tell (currently Unix other than Linux).
+///
+/// Together with the pid it identifies a process even after the pid is
+/// recycled.
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    imp::process_start_time(pid)
+}
+
+/// Whether `pid` is still the process that was recorded with start time
+/// `started`. Without a recorded start time this falls back to
+/// [`process_alive`].
+pub fn is_same_process(pid: u32, started: Option<u64>) -> bool {
+    if !process_alive(pid) {
+        return false;
+    }
+    match started {
+        Some(started) => process_start_time(pid) == Some(started),
+        None => true,
+    }
+}
+
+/// Asks the process to exit (SIGTERM on Unix, TerminateProcess on Windows).
+pub fn terminate(pid: u32) -> io::Result<()> {
+    imp::terminate(pid)
+}
+
+/// Configures a command so the child outlives the terminal it was started
+/// from.
+pub fn detach(cmd: &mut Command) {
+    imp::detach(cmd)
+}
+
+/// Longest file name most filesystems accept, in bytes.
+pub const NAME_MAX: usize = 255;
+
+/// Longest path the OS accepts, in bytes; Windows' limit without long path
+/// support is in characters, so this stays under it too.
+pub const PATH_MAX: usize = if cfg!(windows) { 260 } else { 4096 };
+
+/// Replaces characters the local filesystem cannot store in a file name.
+///
+/// Hoster filenames are chosen by uploaders and routinely contain `:` or `?`,
+/// which Windows rejects; path separators are replaced everywhere. Names
+/// over [`NAME_MAX`] are shortened (see [`fit_filename`]).
+pub fn sanitize_filename(name: &str) -> String {
+    let reserved: &[char] = if cfg!(windows) {
+        &['<', '>', ':', '"', '/', '\\', '|', '?', '*']
+    } else {
+        &['/']
+    };
+
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if reserved.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Windows also refuses trailing dots and spaces
+    let cleaned = if cfg!(windows) {
+        cleaned.trim_end_matches(['.', ' ']).to_string()
+    } else {
+        cleaned
+    };
+
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        "download".to_string()
+    } else {
+        fit_filename(&cleaned, NAME_MAX)
+    }
+}
+
+/// Shortens `name` to at most `max` bytes by cutting out the middle of it,
+/// where release names are least telling. The cut is marked with a hash of
+/// the whole name, so two long names that share their ends stay apart, and
+/// the extension is kept.
+///
+/// ```
+/// use lj_core::platform::fit_filename;
+///
+/// assert_eq!(fit_filename("Show.S01E01.mkv", 255), "Show.S01E01.mkv");
+///
+/// let long = format!("Show.S01E01.{}.1080p.WEB-DL-GRP.mkv", "Episode.Title.".repeat(30));
+/// let short = fit_filename(&long, 100);
+/// assert!(short.len() <= 100);
+/// assert!(short.starts_with("Show.S01E01."));
+/// assert!(short.ends_with("1080p.WEB-DL-GRP.mkv"));
+/// assert_ne!(short, fit_filename(&long.replace("S01E01", "S01E02"), 100));
+/// ```
+pub fn fit_filename(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+    let ext = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().len() + 1)
+        .filter(|&len| len <= 16)
+        .unwrap_or(0);
+    let (stem, ext) = name.split_at(name.len() - ext);
+    let hash: String = Sha256::digest(name.as_bytes())
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let marker = format!("~{}~", hash);
+
+    let room = max.saturating_sub(ext.len() + marker.len());
+    let head = stem.floor_char_boundary(room * 2 / 3);
+    let tail = stem.ceil_char_boundary(stem.len() - (room - head));
+    let short = format!("{}{}{}{}", &stem[..head], marker, &stem[tail..], ext);
+    if short.len() <= max {
+        short
+    } else {
+        // Not even the extension fits
+        hash.chars().take(max.max(1)).collect()
+    }
+}
+
+/// Moves the file `from` to `to`. Renaming fails between filesystems, e.g. a
+/// scratch disk and a media library, so the file is then copied, flushed to
+/// disk and only then removed, with `progress` called with the bytes copied
+/// so far and the file's size. A failed copy leaves `from` untouched.
+pub fn move_file(from: &Path, to: &Path, progress: &mut dyn FnMut(u64, u64)) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        result => return result,
+    }
+    let total = fs::metadata(from)?.len();
+    let mut copied = 0;
+    copy_file(from, to, &mut |n| {
+        copied += n;
+        progress(copied, total);
+    })?;
+    fs::remove_file(from)
+}
+
+/// Moves the directory `from` to `to` like [`move_file`], copying the whole
+/// tree when the two are on different filesystems.
+pub fn move_dir(from: &Path, to: &Path, progress: &mut dyn FnMut(u64, u64)) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        result => return result,
+    }
+    let mut files = Vec::new();
+    list_files(from, &mut files)?;
+    let mut total = 0;
+    for file in &files {
+        total += fs::metadata(file)?.len();
+    }
+    let mut copied = 0;
+    let result = fs::create_dir_all(to).and_then(|_| {
+        files.iter().try_for_each(|file| {
+            let dest = to.join(file.strip_prefix(from).unwrap_or(file));
+            if let Some(dir) = dest.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            copy_file(file, &dest, &mut |n| {
+                copied += n;
+                progress(copied, total);
+            })
+        })
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(to);
+        return Err(e);
+    }
+    fs::remove_dir_all(from)
+}
+
+/// Copies `from` to `to` through a temporary file beside `to`, synced
+/// before it takes `to`'s name, so `to` is never left half written.
+fn copy_file(from: &Path, to: &Path, copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(".lj-move");
+    let tmp = PathBuf::from(tmp);
+
+    let result = (|| {
+        let mut src = File::open(from)?;
+        let mut dest = File::create(&tmp)?;
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            let n = src.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            dest.write_all(&buf[..n])?;
+            copied(n as u64);
+        }
+        dest.sync_all()?;
+        fs::set_permissions(&tmp, src.metadata()?.permissions())?;
+        fs::rename(&tmp, to)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Streaming into a media player for `lj play`.
+//!
+//! An unrestricted link is a plain HTTP URL, so a player that streams over
+//! HTTP can start on it right away, with the file still only on the
+//! provider's servers. `[player] program` picks the player; without it mpv
+//! is tried, then VLC.
+
+use crate::config::PlayerConfig;
+use crate::error::{Error, Result};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Players tried in order when `program` is unset.
+const DEFAULT_PLAYERS: &[&str] = &["mpv", "vlc"];
+
+/// Extensions of the files worth offering to a player.
+const PLAYABLE_EXTENSIONS: &[&str] = &[
+    "mkv", "mp4", "avi", "m4v", "mov", "wmv", "ts", "webm", "mpg", "mpeg", "flv", "mp3", "flac",
+    "m4a", "ogg", "opus",
+];
+
+/// Whether `filename` is a video or audio file.
+///
+/// ```
+/// use lj_core::player::is_playable;
+///
+/// assert!(is_playable("Show.S01E01.1080p.MKV"));
+/// assert!(!is_playable("Show.S01E01.srt"));
+/// ```
+pub fn is_playable(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            PLAYABLE_EXTENSIONS
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(ext))
+        })
+}
+
+/// Plays `url` and waits for the player to be closed.
+pub fn play(config: &PlayerConfig, url: &str) -> Result<()> {
+    let programs: Vec<&str> = match &config.program {
+        Some(program) => vec![program],
+        None => DEFAULT_PLAYERS.to_vec(),
+    };
+    for program in programs {
+        match Command::new(program).args(&config.args).arg(url).status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => {
+                return Err(Error::Other(format!("{} exited with {}", program, status)));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && config.program.is_none() => {}
+            Err(e) => return Err(Error::Other(format!("Failed to run {}: {}", program, e))),
+        }
+    }
+    Err(Error::Other(
+        "No player found; install mpv or VLC, or set [player] program".to_string(),
+    ))
+}
@@ -0,0 +1,78 @@
+//! Plex library refreshes.
+//!
+//! When a download completes, the `[plex]` library section that holds it
+//! gets a partial scan of the file's directory, so the new file shows up
+//! without a full library scan. Sections are picked by path prefix, since
+//! Plex needs to be told which one to scan.
+
+use crate::config::PlexConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::media;
+use crate::notify::Notifier;
+use crate::store;
+use async_trait::async_trait;
+use reqwest::Client;
+use std::collections::BTreeMap;
+
+pub struct Plex {
+    url: String,
+    token: String,
+    sections: BTreeMap<String, u32>,
+    paths: BTreeMap<String, String>,
+}
+
+impl Plex {
+    /// The server described by `config`, if it has a URL, a token and at
+    /// least one section.
+    pub fn from_config(config: &PlexConfig) -> Option<Self> {
+        if config.sections.is_empty() {
+            return None;
+        }
+        Some(Plex {
+            url: config.url.clone()?.trim_end_matches('/').to_string(),
+            token: config.token.clone()?,
+            sections: config.sections.clone(),
+            paths: config.paths.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Plex {
+    fn name(&self) -> &'static str {
+        "plex"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        matches!(event, DownloadEvent::Completed { .. })
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let context = "Plex request failed";
+        let Some(dl) = store::load_download(event.id()) else {
+            return Ok(());
+        };
+        // Downloads outside every configured section are not Plex's
+        let Some((_, section)) = media::longest_prefix(&dl.target_dir, &self.sections) else {
+            return Ok(());
+        };
+
+        let resp = client
+            .get(format!("{}/library/sections/{}/refresh", self.url, section))
+            .query(&[("path", media::server_path(&dl.target_dir, &self.paths))])
+            .header("X-Plex-Token", &self.token)
+            .send()
+            .await
+            .map_err(Error::http(context))?;
+        if !resp.status().is_success() {
+            return Err(Error::Api {
+                context,
+                status: resp.status(),
+                code: None,
+                message: resp.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
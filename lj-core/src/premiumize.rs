@@ -0,0 +1,390 @@
+//! Premiumize.me client.
+//!
+//! Premiumize stores a finished transfer in the user's cloud as a folder (or
+//! a single file), rather than handing out per-file hoster links. Once a
+//! transfer finishes its folder is walked recursively to build the file
+//! list, and each file is reported as a `pm-item:<id>` link which
+//! [`DebridProvider::unrestrict`] turns into the item's direct URL.
+//!
+//! Like AllDebrid, Premiumize has no selection step, so selection is
+//! emulated client-side (see [`EmulatedSelection`]).
+
+use crate::error::{Error, Result};
+use crate::http::{self, Auth, Token};
+use crate::provider::{
+    Account, DebridProvider, EmulatedSelection, TorrentFile, TorrentState, TorrentStatus,
+    UnrestrictedLink,
+};
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+
+pub const PM_BASE_URL: &str = "https://www.premiumize.me/api";
+
+/// Prefix of the links handed out by [`DebridProvider::poll`].
+const ITEM_LINK: &str = "pm-item:";
+
+/// Every response carries `"status": "success" | "error"`, with a
+/// `message` on failure and the payload fields alongside.
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    status: String,
+    message: Option<String>,
+}
+
+/// `customer_id` is a number or a string, and `premium_until` is `false`
+/// for free accounts.
+#[derive(Debug, Deserialize)]
+struct AccountInfo {
+    customer_id: serde_json::Value,
+    #[serde(default)]
+    premium_until: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateData {
+    id: String,
+}
+
+/// One flag per item asked about, in order.
+#[derive(Debug, Deserialize)]
+struct CacheCheck {
+    response: Vec<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransferList {
+    transfers: Vec<Transfer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transfer {
+    id: String,
+    name: Option<String>,
+    message: Option<String>,
+    status: String,
+    /// Fraction between 0 and 1.
+    progress: Option<f64>,
+    folder_id: Option<String>,
+    file_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FolderList {
+    content: Vec<Item>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Item {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    size: Option<u64>,
+    link: Option<String>,
+}
+
+pub struct Premiumize {
+    client: Client,
+    api_key: Token,
+    base_url: String,
+    selection: EmulatedSelection,
+}
+
+impl Premiumize {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(Client::new(), api_key)
+    }
+
+    pub fn with_client(client: Client, api_key: &str) -> Self {
+        Self {
+            client,
+            api_key: Token::new(api_key),
+            base_url: PM_BASE_URL.to_string(),
+            selection: EmulatedSelection::default(),
+        }
+    }
+
+    /// Points the client at another API root, e.g. a proxy or mirror.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        http::request(
+            &self.client,
+            method,
+            format!("{}{}", self.base_url, path),
+            &self.api_key,
+            Auth::Query("apikey"),
+        )
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        req: RequestBuilder,
+        context: &'static str,
+    ) -> Result<T> {
+        let resp = req.send().await.map_err(Error::http(context))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(Error::http(context))?;
+
+        let envelope: Envelope = serde_json::from_str(&text).map_err(|e| {
+            if status.is_success() {
+                Error::Parse {
+                    context,
+                    message: e.to_string(),
+                }
+            } else {
+                Error::Api {
+                    context,
+                    status,
+                    code: None,
+                    message: text.clone(),
+                }
+            }
+        })?;
+
+        if envelope.status != "success" {
+            return Err(api_error(context, status, envelope.message));
+        }
+        serde_json::from_str(&text).map_err(|e| Error::Parse {
+            context,
+            message: e.to_string(),
+        })
+    }
+
+    async fn transfer(&self, transfer_id: &str) -> Result<Transfer> {
+        let list: TransferList = self
+            .call(self.get("/transfer/list"), "Failed to get transfer status")
+            .await?;
+        list.transfers
+            .into_iter()
+            .find(|t| t.id == transfer_id)
+            .ok_or_else(|| Error::Torrent(format!("transfer {} not found", transfer_id)))
+    }
+
+    async fn item(&self, item_id: &str) -> Result<Item> {
+        self.call(
+            self.get("/item/details").query(&[("id", item_id)]),
+            "Failed to get item details",
+        )
+        .await
+    }
+
+    /// Lists every file below `folder_id`, numbered from 1 in path order.
+    async fn files(&self, folder_id: &str) -> Result<Vec<(TorrentFile, String)>> {
+        let mut found = Vec::new();
+        let mut pending = vec![(folder_id.to_string(), String::new())];
+
+        while let Some((id, prefix)) = pending.pop() {
+            let list: FolderList = self
+                .call(
+                    self.get("/folder/list").query(&[("id", &id)]),
+                    "Failed to list folder",
+                )
+                .await?;
+            for item in list.content {
+                let path = if prefix.is_empty() {
+                    item.name.clone()
+                } else {
+                    format!("{}/{}", prefix, item.name)
+                };
+                if item.kind.as_deref() == Some("folder") {
+                    pending.push((item.id, path));
+                } else {
+                    found.push((path, item.size.unwrap_or(0), item.id));
+                }
+            }
+        }
+
+        // The walk order depends on the API; keep ids stable between polls
+        found.sort();
+        Ok(found
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, bytes, id))| {
+                let file = TorrentFile {
+                    id: i as u32 + 1,
+                    path,
+                    bytes,
+                    selected: 1,
+                };
+                (file, format!("{}{}", ITEM_LINK, id))
+            })
+            .collect())
+    }
+
+    /// The finished transfer's files: a folder to walk, or a single file.
+    async fn transfer_files(&self, transfer: &Transfer) -> Result<Vec<(TorrentFile, String)>> {
+        if let Some(file_id) = &transfer.file_id {
+            let item = self.item(file_id).await?;
+            let file = TorrentFile {
+                id: 1,
+                path: item.name,
+                bytes: item.size.unwrap_or(0),
+                selected: 1,
+            };
+            return Ok(vec![(file, format!("{}{}", ITEM_LINK, item.id))]);
+        }
+        match &transfer.folder_id {
+            Some(folder_id) => self.files(folder_id).await,
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+fn api_error(context: &'static str, status: StatusCode, message: Option<String>) -> Error {
+    let message = message.unwrap_or_else(|| "Unknown error".to_string());
+    // Premiumize reports a bad key as "Not logged in."; surface it as a 401
+    // like the other providers
+    let status = if message.to_lowercase().contains("logged in") {
+        StatusCode::UNAUTHORIZED
+    } else {
+        status
+    };
+    Error::Api {
+        context,
+        status,
+        code: None,
+        message,
+    }
+}
+
+/// Maps Premiumize's transfer statuses onto [`TorrentState`].
+fn torrent_state(status: &str, message: Option<&str>) -> TorrentState {
+    match status {
+        "finished" | "seeding" => TorrentState::Ready,
+        "error" | "timeout" | "deleted" | "banned" => {
+            TorrentState::Failed(message.unwrap_or(status).to_string())
+        }
+        _ => TorrentState::Processing,
+    }
+}
+
+#[async_trait]
+impl DebridProvider for Premiumize {
+    fn name(&self) -> &'static str {
+        "premiumize"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Premiumize"
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        let data: CreateData = self
+            .call(
+                self.post("/transfer/create").form(&[("src", magnet)]),
+                "Failed to add magnet",
+            )
+            .await?;
+        Ok(data.id)
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus> {
+        let transfer = self.transfer(torrent_id).await?;
+        let state = torrent_state(&transfer.status, transfer.message.as_deref());
+        let ready = state == TorrentState::Ready;
+
+        let all_files = if ready {
+            Some(self.transfer_files(&transfer).await?)
+        } else {
+            None
+        };
+        let name = transfer.name.as_deref().unwrap_or(torrent_id);
+        let (state, files, links) = self
+            .selection
+            .resolve(torrent_id, state, all_files, name, 0);
+
+        let progress = if ready {
+            100.0
+        } else {
+            transfer.progress.unwrap_or(0.0) * 100.0
+        };
+
+        Ok(TorrentStatus {
+            state,
+            name: transfer.name,
+            status: transfer.status,
+            progress,
+            speed: 0,
+            seeders: 0,
+            files,
+            links,
+        })
+    }
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        self.selection.select(torrent_id, file_ids);
+        Ok(())
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        let item_id = link
+            .strip_prefix(ITEM_LINK)
+            .ok_or_else(|| Error::Other(format!("Not a Premiumize link: {}", link)))?;
+        let item = self.item(item_id).await?;
+        let download = item.link.ok_or_else(|| Error::Parse {
+            context: "Failed to get item details",
+            message: "item has no download link".to_string(),
+        })?;
+        Ok(UnrestrictedLink {
+            filename: item.name,
+            download,
+            filesize: item.size,
+        })
+    }
+
+    async fn delete(&self, torrent_id: &str) -> Result<()> {
+        self.selection.forget(torrent_id);
+        let _: serde_json::Value = self
+            .call(
+                self.post("/transfer/delete").form(&[("id", torrent_id)]),
+                "Failed to delete transfer",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn account(&self) -> Result<Option<Account>> {
+        let info: AccountInfo = self
+            .call(self.get("/account/info"), "Failed to get account")
+            .await?;
+        Ok(Some(Account {
+            username: match info.customer_id {
+                serde_json::Value::String(id) => id,
+                id => id.to_string(),
+            },
+            premium_until: info.premium_until.as_u64().filter(|&t| t > 0),
+        }))
+    }
+    async fn cached(&self, hashes: &[String]) -> Result<Option<HashSet<String>>> {
+        let items: Vec<(&str, &str)> = hashes.iter().map(|h| ("items[]", h.as_str())).collect();
+        let check: CacheCheck = self
+            .call(
+                self.get("/cache/check").query(&items),
+                "Failed to check cache",
+            )
+            .await?;
+        Ok(Some(
+            hashes
+                .iter()
+                .zip(check.response)
+                .filter(|(_, cached)| *cached)
+                .map(|(hash, _)| hash.clone())
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,242 @@
+//! Torrents left with the service, for `lj --background`.
+//!
+//! An uncached torrent can take the service hours to download. Instead of
+//! waiting in the terminal, lj hands it to a detached process once its files
+//! are chosen: the host binary re-executes itself with [`PROCESSOR_FLAG`]
+//! and the record's id, and must dispatch that to [`run`]. The process
+//! selects the files, polls the service, and starts the downloads as `lj`
+//! would have once the links are ready. Until then `lj dl` lists the torrent
+//! with the service's progress.
+
+use crate::config::{self, Config};
+use crate::control;
+use crate::error::{Error, Result};
+use crate::hooks;
+use crate::http;
+use crate::logging::{self, Level};
+use crate::media;
+use crate::pipeline::{self, Staging};
+use crate::platform;
+use crate::provider::{self, Backoff, Stall, TorrentState};
+use crate::store::{self, Processing};
+use crate::torrent;
+use std::env;
+use std::process::{self, Command, Stdio};
+use std::time::Duration;
+
+/// Argument the host binary recognises as "wait on this torrent".
+pub const PROCESSOR_FLAG: &str = "--bg-process";
+
+/// How often the service is asked how far along the torrent is, at most
+/// (see [`Backoff`]).
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Stores `job` and starts a detached process for it, which gets `api_key`
+/// through the provider's token variable so an encrypted key needs no
+/// passphrase there.
+pub fn start(job: &Processing, api_key: &str) -> Result<Processing> {
+    let info = provider::info(&job.provider)?;
+    let id = store::add_processing(job)?;
+
+    let spawned = env::current_exe().and_then(|exe| {
+        let mut cmd = Command::new(exe);
+        cmd.arg(PROCESSOR_FLAG)
+            .arg(id.to_string())
+            .env(info.token_env, api_key)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(profile) = config::profile() {
+            cmd.env(config::PROFILE_ENV, profile);
+        }
+        platform::detach(&mut cmd);
+        cmd.spawn()
+    });
+    let pid = match spawned {
+        Ok(child) => child.id(),
+        Err(e) => {
+            store::delete_processing(id);
+            return Err(e.into());
+        }
+    };
+    // The process may already have recorded itself
+    store::update_processing(id, |job| {
+        if job.pid.is_none() {
+            job.set_process(pid);
+        }
+    })?
+    .ok_or_else(|| Error::Other(format!("Torrent not found: {}", id)))
+}
+
+/// Every torrent waited on, with those whose process died without a word
+/// marked failed.
+pub fn jobs() -> Vec<Processing> {
+    store::processing_jobs()
+        .into_iter()
+        .map(|job| {
+            if job.error.is_some() || job.pid.is_none() || job.process_alive() {
+                return job;
+            }
+            store::update_processing(job.id, |job| {
+                job.error = Some("Process died".to_string());
+                job.speed = 0;
+            })
+            .ok()
+            .flatten()
+            .unwrap_or(job)
+        })
+        .collect()
+}
+
+/// Has the process delete the torrent from the service and stop. A torrent
+/// no longer waited on is forgotten right away. Returns whether it was
+/// still being waited on.
+pub fn cancel(id: i64) -> bool {
+    let mut waiting = false;
+    let _ = store::update_processing(id, |job| {
+        if job.error.is_none() && job.process_alive() {
+            job.cancelled = true;
+            waiting = true;
+        }
+    });
+    if !waiting {
+        store::delete_processing(id);
+    }
+    waiting
+}
+
+/// Process entry point: waits on the torrent `id` names, starts its
+/// downloads and forgets it, or records why it couldn't.
+pub async fn run(id: &str) -> Result<()> {
+    logging::init();
+    let id: i64 = id
+        .parse()
+        .map_err(|_| Error::Other(format!("Torrent not found: {}", id)))?;
+    let job = store::update_processing(id, |job| job.set_process(process::id()))?
+        .ok_or_else(|| Error::Other(format!("Torrent not found: {}", id)))?;
+
+    match wait_and_start(&job).await {
+        Ok(started) => {
+            store::delete_processing(id);
+            logging::log(
+                Level::Info,
+                &format!(
+                    "{} is ready, started {} download(s)",
+                    job.torrent_id, started
+                ),
+                &[("LJ_EVENT", "processed")],
+            );
+            Ok(())
+        }
+        Err(Error::Cancelled) => {
+            store::delete_processing(id);
+            Ok(())
+        }
+        Err(e) => {
+            let _ = store::update_processing(id, |job| {
+                job.error = Some(e.to_string());
+                job.speed = 0;
+            });
+            logging::log(
+                Level::Error,
+                &format!("{}: {}", job.torrent_id, e),
+                &[("LJ_EVENT", "processing_failed")],
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Selects the files, waits for the links and starts a download for each,
+/// returning how many were started.
+async fn wait_and_start(job: &Processing) -> Result<usize> {
+    let mut config = Config::load()?;
+    if let Ok(cwd) = env::current_dir() {
+        config.apply_local(&cwd)?;
+    }
+    let info = provider::info(&job.provider)?;
+    if let Some(url) = &job.api_url {
+        config.set_api_url(info, url);
+    }
+    let client = http::client(&config)?;
+    let api_key = config::load_api_key(info, &config)?
+        .ok_or_else(|| Error::Other(format!("No {} API key found", info.display_name)))?;
+    let provider =
+        provider::from_config(info.name, &api_key, &config.provider_config(info), &client)?;
+    let provider = provider.as_ref();
+
+    provider
+        .select_files(&job.torrent_id, &job.file_ids)
+        .await?;
+    let mut stall = Stall::new(config.wait.stalled_timeout());
+    let mut backoff = Backoff::new(POLL_INTERVAL);
+    let links = loop {
+        let status = provider.poll(&job.torrent_id).await?;
+        match status.state {
+            TorrentState::Ready if status.links.is_empty() => return Err(Error::NoLinks),
+            TorrentState::Ready => break status.links,
+            TorrentState::Failed(e) => return Err(Error::Torrent(e)),
+            _ => {}
+        }
+        if let Err(e) = stall.check(&status) {
+            let _ = provider.delete(&job.torrent_id).await;
+            return Err(e);
+        }
+        let updated = store::update_processing(job.id, |job| {
+            job.progress = status.progress;
+            job.speed = status.speed;
+            job.seeders = status.seeders;
+            if job.name.is_none() {
+                job.name = status.name.clone();
+            }
+        })?;
+        if updated.is_none_or(|job| job.cancelled) {
+            let _ = provider.delete(&job.torrent_id).await;
+            return Err(Error::Cancelled);
+        }
+        tokio::time::sleep(backoff.next(&status)).await;
+    };
+
+    let name = pipeline::torrent_name(provider, &job.torrent_id, &job.magnet).await;
+    let staging = Staging::new(&job.target_dir, name.as_deref(), &job.magnet);
+    let resolved = pipeline::resolve_links(provider, &client, &links, Some(&staging)).await;
+    let _ = provider.delete(&job.torrent_id).await;
+    let (resolved, _) = resolved?;
+    if resolved.is_empty() {
+        return Err(Error::NoLinks);
+    }
+    let links = pipeline::filter_links(resolved, &config.select);
+    if links.is_empty() {
+        return Err(Error::NothingSelected);
+    }
+
+    if job.organize {
+        config.organize.enabled = true;
+    }
+    let batch = store::new_batch_id();
+    let folder = pipeline::torrent_dir(&job.target_dir, name.as_deref(), links.len());
+    let mut vetoed = Vec::new();
+    let mut downloads = Vec::new();
+    for link in links {
+        let mut download = link
+            .download(&job.target_dir)
+            .in_batch(&batch, name.as_deref())
+            .of_torrent(torrent::info_hash(&job.magnet));
+        media::organize(&config.organize, &mut download);
+        // Files the library layout didn't place stay together
+        if download.target_dir == job.target_dir {
+            download.target_dir = folder.clone();
+        }
+        if let Err(e) = hooks::before_download(&config.hooks, &mut download) {
+            store::delete_download(&download.id);
+            vetoed.push(e.to_string());
+            continue;
+        }
+        downloads.push(download);
+    }
+    if downloads.is_empty() && !vetoed.is_empty() {
+        return Err(Error::Other(vetoed.join("; ")));
+    }
+    control::start_downloads(&downloads)?;
+    Ok(downloads.len())
+}
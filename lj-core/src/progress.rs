@@ -0,0 +1,188 @@
+//! Live progress of running transfers.
+//!
+//! A process running transfers writes each one's progress twice a second
+//! into fixed slots of a small board file of its own, named after its pid,
+//! in the [runtime directory](config::runtime_dir), which is in memory on
+//! most Linux systems. `lj dl` and the APIs read the boards of the processes
+//! behind the downloads they list. The store gets the same numbers only every
+//! few seconds, as the durable record, so a big pack doesn't keep SQLite
+//! busy with writes and every listing re-reading them.
+
+use crate::config;
+use crate::platform;
+use crate::store::{Download, DownloadStatus};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// Bytes of a slot given to the download id; longer ids aren't published.
+const ID_LEN: usize = 64;
+
+/// Bytes per slot: the id, then four little-endian 64-bit fields.
+const SLOT: usize = 128;
+
+/// Where a transfer is at.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshot {
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    /// Smoothed bytes per second.
+    pub speed: f64,
+    pub average_speed: f64,
+}
+
+/// This process's board: the file and which download holds each slot.
+struct Board {
+    path: PathBuf,
+    slots: Mutex<(File, Vec<Option<String>>)>,
+}
+
+fn dir() -> PathBuf {
+    config::runtime_dir().join("progress")
+}
+
+/// This process's board, made on first use; `None` if it can't be.
+fn board() -> Option<&'static Board> {
+    static BOARD: OnceLock<Option<Board>> = OnceLock::new();
+    BOARD
+        .get_or_init(|| {
+            fs::create_dir_all(dir()).ok()?;
+            let path = dir().join(std::process::id().to_string());
+            let file = File::create(&path).ok()?;
+            Some(Board {
+                path,
+                slots: Mutex::new((file, Vec::new())),
+            })
+        })
+        .as_ref()
+}
+
+/// Puts `snapshot` on this process's board for `id`. Returns whether it is
+/// there; if not, the caller should save it to the store.
+pub fn publish(id: &str, snapshot: &Snapshot) -> bool {
+    if id.len() > ID_LEN {
+        return false;
+    }
+    let Some(board) = board() else {
+        return false;
+    };
+    let mut slots = board.slots.lock().unwrap();
+    let (file, ids) = &mut *slots;
+    let index = match ids.iter().position(|slot| slot.as_deref() == Some(id)) {
+        Some(index) => index,
+        None => match ids.iter().position(Option::is_none) {
+            Some(index) => {
+                ids[index] = Some(id.to_string());
+                index
+            }
+            None => {
+                ids.push(Some(id.to_string()));
+                ids.len() - 1
+            }
+        },
+    };
+    write_slot(file, index, &encode(id, snapshot)).is_ok()
+}
+
+/// Frees the slot of a transfer that has ended.
+pub fn clear(id: &str) {
+    let Some(board) = board() else {
+        return;
+    };
+    let mut slots = board.slots.lock().unwrap();
+    let (file, ids) = &mut *slots;
+    if let Some(index) = ids.iter().position(|slot| slot.as_deref() == Some(id)) {
+        ids[index] = None;
+        let _ = write_slot(file, index, &[0; SLOT]);
+    }
+}
+
+/// Removes this process's board, once its transfers are over.
+pub fn close() {
+    if let Some(board) = board() {
+        let _ = fs::remove_file(&board.path);
+    }
+}
+
+/// Removes the boards of processes that are gone.
+pub fn sweep() {
+    let Ok(entries) = fs::read_dir(dir()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let pid = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok());
+        if pid.is_some_and(|pid| !platform::process_alive(pid)) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Brings running downloads up to date from their processes' boards.
+pub fn overlay(downloads: &mut [Download]) {
+    let mut boards: BTreeMap<u32, BTreeMap<String, Snapshot>> = BTreeMap::new();
+    for dl in downloads.iter_mut() {
+        let Some(pid) = dl.pid.filter(|_| dl.status == DownloadStatus::Downloading) else {
+            continue;
+        };
+        let board = boards.entry(pid).or_insert_with(|| read_board(pid));
+        if let Some(snapshot) = board.get(&dl.id) {
+            dl.downloaded_bytes = snapshot.downloaded_bytes;
+            dl.total_bytes = snapshot.total_bytes;
+            dl.speed = snapshot.speed;
+            dl.average_speed = snapshot.average_speed;
+        }
+    }
+}
+
+fn read_board(pid: u32) -> BTreeMap<String, Snapshot> {
+    let data = fs::read(dir().join(pid.to_string())).unwrap_or_default();
+    data.chunks_exact(SLOT).filter_map(decode).collect()
+}
+
+fn write_slot(file: &mut File, index: usize, slot: &[u8; SLOT]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start((index * SLOT) as u64))?;
+    file.write_all(slot)
+}
+
+fn encode(id: &str, snapshot: &Snapshot) -> [u8; SLOT] {
+    let fields = [
+        snapshot.downloaded_bytes,
+        snapshot.total_bytes,
+        snapshot.speed.to_bits(),
+        snapshot.average_speed.to_bits(),
+    ];
+    let mut slot = [0; SLOT];
+    slot[..id.len()].copy_from_slice(id.as_bytes());
+    for (i, field) in fields.iter().enumerate() {
+        let at = ID_LEN + i * 8;
+        slot[at..at + 8].copy_from_slice(&field.to_le_bytes());
+    }
+    slot
+}
+
+fn decode(slot: &[u8]) -> Option<(String, Snapshot)> {
+    let len = slot[..ID_LEN]
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(ID_LEN);
+    if len == 0 {
+        return None;
+    }
+    let id = std::str::from_utf8(&slot[..len]).ok()?.to_string();
+    let field = |i: usize| {
+        let at = ID_LEN + i * 8;
+        u64::from_le_bytes(slot[at..at + 8].try_into().unwrap())
+    };
+    let snapshot = Snapshot {
+        downloaded_bytes: field(0),
+        total_bytes: field(1),
+        speed: f64::from_bits(field(2)),
+        average_speed: f64::from_bits(field(3)),
+    };
+    Some((id, snapshot))
+}
@@ -0,0 +1,492 @@
+//! Debrid service abstraction.
+//!
+//! Every backend turns a magnet into a remote torrent, lets the caller pick
+//! files, and eventually hands out hoster links that [`unrestrict`] into
+//! direct HTTP downloads. The polling loops are shared default methods, so a
+//! backend only has to translate its own API and status vocabulary.
+//!
+//! [`unrestrict`]: DebridProvider::unrestrict
+
+use crate::alldebrid::AllDebrid;
+use crate::config::{Config, ProviderConfig, WaitConfig};
+use crate::debridlink::DebridLink;
+use crate::error::{Error, Result};
+use crate::http;
+use crate::premiumize::Premiumize;
+use crate::rd::RealDebrid;
+use crate::torbox::TorBox;
+use crate::torrent;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A file inside a remote torrent.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TorrentFile {
+    pub id: u32,
+    pub path: String,
+    pub bytes: u64,
+    pub selected: u8,
+}
+
+impl TorrentFile {
+    /// Last path component, which is what users recognise.
+    pub fn name(&self) -> &str {
+        self.path.split('/').next_back().unwrap_or(&self.path)
+    }
+}
+
+/// Where a remote torrent is in its lifecycle, normalised across services.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TorrentState {
+    /// Metadata is still being fetched.
+    Resolving,
+    /// The file list is known and the service waits for a selection.
+    AwaitingSelection,
+    /// The service is fetching, queueing or packing the selected files.
+    Processing,
+    /// Links are available.
+    Ready,
+    /// The service gave up on the torrent.
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TorrentStatus {
+    pub state: TorrentState,
+    /// The torrent's name, as the service knows it.
+    pub name: Option<String>,
+    /// The service's own status name, for display.
+    pub status: String,
+    pub progress: f64,
+    /// Bytes per second.
+    pub speed: u64,
+    pub seeders: u32,
+    pub files: Vec<TorrentFile>,
+    pub links: Vec<String>,
+}
+
+/// The account an API key belongs to.
+#[derive(Debug, Clone)]
+pub struct Account {
+    /// User name, or whatever the service identifies the account by.
+    pub username: String,
+    /// Unix time the premium subscription ends, if it is premium.
+    pub premium_until: Option<u64>,
+}
+
+/// A direct, downloadable URL for one file.
+#[derive(Debug, Clone)]
+pub struct UnrestrictedLink {
+    pub filename: String,
+    pub download: String,
+    pub filesize: Option<u64>,
+}
+
+#[async_trait]
+pub trait DebridProvider: Send + Sync {
+    /// Short identifier used for `--provider` and in config.
+    fn name(&self) -> &'static str;
+
+    /// Human-readable service name for messages.
+    fn display_name(&self) -> &'static str;
+
+    /// Submits a magnet and returns the service's torrent id.
+    async fn add_magnet(&self, magnet: &str) -> Result<String>;
+
+    /// Submits a `.torrent` file's contents and returns the torrent id.
+    /// Services without an upload endpoint get the file's magnet instead.
+    async fn add_torrent(&self, data: &[u8]) -> Result<String> {
+        self.add_magnet(&torrent::to_magnet(data)?).await
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus>;
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()>;
+
+    /// Turns a link from [`TorrentStatus::links`] into a direct download.
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink>;
+
+    /// Removes the torrent from the account, which can't be undone.
+    async fn delete(&self, torrent_id: &str) -> Result<()>;
+
+    /// Which of `hashes` (lowercase hex info hashes) the service has cached
+    /// and can hand over without downloading, or `None` if it can't tell.
+    async fn cached(&self, _hashes: &[String]) -> Result<Option<HashSet<String>>> {
+        Ok(None)
+    }
+
+    /// The account behind the API key, failing if the service rejects the
+    /// key. `None` if the service has no way to tell.
+    async fn account(&self) -> Result<Option<Account>> {
+        Ok(None)
+    }
+
+    /// How long [`wait_for_files`](Self::wait_for_files) and
+    /// [`wait_for_download`](Self::wait_for_download) wait.
+    fn wait(&self) -> WaitConfig {
+        WaitConfig::default()
+    }
+
+    /// Polls until the service has resolved the magnet's file list.
+    async fn wait_for_files(&self, torrent_id: &str) -> Result<Vec<TorrentFile>> {
+        let start = Instant::now();
+        let timeout = self.wait().files_timeout();
+
+        loop {
+            if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+                return Err(Error::Timeout("file list"));
+            }
+
+            let status = self.poll(torrent_id).await?;
+            match status.state {
+                TorrentState::AwaitingSelection if !status.files.is_empty() => {
+                    return Ok(status.files);
+                }
+                TorrentState::Failed(e) => return Err(Error::Torrent(e)),
+                _ => {}
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Polls until the selected files are ready and returns their links,
+    /// less often while the torrent doesn't move (see [`Backoff`]).
+    /// `on_progress` is called on every poll while the service is working.
+    /// Fails with [`Error::Stalled`] once the torrent has sat at 0% with no
+    /// seeders for too long, leaving it to the caller to give up on it.
+    async fn wait_for_download(
+        &self,
+        torrent_id: &str,
+        on_progress: &mut (dyn for<'a> FnMut(&'a TorrentStatus) + Send),
+    ) -> Result<Vec<String>> {
+        let start = Instant::now();
+        let timeout = self.wait().processing_timeout();
+        let mut stall = Stall::new(self.wait().stalled_timeout());
+        let mut backoff = Backoff::new(Duration::from_secs(2));
+
+        loop {
+            if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+                return Err(Error::Timeout("the torrent to be processed"));
+            }
+
+            let status = self.poll(torrent_id).await?;
+            match status.state {
+                TorrentState::Ready if status.links.is_empty() => return Err(Error::NoLinks),
+                TorrentState::Ready => return Ok(status.links),
+                TorrentState::Failed(e) => return Err(Error::Torrent(e)),
+                TorrentState::Processing => on_progress(&status),
+                _ => {}
+            }
+            stall.check(&status)?;
+
+            tokio::time::sleep(backoff.next(&status)).await;
+        }
+    }
+}
+
+/// Longest wait between polls of a torrent that isn't moving.
+const POLL_MAX: Duration = Duration::from_secs(30);
+
+/// Spaces out the polls of a torrent the service is working on, so an
+/// uncached one that takes hours doesn't cost thousands of API calls. The
+/// wait starts at a minimum and grows by half each time a poll finds the
+/// torrent where the last one left it, up to [`POLL_MAX`], and drops back
+/// to the minimum once it moves.
+pub struct Backoff {
+    min: Duration,
+    interval: Duration,
+    last: Option<(TorrentState, f64)>,
+}
+
+impl Backoff {
+    pub fn new(min: Duration) -> Self {
+        Backoff {
+            min,
+            interval: min,
+            last: None,
+        }
+    }
+
+    /// How long to wait after a poll that found `status`.
+    pub fn next(&mut self, status: &TorrentStatus) -> Duration {
+        let seen = (status.state.clone(), status.progress);
+        self.interval = if self.last.as_ref() == Some(&seen) {
+            (self.interval * 3 / 2).min(POLL_MAX.max(self.min))
+        } else {
+            self.min
+        };
+        self.last = Some(seen);
+        self.interval
+    }
+}
+
+/// Watches the polls of a torrent for one that is going nowhere: at 0%
+/// with no seeders for longer than `limit`.
+pub struct Stall {
+    limit: Option<Duration>,
+    since: Option<Instant>,
+}
+
+impl Stall {
+    pub fn new(limit: Option<Duration>) -> Self {
+        Stall { limit, since: None }
+    }
+
+    /// Fails with [`Error::Stalled`] if the torrent has been stuck since
+    /// before `limit`, counting from the first poll that found it stuck.
+    pub fn check(&mut self, status: &TorrentStatus) -> Result<()> {
+        let stuck = status.state == TorrentState::Processing
+            && status.progress == 0.0
+            && status.seeders == 0;
+        if !stuck {
+            self.since = None;
+            return Ok(());
+        }
+        let since = *self.since.get_or_insert_with(Instant::now);
+        match self.limit {
+            Some(limit) if since.elapsed() > limit => Err(Error::Stalled(limit.as_secs())),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Client-side file selection for services that always fetch whole
+/// torrents and have no selection step.
+///
+/// [`DebridProvider::select_files`] only records the chosen ids, and
+/// [`resolve`](Self::resolve) reports links for those files alone. Until the
+/// service has the whole torrent its file list is unknown, so it is
+/// presented as a single [`WHOLE_TORRENT`](Self::WHOLE_TORRENT) entry.
+#[derive(Default)]
+pub(crate) struct EmulatedSelection {
+    /// Keyed by torrent id. An empty list means "everything".
+    selections: Mutex<HashMap<String, Vec<u32>>>,
+}
+
+impl EmulatedSelection {
+    pub const WHOLE_TORRENT: u32 = 0;
+
+    pub fn select(&self, torrent_id: &str, file_ids: &[u32]) {
+        let ids = if file_ids.contains(&Self::WHOLE_TORRENT) {
+            Vec::new()
+        } else {
+            file_ids.to_vec()
+        };
+        self.selections
+            .lock()
+            .unwrap()
+            .insert(torrent_id.to_string(), ids);
+    }
+
+    pub fn forget(&self, torrent_id: &str) {
+        self.selections.lock().unwrap().remove(torrent_id);
+    }
+
+    /// Combines the service's own state with the recorded selection.
+    /// `files` holds every file with its link once the torrent is ready.
+    pub fn resolve(
+        &self,
+        torrent_id: &str,
+        state: TorrentState,
+        files: Option<Vec<(TorrentFile, String)>>,
+        name: &str,
+        size: u64,
+    ) -> (TorrentState, Vec<TorrentFile>, Vec<String>) {
+        let selection = self.selections.lock().unwrap().get(torrent_id).cloned();
+
+        let (files, links) = match files {
+            Some(files) => {
+                let links = files
+                    .iter()
+                    .filter(|(f, _)| match &selection {
+                        Some(ids) => ids.is_empty() || ids.contains(&f.id),
+                        None => true,
+                    })
+                    .map(|(_, link)| link.clone())
+                    .collect();
+                (files.into_iter().map(|(f, _)| f).collect(), links)
+            }
+            None => {
+                let whole = TorrentFile {
+                    id: Self::WHOLE_TORRENT,
+                    path: name.to_string(),
+                    bytes: size,
+                    selected: 1,
+                };
+                (vec![whole], Vec::new())
+            }
+        };
+
+        let state = match state {
+            TorrentState::Failed(e) => TorrentState::Failed(e),
+            _ if selection.is_none() => TorrentState::AwaitingSelection,
+            state => state,
+        };
+        (state, files, links)
+    }
+}
+
+/// Static facts about a supported service.
+#[derive(Debug)]
+pub struct ProviderInfo {
+    /// Identifier accepted by `--provider` and in config.
+    pub name: &'static str,
+    pub display_name: &'static str,
+    /// Environment variable that overrides the stored token.
+    pub token_env: &'static str,
+    /// Where users can create a token.
+    pub token_url: &'static str,
+}
+
+pub const PROVIDERS: &[ProviderInfo] = &[
+    ProviderInfo {
+        name: "real-debrid",
+        display_name: "Real-Debrid",
+        token_env: "RD_API_TOKEN",
+        token_url: "https://real-debrid.com/apitoken",
+    },
+    ProviderInfo {
+        name: "alldebrid",
+        display_name: "AllDebrid",
+        token_env: "ALLDEBRID_API_KEY",
+        token_url: "https://alldebrid.com/apikeys",
+    },
+    ProviderInfo {
+        name: "premiumize",
+        display_name: "Premiumize",
+        token_env: "PREMIUMIZE_API_KEY",
+        token_url: "https://www.premiumize.me/account",
+    },
+    ProviderInfo {
+        name: "torbox",
+        display_name: "TorBox",
+        token_env: "TORBOX_API_KEY",
+        token_url: "https://torbox.app/settings",
+    },
+    ProviderInfo {
+        name: "debrid-link",
+        display_name: "Debrid-Link",
+        token_env: "DEBRIDLINK_API_KEY",
+        token_url: "https://debrid-link.com/webapp/apikey",
+    },
+];
+
+pub const DEFAULT_PROVIDER: &str = "real-debrid";
+
+/// Looks up a provider by name; `rd`, `ad`, `pm`, `tb` and `dl` are accepted
+/// as aliases.
+pub fn info(name: &str) -> Result<&'static ProviderInfo> {
+    let name = match name {
+        "rd" => "real-debrid",
+        "ad" => "alldebrid",
+        "pm" => "premiumize",
+        "tb" => "torbox",
+        "dl" => "debrid-link",
+        other => other,
+    };
+    PROVIDERS.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names: Vec<_> = PROVIDERS.iter().map(|p| p.name).collect();
+        Error::Other(format!(
+            "Unknown provider '{}' (available: {})",
+            name,
+            names.join(", ")
+        ))
+    })
+}
+
+/// Builds the provider called `name` (see [`PROVIDERS`]) with a default
+/// [`http::client`].
+pub fn from_name(name: &str, api_key: &str) -> Result<Box<dyn DebridProvider>> {
+    let client = http::client(&Config::default())?;
+    from_config(name, api_key, &ProviderConfig::default(), &client)
+}
+
+/// Builds the provider called `name` with its `config.toml` settings
+/// applied, sending requests through `client`.
+pub fn from_config(
+    name: &str,
+    api_key: &str,
+    config: &ProviderConfig,
+    client: &Client,
+) -> Result<Box<dyn DebridProvider>> {
+    let name = info(name)?.name;
+    let client = client.clone();
+    let inner: Box<dyn DebridProvider> = match config.api_url.as_deref() {
+        None => match name {
+            "alldebrid" => Box::new(AllDebrid::with_client(client, api_key)),
+            "premiumize" => Box::new(Premiumize::with_client(client, api_key)),
+            "torbox" => Box::new(TorBox::with_client(client, api_key)),
+            "debrid-link" => Box::new(DebridLink::with_client(client, api_key)),
+            _ => Box::new(RealDebrid::with_client(client, api_key)),
+        },
+        Some(url) => match name {
+            "alldebrid" => Box::new(AllDebrid::with_client(client, api_key).with_base_url(url)),
+            "premiumize" => Box::new(Premiumize::with_client(client, api_key).with_base_url(url)),
+            "torbox" => Box::new(TorBox::with_client(client, api_key).with_base_url(url)),
+            "debrid-link" => Box::new(DebridLink::with_client(client, api_key).with_base_url(url)),
+            _ => Box::new(RealDebrid::with_client(client, api_key).with_base_url(url)),
+        },
+    };
+    Ok(Box::new(Waiting {
+        inner,
+        wait: config.wait,
+    }))
+}
+
+/// A provider that waits on the service as long as `[wait]` says.
+struct Waiting {
+    inner: Box<dyn DebridProvider>,
+    wait: WaitConfig,
+}
+
+#[async_trait]
+impl DebridProvider for Waiting {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn display_name(&self) -> &'static str {
+        self.inner.display_name()
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        self.inner.add_magnet(magnet).await
+    }
+
+    async fn add_torrent(&self, data: &[u8]) -> Result<String> {
+        self.inner.add_torrent(data).await
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus> {
+        self.inner.poll(torrent_id).await
+    }
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        self.inner.select_files(torrent_id, file_ids).await
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        self.inner.unrestrict(link).await
+    }
+
+    async fn delete(&self, torrent_id: &str) -> Result<()> {
+        self.inner.delete(torrent_id).await
+    }
+
+    async fn cached(&self, hashes: &[String]) -> Result<Option<HashSet<String>>> {
+        self.inner.cached(hashes).await
+    }
+
+    async fn account(&self) -> Result<Option<Account>> {
+        self.inner.account().await
+    }
+
+    fn wait(&self) -> WaitConfig {
+        self.wait
+    }
+}
@@ -0,0 +1,36 @@
+//! Token-bucket bandwidth limiter shared between concurrent transfers.
+
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    // (last refill, available tokens); tokens go negative when a caller
+    // overdraws and the deficit is slept off while holding the lock.
+    bucket: Mutex<(Instant, f64)>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new((Instant::now(), bytes_per_sec as f64)),
+        }
+    }
+
+    /// Waits until `bytes` may be transferred without exceeding the limit.
+    pub async fn acquire(&self, bytes: usize) {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.0).as_secs_f64() * self.bytes_per_sec;
+        bucket.0 = now;
+        bucket.1 = (bucket.1 + refill).min(self.bytes_per_sec) - bytes as f64;
+
+        if bucket.1 < 0.0 {
+            let wait = Duration::from_secs_f64(-bucket.1 / self.bytes_per_sec);
+            tokio::time::sleep(wait).await;
+            bucket.0 = Instant::now();
+            bucket.1 = 0.0;
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Real-Debrid REST client.
+
+use crate::error::{Error, Result};
+use crate::format::parse_timestamp;
+use crate::http::{self, Auth, Token};
+use crate::logging::{self, Level};
+use crate::provider::{Account, DebridProvider, TorrentState, TorrentStatus, UnrestrictedLink};
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, header};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+pub use crate::provider::TorrentFile;
+
+pub const RD_BASE_URL: &str = "https://api.real-debrid.com/rest/1.0";
+
+/// How long a request waits out RD being unavailable before it fails.
+const UNAVAILABLE_WAIT: Duration = Duration::from_secs(10 * 60);
+
+/// Longest pause between two tries of a request RD was unavailable for.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct AddMagnetResponse {
+    pub id: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TorrentInfo {
+    pub id: String,
+    /// The torrent's name.
+    pub filename: Option<String>,
+    pub status: String,
+    pub files: Option<Vec<TorrentFile>>,
+    pub links: Option<Vec<String>>,
+    pub progress: Option<f64>,
+    pub speed: Option<u64>,
+    pub seeders: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnrestrictResponse {
+    pub filename: String,
+    pub download: String,
+    pub filesize: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// `premium` or `free`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// When premium ends, e.g. `2025-01-01T12:00:00.000Z`.
+    pub expiration: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    error: Option<String>,
+    error_code: Option<i64>,
+}
+
+/// Turns a non-success response into [`Error::Api`], keeping RD's error
+/// code from the body when there is one.
+async fn check(resp: Response, context: &'static str) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+
+    let text = resp.text().await.unwrap_or_default();
+    let body = serde_json::from_str::<ApiErrorBody>(&text).ok();
+    let code = body.as_ref().and_then(|b| b.error_code);
+    let message = body.and_then(|b| b.error).unwrap_or(text);
+    Err(Error::Api {
+        context,
+        status,
+        code,
+        message,
+    })
+}
+
+/// Whether RD answered that it is down for maintenance or overloaded, which
+/// passes within minutes and leaves the request undone.
+fn unavailable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+    )
+}
+
+async fn parse<T: serde::de::DeserializeOwned>(resp: Response, context: &'static str) -> Result<T> {
+    resp.json().await.map_err(|e| Error::Parse {
+        context,
+        message: e.to_string(),
+    })
+}
+
+/// Authenticated handle on the Real-Debrid API.
+///
+/// Cheap to clone; clones share the underlying connection pool.
+#[derive(Clone)]
+pub struct RealDebrid {
+    client: Client,
+    api_key: Token,
+    base_url: String,
+}
+
+impl RealDebrid {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(Client::new(), api_key)
+    }
+
+    pub fn with_client(client: Client, api_key: &str) -> Self {
+        Self {
+            client,
+            api_key: Token::new(api_key),
+            base_url: RD_BASE_URL.to_string(),
+        }
+    }
+
+    /// Points the client at another API root, e.g. a proxy or mirror.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Sends the request `build` makes and [`check`]s the response. While RD
+    /// is [`unavailable`] the request is made again, after a growing delay
+    /// or the one RD asks for, for up to [`UNAVAILABLE_WAIT`]; its
+    /// maintenance windows are short but frequent, and failing would throw
+    /// away a magnet half way through.
+    async fn send(
+        &self,
+        context: &'static str,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Response> {
+        let start = Instant::now();
+        let mut delay = Duration::from_secs(5);
+        loop {
+            let resp = build().send().await.map_err(Error::http(context))?;
+            let wait = resp
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse().ok())
+                .map_or(delay, Duration::from_secs)
+                .min(MAX_RETRY_DELAY);
+            if !unavailable(resp.status()) || start.elapsed() + wait > UNAVAILABLE_WAIT {
+                return check(resp, context).await;
+            }
+            logging::log(
+                Level::Warning,
+                &format!(
+                    "{}: Real-Debrid answered {}; trying again in {}s",
+                    context,
+                    resp.status(),
+                    wait.as_secs()
+                ),
+                &[("LJ_EVENT", "provider_unavailable")],
+            );
+            tokio::time::sleep(wait).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        http::request(
+            &self.client,
+            method,
+            format!("{}{}", self.base_url, path),
+            &self.api_key,
+            Auth::Bearer,
+        )
+    }
+
+    /// The account the API key belongs to.
+    pub async fn user(&self) -> Result<User> {
+        let resp = self
+            .request(Method::GET, "/user")
+            .send()
+            .await
+            .map_err(Error::http("Failed to get account"))?;
+        let resp = check(resp, "Failed to get account").await?;
+        parse(resp, "Failed to parse account").await
+    }
+
+    /// Submits a magnet and returns the new torrent id.
+    pub async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        let resp = self
+            .send("Failed to add magnet", || {
+                self.request(Method::POST, "/torrents/addMagnet")
+                    .form(&[("magnet", magnet)])
+            })
+            .await?;
+
+        let data: AddMagnetResponse = parse(resp, "Failed to parse response").await?;
+
+        Ok(data.id)
+    }
+
+    /// Uploads a `.torrent` file and returns the new torrent id. Unlike its
+    /// magnet, the file keeps a private tracker's passkey.
+    pub async fn add_torrent(&self, data: &[u8]) -> Result<String> {
+        let resp = self
+            .send("Failed to add torrent", || {
+                self.request(Method::PUT, "/torrents/addTorrent")
+                    .body(data.to_vec())
+            })
+            .await?;
+
+        let data: AddMagnetResponse = parse(resp, "Failed to parse response").await?;
+
+        Ok(data.id)
+    }
+
+    pub async fn torrent_info(&self, torrent_id: &str) -> Result<TorrentInfo> {
+        let resp = self
+            .send("Failed to get torrent info", || {
+                self.request(Method::GET, &format!("/torrents/info/{}", torrent_id))
+            })
+            .await?;
+
+        parse(resp, "Failed to parse torrent info").await
+    }
+
+    pub async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        let ids = file_ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        self.send("Failed to select files", || {
+            self.request(
+                Method::POST,
+                &format!("/torrents/selectFiles/{}", torrent_id),
+            )
+            .form(&[("files", &ids)])
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn unrestrict_link(&self, link: &str) -> Result<UnrestrictResponse> {
+        let resp = self
+            .send("Failed to unrestrict link", || {
+                self.request(Method::POST, "/unrestrict/link")
+                    .form(&[("link", link)])
+            })
+            .await?;
+
+        parse(resp, "Failed to parse unrestrict response").await
+    }
+
+    pub async fn delete_torrent(&self, torrent_id: &str) -> Result<()> {
+        let resp = self
+            .request(Method::DELETE, &format!("/torrents/delete/{}", torrent_id))
+            .send()
+            .await
+            .map_err(Error::http("Failed to delete torrent"))?;
+        check(resp, "Failed to delete torrent").await?;
+
+        Ok(())
+    }
+
+    /// Which of `hashes` RD has cached. RD has disabled this endpoint on its
+    /// own API, so it mostly answers through compatible mirrors.
+    pub async fn instant_availability(&self, hashes: &[String]) -> Result<HashSet<String>> {
+        let resp = self
+            .request(
+                Method::GET,
+                &format!("/torrents/instantAvailability/{}", hashes.join("/")),
+            )
+            .send()
+            .await
+            .map_err(Error::http("Failed to check cache"))?;
+        let resp = check(resp, "Failed to check cache").await?;
+
+        // Cached hashes map to `{"rd": [variants]}`, others to `[]` or `{}`
+        let found: HashMap<String, serde_json::Value> =
+            parse(resp, "Failed to parse cache check").await?;
+        Ok(found
+            .into_iter()
+            .filter(|(_, hosts)| {
+                hosts
+                    .get("rd")
+                    .and_then(|variants| variants.as_array())
+                    .is_some_and(|variants| !variants.is_empty())
+            })
+            .map(|(hash, _)| hash.to_lowercase())
+            .collect())
+    }
+}
+
+/// Maps RD's status vocabulary onto [`TorrentState`].
+fn torrent_state(status: &str) -> TorrentState {
+    match status {
+        "magnet_conversion" => TorrentState::Resolving,
+        "waiting_files_selection" => TorrentState::AwaitingSelection,
+        "downloaded" => TorrentState::Ready,
+        "magnet_error" | "dead" | "error" | "virus" => TorrentState::Failed(status.to_string()),
+        "downloading" | "queued" | "compressing" | "uploading" => TorrentState::Processing,
+        _ => TorrentState::Resolving,
+    }
+}
+
+#[async_trait]
+impl DebridProvider for RealDebrid {
+    fn name(&self) -> &'static str {
+        "real-debrid"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Real-Debrid"
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        RealDebrid::add_magnet(self, magnet).await
+    }
+
+    async fn add_torrent(&self, data: &[u8]) -> Result<String> {
+        RealDebrid::add_torrent(self, data).await
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus> {
+        let info = self.torrent_info(torrent_id).await?;
+        Ok(TorrentStatus {
+            state: torrent_state(&info.status),
+            name: info.filename,
+            progress: info.progress.unwrap_or(0.0),
+            speed: info.speed.unwrap_or(0),
+            seeders: info.seeders.unwrap_or(0),
+            files: info.files.unwrap_or_default(),
+            links: info.links.unwrap_or_default(),
+            status: info.status,
+        })
+    }
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        RealDebrid::select_files(self, torrent_id, file_ids).await
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        let resp = self.unrestrict_link(link).await?;
+        Ok(UnrestrictedLink {
+            filename: resp.filename,
+            download: resp.download,
+            filesize: resp.filesize,
+        })
+    }
+
+    async fn delete(&self, torrent_id: &str) -> Result<()> {
+        self.delete_torrent(torrent_id).await
+    }
+
+    async fn cached(&self, hashes: &[String]) -> Result<Option<HashSet<String>>> {
+        // Without the endpoint, RD can't tell
+        Ok(self.instant_availability(hashes).await.ok())
+    }
+
+    async fn account(&self) -> Result<Option<Account>> {
+        let user = self.user().await?;
+        let premium_until = user
+            .expiration
+            .filter(|_| user.kind == "premium")
+            // Fractional seconds aren't needed
+            .and_then(|e| parse_timestamp(e.get(..19).unwrap_or(&e)));
+        Ok(Some(Account {
+            username: user.username,
+            premium_until,
+        }))
+    }
+}
@@ -0,0 +1,432 @@
+//! RSS and Atom feed subscriptions for `lj rss`.
+//!
+//! Each `[rss.feeds.<name>]` is fetched on every poll, by `lj rss poll`
+//! from cron or by the daemon every `[rss] interval` minutes. Items whose
+//! titles pass the feed's include and exclude regexes are run through the
+//! provider like any magnet, with every selectable file queued. Items are
+//! remembered in the store by GUID and by info hash, so an episode is taken
+//! once even when several feeds carry it.
+
+use crate::config::{Config, FeedConfig, HooksConfig, OrganizeConfig, SelectConfig};
+use crate::error::{Error, Result};
+use crate::hooks;
+use crate::logging::{self, Level};
+use crate::magnet;
+use crate::media;
+use crate::pipeline;
+use crate::provider::DebridProvider;
+use crate::store::{self, Download};
+use crate::torrent;
+use regex::{Regex, RegexBuilder};
+use reqwest::Client;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+/// One entry of a feed.
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub title: String,
+    /// The item's GUID or Atom id, falling back to its link.
+    pub guid: Option<String>,
+    pub magnet: Option<String>,
+    /// Where the `.torrent` file is, if the feed gave no magnet.
+    pub link: Option<String>,
+}
+
+impl FeedItem {
+    fn info_hash(&self) -> Option<String> {
+        self.magnet.as_deref().and_then(torrent::info_hash)
+    }
+}
+
+fn feed_error(message: String) -> Error {
+    Error::Parse {
+        context: "Invalid feed",
+        message,
+    }
+}
+
+fn is_torrent_link(url: &str, mime: Option<&str>) -> bool {
+    mime == Some("application/x-bittorrent")
+        || url
+            .split(['?', '#'])
+            .next()
+            .is_some_and(|path| path.to_lowercase().ends_with(".torrent"))
+}
+
+/// Reads the items of an RSS or Atom feed.
+///
+/// ```
+/// use lj_core::rss::parse_feed;
+///
+/// let xml = r#"<rss><channel><item>
+///   <title>Show S01E01 1080p</title>
+///   <guid>abc</guid>
+///   <enclosure url="https://example.com/1.torrent" type="application/x-bittorrent"/>
+/// </item></channel></rss>"#;
+/// let items = parse_feed(xml).unwrap();
+/// assert_eq!(items[0].title, "Show S01E01 1080p");
+/// assert_eq!(items[0].link.as_deref(), Some("https://example.com/1.torrent"));
+/// ```
+pub fn parse_feed(xml: &str) -> Result<Vec<FeedItem>> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| feed_error(e.to_string()))?;
+    let root = doc.root_element();
+    if !root.has_tag_name("rss") && !root.has_tag_name("feed") && !root.has_tag_name("RDF") {
+        return Err(feed_error(format!(
+            "expected RSS or Atom, got <{}>",
+            root.tag_name().name()
+        )));
+    }
+
+    let mut items = Vec::new();
+    for item in doc
+        .descendants()
+        .filter(|n| n.has_tag_name("item") || n.has_tag_name("entry"))
+    {
+        // Matched by local name, so namespaced tags like torrent:magnetURI
+        // count too
+        let child = |name: &str| item.children().find(|n| n.tag_name().name() == name);
+        let text = |name: &str| {
+            child(name)
+                .and_then(|n| n.text())
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+        };
+        let Some(title) = text("title") else {
+            continue;
+        };
+
+        // Links in order of preference: enclosures, then the item's links
+        let mut urls: Vec<(String, Option<&str>)> = Vec::new();
+        for node in item.children() {
+            match node.tag_name().name() {
+                "enclosure" => {
+                    if let Some(url) = node.attribute("url") {
+                        urls.insert(0, (url.to_string(), node.attribute("type")));
+                    }
+                }
+                // Atom links are in href
+                "link" => match node.attribute("href") {
+                    Some(href) => urls.push((href.to_string(), node.attribute("type"))),
+                    None => {
+                        if let Some(url) = node.text().map(str::trim).filter(|t| !t.is_empty()) {
+                            urls.push((url.to_string(), None));
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        let torznab_magnet = item
+            .children()
+            .find(|n| n.tag_name().name() == "attr" && n.attribute("name") == Some("magneturl"))
+            .and_then(|n| n.attribute("value"))
+            .map(str::to_string);
+        let magnet = text("magnetURI").or(torznab_magnet).or_else(|| {
+            urls.iter()
+                .map(|(url, _)| url)
+                .find(|url| url.starts_with("magnet:"))
+                .cloned()
+        });
+        let link = urls
+            .iter()
+            .find(|(url, mime)| is_torrent_link(url, *mime))
+            .or_else(|| urls.iter().find(|(url, _)| !url.starts_with("magnet:")))
+            .map(|(url, _)| url.clone());
+
+        items.push(FeedItem {
+            guid: text("guid").or_else(|| text("id")).or_else(|| link.clone()),
+            title,
+            magnet,
+            link,
+        });
+    }
+    Ok(items)
+}
+
+fn compile(feed: &str, patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| Error::Config(format!("rss.feeds.{}: {}", feed, e)))
+        })
+        .collect()
+}
+
+/// A subscription with its filters compiled.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub name: String,
+    pub url: String,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    download_dir: Option<PathBuf>,
+}
+
+impl Feed {
+    pub fn from_config(name: &str, config: &FeedConfig) -> Result<Self> {
+        if config.url.trim().is_empty() {
+            return Err(Error::Config(format!("rss.feeds.{} has no url", name)));
+        }
+        Ok(Feed {
+            name: name.to_string(),
+            url: config.url.clone(),
+            include: compile(name, &config.include)?,
+            exclude: compile(name, &config.exclude)?,
+            download_dir: config.download_dir.clone(),
+        })
+    }
+
+    /// Every `[rss.feeds.<name>]` in `config`.
+    pub fn all(config: &Config) -> Result<Vec<Self>> {
+        config
+            .rss
+            .feeds
+            .iter()
+            .map(|(name, feed)| Feed::from_config(name, feed))
+            .collect()
+    }
+
+    /// Whether an item titled `title` passes the filters. Patterns are
+    /// case-insensitive and match anywhere in the title.
+    pub fn matches(&self, title: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(title)))
+            && !self.exclude.iter().any(|re| re.is_match(title))
+    }
+
+    pub async fn fetch(&self, client: &Client) -> Result<Vec<FeedItem>> {
+        let context = "Failed to fetch feed";
+        let resp = client
+            .get(&self.url)
+            .send()
+            .await
+            .map_err(Error::http(context))?;
+        let status = resp.status();
+        let body = resp.text().await.map_err(Error::http(context))?;
+        if !status.is_success() {
+            return Err(Error::Api {
+                context,
+                status,
+                code: None,
+                message: body,
+            });
+        }
+        parse_feed(&body)
+    }
+
+    fn guid_key(&self, item: &FeedItem) -> String {
+        format!(
+            "guid:{}:{}",
+            self.name,
+            item.guid.as_deref().unwrap_or(&item.title)
+        )
+    }
+
+    /// Whether a poll already took or skipped `item`. Items only known by
+    /// their `.torrent` link are checked by info hash once it is fetched.
+    pub fn seen(&self, item: &FeedItem) -> bool {
+        store::rss_seen(&self.guid_key(item))
+            || item
+                .info_hash()
+                .is_some_and(|hash| store::rss_seen(&hash_key(&hash)))
+    }
+}
+
+fn hash_key(hash: &str) -> String {
+    format!("btih:{}", hash)
+}
+
+/// Failures that will happen again on the next poll; the item is not
+/// retried.
+fn permanent(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Torrent(_) | Error::NoFiles | Error::NothingSelected | Error::NoLinks
+    )
+}
+
+/// What a poll did.
+#[derive(Debug, Default)]
+pub struct PollReport {
+    /// Titles of the items that were queued.
+    pub queued: Vec<String>,
+    /// Items that failed, with why; they are tried again next poll unless
+    /// the failure can't go away.
+    pub failed: Vec<(String, Error)>,
+    /// Feeds that could not be fetched.
+    pub feed_errors: Vec<(String, Error)>,
+    /// New matching items marked seen without downloading (`--catch-up`).
+    pub skipped: usize,
+}
+
+/// Polls the subscriptions and queues what matches.
+#[derive(Clone)]
+pub struct Poller {
+    provider: std::sync::Arc<dyn DebridProvider>,
+    client: Client,
+    feeds: Vec<Feed>,
+    target_dir: PathBuf,
+    select: SelectConfig,
+    trackers: Vec<String>,
+    organize: OrganizeConfig,
+    hooks: HooksConfig,
+}
+
+impl fmt::Debug for Poller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Poller")
+            .field("feeds", &self.feeds)
+            .field("target_dir", &self.target_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Poller {
+    pub fn new(provider: Box<dyn DebridProvider>, client: Client, config: &Config) -> Result<Self> {
+        Ok(Poller {
+            provider: provider.into(),
+            client,
+            feeds: Feed::all(config)?,
+            target_dir: config
+                .download_dir
+                .clone()
+                .unwrap_or_else(|| env::current_dir().unwrap_or_else(|_| PathBuf::from("."))),
+            select: config.select.clone(),
+            trackers: config.trackers.clone(),
+            organize: config.organize.clone(),
+            hooks: config.hooks.clone(),
+        })
+    }
+
+    /// Fetches every feed and hands the downloads of new matching items to
+    /// `start`. With `catch_up` they are only marked seen, so subscribing
+    /// to a feed doesn't download its whole backlog.
+    pub async fn poll(
+        &self,
+        catch_up: bool,
+        start: &(dyn Fn(&Download) -> Result<()> + Sync),
+    ) -> PollReport {
+        let mut report = PollReport::default();
+        for feed in &self.feeds {
+            let items = match feed.fetch(&self.client).await {
+                Ok(items) => items,
+                Err(e) => {
+                    logging::log(
+                        Level::Warning,
+                        &format!("Feed {} failed: {}", feed.name, e),
+                        &[("LJ_EVENT", "rss_feed_failed")],
+                    );
+                    report.feed_errors.push((feed.name.clone(), e));
+                    continue;
+                }
+            };
+            for item in items {
+                let linked = item.magnet.is_some() || item.link.is_some();
+                if !linked || !feed.matches(&item.title) || feed.seen(&item) {
+                    continue;
+                }
+                if catch_up {
+                    let mut keys = vec![feed.guid_key(&item)];
+                    keys.extend(item.info_hash().map(|hash| hash_key(&hash)));
+                    store::mark_rss_seen(&keys).ok();
+                    report.skipped += 1;
+                    continue;
+                }
+                match self.take(feed, &item, start).await {
+                    Ok(()) => {
+                        logging::log(
+                            Level::Info,
+                            &format!("Queued {} from feed {}", item.title, feed.name),
+                            &[("LJ_EVENT", "rss_queued")],
+                        );
+                        report.queued.push(item.title);
+                    }
+                    Err(e) => {
+                        logging::log(
+                            Level::Warning,
+                            &format!("Feed item {} failed: {}", item.title, e),
+                            &[("LJ_EVENT", "rss_failed")],
+                        );
+                        if permanent(&e) {
+                            store::mark_rss_seen(&[feed.guid_key(&item)]).ok();
+                        }
+                        report.failed.push((item.title, e));
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    /// Queues one item and marks it seen.
+    async fn take(
+        &self,
+        feed: &Feed,
+        item: &FeedItem,
+        start: &(dyn Fn(&Download) -> Result<()> + Sync),
+    ) -> Result<()> {
+        let magnet = match (&item.magnet, &item.link) {
+            (Some(magnet), _) => magnet.clone(),
+            (None, Some(link)) => torrent::magnet_from_url(&self.client, link).await?,
+            (None, None) => return Err(Error::Other(format!("{} has no link", item.title))),
+        };
+        let mut keys = vec![feed.guid_key(item)];
+        if let Some(hash) = torrent::info_hash(&magnet) {
+            let key = hash_key(&hash);
+            // Another feed, or a .torrent link under a new GUID, had it
+            if store::rss_seen(&key) {
+                store::mark_rss_seen(&keys)?;
+                return Ok(());
+            }
+            keys.push(key);
+        }
+
+        let target_dir = feed
+            .download_dir
+            .as_ref()
+            .unwrap_or(&self.target_dir)
+            .to_string_lossy()
+            .to_string();
+        let fetched = pipeline::fetch_magnet(
+            self.provider.as_ref(),
+            &self.client,
+            &magnet::with_trackers(&magnet, &self.trackers),
+            &self.select,
+            Some(&target_dir),
+            &mut |_| {},
+        )
+        .await?;
+        let batch = store::new_batch_id();
+        let torrent = fetched.name.or_else(|| Some(item.title.clone()));
+        let folder = pipeline::torrent_dir(&target_dir, torrent.as_deref(), fetched.links.len());
+        let mut vetoed = Vec::new();
+        let mut started = 0;
+        for link in fetched.links {
+            let mut download = link
+                .download(&target_dir)
+                .in_batch(&batch, torrent.as_deref())
+                .of_torrent(torrent::info_hash(&magnet));
+            media::organize(&self.organize, &mut download);
+            // Files the library layout didn't place stay together
+            if download.target_dir == target_dir {
+                download.target_dir = folder.clone();
+            }
+            if let Err(e) = hooks::before_download(&self.hooks, &mut download) {
+                store::delete_download(&download.id);
+                vetoed.push(e.to_string());
+                continue;
+            }
+            start(&download)?;
+            started += 1;
+        }
+        store::mark_rss_seen(&keys)?;
+        if started == 0 && !vetoed.is_empty() {
+            return Err(Error::Other(vetoed.join("; ")));
+        }
+        Ok(())
+    }
+}
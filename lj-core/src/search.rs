@@ -0,0 +1,198 @@
+//! Torrent search through Torznab indexers (Jackett, Prowlarr and others).
+//!
+//! Every `[indexers.<name>]` endpoint is queried at once and the results
+//! merged, best seeded first, with one result per info hash. Where the
+//! provider can tell, each result is marked with whether the service
+//! already has it cached and can hand it over right away.
+
+use crate::config::IndexerConfig;
+use crate::error::{Error, Result};
+use crate::provider::DebridProvider;
+use crate::torrent;
+use futures_util::future::join_all;
+use reqwest::Client;
+use std::collections::{BTreeMap, HashSet};
+
+/// Results asked of each indexer.
+const LIMIT: u32 = 100;
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub title: String,
+    /// The indexer that found it, as the feed names it.
+    pub indexer: String,
+    pub size: u64,
+    pub seeders: Option<u32>,
+    pub info_hash: Option<String>,
+    pub magnet: Option<String>,
+    /// Where the `.torrent` file (or a redirect to the magnet) is.
+    pub link: Option<String>,
+    /// Whether the provider has it cached, if it can tell.
+    pub cached: Option<bool>,
+}
+
+fn feed_error(message: String) -> Error {
+    Error::Parse {
+        context: "Invalid Torznab response",
+        message,
+    }
+}
+
+/// Reads the items of a Torznab feed.
+pub fn parse_results(xml: &str, indexer: &str) -> Result<Vec<SearchResult>> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| feed_error(e.to_string()))?;
+    let root = doc.root_element();
+    if root.has_tag_name("error") {
+        return Err(feed_error(
+            root.attribute("description")
+                .unwrap_or("unknown error")
+                .to_string(),
+        ));
+    }
+
+    let mut results = Vec::new();
+    for item in doc.descendants().filter(|n| n.has_tag_name("item")) {
+        let text = |name: &str| {
+            item.children()
+                .find(|n| n.has_tag_name(name))
+                .and_then(|n| n.text())
+                .map(|t| t.trim().to_string())
+        };
+        let attr = |name: &str| {
+            item.children()
+                .find(|n| n.tag_name().name() == "attr" && n.attribute("name") == Some(name))
+                .and_then(|n| n.attribute("value"))
+                .map(str::to_string)
+        };
+        let enclosure = item.children().find(|n| n.has_tag_name("enclosure"));
+
+        let Some(title) = text("title") else {
+            continue;
+        };
+        let link = text("link").or_else(|| {
+            enclosure
+                .and_then(|n| n.attribute("url"))
+                .map(str::to_string)
+        });
+        // Some indexers put the magnet in the link itself
+        let magnet =
+            attr("magneturl").or_else(|| link.clone().filter(|l| l.starts_with("magnet:")));
+        let size = text("size")
+            .or_else(|| attr("size"))
+            .or_else(|| {
+                enclosure
+                    .and_then(|n| n.attribute("length"))
+                    .map(str::to_string)
+            })
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        results.push(SearchResult {
+            indexer: text("jackettindexer")
+                .or_else(|| text("prowlarrindexer"))
+                .unwrap_or_else(|| indexer.to_string()),
+            size,
+            seeders: attr("seeders").and_then(|s| s.parse().ok()),
+            info_hash: attr("infohash")
+                .map(|h| h.to_lowercase())
+                .or_else(|| magnet.as_deref().and_then(torrent::info_hash)),
+            link: link.filter(|l| !l.starts_with("magnet:")),
+            magnet,
+            title,
+            cached: None,
+        });
+    }
+    Ok(results)
+}
+
+async fn query(
+    client: &Client,
+    name: &str,
+    indexer: &IndexerConfig,
+    query: &str,
+) -> Result<Vec<SearchResult>> {
+    let context = "Indexer search failed";
+    let mut params = vec![
+        ("t", "search".to_string()),
+        ("q", query.to_string()),
+        ("limit", LIMIT.to_string()),
+    ];
+    if let Some(key) = &indexer.api_key {
+        params.push(("apikey", key.clone()));
+    }
+    let resp = client
+        .get(format!("{}/api", indexer.url.trim_end_matches('/')))
+        .query(&params)
+        .send()
+        .await
+        .map_err(Error::http(context))?;
+    let status = resp.status();
+    let body = resp.text().await.map_err(Error::http(context))?;
+    if !status.is_success() {
+        return Err(Error::Api {
+            context,
+            status,
+            code: None,
+            message: body,
+        });
+    }
+    parse_results(&body, name)
+}
+
+/// Searches every indexer for `text`. Results come best seeded first, one
+/// per torrent; indexers that failed are returned with their error.
+pub async fn search(
+    client: &Client,
+    indexers: &BTreeMap<String, IndexerConfig>,
+    text: &str,
+) -> (Vec<SearchResult>, Vec<(String, Error)>) {
+    let replies =
+        join_all(indexers.iter().map(|(name, indexer)| async move {
+            (name, query(client, name, indexer, text).await)
+        }))
+        .await;
+
+    let mut results = Vec::new();
+    let mut failures = Vec::new();
+    for (name, reply) in replies {
+        match reply {
+            Ok(found) => results.extend(found),
+            Err(e) => failures.push((name.clone(), e)),
+        }
+    }
+
+    results.sort_by_key(|r| std::cmp::Reverse(r.seeders.unwrap_or(0)));
+    let mut seen = HashSet::new();
+    results.retain(|r| r.info_hash.as_ref().is_none_or(|h| seen.insert(h.clone())));
+    (results, failures)
+}
+
+/// Marks which results `provider` has cached. Results stay unmarked if it
+/// can't tell.
+pub async fn mark_cached(provider: &dyn DebridProvider, results: &mut [SearchResult]) {
+    let hashes: Vec<String> = results.iter().filter_map(|r| r.info_hash.clone()).collect();
+    if hashes.is_empty() {
+        return;
+    }
+    if let Ok(Some(cached)) = provider.cached(&hashes).await {
+        for result in results {
+            if let Some(hash) = &result.info_hash {
+                result.cached = Some(cached.contains(hash));
+            }
+        }
+    }
+}
+
+/// The magnet link for a result, fetching its `.torrent` file if the feed
+/// gave none.
+pub async fn magnet(client: &Client, result: &SearchResult) -> Result<String> {
+    if let Some(magnet) = &result.magnet {
+        return Ok(magnet.clone());
+    }
+    let Some(link) = &result.link else {
+        return Err(Error::Other(format!(
+            "{} has no download link",
+            result.title
+        )));
+    };
+    torrent::magnet_from_url(client, link).await
+}
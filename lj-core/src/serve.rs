@@ -0,0 +1,537 @@
+//! HTTP/JSON API for controlling lj remotely.
+//!
+//! `lj serve` exposes the same operations as the CLI over HTTP, backed by
+//! the same [`control`](crate::control) functions, so it works alongside a
+//! running daemon or on its own with worker processes. Every request must
+//! carry `Authorization: Bearer <token>` when a token is configured.
+//!
+//! `/` serves a small single-page UI built into the binary. The page itself
+//! is public; it asks for the token and sends it with its API calls.
+//!
+//! | Method | Path | |
+//! |--------|------|-|
+//! | `GET` | `/api/downloads` | List download records |
+//! | `GET` | `/api/downloads/{id}` | One record, with its progress |
+//! | `POST` | `/api/downloads/{id}/cancel` | Cancel a running download |
+//! | `POST` | `/api/downloads/{id}/retry` | Restart a failed or cancelled download |
+//! | `POST` | `/api/magnets` | Add a magnet: `{"magnet": "magnet:?..."}` |
+//! | `GET` | `/api/magnets/{id}` | Where an added magnet is in the pipeline |
+//!
+//! Given a [`telegram::Bot`], the server also takes magnet links sent to it
+//! from the bot's chat and replies with how they went. Given a
+//! [`Blackhole`], it picks up the files Sonarr and Radarr drop into it.
+//! With `[qbittorrent] enabled`, it also answers the qBittorrent WebUI API
+//! under `/api/v2`, so the *arr apps can use lj as a download client.
+//! Given a [`Companion`], a second loopback listener takes magnets from
+//! browser extensions and bookmarklets.
+
+use crate::blackhole::{self, Blackhole};
+use crate::config::{ExtractConfig, HooksConfig, OrganizeConfig, QbittorrentConfig, SelectConfig};
+use crate::control;
+use crate::error::Result;
+use crate::hooks;
+use crate::magnet::{self, Magnet};
+use crate::media;
+use crate::pipeline;
+use crate::provider::{DebridProvider, TorrentStatus};
+use crate::store::{self, Download};
+use crate::telegram::{self, Bot};
+use crate::torrent;
+use axum::extract::{Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+mod companion;
+mod qbittorrent;
+
+pub use companion::{Companion, DEFAULT_COMPANION_BIND};
+
+/// Where the API listens when `[serve] bind` is unset.
+pub const DEFAULT_BIND: &str = "127.0.0.1:7878";
+
+const INDEX_HTML: &str = include_str!("serve/index.html");
+
+/// Pause before polling the bot again after an error.
+const TELEGRAM_RETRY: Duration = Duration::from_secs(5);
+
+/// How often the blackhole folders are checked.
+const BLACKHOLE_POLL: Duration = Duration::from_secs(5);
+
+pub struct ServeOptions {
+    pub bind: SocketAddr,
+    /// Bearer token required on every request, if any.
+    pub token: Option<String>,
+    /// Provider that added magnets are sent to.
+    pub provider: Box<dyn DebridProvider>,
+    pub client: Client,
+    /// Directory new downloads are saved to.
+    pub target_dir: String,
+    /// Which files of added magnets to download.
+    pub select: SelectConfig,
+    /// Trackers added to magnets that name none.
+    pub trackers: Vec<String>,
+    /// Bot whose chat may add magnets too.
+    pub telegram: Option<Bot>,
+    /// Runs `before_download` on added downloads.
+    pub hooks: HooksConfig,
+    /// Library layout for added downloads.
+    pub organize: OrganizeConfig,
+    /// Folders to take Sonarr/Radarr drops from.
+    pub blackhole: Option<Blackhole>,
+    /// Whether *arr downloads are extracted before they are handed over.
+    pub extract: ExtractConfig,
+    /// The qBittorrent API for Sonarr, Radarr and Prowlarr.
+    pub qbittorrent: QbittorrentConfig,
+    /// Listener for the browser companion.
+    pub companion: Option<Companion>,
+}
+
+/// Progress of a magnet added through the API.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum MagnetJob {
+    /// The provider is resolving or fetching the torrent.
+    Processing {
+        progress: f64,
+    },
+    /// Links are out and these downloads were started.
+    Started {
+        downloads: Vec<String>,
+        /// Why files were skipped by the `before_download` hook.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        vetoed: Vec<String>,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+struct Server {
+    token: Option<String>,
+    provider: Box<dyn DebridProvider>,
+    client: Client,
+    target_dir: String,
+    select: SelectConfig,
+    trackers: Vec<String>,
+    hooks: HooksConfig,
+    organize: OrganizeConfig,
+    extract: ExtractConfig,
+    qbittorrent: Option<qbittorrent::Qbittorrent>,
+    jobs: Mutex<HashMap<u64, MagnetJob>>,
+    next_job: AtomicU64,
+}
+
+/// An error reply, sent as `{"error": "..."}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.1 });
+        (self.0, Json(body)).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<Json<T>, ApiError>;
+
+/// A download record as the API reports it.
+#[derive(Serialize)]
+struct DownloadView {
+    #[serde(flatten)]
+    download: Download,
+    /// Percent done, or `None` while the size is unknown.
+    progress: Option<f64>,
+    size_known: bool,
+}
+
+impl From<Download> for DownloadView {
+    fn from(download: Download) -> Self {
+        let progress = (download.total_bytes > 0)
+            .then(|| download.downloaded_bytes as f64 / download.total_bytes as f64 * 100.0);
+        DownloadView {
+            size_known: download.size_known(),
+            download,
+            progress,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AddMagnet {
+    magnet: String,
+}
+
+/// Serves the API until SIGINT.
+pub async fn run_server(opts: ServeOptions) -> io::Result<()> {
+    let server = Arc::new(Server {
+        token: opts.token,
+        provider: opts.provider,
+        client: opts.client,
+        target_dir: opts.target_dir,
+        select: opts.select,
+        trackers: opts.trackers,
+        hooks: opts.hooks,
+        organize: opts.organize,
+        extract: opts.extract,
+        qbittorrent: qbittorrent::Qbittorrent::from_config(&opts.qbittorrent)?,
+        jobs: Mutex::new(HashMap::new()),
+        next_job: AtomicU64::new(1),
+    });
+
+    if let Some(bot) = opts.telegram {
+        tokio::spawn(run_telegram(server.clone(), bot));
+    }
+    if let Some(blackhole) = opts.blackhole {
+        tokio::spawn(run_blackhole(server.clone(), blackhole));
+    }
+
+    if let Some(companion) = opts.companion {
+        let listener = TcpListener::bind(companion.bind).await?;
+        let router = companion::router(server.clone(), companion);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+    }
+
+    let listener = TcpListener::bind(opts.bind).await?;
+    axum::serve(listener, router(server))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+}
+
+fn router(server: Arc<Server>) -> Router {
+    let router = Router::new()
+        .route("/api/downloads", get(list_downloads))
+        .route("/api/downloads/{id}", get(get_download))
+        .route("/api/downloads/{id}/cancel", post(cancel_download))
+        .route("/api/downloads/{id}/retry", post(retry_download))
+        .route("/api/magnets", post(add_magnet))
+        .route("/api/magnets/{id}", get(get_magnet))
+        .layer(middleware::from_fn_with_state(server.clone(), authorize))
+        // Added after the auth layer, which only wraps the routes above
+        .route("/", get(index));
+    // The qBittorrent API has its own login
+    let router = if server.qbittorrent.is_some() {
+        router.merge(qbittorrent::router(server.clone()))
+    } else {
+        router
+    };
+    router.with_state(server)
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+async fn authorize(
+    State(server): State<Arc<Server>>,
+    req: Request,
+    next: Next,
+) -> std::result::Result<Response, ApiError> {
+    if let Some(token) = &server.token {
+        let given = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if given != Some(token.as_str()) {
+            return Err(ApiError(
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid token".to_string(),
+            ));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("Download not found: {}", id))
+}
+
+async fn list_downloads() -> ApiResult<Vec<DownloadView>> {
+    let downloads = control::list_downloads();
+    Ok(Json(
+        downloads.into_iter().map(DownloadView::from).collect(),
+    ))
+}
+
+async fn get_download(Path(id): Path<String>) -> ApiResult<DownloadView> {
+    store::load_download(&id)
+        .map(|dl| Json(dl.into()))
+        .ok_or_else(|| not_found(&id))
+}
+
+async fn cancel_download(Path(id): Path<String>) -> ApiResult<serde_json::Value> {
+    if store::load_download(&id).is_none() {
+        return Err(not_found(&id));
+    }
+    let cancelled = control::cancel_download(&id);
+    Ok(Json(serde_json::json!({ "cancelled": cancelled })))
+}
+
+async fn retry_download(Path(id): Path<String>) -> ApiResult<DownloadView> {
+    if store::load_download(&id).is_none() {
+        return Err(not_found(&id));
+    }
+    control::retry_download(&id)
+        .map(|dl| Json(dl.into()))
+        .map_err(|e| ApiError(StatusCode::CONFLICT, e.to_string()))
+}
+
+async fn add_magnet(
+    State(server): State<Arc<Server>>,
+    Json(body): Json<AddMagnet>,
+) -> std::result::Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    if let Err(e) = Magnet::parse(&body.magnet) {
+        return Err(ApiError(StatusCode::BAD_REQUEST, e.to_string()));
+    }
+
+    let (id, _) = server.submit(body.magnet);
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "id": id.to_string() })),
+    ))
+}
+
+async fn get_magnet(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<u64>,
+) -> ApiResult<MagnetJob> {
+    server
+        .jobs
+        .lock()
+        .unwrap()
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("Magnet not found: {}", id)))
+}
+
+/// Takes magnets from the bot's chat until the server stops. Messages from
+/// any other chat are ignored.
+async fn run_telegram(server: Arc<Server>, bot: Bot) {
+    let mut offset = 0;
+    loop {
+        let messages = match bot.messages(&server.client, &mut offset).await {
+            Ok(messages) => messages,
+            Err(_) => {
+                tokio::time::sleep(TELEGRAM_RETRY).await;
+                continue;
+            }
+        };
+        for message in messages {
+            if message.chat_id != bot.chat_id() {
+                continue;
+            }
+            let magnets = telegram::magnets(&message.text);
+            if magnets.is_empty() {
+                let _ = bot
+                    .send_message(&server.client, "Send a magnet link to download it.")
+                    .await;
+                continue;
+            }
+            for magnet in magnets {
+                let (id, task) = server.submit(magnet.to_string());
+                let _ = bot
+                    .send_message(&server.client, &format!("Added magnet {}", id))
+                    .await;
+                let bot = bot.clone();
+                let client = server.client.clone();
+                tokio::spawn(async move {
+                    let reply = match task.await {
+                        Ok(MagnetJob::Started { downloads, vetoed }) => {
+                            let names: Vec<String> = downloads
+                                .iter()
+                                .filter_map(|id| store::load_download(id))
+                                .map(|dl| dl.filename)
+                                .collect();
+                            let mut reply =
+                                format!("Magnet {}: downloading {}", id, names.join(", "));
+                            for reason in vetoed {
+                                reply.push_str(&format!("\nSkipped {}", reason));
+                            }
+                            reply
+                        }
+                        Ok(MagnetJob::Failed { error }) => {
+                            format!("Magnet {} failed: {}", id, error)
+                        }
+                        _ => return,
+                    };
+                    let _ = bot.send_message(&client, &reply).await;
+                });
+            }
+        }
+    }
+}
+
+/// Fetches what lands in the blackhole and hands finished releases over,
+/// until the server stops.
+async fn run_blackhole(server: Arc<Server>, blackhole: Blackhole) {
+    let blackhole = Arc::new(blackhole);
+    // Drops stay in the folder while they are fetched, so a restart
+    // picks them up again
+    let in_flight: Arc<Mutex<HashSet<PathBuf>>> = Arc::default();
+    loop {
+        for path in blackhole.dropped() {
+            if !in_flight.lock().unwrap().insert(path.clone()) {
+                continue;
+            }
+            let server = server.clone();
+            let blackhole = blackhole.clone();
+            let in_flight = in_flight.clone();
+            tokio::spawn(async move {
+                match server.fetch_dropped(&blackhole, &path).await {
+                    Ok(()) => {
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    Err(e) => blackhole.reject(&path, &e),
+                }
+                in_flight.lock().unwrap().remove(&path);
+            });
+        }
+        blackhole.finish_releases(&server.extract);
+        tokio::time::sleep(BLACKHOLE_POLL).await;
+    }
+}
+
+impl Server {
+    /// Starts running `magnet` through the pipeline as a new job. The task
+    /// ends with the job's final state.
+    fn submit(self: &Arc<Self>, magnet: String) -> (u64, JoinHandle<MagnetJob>) {
+        let id = self.next_job.fetch_add(1, Ordering::Relaxed);
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(id, MagnetJob::Processing { progress: 0.0 });
+
+        let server = self.clone();
+        let task = tokio::spawn(async move {
+            let job = server.fetch(id, &magnet).await;
+            server.jobs.lock().unwrap().insert(id, job.clone());
+            job
+        });
+        (id, task)
+    }
+
+    /// Runs one magnet through the pipeline and starts its downloads.
+    async fn fetch(&self, job: u64, magnet: &str) -> MagnetJob {
+        let fetched = pipeline::fetch_magnet(
+            self.provider.as_ref(),
+            &self.client,
+            &magnet::with_trackers(magnet, &self.trackers),
+            &self.select,
+            Some(&self.target_dir),
+            &mut |status| {
+                self.jobs.lock().unwrap().insert(
+                    job,
+                    MagnetJob::Processing {
+                        progress: status.progress,
+                    },
+                );
+            },
+        )
+        .await;
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                return MagnetJob::Failed {
+                    error: e.to_string(),
+                };
+            }
+        };
+
+        let batch = store::new_batch_id();
+        let torrent = fetched.name;
+        let folder =
+            pipeline::torrent_dir(&self.target_dir, torrent.as_deref(), fetched.links.len());
+        let mut downloads = Vec::new();
+        let mut vetoed = Vec::new();
+        for link in fetched.links {
+            let mut download = link
+                .download(&self.target_dir)
+                .in_batch(&batch, torrent.as_deref())
+                .of_torrent(torrent::info_hash(magnet));
+            media::organize(&self.organize, &mut download);
+            // Files the library layout didn't place stay together
+            if download.target_dir == self.target_dir {
+                download.target_dir = folder.clone();
+            }
+            if let Err(e) = hooks::before_download(&self.hooks, &mut download) {
+                store::delete_download(&download.id);
+                vetoed.push(e.to_string());
+                continue;
+            }
+            downloads.push(download);
+        }
+        if downloads.is_empty() && !vetoed.is_empty() {
+            return MagnetJob::Failed {
+                error: vetoed.join("; "),
+            };
+        }
+        if let Err(e) = control::start_downloads(&downloads) {
+            return MagnetJob::Failed {
+                error: e.to_string(),
+            };
+        }
+        MagnetJob::Started {
+            downloads: downloads.into_iter().map(|dl| dl.id).collect(),
+            vetoed,
+        }
+    }
+
+    /// Runs a magnet for an *arr, which renames and places the files
+    /// itself, so there is no organizing or `before_download`. The
+    /// downloads come back unstarted, for the caller to place.
+    async fn fetch_release(
+        &self,
+        magnet: &str,
+        on_progress: &mut (dyn for<'a> FnMut(&'a TorrentStatus) + Send),
+    ) -> Result<Vec<Download>> {
+        let fetched = pipeline::fetch_magnet(
+            self.provider.as_ref(),
+            &self.client,
+            &magnet::with_trackers(magnet, &self.trackers),
+            &self.select,
+            Some(&self.target_dir),
+            on_progress,
+        )
+        .await?;
+
+        let batch = store::new_batch_id();
+        let torrent = fetched.name;
+        Ok(fetched
+            .links
+            .into_iter()
+            .map(|link| {
+                link.download(&self.target_dir)
+                    .in_batch(&batch, torrent.as_deref())
+                    .of_torrent(torrent::info_hash(magnet))
+            })
+            .collect())
+    }
+
+    /// Downloads every file of a dropped torrent into its release's
+    /// staging folder.
+    async fn fetch_dropped(&self, blackhole: &Blackhole, path: &std::path::Path) -> Result<()> {
+        let magnet = blackhole::read_drop(path)?;
+        let target_dir = blackhole.staging_dir(&magnet).to_string_lossy().to_string();
+        let mut downloads = self.fetch_release(&magnet, &mut |_| {}).await?;
+        for download in &mut downloads {
+            download.target_dir = target_dir.clone();
+        }
+        control::start_downloads(&downloads)
+    }
+}
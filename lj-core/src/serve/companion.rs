@@ -0,0 +1,112 @@
+//! Listener for browser extensions and bookmarklets.
+//!
+//! A script on a tracker page runs with that page's origin, so the request
+//! it makes to lj is cross-origin and comes from whatever site the user is
+//! on. The companion therefore listens on its own loopback address, answers
+//! CORS preflights from any origin, and only takes magnets, with a token of
+//! its own: a leaked bookmarklet can add downloads but not list or cancel
+//! them. It offers the same `/api/magnets` routes as the main API, so an
+//! extension can talk to either.
+
+use super::{Server, add_magnet, get_magnet};
+use crate::config::CompanionConfig;
+use crate::error::{Error, Result};
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Where the companion listens when `[companion] bind` is unset.
+pub const DEFAULT_COMPANION_BIND: &str = "127.0.0.1:7879";
+
+#[derive(Debug, Clone)]
+pub struct Companion {
+    pub bind: SocketAddr,
+    token: String,
+}
+
+impl Companion {
+    /// The listener described by `config`, if it is enabled. It must be on
+    /// a loopback address and have a token, since any page the browser
+    /// opens can reach it.
+    pub fn from_config(config: &CompanionConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let bind = config.bind.as_deref().unwrap_or(DEFAULT_COMPANION_BIND);
+        let bind: SocketAddr = bind
+            .parse()
+            .map_err(|_| Error::Config(format!("invalid companion address '{}'", bind)))?;
+        if !bind.ip().is_loopback() {
+            return Err(Error::Config(format!(
+                "companion.bind must be a loopback address, not {}",
+                bind
+            )));
+        }
+        let token = config
+            .token
+            .clone()
+            .filter(|t| !t.trim().is_empty())
+            .ok_or_else(|| Error::Config("companion needs a token".to_string()))?;
+        Ok(Some(Companion { bind, token }))
+    }
+}
+
+pub(super) fn router(server: Arc<Server>, companion: Companion) -> Router {
+    Router::new()
+        .route("/api/magnets", post(add_magnet))
+        .route("/api/magnets/{id}", get(get_magnet))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(companion),
+            authorize,
+        ))
+        // Outermost, so preflights skip the token check and errors can be
+        // read by the page
+        .layer(middleware::from_fn(cors))
+        .with_state(server)
+}
+
+async fn authorize(State(companion): State<Arc<Companion>>, req: Request, next: Next) -> Response {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if given != Some(companion.token.as_str()) {
+        let body = serde_json::json!({ "error": "Missing or invalid token" });
+        return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+    }
+    next.run(req).await
+}
+
+async fn cors(req: Request, next: Next) -> Response {
+    let mut resp = if req.method() == Method::OPTIONS {
+        let mut resp = StatusCode::NO_CONTENT.into_response();
+        let headers = resp.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, POST"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("authorization, content-type"),
+        );
+        // Chrome asks before letting a public site reach a loopback address
+        headers.insert(
+            "access-control-allow-private-network",
+            HeaderValue::from_static("true"),
+        );
+        resp
+    } else {
+        next.run(req).await
+    };
+    resp.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    resp
+}
@@ -0,0 +1,774 @@
+//! qBittorrent WebUI API (v2), enough of it for Sonarr, Radarr and
+//! Prowlarr to use lj as their download client.
+//!
+//! Added torrents go through the provider like any magnet, and their files
+//! are downloaded into the save path, in a folder named after the torrent
+//! when there is more than one, as qBittorrent does. Torrents are listed
+//! by info hash from the download records, so they survive restarts; one
+//! still with the provider only exists in memory until its downloads
+//! start. A torrent whose files have all completed is reported as paused
+//! after seeding (`pausedUP`), which the *arr imports and may then remove.
+
+use super::Server;
+use crate::config::{ExtractConfig, QbittorrentConfig};
+use crate::control;
+use crate::extract;
+use crate::magnet::Magnet;
+use crate::platform::sanitize_filename;
+use crate::progress;
+use crate::store::{self, Download, DownloadStatus};
+use crate::torrent;
+use axum::extract::{FromRequest, Multipart, Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::AbortHandle;
+
+/// qBittorrent and API versions reported to clients.
+const APP_VERSION: &str = "v4.6.7";
+const WEBAPI_VERSION: &str = "2.9.3";
+
+/// What qBittorrent reports as an unknown ETA.
+const ETA_UNKNOWN: u64 = 8_640_000;
+
+pub(super) struct Qbittorrent {
+    username: Option<String>,
+    password: Option<String>,
+    /// Session cookie handed out on login.
+    sid: String,
+    /// Torrents still with the provider, by hash.
+    pending: Mutex<HashMap<String, Pending>>,
+    /// Categories created by clients, on top of those in use.
+    categories: Mutex<BTreeSet<String>>,
+}
+
+struct Pending {
+    name: String,
+    category: String,
+    save_path: String,
+    added_on: u64,
+    progress: f64,
+    error: Option<String>,
+    task: Option<AbortHandle>,
+}
+
+impl Qbittorrent {
+    /// The API described by `config`, if it is enabled. Fails if the OS
+    /// has no randomness for the session cookie.
+    pub(super) fn from_config(config: &QbittorrentConfig) -> io::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let mut seed = [0u8; 32];
+        getrandom::fill(&mut seed)?;
+        let sid = seed.iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(Some(Qbittorrent {
+            username: config.username.clone(),
+            password: config.password.clone(),
+            sid,
+            pending: Mutex::new(HashMap::new()),
+            categories: Mutex::new(BTreeSet::new()),
+        }))
+    }
+}
+
+pub(super) fn router(server: Arc<Server>) -> Router<Arc<Server>> {
+    Router::new()
+        .route("/api/v2/auth/logout", post(ok))
+        .route("/api/v2/app/version", get(|| async { APP_VERSION }))
+        .route(
+            "/api/v2/app/webapiVersion",
+            get(|| async { WEBAPI_VERSION }),
+        )
+        .route("/api/v2/app/preferences", get(preferences))
+        .route("/api/v2/torrents/categories", get(categories))
+        .route("/api/v2/torrents/createCategory", post(create_category))
+        .route("/api/v2/torrents/add", post(add))
+        .route("/api/v2/torrents/info", get(info))
+        .route("/api/v2/torrents/properties", get(properties))
+        .route("/api/v2/torrents/files", get(files))
+        .route("/api/v2/torrents/delete", post(delete))
+        .route("/api/v2/torrents/setCategory", post(set_category))
+        // Seeding and queueing don't apply; accepted so clients don't fail
+        .route("/api/v2/torrents/setShareLimits", post(ok))
+        .route("/api/v2/torrents/setForceStart", post(ok))
+        .route("/api/v2/torrents/topPrio", post(ok))
+        .route("/api/v2/torrents/bottomPrio", post(ok))
+        .layer(middleware::from_fn_with_state(server, authorize))
+        .route("/api/v2/auth/login", post(login))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The info hash of a download added through this API. Other downloads
+/// record theirs too, but aren't this API's to list or delete.
+fn hash_of(dl: &Download) -> Option<&String> {
+    dl.category.as_ref().and(dl.info_hash.as_ref())
+}
+
+impl Server {
+    fn qbittorrent(&self) -> &Qbittorrent {
+        self.qbittorrent
+            .as_ref()
+            .expect("the API is only routed when configured")
+    }
+}
+
+async fn ok() -> &'static str {
+    "Ok."
+}
+
+async fn login(
+    State(server): State<Arc<Server>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let qb = server.qbittorrent();
+    let matches = |expected: &Option<String>, field: &str| {
+        expected
+            .as_ref()
+            .is_none_or(|value| form.get(field) == Some(value))
+    };
+    if !matches(&qb.username, "username") || !matches(&qb.password, "password") {
+        return "Fails.".into_response();
+    }
+    (
+        [(
+            header::SET_COOKIE,
+            format!("SID={}; HttpOnly; path=/", qb.sid),
+        )],
+        "Ok.",
+    )
+        .into_response()
+}
+
+/// Without a login configured, every request is let through, like
+/// qBittorrent's "bypass authentication" options.
+async fn authorize(State(server): State<Arc<Server>>, req: Request, next: Next) -> Response {
+    let qb = server.qbittorrent();
+    if qb.username.is_some() || qb.password.is_some() {
+        let sid = req
+            .headers()
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .find_map(|pair| pair.trim().strip_prefix("SID="));
+        if sid != Some(qb.sid.as_str()) {
+            return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+        }
+    }
+    next.run(req).await
+}
+
+async fn preferences(State(server): State<Arc<Server>>) -> Json<Value> {
+    Json(json!({
+        "save_path": server.target_dir,
+        "temp_path_enabled": false,
+        "queueing_enabled": false,
+        "dht": true,
+        // Nothing is seeded, so every completed torrent is past its limits
+        "max_ratio_enabled": true,
+        "max_ratio": 0,
+        "max_ratio_act": 0,
+        "max_seeding_time_enabled": true,
+        "max_seeding_time": 0,
+    }))
+}
+
+async fn categories(State(server): State<Arc<Server>>) -> Json<Value> {
+    let qb = server.qbittorrent();
+    let mut names = qb.categories.lock().unwrap().clone();
+    names.extend(
+        qb.pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|p| p.category.clone()),
+    );
+    names.extend(
+        store::load_all_downloads()
+            .into_iter()
+            .filter(|dl| hash_of(dl).is_some())
+            .filter_map(|dl| dl.category),
+    );
+    let categories: BTreeMap<String, Value> = names
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .map(|name| (name.clone(), json!({ "name": name, "savePath": "" })))
+        .collect();
+    Json(json!(categories))
+}
+
+async fn create_category(
+    State(server): State<Arc<Server>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> &'static str {
+    if let Some(name) = form.get("category").filter(|c| !c.is_empty()) {
+        server
+            .qbittorrent()
+            .categories
+            .lock()
+            .unwrap()
+            .insert(name.clone());
+    }
+    "Ok."
+}
+
+/// The fields of an add request, sent as a form or as multipart with
+/// `.torrent` file uploads.
+#[derive(Default)]
+struct AddForm {
+    urls: Vec<String>,
+    torrents: Vec<Vec<u8>>,
+    fields: HashMap<String, String>,
+}
+
+async fn read_add_form(req: Request) -> Option<AddForm> {
+    let multipart = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+    let mut form = AddForm::default();
+    let mut fields = Vec::new();
+    if multipart {
+        let mut multipart = Multipart::from_request(req, &()).await.ok()?;
+        while let Some(field) = multipart.next_field().await.ok()? {
+            let name = field.name().unwrap_or_default().to_string();
+            if name == "torrents" {
+                form.torrents.push(field.bytes().await.ok()?.to_vec());
+            } else {
+                fields.push((name, field.text().await.ok()?));
+            }
+        }
+    } else {
+        let Form(pairs) = Form::<Vec<(String, String)>>::from_request(req, &())
+            .await
+            .ok()?;
+        fields = pairs;
+    }
+    for (name, value) in fields {
+        if name == "urls" {
+            form.urls.extend(
+                value
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(str::to_string),
+            );
+        } else {
+            form.fields.insert(name, value);
+        }
+    }
+    Some(form)
+}
+
+/// The magnet for a URL given to `add`: magnets as they are, anything else
+/// fetched as a `.torrent` file.
+async fn url_magnet(server: &Server, url: &str) -> Option<String> {
+    if url.starts_with("magnet:") {
+        return Some(url.to_string());
+    }
+    torrent::magnet_from_url(&server.client, url).await.ok()
+}
+
+async fn add(State(server): State<Arc<Server>>, req: Request) -> Response {
+    let Some(form) = read_add_form(req).await else {
+        return (StatusCode::BAD_REQUEST, "Invalid request").into_response();
+    };
+    let mut magnets = Vec::new();
+    for url in &form.urls {
+        magnets.extend(url_magnet(&server, url).await);
+    }
+    magnets.extend(
+        form.torrents
+            .iter()
+            .filter_map(|data| torrent::to_magnet(data).ok()),
+    );
+
+    let category = form.fields.get("category").cloned().unwrap_or_default();
+    let save_path = form
+        .fields
+        .get("savepath")
+        .filter(|p| !p.is_empty())
+        .cloned()
+        .unwrap_or_else(|| server.target_dir.clone());
+    if let Err(e) = control::check_target_dir(&save_path) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    let known: BTreeSet<String> = store::load_all_downloads()
+        .into_iter()
+        .filter_map(|dl| hash_of(&dl).cloned())
+        .collect();
+
+    let mut added = 0;
+    for magnet in magnets {
+        let Some(hash) = torrent::info_hash(&magnet) else {
+            continue;
+        };
+        added += 1;
+        // Like qBittorrent, a torrent that is already there is left alone
+        if known.contains(&hash)
+            || server
+                .qbittorrent()
+                .pending
+                .lock()
+                .unwrap()
+                .contains_key(&hash)
+        {
+            continue;
+        }
+        server.qbittorrent().pending.lock().unwrap().insert(
+            hash.clone(),
+            Pending {
+                name: Magnet::parse(&magnet)
+                    .map(|magnet| magnet.display_name().to_string())
+                    .unwrap_or_else(|_| hash.clone()),
+                category: category.clone(),
+                save_path: save_path.clone(),
+                added_on: now(),
+                progress: 0.0,
+                error: None,
+                task: None,
+            },
+        );
+        let task = tokio::spawn(fetch(server.clone(), magnet, hash.clone()));
+        if let Some(pending) = server.qbittorrent().pending.lock().unwrap().get_mut(&hash) {
+            pending.task = Some(task.abort_handle());
+        }
+    }
+
+    if added == 0 {
+        return "Fails.".into_response();
+    }
+    "Ok.".into_response()
+}
+
+/// Runs a pending torrent through the provider and starts its downloads.
+async fn fetch(server: Arc<Server>, magnet: String, hash: String) {
+    let qb = server.qbittorrent();
+    let result = server
+        .fetch_release(&magnet, &mut |status| {
+            if let Some(pending) = qb.pending.lock().unwrap().get_mut(&hash) {
+                pending.progress = status.progress;
+            }
+        })
+        .await;
+
+    let Some((name, category, save_path)) = qb
+        .pending
+        .lock()
+        .unwrap()
+        .get(&hash)
+        .map(|p| (p.name.clone(), p.category.clone(), p.save_path.clone()))
+    else {
+        return;
+    };
+    let result = result.and_then(|mut downloads| {
+        let target_dir = if downloads.len() > 1 {
+            Path::new(&save_path)
+                .join(sanitize_filename(&name))
+                .to_string_lossy()
+                .to_string()
+        } else {
+            save_path
+        };
+        for download in &mut downloads {
+            download.target_dir = target_dir.clone();
+            download.info_hash = Some(hash.clone());
+            download.category = Some(category.clone());
+        }
+        control::start_downloads(&downloads)
+    });
+
+    let mut pending = qb.pending.lock().unwrap();
+    match result {
+        Ok(()) => {
+            pending.remove(&hash);
+        }
+        Err(e) => {
+            if let Some(p) = pending.get_mut(&hash) {
+                p.error = Some(e.to_string());
+                p.task = None;
+            }
+        }
+    }
+}
+
+/// A torrent as `torrents/info` lists it.
+struct Torrent {
+    hash: String,
+    name: String,
+    category: String,
+    save_path: String,
+    content_path: String,
+    size: u64,
+    downloaded: u64,
+    speed: f64,
+    state: &'static str,
+    added_on: u64,
+    /// Paths relative to `save_path`, with sizes and bytes done.
+    files: Vec<(String, u64, u64)>,
+}
+
+impl Torrent {
+    fn from_downloads(hash: String, downloads: &[Download], extract: &ExtractConfig) -> Self {
+        let first = &downloads[0];
+        let dir = Path::new(&first.target_dir);
+        // A single file is saved straight into the save path
+        let (save_path, content_path, prefix) = if downloads.len() > 1 {
+            let name = dir.file_name().map(|n| n.to_string_lossy().to_string());
+            (
+                dir.parent().unwrap_or(dir).to_string_lossy().to_string(),
+                first.target_dir.clone(),
+                name.map(|n| format!("{}/", n)).unwrap_or_default(),
+            )
+        } else {
+            (
+                first.target_dir.clone(),
+                first.target_path().to_string_lossy().to_string(),
+                String::new(),
+            )
+        };
+
+        let all = |f: fn(&DownloadStatus) -> bool| downloads.iter().all(|dl| f(&dl.status));
+        let any = |f: fn(&DownloadStatus) -> bool| downloads.iter().any(|dl| f(&dl.status));
+        let state = if all(|s| *s == DownloadStatus::Completed) {
+            // Held back from import until the archives are extracted
+            if extract::is_pending(extract, downloads) {
+                "checkingUP"
+            } else {
+                "pausedUP"
+            }
+        } else if any(|s| *s == DownloadStatus::Downloading) {
+            "downloading"
+        } else if any(|s| *s == DownloadStatus::Pending) {
+            "queuedDL"
+        } else if any(|s| matches!(s, DownloadStatus::Failed(_))) {
+            "error"
+        } else {
+            "pausedDL"
+        };
+
+        let name = if downloads.len() > 1 {
+            first.torrent.clone().unwrap_or_else(|| hash.clone())
+        } else {
+            first.filename.clone()
+        };
+        Torrent {
+            hash,
+            name,
+            category: first.category.clone().unwrap_or_default(),
+            save_path,
+            content_path,
+            size: downloads.iter().map(|dl| dl.total_bytes).sum(),
+            downloaded: downloads.iter().map(|dl| dl.downloaded_bytes).sum(),
+            speed: downloads.iter().map(|dl| dl.speed).sum(),
+            state,
+            added_on: downloads.iter().map(|dl| dl.started_at).min().unwrap_or(0),
+            files: downloads
+                .iter()
+                .map(|dl| {
+                    (
+                        format!("{}{}", prefix, dl.filename),
+                        dl.total_bytes,
+                        dl.downloaded_bytes,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn from_pending(hash: String, pending: &Pending) -> Self {
+        let state = match (&pending.error, pending.progress) {
+            (Some(_), _) => "error",
+            (None, p) if p > 0.0 => "downloading",
+            _ => "metaDL",
+        };
+        Torrent {
+            content_path: Path::new(&pending.save_path)
+                .join(sanitize_filename(&pending.name))
+                .to_string_lossy()
+                .to_string(),
+            hash,
+            name: pending.name.clone(),
+            category: pending.category.clone(),
+            save_path: pending.save_path.clone(),
+            size: 0,
+            downloaded: 0,
+            speed: 0.0,
+            state,
+            added_on: pending.added_on,
+            files: Vec::new(),
+        }
+    }
+
+    fn progress(&self) -> f64 {
+        match self.state {
+            "pausedUP" => 1.0,
+            _ if self.size == 0 => 0.0,
+            _ => self.downloaded as f64 / self.size as f64,
+        }
+    }
+
+    fn eta(&self) -> u64 {
+        match self.state {
+            "pausedUP" => 0,
+            _ if self.speed < 1.0 => ETA_UNKNOWN,
+            _ => (self.size.saturating_sub(self.downloaded) as f64 / self.speed) as u64,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "hash": self.hash,
+            "name": self.name,
+            "category": self.category,
+            "tags": "",
+            "save_path": self.save_path,
+            "content_path": self.content_path,
+            "size": self.size,
+            "total_size": self.size,
+            "downloaded": self.downloaded,
+            "amount_left": self.size.saturating_sub(self.downloaded),
+            "progress": self.progress(),
+            "dlspeed": self.speed as u64,
+            "upspeed": 0,
+            "eta": self.eta(),
+            "state": self.state,
+            "added_on": self.added_on,
+            "ratio": 0,
+            "ratio_limit": -2,
+            "seeding_time": 0,
+            "seeding_time_limit": -2,
+            "inactive_seeding_time_limit": -2,
+            "num_seeds": 0,
+            "num_leechs": 0,
+            "last_activity": now(),
+        })
+    }
+}
+
+/// Every torrent, from the download records and the pending ones.
+fn torrents(server: &Server) -> Vec<Torrent> {
+    let mut by_hash: BTreeMap<String, Vec<Download>> = BTreeMap::new();
+    let mut downloads = store::load_all_downloads();
+    progress::overlay(&mut downloads);
+    for dl in downloads {
+        if let Some(hash) = hash_of(&dl).cloned() {
+            by_hash.entry(hash).or_default().push(dl);
+        }
+    }
+    let mut torrents: Vec<Torrent> = by_hash
+        .into_iter()
+        .map(|(hash, downloads)| Torrent::from_downloads(hash, &downloads, &server.extract))
+        .collect();
+    for (hash, pending) in server.qbittorrent().pending.lock().unwrap().iter() {
+        if !torrents.iter().any(|t| &t.hash == hash) {
+            torrents.push(Torrent::from_pending(hash.clone(), pending));
+        }
+    }
+    torrents
+}
+
+/// Which torrents a `hashes` parameter names.
+enum Hashes {
+    /// The parameter is missing or empty.
+    Missing,
+    /// `all`.
+    All,
+    /// `|`-separated hashes.
+    Some(Vec<String>),
+}
+
+impl Hashes {
+    fn from(params: &HashMap<String, String>, key: &str) -> Hashes {
+        match params.get(key).map(String::as_str) {
+            None | Some("") => Hashes::Missing,
+            Some("all") => Hashes::All,
+            Some(value) => Hashes::Some(value.split('|').map(|h| h.to_lowercase()).collect()),
+        }
+    }
+
+    fn contains(&self, hash: &String) -> bool {
+        match self {
+            Hashes::Missing => false,
+            Hashes::All => true,
+            Hashes::Some(hashes) => hashes.contains(hash),
+        }
+    }
+}
+
+/// The torrents `params` names, every one if it names none, as a listing
+/// is filtered rather than acted on.
+fn selected(server: &Server, params: &HashMap<String, String>) -> Vec<Torrent> {
+    let wanted = match Hashes::from(params, "hashes") {
+        Hashes::Missing => Hashes::All,
+        wanted => wanted,
+    };
+    torrents(server)
+        .into_iter()
+        .filter(|t| wanted.contains(&t.hash))
+        .collect()
+}
+
+async fn info(
+    State(server): State<Arc<Server>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Vec<Value>> {
+    let category = params.get("category");
+    let torrents = selected(&server, &params)
+        .into_iter()
+        .filter(|t| category.is_none_or(|c| &t.category == c))
+        .map(|t| t.to_json())
+        .collect();
+    Json(torrents)
+}
+
+type NotFound = (StatusCode, &'static str);
+
+fn one(server: &Server, params: &HashMap<String, String>) -> Result<Torrent, NotFound> {
+    let hash = params.get("hash").map(|h| h.to_lowercase());
+    torrents(server)
+        .into_iter()
+        .find(|t| Some(&t.hash) == hash.as_ref())
+        .ok_or((StatusCode::NOT_FOUND, "Torrent hash was not found"))
+}
+
+async fn properties(
+    State(server): State<Arc<Server>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, NotFound> {
+    let t = one(&server, &params)?;
+    Ok(Json(json!({
+        "hash": t.hash,
+        "name": t.name,
+        "save_path": t.save_path,
+        "total_size": t.size,
+        "total_downloaded": t.downloaded,
+        "dl_speed": t.speed as u64,
+        "eta": t.eta(),
+        "addition_date": t.added_on,
+        "completion_date": -1,
+        "creation_date": t.added_on,
+        "seeding_time": 0,
+        "share_ratio": 0,
+        "piece_size": 0,
+        "comment": "",
+    })))
+}
+
+async fn files(
+    State(server): State<Arc<Server>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Value>>, NotFound> {
+    let t = one(&server, &params)?;
+    Ok(Json(
+        t.files
+            .iter()
+            .enumerate()
+            .map(|(index, (name, size, done))| {
+                let progress = if *size == 0 {
+                    0.0
+                } else {
+                    *done as f64 / *size as f64
+                };
+                json!({
+                    "index": index,
+                    "name": name,
+                    "size": size,
+                    "progress": progress,
+                    "priority": 1,
+                    "is_seed": progress >= 1.0,
+                })
+            })
+            .collect(),
+    ))
+}
+
+async fn delete(
+    State(server): State<Arc<Server>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let wanted = Hashes::from(&form, "hashes");
+    if let Hashes::Missing = wanted {
+        return (StatusCode::BAD_REQUEST, "Missing hashes").into_response();
+    }
+    let delete_files = form.get("deleteFiles").is_some_and(|v| v == "true");
+    let is_wanted = |hash: &String| wanted.contains(hash);
+
+    let mut pending = server.qbittorrent().pending.lock().unwrap();
+    pending.retain(|hash, p| {
+        if is_wanted(hash) {
+            if let Some(task) = &p.task {
+                task.abort();
+            }
+            return false;
+        }
+        true
+    });
+    drop(pending);
+
+    let mut dirs = BTreeSet::new();
+    for dl in store::load_all_downloads() {
+        let Some(hash) = hash_of(&dl) else {
+            continue;
+        };
+        if !is_wanted(hash) {
+            continue;
+        }
+        if !dl.status.is_finished() {
+            control::cancel_download(&dl.id);
+        }
+        if delete_files {
+            let _ = fs::remove_file(dl.target_path());
+            dirs.insert(dl.target_dir.clone());
+        }
+        store::delete_download(&dl.id);
+    }
+    // Torrent folders go too once empty; the save path never is
+    for dir in dirs {
+        if dir != server.target_dir {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+    "Ok.".into_response()
+}
+
+async fn set_category(
+    State(server): State<Arc<Server>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let wanted = Hashes::from(&form, "hashes");
+    if let Hashes::Missing = wanted {
+        return (StatusCode::BAD_REQUEST, "Missing hashes").into_response();
+    }
+    let category = form.get("category").cloned().unwrap_or_default();
+    let is_wanted = |hash: &String| wanted.contains(hash);
+
+    for (hash, p) in server.qbittorrent().pending.lock().unwrap().iter_mut() {
+        if is_wanted(hash) {
+            p.category = category.clone();
+        }
+    }
+    for dl in store::load_all_downloads() {
+        if hash_of(&dl).is_some_and(is_wanted) {
+            let _ = store::update_download(&dl.id, |dl| dl.category = Some(category.clone()));
+        }
+    }
+    "Ok.".into_response()
+}
@@ -0,0 +1,328 @@
+//! Writing downloaded data to disk.
+//!
+//! A [`FileSink`] copies the chunks a response arrives in into a buffer of
+//! its own and writes it out a few MB at a time on the blocking pool. That
+//! is one copy per chunk, as writing through tokio's files would make too,
+//! but one blocking-pool round trip per few MB rather than per chunk of a
+//! few KB.
+//!
+//! Opened `direct` on Linux, it writes with `O_DIRECT`, in aligned blocks,
+//! so a huge download doesn't push everything else out of the page cache.
+//! A filesystem that doesn't take `O_DIRECT` is written the usual way.
+//!
+//! Built with the `io-uring` feature on Linux, it otherwise hands the chunks
+//! as they are to io_uring on a thread of its own, which does without the
+//! copy and the blocking pool. Where the kernel won't set up a ring (older
+//! kernels, containers that filter it out) it falls back to the buffer.
+
+use bytes::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// A file a transfer appends to.
+pub struct FileSink {
+    inner: Inner,
+}
+
+enum Inner {
+    Buffered(buffered::Writer),
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    Uring(uring::Writer),
+}
+
+impl FileSink {
+    /// Opens `path` to write after what it holds if `append`, or else
+    /// creates it empty. `direct` bypasses the page cache where it can.
+    pub async fn open(path: &Path, append: bool, direct: bool) -> io::Result<FileSink> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let (file, direct) = open(&path, append, direct)?;
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            let file = match direct {
+                true => file,
+                false => match uring::Writer::start(file) {
+                    Ok(writer) => {
+                        return Ok(FileSink {
+                            inner: Inner::Uring(writer),
+                        });
+                    }
+                    Err(file) => file,
+                },
+            };
+            let writer = buffered::Writer::new(file, &path, append, direct)?;
+            Ok(FileSink {
+                inner: Inner::Buffered(writer),
+            })
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    /// Takes all of `data`. It may only reach the file once more has come
+    /// or in [`finish`](Self::finish); dropping the sink loses what hasn't.
+    pub async fn write(&mut self, data: Bytes) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Buffered(writer) => writer.write(&data).await,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(writer) => writer.write(data).await,
+        }
+    }
+
+    /// Writes out what is left and closes the file.
+    pub async fn finish(self) -> io::Result<()> {
+        match self.inner {
+            Inner::Buffered(writer) => writer.finish().await,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(writer) => writer.finish().await,
+        }
+    }
+}
+
+/// Opens the file, with `O_DIRECT` if `direct` and its filesystem allows
+/// it. Returns whether it did.
+fn open(path: &Path, append: bool, direct: bool) -> io::Result<(File, bool)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(!append).truncate(!append);
+    #[cfg(target_os = "linux")]
+    if direct {
+        use nix::errno::Errno;
+        use nix::fcntl::OFlag;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        match options
+            .clone()
+            .custom_flags(OFlag::O_DIRECT.bits())
+            .open(path)
+        {
+            Err(e) if e.raw_os_error() == Some(Errno::EINVAL as i32) => {}
+            result => return result.map(|file| (file, true)),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = direct;
+    options.open(path).map(|file| (file, false))
+}
+
+mod buffered {
+    use std::fs::File;
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::mem;
+    use std::path::Path;
+
+    /// What `O_DIRECT` writes' offsets, lengths and memory must be
+    /// multiples of; the usual logical block size.
+    const ALIGN: usize = 4096;
+
+    /// How much is gathered before it is written.
+    const BUFFER: usize = 2 * 1024 * 1024;
+
+    /// Gathers data and writes it [`BUFFER`] bytes at a time. Written
+    /// `direct`, the file always ends at a block boundary until
+    /// [`finish`](Self::finish) writes the last, partial block and trims the
+    /// file to size.
+    pub struct Writer {
+        file: Option<File>,
+        buf: Vec<u8>,
+        /// Where in `buf` the aligned part begins.
+        start: usize,
+        /// How much of the aligned part is filled.
+        filled: usize,
+        /// Where in the file the aligned part goes.
+        pos: u64,
+        direct: bool,
+    }
+
+    impl Writer {
+        /// Writes `file`, at `path`, from its end if `append`. Appending
+        /// `direct` picks up the partial last block of what is there to
+        /// write it out again.
+        pub fn new(file: File, path: &Path, append: bool, direct: bool) -> io::Result<Writer> {
+            let len = if append { file.metadata()?.len() } else { 0 };
+            let mut buf = vec![0; BUFFER + ALIGN];
+            let start = buf.as_ptr().align_offset(ALIGN);
+            let pos = if direct {
+                len - len % ALIGN as u64
+            } else {
+                len
+            };
+            let filled = (len - pos) as usize;
+            if filled > 0 {
+                let mut reader = File::open(path)?;
+                reader.seek(SeekFrom::Start(pos))?;
+                reader.read_exact(&mut buf[start..start + filled])?;
+            }
+            Ok(Writer {
+                file: Some(file),
+                buf,
+                start,
+                filled,
+                pos,
+                direct,
+            })
+        }
+
+        pub async fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+            while !data.is_empty() {
+                let n = data.len().min(BUFFER - self.filled);
+                let at = self.start + self.filled;
+                self.buf[at..at + n].copy_from_slice(&data[..n]);
+                self.filled += n;
+                data = &data[n..];
+                if self.filled == BUFFER {
+                    self.flush(BUFFER).await?;
+                    self.pos += BUFFER as u64;
+                    self.filled = 0;
+                }
+            }
+            Ok(())
+        }
+
+        pub async fn finish(mut self) -> io::Result<()> {
+            let len = self.pos + self.filled as u64;
+            let padded = match self.direct {
+                true => self.filled.div_ceil(ALIGN) * ALIGN,
+                false => self.filled,
+            };
+            if padded > 0 {
+                let at = self.start + self.filled;
+                self.buf[at..self.start + padded].fill(0);
+                self.flush(padded).await?;
+            }
+            let file = self.file.take().ok_or_else(stopped)?;
+            if !self.direct {
+                return Ok(());
+            }
+            tokio::task::spawn_blocking(move || file.set_len(len))
+                .await
+                .map_err(io::Error::other)?
+        }
+
+        /// Writes the first `len` bytes of the aligned part at `pos`.
+        async fn flush(&mut self, len: usize) -> io::Result<()> {
+            let file = self.file.take().ok_or_else(stopped)?;
+            let buf = mem::take(&mut self.buf);
+            let (start, pos, direct) = (self.start, self.pos, self.direct);
+            let (file, buf, result) = tokio::task::spawn_blocking(move || {
+                let result = write_at(&file, &buf[start..start + len], pos, direct);
+                (file, buf, result)
+            })
+            .await
+            .map_err(io::Error::other)?;
+            self.file = Some(file);
+            self.buf = buf;
+            result
+        }
+    }
+
+    /// Writes `data` at `pos`. Written `direct`, `O_DIRECT` is turned off
+    /// for good if the filesystem took the flag but refuses such writes.
+    fn write_at(mut file: &File, data: &[u8], pos: u64, direct: bool) -> io::Result<()> {
+        file.seek(SeekFrom::Start(pos))?;
+        #[cfg(target_os = "linux")]
+        if direct {
+            use nix::errno::Errno;
+            use nix::fcntl::{FcntlArg, OFlag, fcntl};
+            use std::os::fd::AsRawFd;
+
+            return match file.write_all(data) {
+                Err(e) if e.raw_os_error() == Some(Errno::EINVAL as i32) => {
+                    let fd = file.as_raw_fd();
+                    let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+                    fcntl(fd, FcntlArg::F_SETFL(flags - OFlag::O_DIRECT))?;
+                    file.seek(SeekFrom::Start(pos))?;
+                    file.write_all(data)
+                }
+                result => result,
+            };
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = direct;
+        file.write_all(data)
+    }
+
+    fn stopped() -> io::Error {
+        io::Error::other("an earlier write failed")
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring {
+    use bytes::Bytes;
+    use std::fs::File;
+    use std::io;
+    use std::thread;
+    use tokio::sync::{mpsc, oneshot};
+
+    /// Hands chunks to a thread running a tokio-uring runtime, which
+    /// writes them in order. One chunk waits while another is written.
+    pub struct Writer {
+        chunks: mpsc::Sender<Bytes>,
+        done: oneshot::Receiver<io::Result<()>>,
+    }
+
+    impl Writer {
+        /// Starts the writing thread for `file`, or gives the file back if
+        /// io_uring isn't available.
+        pub fn start(file: File) -> Result<Writer, File> {
+            let Ok(clone) = file.try_clone() else {
+                return Err(file);
+            };
+            let (chunks, receiver) = mpsc::channel(1);
+            let (ready, started) = std::sync::mpsc::channel();
+            let (finished, done) = oneshot::channel();
+            let spawned = thread::Builder::new()
+                .name("lj-uring".to_string())
+                .spawn(move || {
+                    let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                        Ok(runtime) => runtime,
+                        Err(_) => {
+                            let _ = ready.send(false);
+                            return;
+                        }
+                    };
+                    let _ = ready.send(true);
+                    let _ = finished.send(runtime.block_on(write_all(clone, receiver)));
+                });
+            if spawned.is_err() || !started.recv().unwrap_or(false) {
+                return Err(file);
+            }
+            Ok(Writer { chunks, done })
+        }
+
+        pub async fn write(&mut self, data: Bytes) -> io::Result<()> {
+            if self.chunks.send(data).await.is_ok() {
+                return Ok(());
+            }
+            // The thread only stops taking chunks once a write failed
+            match (&mut self.done).await {
+                Ok(Err(e)) => Err(e),
+                _ => Err(io::Error::other("io_uring writer stopped")),
+            }
+        }
+
+        pub async fn finish(self) -> io::Result<()> {
+            drop(self.chunks);
+            self.done
+                .await
+                .unwrap_or_else(|_| Err(io::Error::other("io_uring writer stopped")))
+        }
+    }
+
+    async fn write_all(file: File, mut chunks: mpsc::Receiver<Bytes>) -> io::Result<()> {
+        let mut pos = file.metadata()?.len();
+        let file = tokio_uring::fs::File::from_std(file);
+        while let Some(mut chunk) = chunks.recv().await {
+            while !chunk.is_empty() {
+                let (written, buf) = file.write_at(chunk, pos).await;
+                let written = written?;
+                if written == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                pos += written as u64;
+                chunk = buf.slice(written..);
+            }
+        }
+        file.close().await
+    }
+}
@@ -0,0 +1,65 @@
+//! Transfer speeds that hold still enough to read.
+
+use std::time::Duration;
+
+/// How long it takes the smoothed speed to cover half the way to a new
+/// speed it keeps seeing.
+const HALF_LIFE: Duration = Duration::from_secs(3);
+
+/// Follows a transfer through samples of how much it moved and in what time,
+/// keeping an exponentially weighted moving average of the speed and the
+/// average over every sample.
+///
+/// ```
+/// use lj_core::speed::SpeedMeter;
+/// use std::time::Duration;
+///
+/// let mut meter = SpeedMeter::default();
+/// meter.sample(1000, Duration::from_secs(1));
+/// assert_eq!(meter.smoothed(), 1000.0);
+///
+/// // A burst moves the smoothed speed only part of the way
+/// meter.sample(5000, Duration::from_millis(500));
+/// assert!(meter.smoothed() > 1000.0 && meter.smoothed() < 10_000.0);
+/// assert_eq!(meter.average(), 4000.0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SpeedMeter {
+    bytes: u64,
+    elapsed: Duration,
+    smoothed: Option<f64>,
+}
+
+impl SpeedMeter {
+    /// Records `bytes` moved in `elapsed` since the last sample and returns
+    /// the smoothed speed, in bytes per second.
+    pub fn sample(&mut self, bytes: u64, elapsed: Duration) -> f64 {
+        self.bytes += bytes;
+        self.elapsed += elapsed;
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            let rate = bytes as f64 / secs;
+            let weight = 1.0 - 0.5f64.powf(secs / HALF_LIFE.as_secs_f64());
+            self.smoothed = Some(match self.smoothed {
+                Some(smoothed) => smoothed + weight * (rate - smoothed),
+                None => rate,
+            });
+        }
+        self.smoothed()
+    }
+
+    /// The moving average, in bytes per second.
+    pub fn smoothed(&self) -> f64 {
+        self.smoothed.unwrap_or(0.0)
+    }
+
+    /// Bytes per second over all the samples.
+    pub fn average(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Moving lj to another machine with `lj export-state` and `lj import-state`.
+//!
+//! A [`StateArchive`] is one JSON file holding `config.toml`, optionally
+//! the provider API keys, and every download record of the active profile.
+//! Importing merges the records into the store: a record that is already
+//! there is skipped, one whose id is taken by a different download gets a
+//! fresh id, and `paths` rewrites target directories for the new machine's
+//! layout. Downloads that were still running belong to processes on the old
+//! machine, so they come in as failed and can be retried.
+
+use crate::config::{self, api_key_file, config_dir, config_file};
+use crate::error::{Error, Result};
+use crate::media;
+use crate::provider::PROVIDERS;
+use crate::store::{self, Download, DownloadStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format version written into new archives.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateArchive {
+    pub version: u32,
+    /// Unix time the archive was made.
+    pub created_at: u64,
+    /// `config.toml` as it was written, comments included.
+    pub config: Option<String>,
+    /// Key file name to API key, if keys were exported.
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+    pub downloads: Vec<Download>,
+}
+
+impl StateArchive {
+    /// Collects the active profile's state.
+    pub fn collect(include_keys: bool) -> Result<Self> {
+        let config = match fs::read_to_string(config_file()) {
+            Ok(text) => Some(text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut keys = BTreeMap::new();
+        if include_keys {
+            for provider in PROVIDERS {
+                if let Some(key) = config::stored_api_key(provider) {
+                    keys.insert(file_name(&api_key_file(provider)), key);
+                }
+            }
+        }
+        Ok(StateArchive {
+            version: ARCHIVE_VERSION,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            config,
+            keys,
+            downloads: store::load_all_downloads(),
+        })
+    }
+
+    /// Writes the archive to `path`, readable only by the owner since it
+    /// may hold API keys.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| Error::Other(e.to_string()))?;
+        config::write_private(path, &data)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let archive: StateArchive = serde_json::from_slice(&data).map_err(|e| Error::Parse {
+            context: "Invalid state archive",
+            message: e.to_string(),
+        })?;
+        if archive.version > ARCHIVE_VERSION {
+            return Err(Error::Other(format!(
+                "State archive version {} is newer than this lj understands ({})",
+                archive.version, ARCHIVE_VERSION
+            )));
+        }
+        Ok(archive)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ImportOptions {
+    /// Old path prefixes and what they become on this machine.
+    pub paths: BTreeMap<String, String>,
+    /// Replace a `config.toml` or key file that differs from the archive's.
+    pub overwrite: bool,
+}
+
+/// What an import did.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Files written, e.g. `config.toml`.
+    pub written: Vec<String>,
+    /// Files that differ from the archive's and were left alone; the
+    /// archive's config is saved next to it as `config.toml.imported`.
+    pub kept: Vec<String>,
+    pub imported: usize,
+    /// Records imported under a new id because theirs was taken.
+    pub renamed: usize,
+    /// Records that were already in the store.
+    pub skipped: usize,
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+enum Restored {
+    Written,
+    Unchanged,
+    /// A different file is there and was left alone.
+    Kept,
+}
+
+/// Writes `contents` to `path`. A different file already there is only
+/// replaced with `overwrite`.
+fn restore_file(path: &Path, contents: &str, overwrite: bool) -> Result<Restored> {
+    match fs::read_to_string(path) {
+        Ok(current) if current.trim() == contents.trim() => Ok(Restored::Unchanged),
+        Ok(_) if !overwrite => Ok(Restored::Kept),
+        _ => {
+            config::write_atomic(path, contents.as_bytes())?;
+            Ok(Restored::Written)
+        }
+    }
+}
+
+/// Readies a record from another machine for this one's store.
+fn adapt(mut dl: Download, paths: &BTreeMap<String, String>) -> Download {
+    dl.target_dir = media::server_path(&dl.target_dir, paths);
+    if !dl.status.is_finished() {
+        dl.status = DownloadStatus::Failed("Not finished when exported".to_string());
+        dl.finished_at = None;
+    }
+    dl.speed = 0.0;
+    dl.pid = None;
+    dl.pid_started = None;
+    dl
+}
+
+fn same_record(a: &Download, b: &Download) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Restores `archive` into the active profile.
+pub fn import(archive: StateArchive, options: &ImportOptions) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    fs::create_dir_all(config_dir())?;
+
+    if let Some(text) = &archive.config {
+        let path = config_file();
+        match restore_file(&path, text, options.overwrite)? {
+            Restored::Written => report.written.push(file_name(&path)),
+            Restored::Unchanged => {}
+            Restored::Kept => {
+                let mut imported = path.clone().into_os_string();
+                imported.push(".imported");
+                config::write_atomic(&PathBuf::from(imported), text.as_bytes())?;
+                report.kept.push(file_name(&path));
+            }
+        }
+    }
+    for (name, key) in &archive.keys {
+        // Only the key files lj knows, never a path from the archive
+        let Some(provider) = PROVIDERS
+            .iter()
+            .find(|provider| file_name(&api_key_file(provider)) == *name)
+        else {
+            continue;
+        };
+        // Stored like `set-key` would, in the keyring if there is one
+        match config::stored_api_key(provider) {
+            Some(current) if current == key.trim() => {}
+            Some(_) if !options.overwrite => report.kept.push(name.clone()),
+            _ => {
+                config::save_api_key(provider, key.trim(), false)?;
+                report.written.push(name.clone());
+            }
+        }
+    }
+
+    for dl in archive.downloads {
+        let mut dl = adapt(dl, &options.paths);
+        let original = dl.id.clone();
+        let mut suffix = 1;
+        loop {
+            if store::import_download(&dl)? {
+                report.imported += 1;
+                if dl.id != original {
+                    report.renamed += 1;
+                }
+                break;
+            }
+            if store::load_download(&dl.id).is_some_and(|existing| same_record(&existing, &dl)) {
+                report.skipped += 1;
+                break;
+            }
+            dl.id = format!("{}-{}", original, suffix);
+            suffix += 1;
+        }
+    }
+    Ok(report)
+}
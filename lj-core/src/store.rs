@@ -0,0 +1,995 @@
+//! Download records, persisted in a SQLite database shared by workers, the
+//! daemon and the UI.
+
+use crate::config::{config_dir, state_dir};
+use crate::platform::{self, PATH_MAX, fit_filename, sanitize_filename};
+use rusqlite::types::Type;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params, params_from_iter};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Download {
+    pub id: String,
+    pub filename: String,
+    pub url: String,
+    pub target_dir: String,
+    pub total_bytes: u64,
+    pub downloaded_bytes: u64,
+    /// Bytes per second, smoothed (see [`SpeedMeter`](crate::speed::SpeedMeter)).
+    pub speed: f64,
+    /// Bytes per second over the whole transfer so far, or the finished one.
+    #[serde(default)]
+    pub average_speed: f64,
+    pub status: DownloadStatus,
+    pub started_at: u64,
+    /// When the download completed, failed or was cancelled; set by the
+    /// store as the record reaches such a status.
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+    pub pid: Option<u32>,
+    /// Start time of `pid` (see [`platform::process_start_time`]), so a
+    /// recycled pid is not mistaken for the worker.
+    #[serde(default)]
+    pub pid_started: Option<u64>,
+    /// How many times the supervisor relaunched this download after its
+    /// worker died.
+    #[serde(default)]
+    pub restarts: u32,
+    /// Shared by the downloads started from one magnet (see
+    /// [`new_batch_id`]).
+    #[serde(default)]
+    pub batch: Option<String>,
+    /// Name of the torrent the file came from, if the magnet had one.
+    #[serde(default)]
+    pub torrent: Option<String>,
+    /// For the first volume of an archive set, how extracting it went (see
+    /// [`extract`](crate::extract)).
+    #[serde(default)]
+    pub extraction: Option<Extraction>,
+    /// Info hash of the torrent the file came from, which spots the same
+    /// torrent downloaded twice. The qBittorrent API lists its downloads
+    /// by it.
+    #[serde(default)]
+    pub info_hash: Option<String>,
+    /// qBittorrent category, e.g. `tv-sonarr`; set, if only to `""`, on
+    /// every download added through the qBittorrent API.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Where the file was uploaded to by [`upload`](crate::upload), e.g.
+    /// `gdrive:Media/Show/file.mkv`.
+    #[serde(default)]
+    pub remote: Option<String>,
+    /// Name the debrid service gave the file, when the host named it
+    /// differently and `filename` took the host's name (see
+    /// [`Config::host_filenames`](crate::config::Config::host_filenames)).
+    #[serde(default)]
+    pub service_filename: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Extraction {
+    /// Percent done.
+    Extracting {
+        progress: u8,
+    },
+    Extracted,
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum DownloadStatus {
+    Pending,
+    Downloading,
+    Completed,
+    Failed(String),
+    Cancelled,
+}
+
+impl DownloadStatus {
+    /// Stable lowercase name, used as the indexed status column.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadStatus::Pending => "pending",
+            DownloadStatus::Downloading => "downloading",
+            DownloadStatus::Completed => "completed",
+            DownloadStatus::Failed(_) => "failed",
+            DownloadStatus::Cancelled => "cancelled",
+        }
+    }
+
+    /// Completed, failed and cancelled records are finished and may be cleared.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            DownloadStatus::Completed | DownloadStatus::Failed(_) | DownloadStatus::Cancelled
+        )
+    }
+}
+
+/// Records made by this process so far, so ids made in the same
+/// millisecond differ.
+static RECORDS: AtomicU64 = AtomicU64::new(0);
+
+impl Download {
+    /// A new pending record for an unrestricted link, with an id unique
+    /// across processes.
+    pub fn new(filename: &str, url: &str, target_dir: &str, total_bytes: u64) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Download {
+            id: format!(
+                "{}-{}-{}",
+                now.as_millis(),
+                std::process::id(),
+                RECORDS.fetch_add(1, Ordering::Relaxed)
+            ),
+            filename: filename.to_string(),
+            url: url.to_string(),
+            target_dir: target_dir.to_string(),
+            total_bytes,
+            downloaded_bytes: 0,
+            speed: 0.0,
+            average_speed: 0.0,
+            status: DownloadStatus::Pending,
+            started_at: now.as_secs(),
+            finished_at: None,
+            pid: None,
+            pid_started: None,
+            restarts: 0,
+            batch: None,
+            torrent: None,
+            extraction: None,
+            info_hash: None,
+            category: None,
+            remote: None,
+            service_filename: None,
+        }
+    }
+
+    /// Marks this download as part of `batch`, from the torrent `torrent`.
+    pub fn in_batch(mut self, batch: &str, torrent: Option<&str>) -> Self {
+        self.batch = Some(batch.to_string());
+        self.torrent = torrent.map(str::to_string);
+        self
+    }
+
+    /// Records the info hash of the torrent the file came from.
+    pub fn of_torrent(mut self, info_hash: Option<String>) -> Self {
+        self.info_hash = info_hash;
+        self
+    }
+
+    /// Records `pid` as the process running this download.
+    pub fn set_process(&mut self, pid: u32) {
+        self.pid = Some(pid);
+        self.pid_started = platform::process_start_time(pid);
+    }
+
+    /// Forgets the process, returning it if it is still the one that was
+    /// recorded and not an unrelated process that reused its pid.
+    pub fn take_process(&mut self) -> Option<u32> {
+        let pid = self.pid.take()?;
+        let started = self.pid_started.take();
+        platform::is_same_process(pid, started).then_some(pid)
+    }
+
+    /// Whether the recorded process is still running.
+    pub fn process_alive(&self) -> bool {
+        self.pid
+            .is_some_and(|pid| platform::is_same_process(pid, self.pid_started))
+    }
+
+    /// Seconds left at the smoothed speed, for a running download of known
+    /// size that is moving.
+    pub fn eta(&self) -> Option<u64> {
+        let moving = self.status == DownloadStatus::Downloading && self.speed >= 1.0;
+        (moving && self.total_bytes > 0).then(|| {
+            (self.total_bytes.saturating_sub(self.downloaded_bytes) as f64 / self.speed) as u64
+        })
+    }
+
+    /// Whether the file's size is known. A host that sends it without a
+    /// Content-Length, for a service that didn't say either, leaves it at 0
+    /// until the download completes.
+    pub fn size_known(&self) -> bool {
+        self.total_bytes > 0 || self.status == DownloadStatus::Completed
+    }
+
+    /// Where the file is saved. Its name is shortened as far as it takes to
+    /// keep the whole path under [`PATH_MAX`], so a deep `target_dir` does
+    /// not fail the download with "file name too long" halfway through.
+    pub fn target_path(&self) -> PathBuf {
+        let dir = PathBuf::from(&self.target_dir);
+        let room = PATH_MAX.saturating_sub(dir.as_os_str().len() + 1);
+        dir.join(fit_filename(&sanitize_filename(&self.filename), room))
+    }
+
+    /// Whether the download's folder has gone, deleted or on a drive that
+    /// is no longer mounted, after its worker had started writing there.
+    pub fn target_missing(&self) -> bool {
+        let started = match self.status {
+            DownloadStatus::Downloading | DownloadStatus::Failed(_) => true,
+            DownloadStatus::Pending => self.downloaded_bytes > 0,
+            DownloadStatus::Completed | DownloadStatus::Cancelled => false,
+        };
+        started && !Path::new(&self.target_dir).is_dir()
+    }
+
+    /// Sets `finished_at` once the status is final, and clears it again if
+    /// the download is retried.
+    fn stamp_finished(&mut self) {
+        if !self.status.is_finished() {
+            self.finished_at = None;
+        } else if self.finished_at.is_none() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            self.finished_at = Some(now.as_secs());
+        }
+    }
+}
+
+/// A fresh id for the downloads started from one magnet.
+pub fn new_batch_id() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!("{}-{}", now.as_millis(), std::process::id())
+}
+
+/// Legacy per-download JSON directory, imported into the database on first use.
+pub fn downloads_dir() -> PathBuf {
+    config_dir().join("downloads")
+}
+
+pub fn db_path() -> PathBuf {
+    state_dir().join("lj.db")
+}
+
+/// Where the database lived before it moved to the state directory.
+fn legacy_db_path() -> PathBuf {
+    config_dir().join("lj.db")
+}
+
+/// Moves a database left in the config directory by an older version over
+/// to the state directory, along with its WAL files.
+fn migrate_legacy_db() -> io::Result<()> {
+    let (old, new) = (legacy_db_path(), db_path());
+    if old == new || !old.exists() || new.exists() {
+        return Ok(());
+    }
+    for suffix in ["", "-wal", "-shm"] {
+        let mut from = old.clone().into_os_string();
+        from.push(suffix);
+        let mut to = new.clone().into_os_string();
+        to.push(suffix);
+        let (from, to) = (PathBuf::from(from), PathBuf::from(to));
+        if !from.exists() {
+            continue;
+        }
+        // The state and config directories may be on different filesystems
+        platform::move_file(&from, &to, &mut |_, _| {})?;
+    }
+    Ok(())
+}
+
+const SCHEMA_VERSION: i32 = 7;
+
+static DB: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+fn open_db() -> rusqlite::Result<Connection> {
+    let conn = Connection::open(db_path())?;
+    // Workers and the UI write concurrently from separate processes
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+
+    let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version < 1 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS downloads (
+                 id TEXT PRIMARY KEY,
+                 status TEXT NOT NULL,
+                 started_at INTEGER NOT NULL,
+                 data TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS downloads_status ON downloads(status);",
+        )?;
+        migrate_json_records(&conn)?;
+    }
+    if version < 2 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS quarantine (
+                 id TEXT NOT NULL,
+                 data TEXT NOT NULL,
+                 error TEXT NOT NULL,
+                 quarantined_at INTEGER NOT NULL
+             );",
+        )?;
+    }
+    if version < 3 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS finished_batches (
+                 batch TEXT PRIMARY KEY,
+                 finished_at INTEGER NOT NULL
+             );",
+        )?;
+    }
+    if version < 4 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS rss_seen (
+                 key TEXT PRIMARY KEY,
+                 seen_at INTEGER NOT NULL
+             );",
+        )?;
+    }
+    if version < 5 {
+        // Batches are claimed per consumer, so hooks and health checks
+        // each see every batch finish once
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS batch_claims (
+                 batch TEXT NOT NULL,
+                 claimant TEXT NOT NULL,
+                 claimed_at INTEGER NOT NULL,
+                 PRIMARY KEY (batch, claimant)
+             );
+             INSERT OR IGNORE INTO batch_claims (batch, claimant, claimed_at)
+                 SELECT batch, 'hooks', finished_at FROM finished_batches;
+             DROP TABLE finished_batches;",
+        )?;
+    }
+    if version < 6 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS queue (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 magnet TEXT NOT NULL,
+                 added_at INTEGER NOT NULL,
+                 status TEXT NOT NULL,
+                 error TEXT,
+                 finished_at INTEGER
+             );",
+        )?;
+    }
+    if version < 7 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS processing (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 data TEXT NOT NULL
+             );",
+        )?;
+    }
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(conn)
+}
+
+/// Imports records from the old JSON directory and moves it aside.
+fn migrate_json_records(conn: &Connection) -> rusqlite::Result<()> {
+    let dir = downloads_dir();
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(());
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().map(|e| e == "json").unwrap_or(false)
+            && let Ok(data) = fs::read_to_string(&path)
+        {
+            match serde_json::from_str::<Download>(&data) {
+                Ok(dl) => insert(conn, &dl, "INSERT OR IGNORE")?,
+                Err(e) => {
+                    let id = path.file_stem().unwrap_or_default().to_string_lossy();
+                    quarantine(conn, &id, &data, &e.to_string())?;
+                }
+            }
+        }
+    }
+
+    let _ = fs::rename(&dir, dir.with_extension("migrated"));
+    Ok(())
+}
+
+fn encode(download: &Download) -> rusqlite::Result<String> {
+    serde_json::to_string(download)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn insert(conn: &Connection, download: &Download, verb: &str) -> rusqlite::Result<()> {
+    insert_encoded(conn, download, &encode(download)?, verb)
+}
+
+fn insert_encoded(
+    conn: &Connection,
+    download: &Download,
+    data: &str,
+    verb: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!(
+            "{} INTO downloads (id, status, started_at, data) VALUES (?1, ?2, ?3, ?4)",
+            verb
+        ),
+        params![
+            download.id,
+            download.status.kind(),
+            download.started_at as i64,
+            data
+        ],
+    )?;
+    Ok(())
+}
+
+fn with_db<T>(f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> io::Result<T> {
+    let db = match DB.get() {
+        Some(db) => db,
+        None => {
+            fs::create_dir_all(state_dir())?;
+            migrate_legacy_db()?;
+            let conn = open_db().map_err(io::Error::other)?;
+            DB.get_or_init(|| Mutex::new(conn))
+        }
+    };
+    let mut conn = db.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut conn).map_err(io::Error::other)
+}
+
+/// A record that could not be decoded and was moved out of the downloads
+/// table so it can be inspected and repaired by hand.
+#[derive(Debug, Clone)]
+pub struct QuarantinedRecord {
+    pub id: String,
+    pub data: String,
+    pub error: String,
+    pub quarantined_at: u64,
+}
+
+fn quarantine(conn: &Connection, id: &str, data: &str, error: &str) -> rusqlite::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    conn.execute(
+        "INSERT INTO quarantine (id, data, error, quarantined_at) VALUES (?1, ?2, ?3, ?4)",
+        params![id, data, error, now as i64],
+    )?;
+    conn.execute("DELETE FROM downloads WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Decodes a stored record, quarantining it if it is corrupt.
+fn decode(conn: &Connection, id: &str, data: &str) -> rusqlite::Result<Option<Download>> {
+    match serde_json::from_str(data) {
+        Ok(dl) => Ok(Some(dl)),
+        Err(e) => {
+            quarantine(conn, id, data, &e.to_string())?;
+            Ok(None)
+        }
+    }
+}
+
+fn query_downloads(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<Download>> {
+    let rows = {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<rusqlite::Result<Vec<(String, String)>>>()?
+    };
+
+    let mut downloads = Vec::with_capacity(rows.len());
+    for (id, data) in rows {
+        downloads.extend(decode(conn, &id, &data)?);
+    }
+    Ok(downloads)
+}
+
+pub fn save_download(download: &Download) -> io::Result<()> {
+    let mut download = download.clone();
+    download.stamp_finished();
+    with_db(|conn| insert(conn, &download, "INSERT OR REPLACE"))
+}
+
+/// Adds a record as it is, unless its id is taken. Returns whether it was
+/// added.
+pub fn import_download(download: &Download) -> io::Result<bool> {
+    with_db(|conn| {
+        insert(conn, download, "INSERT OR IGNORE")?;
+        Ok(conn.changes() == 1)
+    })
+}
+
+/// Atomically applies `f` to the stored record and writes it back.
+///
+/// The read and the write happen inside one `BEGIN IMMEDIATE` transaction,
+/// which holds SQLite's write lock, so concurrent updates from workers, the
+/// daemon and `lj dl` cannot clobber each other. Nothing is written when `f`
+/// leaves the record as it was, so callers can use this to inspect a record
+/// under the lock. Returns the updated record, or `None` if there is no
+/// record with this id.
+pub fn update_download(id: &str, f: impl FnOnce(&mut Download)) -> io::Result<Option<Download>> {
+    with_db(|conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let data: Option<String> = tx
+            .query_row("SELECT data FROM downloads WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let Some(data) = data else {
+            return Ok(None);
+        };
+        let Some(mut dl) = decode(&tx, id, &data)? else {
+            tx.commit()?;
+            return Ok(None);
+        };
+
+        f(&mut dl);
+        dl.stamp_finished();
+        let updated = encode(&dl)?;
+        if updated != data {
+            insert_encoded(&tx, &dl, &updated, "INSERT OR REPLACE")?;
+        }
+        tx.commit()?;
+        Ok(Some(dl))
+    })
+}
+
+fn batch_downloads(conn: &Connection, batch: &str) -> rusqlite::Result<Vec<Download>> {
+    query_downloads(
+        conn,
+        "SELECT id, data FROM downloads WHERE json_extract(data, '$.batch') = ?1
+         ORDER BY started_at, id",
+        [batch],
+    )
+}
+
+fn insert_claim(conn: &Connection, batch: &str, claimant: &str) -> rusqlite::Result<bool> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(conn.execute(
+        "INSERT OR IGNORE INTO batch_claims (batch, claimant, claimed_at) VALUES (?1, ?2, ?3)",
+        params![batch, claimant, now as i64],
+    )? == 1)
+}
+
+/// Claims `batch` for `claimant` once every download in it has finished.
+/// Exactly one caller per claimant gets the batch's records, oldest first;
+/// until the batch is done, and after it has been claimed, everyone gets
+/// `None`.
+pub fn claim_finished_batch(batch: &str, claimant: &str) -> io::Result<Option<Vec<Download>>> {
+    with_db(|conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let downloads = batch_downloads(&tx, batch)?;
+        if downloads.is_empty() || !downloads.iter().all(|dl| dl.status.is_finished()) {
+            tx.commit()?;
+            return Ok(None);
+        }
+        let claimed = insert_claim(&tx, batch, claimant)?;
+        tx.commit()?;
+        Ok(claimed.then_some(downloads))
+    })
+}
+
+/// The records of `batch`, oldest first, if every download in it has
+/// finished and `claimant` hasn't [claimed](claim_batch) it yet. For work
+/// that has to succeed before the batch is claimed.
+pub fn unclaimed_finished_batch(batch: &str, claimant: &str) -> Option<Vec<Download>> {
+    with_db(|conn| {
+        let claimed = conn
+            .query_row(
+                "SELECT 1 FROM batch_claims WHERE batch = ?1 AND claimant = ?2",
+                [batch, claimant],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        let downloads = batch_downloads(conn, batch)?;
+        Ok((!claimed
+            && !downloads.is_empty()
+            && downloads.iter().all(|dl| dl.status.is_finished()))
+        .then_some(downloads))
+    })
+    .ok()
+    .flatten()
+}
+
+/// Claims `batch` for `claimant`, finished or not. Returns whether this
+/// call was the first.
+pub fn claim_batch(batch: &str, claimant: &str) -> io::Result<bool> {
+    with_db(|conn| insert_claim(conn, batch, claimant))
+}
+
+pub fn load_download(id: &str) -> Option<Download> {
+    with_db(|conn| query_downloads(conn, "SELECT id, data FROM downloads WHERE id = ?1", [id]))
+        .ok()?
+        .pop()
+}
+
+/// All records, oldest first.
+pub fn load_all_downloads() -> Vec<Download> {
+    with_db(|conn| {
+        query_downloads(
+            conn,
+            "SELECT id, data FROM downloads ORDER BY started_at, id",
+            [],
+        )
+    })
+    .unwrap_or_default()
+}
+
+/// Records whose status is one of `kinds` (see [`DownloadStatus::kind`]),
+/// oldest first.
+pub fn load_downloads_with_status(kinds: &[&str]) -> Vec<Download> {
+    with_db(|conn| {
+        let placeholders = vec!["?"; kinds.len()].join(",");
+        let sql = format!(
+            "SELECT id, data FROM downloads WHERE status IN ({}) ORDER BY started_at, id",
+            placeholders
+        );
+        query_downloads(conn, &sql, params_from_iter(kinds))
+    })
+    .unwrap_or_default()
+}
+
+/// Records of the torrent with info hash `hash` that are pending,
+/// downloading or completed, i.e. what downloading it again would repeat.
+pub fn downloads_of_torrent(hash: &str) -> Vec<Download> {
+    load_downloads_with_status(&["pending", "downloading", "completed"])
+        .into_iter()
+        .filter(|dl| dl.info_hash.as_deref() == Some(hash))
+        .collect()
+}
+
+/// Number of records per status kind.
+pub fn status_counts() -> Vec<(String, u64)> {
+    with_db(|conn| {
+        let mut stmt =
+            conn.prepare("SELECT status, COUNT(*) FROM downloads GROUP BY status ORDER BY status")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+        rows.collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Whether a feed item was already taken (see [`mark_rss_seen`]).
+pub fn rss_seen(key: &str) -> bool {
+    with_db(|conn| {
+        conn.query_row("SELECT 1 FROM rss_seen WHERE key = ?1", [key], |_| Ok(()))
+            .optional()
+    })
+    .ok()
+    .flatten()
+    .is_some()
+}
+
+/// Remembers feed items, by GUID or info hash, so later polls skip them.
+pub fn mark_rss_seen(keys: &[String]) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    with_db(|conn| {
+        for key in keys {
+            conn.execute(
+                "INSERT OR IGNORE INTO rss_seen (key, seen_at) VALUES (?1, ?2)",
+                params![key, now as i64],
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// A magnet stashed with `lj queue add`, to be run later.
+#[derive(Debug, Clone)]
+pub struct QueueItem {
+    pub id: i64,
+    /// Magnet link or `.torrent` URL.
+    pub magnet: String,
+    pub added_at: u64,
+    pub status: QueueStatus,
+    /// When the item was last done or failed.
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueStatus {
+    Pending,
+    /// Taken by a run, which either is still going or was interrupted.
+    Running,
+    Done,
+    Failed(String),
+}
+
+impl QueueStatus {
+    /// Stable lowercase name, as stored in the status column.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QueueStatus::Pending => "pending",
+            QueueStatus::Running => "running",
+            QueueStatus::Done => "done",
+            QueueStatus::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Adds `magnet` to the end of the queue and returns its id, or `None` if
+/// it is already queued and not done yet.
+pub fn queue_add(magnet: &str) -> io::Result<Option<i64>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    with_db(|conn| {
+        let queued = conn
+            .query_row(
+                "SELECT 1 FROM queue WHERE magnet = ?1 AND status != 'done'",
+                [magnet],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if queued.is_some() {
+            return Ok(None);
+        }
+        conn.execute(
+            "INSERT INTO queue (magnet, added_at, status) VALUES (?1, ?2, 'pending')",
+            params![magnet, now as i64],
+        )?;
+        Ok(Some(conn.last_insert_rowid()))
+    })
+}
+
+/// Every queued item, in the order they were added.
+pub fn queue_items() -> Vec<QueueItem> {
+    with_db(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, magnet, added_at, status, error, finished_at FROM queue ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let status = match row.get::<_, String>(3)?.as_str() {
+                "running" => QueueStatus::Running,
+                "done" => QueueStatus::Done,
+                "failed" => {
+                    QueueStatus::Failed(row.get::<_, Option<String>>(4)?.unwrap_or_default())
+                }
+                _ => QueueStatus::Pending,
+            };
+            Ok(QueueItem {
+                id: row.get(0)?,
+                magnet: row.get(1)?,
+                added_at: row.get::<_, i64>(2)? as u64,
+                status,
+                finished_at: row.get::<_, Option<i64>>(5)?.map(|t| t as u64),
+            })
+        })?;
+        rows.collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Marks item `id` running if its status is one of `kinds` (see
+/// [`QueueStatus::kind`]). Returns whether it did, so two runs never take
+/// the same item.
+pub fn claim_queue_item(id: i64, kinds: &[&str]) -> io::Result<bool> {
+    with_db(|conn| {
+        let placeholders = vec!["?"; kinds.len()].join(",");
+        let sql = format!(
+            "UPDATE queue SET status = 'running' WHERE id = ? AND status IN ({})",
+            placeholders
+        );
+        let mut params: Vec<&dyn rusqlite::ToSql> = vec![&id];
+        params.extend(kinds.iter().map(|kind| kind as &dyn rusqlite::ToSql));
+        Ok(conn.execute(&sql, params.as_slice())? > 0)
+    })
+}
+
+/// Marks item `id` done, or failed with `error`.
+pub fn finish_queue_item(id: i64, error: Option<&str>) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let status = if error.is_some() { "failed" } else { "done" };
+    with_db(|conn| {
+        conn.execute(
+            "UPDATE queue SET status = ?1, error = ?2, finished_at = ?3 WHERE id = ?4",
+            params![status, error, now as i64, id],
+        )?;
+        Ok(())
+    })
+}
+
+/// Removes the items with the given ids, returning how many there were.
+pub fn remove_queue_items(ids: &[i64]) -> io::Result<usize> {
+    with_db(|conn| {
+        let mut removed = 0;
+        for id in ids {
+            removed += conn.execute("DELETE FROM queue WHERE id = ?1", [id])?;
+        }
+        Ok(removed)
+    })
+}
+
+/// Removes the items that are done, returning how many there were.
+pub fn clear_done_queue_items() -> io::Result<usize> {
+    with_db(|conn| conn.execute("DELETE FROM queue WHERE status = 'done'", []))
+}
+
+/// A torrent left with the service by `lj --background`, waited on by a
+/// process of its own (see [`processing`](crate::processing)) until its
+/// files can be downloaded.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Processing {
+    #[serde(skip)]
+    pub id: i64,
+    /// Provider the torrent is on (see
+    /// [`PROVIDERS`](crate::provider::PROVIDERS)).
+    pub provider: String,
+    /// API root it was added through, if not the service's own.
+    pub api_url: Option<String>,
+    pub torrent_id: String,
+    /// Magnet the torrent was added from, also for `.torrent` files.
+    pub magnet: String,
+    /// The torrent's name, once known.
+    pub name: Option<String>,
+    /// Files to select, as the service numbers them.
+    pub file_ids: Vec<u32>,
+    /// Download directory the files are saved to.
+    pub target_dir: String,
+    /// Whether to sort them as `--organize` does.
+    pub organize: bool,
+    /// Percent of the torrent the service has.
+    pub progress: f64,
+    /// Bytes per second.
+    pub speed: u64,
+    pub seeders: u32,
+    /// Why it stopped, if it did.
+    pub error: Option<String>,
+    /// Set by `lj dl` for the process to delete the torrent and stop.
+    pub cancelled: bool,
+    pub pid: Option<u32>,
+    /// Start time of `pid`, as for [`Download::pid_started`].
+    pub pid_started: Option<u64>,
+    pub started_at: u64,
+}
+
+impl Processing {
+    /// A new record for `torrent_id` on `provider`, waiting to have
+    /// `file_ids` selected.
+    pub fn new(
+        provider: &str,
+        torrent_id: &str,
+        magnet: &str,
+        file_ids: Vec<u32>,
+        target_dir: &str,
+    ) -> Self {
+        Processing {
+            id: 0,
+            provider: provider.to_string(),
+            api_url: None,
+            torrent_id: torrent_id.to_string(),
+            magnet: magnet.to_string(),
+            name: None,
+            file_ids,
+            target_dir: target_dir.to_string(),
+            organize: false,
+            progress: 0.0,
+            speed: 0,
+            seeders: 0,
+            error: None,
+            cancelled: false,
+            pid: None,
+            pid_started: None,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+
+    /// Records `pid` as the process waiting on this torrent.
+    pub fn set_process(&mut self, pid: u32) {
+        self.pid = Some(pid);
+        self.pid_started = platform::process_start_time(pid);
+    }
+
+    /// Whether the recorded process is still running.
+    pub fn process_alive(&self) -> bool {
+        self.pid
+            .is_some_and(|pid| platform::is_same_process(pid, self.pid_started))
+    }
+}
+
+fn decode_processing(id: i64, data: &str) -> rusqlite::Result<Processing> {
+    let mut job: Processing = serde_json::from_str(data)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))?;
+    job.id = id;
+    Ok(job)
+}
+
+fn encode_processing(job: &Processing) -> rusqlite::Result<String> {
+    serde_json::to_string(job).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+/// Stores a new torrent to wait on and returns its id.
+pub fn add_processing(job: &Processing) -> io::Result<i64> {
+    with_db(|conn| {
+        conn.execute(
+            "INSERT INTO processing (data) VALUES (?1)",
+            [encode_processing(job)?],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+}
+
+/// Every torrent waited on, oldest first.
+pub fn processing_jobs() -> Vec<Processing> {
+    with_db(|conn| {
+        let mut stmt = conn.prepare("SELECT id, data FROM processing ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            decode_processing(row.get(0)?, &row.get::<_, String>(1)?)
+        })?;
+        rows.collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Atomically applies `f` to the stored torrent and writes it back, as
+/// [`update_download`] does. Returns `None` if there is none with this id.
+pub fn update_processing(
+    id: i64,
+    f: impl FnOnce(&mut Processing),
+) -> io::Result<Option<Processing>> {
+    with_db(|conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let data: Option<String> = tx
+            .query_row("SELECT data FROM processing WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let Some(data) = data else {
+            return Ok(None);
+        };
+        let mut job = decode_processing(id, &data)?;
+        f(&mut job);
+        tx.execute(
+            "UPDATE processing SET data = ?1 WHERE id = ?2",
+            params![encode_processing(&job)?, id],
+        )?;
+        tx.commit()?;
+        Ok(Some(job))
+    })
+}
+
+pub fn delete_processing(id: i64) {
+    let _ = with_db(|conn| conn.execute("DELETE FROM processing WHERE id = ?1", [id]));
+}
+
+pub fn delete_download(id: &str) {
+    let _ = with_db(|conn| conn.execute("DELETE FROM downloads WHERE id = ?1", [id]));
+}
+
+/// Records that failed to decode, newest first.
+pub fn quarantined_records() -> Vec<QuarantinedRecord> {
+    with_db(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, data, error, quarantined_at FROM quarantine ORDER BY quarantined_at DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(QuarantinedRecord {
+                id: row.get(0)?,
+                data: row.get(1)?,
+                error: row.get(2)?,
+                quarantined_at: row.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        rows.collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Drops all quarantined records.
+pub fn clear_quarantine() -> io::Result<()> {
+    with_db(|conn| conn.execute("DELETE FROM quarantine", []).map(|_| ()))
+}
@@ -0,0 +1,94 @@
+//! systemd user-service integration: unit generation and `sd_notify`.
+//!
+//! Notifications are plain datagrams to `$NOTIFY_SOCKET`, so there is no
+//! libsystemd dependency; outside systemd every call is a no-op.
+
+use crate::config::{self, PROFILE_ENV};
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Sends `state` (e.g. `READY=1`) to the service manager, if there is one.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = socket.to_string_lossy();
+    let sock = UnixDatagram::unbound()?;
+
+    if let Some(name) = socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sock.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = name;
+    } else {
+        sock.send_to(state.as_bytes(), socket.as_ref())?;
+    }
+    Ok(())
+}
+
+/// How often to send `WATCHDOG=1`: half the interval systemd asked for, or
+/// `None` if the watchdog is off or meant for another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Unit name for the active profile: `lj` or `lj-<profile>`.
+pub fn unit_name() -> String {
+    match config::profile() {
+        Some(profile) => format!("lj-{}", profile),
+        None => "lj".to_string(),
+    }
+}
+
+/// Where user units live: `~/.config/systemd/user/<unit>.service`.
+pub fn unit_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("systemd")
+        .join("user")
+        .join(format!("{}.service", unit_name()))
+}
+
+/// A user unit that runs `exe daemon <args>` for the active profile.
+pub fn unit_file(exe: &Path, args: &[String]) -> String {
+    let mut exec = format!("\"{}\" daemon", exe.display());
+    for arg in args {
+        exec.push(' ');
+        exec.push_str(arg);
+    }
+    let environment = match config::profile() {
+        Some(profile) => format!("Environment={}={}\n", PROFILE_ENV, profile),
+        None => String::new(),
+    };
+
+    format!(
+        "[Unit]
+Description=lj download daemon
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=notify
+ExecStart={exec}
+{environment}Restart=on-failure
+RestartSec=5
+WatchdogSec=30
+
+[Install]
+WantedBy=default.target
+"
+    )
+}
@@ -0,0 +1,197 @@
+//! Telegram bot notifications and remote control.
+//!
+//! With a bot `token` and `chat_id` in `[telegram]`, each event is sent to
+//! that chat as a message. With `accept_magnets` on as well,
+//! [`serve`](crate::serve) polls the bot and queues any magnet link sent
+//! from that chat, and only that chat, through the normal pipeline.
+
+use crate::config::TelegramConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::notify::{self, Notifier};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Bot API root used when the section names none.
+pub const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+/// How long one `getUpdates` call waits for messages. Stays under the
+/// client's read timeout.
+const POLL_TIMEOUT_SECS: u64 = 25;
+
+/// Events sent when the section lists none.
+const DEFAULT_EVENTS: &[&str] = &["completed", "failed"];
+
+/// A bot talking to one chat. Not `Debug`, since it holds the token.
+#[derive(Clone)]
+pub struct Bot {
+    base: String,
+    chat_id: String,
+}
+
+/// A text message sent to the bot.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub chat_id: String,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+struct Reply<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+    error_code: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<IncomingMessage>,
+}
+
+#[derive(Deserialize)]
+struct IncomingMessage {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: Option<String>,
+}
+
+impl Bot {
+    /// The bot described by `config`, if it has a token and a chat.
+    pub fn from_config(config: &TelegramConfig) -> Option<Self> {
+        let api = config.api_url.as_deref().unwrap_or(DEFAULT_API_URL);
+        Some(Bot {
+            base: format!(
+                "{}/bot{}",
+                api.trim_end_matches('/'),
+                config.token.as_ref()?
+            ),
+            chat_id: config.chat_id.clone()?,
+        })
+    }
+
+    /// The chat the bot reports to and takes magnets from.
+    pub fn chat_id(&self) -> &str {
+        &self.chat_id
+    }
+
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        client: &Client,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T> {
+        let context = "Telegram request failed";
+        let resp = client
+            .post(format!("{}/{}", self.base, method))
+            .json(&body)
+            .send()
+            .await
+            .map_err(Error::http(context))?;
+        let status = resp.status();
+        let reply: Reply<T> = resp.json().await.map_err(|e| Error::Parse {
+            context,
+            message: e.to_string(),
+        })?;
+        match reply.result {
+            Some(result) if reply.ok => Ok(result),
+            _ => Err(Error::Api {
+                context,
+                status,
+                code: reply.error_code,
+                message: reply.description.unwrap_or_default(),
+            }),
+        }
+    }
+
+    /// The bot's username, which also checks the token.
+    pub async fn username(&self, client: &Client) -> Result<String> {
+        let me: User = self.call(client, "getMe", json!({})).await?;
+        Ok(me.username.unwrap_or_default())
+    }
+
+    /// Sends `text` to the bot's chat.
+    pub async fn send_message(&self, client: &Client, text: &str) -> Result<()> {
+        let body = json!({ "chat_id": self.chat_id, "text": text });
+        self.call::<serde_json::Value>(client, "sendMessage", body)
+            .await
+            .map(|_| ())
+    }
+
+    /// Waits for new text messages. `offset` tracks what has been seen
+    /// and is advanced past the returned updates.
+    pub async fn messages(&self, client: &Client, offset: &mut i64) -> Result<Vec<Message>> {
+        let body = json!({
+            "offset": *offset,
+            "timeout": POLL_TIMEOUT_SECS,
+            "allowed_updates": ["message"],
+        });
+        let updates: Vec<Update> = self.call(client, "getUpdates", body).await?;
+        let mut messages = Vec::new();
+        for update in updates {
+            *offset = (*offset).max(update.update_id + 1);
+            if let Some(msg) = update.message
+                && let Some(text) = msg.text
+            {
+                messages.push(Message {
+                    chat_id: msg.chat.id.to_string(),
+                    text,
+                });
+            }
+        }
+        Ok(messages)
+    }
+}
+
+/// The magnet links in a message.
+pub fn magnets(text: &str) -> Vec<&str> {
+    text.split_whitespace()
+        .filter(|word| word.starts_with("magnet:?"))
+        .collect()
+}
+
+/// Sends lifecycle events to the bot's chat.
+pub struct Telegram {
+    bot: Bot,
+    events: Vec<String>,
+}
+
+impl Telegram {
+    /// The notifier described by `config`, if it has a token and a chat.
+    pub fn from_config(config: &TelegramConfig) -> Option<Self> {
+        Some(Telegram {
+            bot: Bot::from_config(config)?,
+            events: config.events.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        notify::selects(&self.events, DEFAULT_EVENTS, event)
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let (title, message) = notify::describe(event);
+        self.bot
+            .send_message(client, &format!("{}\n{}", title, message))
+            .await
+    }
+}
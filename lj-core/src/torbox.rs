@@ -0,0 +1,434 @@
+//! TorBox client.
+//!
+//! TorBox fetches whole torrents and has no selection step, so selection is
+//! emulated client-side (see [`EmulatedSelection`]). Cached torrents are
+//! ready as soon as they are added.
+//!
+//! When every active slot on the account is busy, TorBox queues a new
+//! torrent instead of starting it and hands back a queue id. Such torrents
+//! are tracked as `queued:<queue id>:<info hash>` and reported as
+//! processing until one with the same hash shows up in the torrent list.
+
+use crate::error::{Error, Result};
+use crate::format::parse_timestamp;
+use crate::http::{self, Auth, Token};
+use crate::provider::{
+    Account, DebridProvider, EmulatedSelection, TorrentFile, TorrentState, TorrentStatus,
+    UnrestrictedLink,
+};
+use async_trait::async_trait;
+use reqwest::multipart::Form;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+
+pub const TB_BASE_URL: &str = "https://api.torbox.app/v1/api";
+
+const QUEUED: &str = "queued:";
+/// Prefix of the links handed out by [`DebridProvider::poll`].
+const FILE_LINK: &str = "tb:";
+
+/// Every response is `{"success": ..., "error": ..., "detail": ..., "data": ...}`.
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    error: Option<String>,
+    detail: Option<String>,
+    data: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    email: String,
+    /// 0 for free accounts.
+    #[serde(default)]
+    plan: u32,
+    premium_expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateData {
+    torrent_id: Option<u64>,
+    queued_id: Option<u64>,
+    hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Torrent {
+    id: u64,
+    hash: Option<String>,
+    name: Option<String>,
+    size: Option<u64>,
+    download_state: String,
+    /// Fraction between 0 and 1.
+    progress: Option<f64>,
+    download_speed: Option<u64>,
+    seeds: Option<u32>,
+    download_present: Option<bool>,
+    files: Option<Vec<File>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CachedTorrent {
+    hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct File {
+    id: u32,
+    name: String,
+    size: Option<u64>,
+}
+
+pub struct TorBox {
+    client: Client,
+    api_key: Token,
+    base_url: String,
+    selection: EmulatedSelection,
+}
+
+impl TorBox {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(Client::new(), api_key)
+    }
+
+    pub fn with_client(client: Client, api_key: &str) -> Self {
+        Self {
+            client,
+            api_key: Token::new(api_key),
+            base_url: TB_BASE_URL.to_string(),
+            selection: EmulatedSelection::default(),
+        }
+    }
+
+    /// Points the client at another API root, e.g. a proxy or mirror.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        http::request(
+            &self.client,
+            method,
+            format!("{}{}", self.base_url, path),
+            &self.api_key,
+            Auth::Bearer,
+        )
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        req: RequestBuilder,
+        context: &'static str,
+    ) -> Result<T> {
+        let resp = req.send().await.map_err(Error::http(context))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(Error::http(context))?;
+
+        let envelope: Envelope<T> = serde_json::from_str(&text).map_err(|e| {
+            if status.is_success() {
+                Error::Parse {
+                    context,
+                    message: e.to_string(),
+                }
+            } else {
+                Error::Api {
+                    context,
+                    status,
+                    code: None,
+                    message: text.clone(),
+                }
+            }
+        })?;
+
+        if !envelope.success {
+            return Err(api_error(context, status, envelope.error, envelope.detail));
+        }
+        // Control endpoints answer with `"data": null`
+        match envelope.data {
+            Some(data) => Ok(data),
+            None => serde_json::from_value(serde_json::Value::Null).map_err(|e| Error::Parse {
+                context,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn torrent(&self, torrent_id: &str) -> Result<Torrent> {
+        self.call(
+            self.get("/torrents/mylist")
+                .query(&[("id", torrent_id), ("bypass_cache", "true")]),
+            "Failed to get torrent info",
+        )
+        .await
+    }
+
+    /// The started torrent with `hash`, if the queued one has left the queue.
+    async fn dequeued(&self, hash: &str) -> Result<Option<Torrent>> {
+        let torrents: Vec<Torrent> = self
+            .call(
+                self.get("/torrents/mylist")
+                    .query(&[("bypass_cache", "true")]),
+                "Failed to list torrents",
+            )
+            .await?;
+        Ok(torrents.into_iter().find(|t| {
+            t.hash
+                .as_deref()
+                .is_some_and(|h| h.eq_ignore_ascii_case(hash))
+        }))
+    }
+
+    /// Looks up a torrent by the id [`DebridProvider::add_magnet`] returned.
+    /// `None` means it is still waiting in the account's queue.
+    async fn resolve_id(&self, torrent_id: &str) -> Result<Option<Torrent>> {
+        match torrent_id.strip_prefix(QUEUED) {
+            Some(queued) => {
+                let (_, hash) = queued.split_once(':').unwrap_or((queued, ""));
+                self.dequeued(hash).await
+            }
+            None => self.torrent(torrent_id).await.map(Some),
+        }
+    }
+}
+
+fn api_error(
+    context: &'static str,
+    status: StatusCode,
+    error: Option<String>,
+    detail: Option<String>,
+) -> Error {
+    let code = error.unwrap_or_else(|| "UNKNOWN".to_string());
+    let message = detail.unwrap_or_else(|| "Unknown error".to_string());
+    // Surface auth failures the same way RD's 401s are, so callers can
+    // treat every provider alike
+    let status = if matches!(code.as_str(), "AUTH_ERROR" | "NO_AUTH" | "BAD_TOKEN") {
+        StatusCode::UNAUTHORIZED
+    } else {
+        status
+    };
+    Error::Api {
+        context,
+        status,
+        code: None,
+        message: format!("{} ({})", message, code),
+    }
+}
+
+/// Maps TorBox's download states onto [`TorrentState`].
+fn torrent_state(torrent: &Torrent) -> TorrentState {
+    let state = torrent.download_state.as_str();
+    if torrent.download_present.unwrap_or(false) {
+        TorrentState::Ready
+    } else if state == "error" || state == "missingFiles" || state.starts_with("failed") {
+        TorrentState::Failed(state.to_string())
+    } else if state == "metaDL" {
+        TorrentState::Resolving
+    } else {
+        TorrentState::Processing
+    }
+}
+
+#[async_trait]
+impl DebridProvider for TorBox {
+    fn name(&self) -> &'static str {
+        "torbox"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "TorBox"
+    }
+
+    async fn add_magnet(&self, magnet: &str) -> Result<String> {
+        let form = Form::new().text("magnet", magnet.to_string());
+        let data: CreateData = self
+            .call(
+                self.post("/torrents/createtorrent").multipart(form),
+                "Failed to add magnet",
+            )
+            .await?;
+
+        match (data.torrent_id, data.queued_id) {
+            (Some(id), _) => Ok(id.to_string()),
+            (None, Some(queued)) => Ok(format!(
+                "{}{}:{}",
+                QUEUED,
+                queued,
+                data.hash.unwrap_or_default()
+            )),
+            (None, None) => Err(Error::Torrent("magnet rejected".to_string())),
+        }
+    }
+
+    async fn poll(&self, torrent_id: &str) -> Result<TorrentStatus> {
+        let Some(torrent) = self.resolve_id(torrent_id).await? else {
+            let (state, files, links) =
+                self.selection
+                    .resolve(torrent_id, TorrentState::Processing, None, torrent_id, 0);
+            return Ok(TorrentStatus {
+                state,
+                name: None,
+                status: "queued".to_string(),
+                progress: 0.0,
+                speed: 0,
+                seeders: 0,
+                files,
+                links,
+            });
+        };
+
+        let state = torrent_state(&torrent);
+        let ready = state == TorrentState::Ready;
+        let all_files = ready.then(|| {
+            torrent
+                .files
+                .iter()
+                .flatten()
+                .map(|f| {
+                    let file = TorrentFile {
+                        id: f.id,
+                        path: f.name.clone(),
+                        bytes: f.size.unwrap_or(0),
+                        selected: 1,
+                    };
+                    (file, format!("{}{}:{}", FILE_LINK, torrent.id, f.id))
+                })
+                .collect()
+        });
+        let name = torrent.name.as_deref().unwrap_or(torrent_id);
+        let (state, files, links) = self.selection.resolve(
+            torrent_id,
+            state,
+            all_files,
+            name,
+            torrent.size.unwrap_or(0),
+        );
+
+        let progress = if ready {
+            100.0
+        } else {
+            torrent.progress.unwrap_or(0.0) * 100.0
+        };
+
+        Ok(TorrentStatus {
+            state,
+            name: torrent.name,
+            status: torrent.download_state,
+            progress,
+            speed: torrent.download_speed.unwrap_or(0),
+            seeders: torrent.seeds.unwrap_or(0),
+            files,
+            links,
+        })
+    }
+
+    async fn select_files(&self, torrent_id: &str, file_ids: &[u32]) -> Result<()> {
+        self.selection.select(torrent_id, file_ids);
+        Ok(())
+    }
+
+    async fn unrestrict(&self, link: &str) -> Result<UnrestrictedLink> {
+        let (torrent_id, file_id) = link
+            .strip_prefix(FILE_LINK)
+            .and_then(|ids| ids.split_once(':'))
+            .ok_or_else(|| Error::Other(format!("Not a TorBox link: {}", link)))?;
+
+        let torrent = self.torrent(torrent_id).await?;
+        let file = torrent
+            .files
+            .into_iter()
+            .flatten()
+            .find(|f| f.id.to_string() == file_id)
+            .ok_or_else(|| Error::Torrent(format!("file {} not found", file_id)))?;
+
+        let download: String = self
+            .call(
+                self.get("/torrents/requestdl").query(&[
+                    http::token_param("token", &self.api_key),
+                    ("torrent_id", torrent_id),
+                    ("file_id", file_id),
+                ]),
+                "Failed to request download link",
+            )
+            .await?;
+        Ok(UnrestrictedLink {
+            filename: file
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or(&file.name)
+                .to_string(),
+            download,
+            filesize: file.size,
+        })
+    }
+
+    async fn delete(&self, torrent_id: &str) -> Result<()> {
+        self.selection.forget(torrent_id);
+        let queued = torrent_id
+            .strip_prefix(QUEUED)
+            .map(|q| q.split_once(':').unwrap_or((q, "")));
+        let started = match queued {
+            Some((_, hash)) => self.dequeued(hash).await?.map(|t| t.id),
+            None => Some(torrent_id.parse().unwrap_or_default()),
+        };
+        let req = match (started, queued) {
+            (Some(id), _) => self
+                .post("/torrents/controltorrent")
+                .json(&serde_json::json!({
+                    "torrent_id": id,
+                    "operation": "delete",
+                })),
+            (None, Some((queued_id, _))) => {
+                let queued_id: u64 = queued_id.parse().unwrap_or_default();
+                self.post("/queued/controlqueued").json(&serde_json::json!({
+                    "queued_id": queued_id,
+                    "operation": "delete",
+                }))
+            }
+            (None, None) => return Ok(()),
+        };
+        let _: serde_json::Value = self.call(req, "Failed to delete torrent").await?;
+        Ok(())
+    }
+
+    async fn account(&self) -> Result<Option<Account>> {
+        let user: User = self
+            .call(self.get("/user/me"), "Failed to get account")
+            .await?;
+        let premium_until = user
+            .premium_expires_at
+            .filter(|_| user.plan > 0)
+            .and_then(|at| parse_timestamp(at.get(..19).unwrap_or(&at)));
+        Ok(Some(Account {
+            username: user.email,
+            premium_until,
+        }))
+    }
+    async fn cached(&self, hashes: &[String]) -> Result<Option<HashSet<String>>> {
+        let found: Option<Vec<CachedTorrent>> = self
+            .call(
+                self.get("/torrents/checkcached")
+                    .query(&[("hash", hashes.join(",")), ("format", "list".to_string())]),
+                "Failed to check cache",
+            )
+            .await?;
+        Ok(Some(
+            found
+                .unwrap_or_default()
+                .into_iter()
+                .map(|t| t.hash.to_lowercase())
+                .collect(),
+        ))
+    }
+}
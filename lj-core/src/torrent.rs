@@ -0,0 +1,234 @@
+//! `.torrent` files, read just far enough to turn them into magnet links.
+//!
+//! Providers take magnets, so a torrent file is reduced to its info hash,
+//! name and trackers. Only v1 (and hybrid) torrents have the SHA-1 info
+//! hash a magnet needs. Where a provider takes uploads, a [`Source`] keeps
+//! the file itself, so private trackers' passkeys survive.
+
+use crate::error::{Error, Result};
+use crate::magnet::{Magnet, encode};
+use crate::provider::DebridProvider;
+use reqwest::Client;
+use reqwest::header::LOCATION;
+use sha1::{Digest, Sha1};
+
+/// A decoded bencode value. Dicts keep their raw bytes, which is what the
+/// info hash is taken over.
+enum Value<'a> {
+    Int,
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    Dict(Vec<(&'a [u8], Value<'a>)>, &'a [u8]),
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Parse {
+        context: "Invalid torrent file",
+        message: message.to_string(),
+    }
+}
+
+/// Reads the value starting at `pos`, returning it and where it ends.
+fn parse(data: &[u8], pos: usize) -> Result<(Value<'_>, usize)> {
+    let end_of = |from: usize, byte: u8| {
+        data[from..]
+            .iter()
+            .position(|&b| b == byte)
+            .map(|i| from + i)
+            .ok_or_else(|| invalid("unexpected end of data"))
+    };
+    match data.get(pos) {
+        Some(b'i') => Ok((Value::Int, end_of(pos, b'e')? + 1)),
+        Some(b'l') => {
+            let mut items = Vec::new();
+            let mut pos = pos + 1;
+            while data.get(pos) != Some(&b'e') {
+                let (item, next) = parse(data, pos)?;
+                items.push(item);
+                pos = next;
+            }
+            Ok((Value::List(items), pos + 1))
+        }
+        Some(b'd') => {
+            let start = pos;
+            let mut entries = Vec::new();
+            let mut pos = pos + 1;
+            while data.get(pos) != Some(&b'e') {
+                let (Value::Bytes(key), next) = parse(data, pos)? else {
+                    return Err(invalid("dictionary key is not a string"));
+                };
+                let (value, next) = parse(data, next)?;
+                entries.push((key, value));
+                pos = next;
+            }
+            Ok((Value::Dict(entries, &data[start..=pos]), pos + 1))
+        }
+        Some(b'0'..=b'9') => {
+            let colon = end_of(pos, b':')?;
+            let len: usize = std::str::from_utf8(&data[pos..colon])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| invalid("bad string length"))?;
+            let end = colon + 1 + len;
+            let bytes = data
+                .get(colon + 1..end)
+                .ok_or_else(|| invalid("unexpected end of data"))?;
+            Ok((Value::Bytes(bytes), end))
+        }
+        _ => Err(invalid("unexpected byte")),
+    }
+}
+
+fn get<'a, 'v>(entries: &'v [(&'a [u8], Value<'a>)], key: &str) -> Option<&'v Value<'a>> {
+    entries
+        .iter()
+        .find(|(k, _)| *k == key.as_bytes())
+        .map(|(_, v)| v)
+}
+
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        _ => None,
+    }
+}
+
+/// The magnet link for a `.torrent` file's contents, with its name and
+/// trackers.
+pub fn to_magnet(data: &[u8]) -> Result<String> {
+    let (Value::Dict(root, _), _) = parse(data, 0)? else {
+        return Err(invalid("not a dictionary"));
+    };
+    let Some(Value::Dict(info, raw)) = get(&root, "info") else {
+        return Err(invalid("no info dictionary"));
+    };
+
+    let hash: String = Sha1::digest(raw)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let mut magnet = format!("magnet:?xt=urn:btih:{}", hash);
+    if let Some(name) = text(get(info, "name")) {
+        magnet.push_str(&format!("&dn={}", encode(&name)));
+    }
+
+    let mut trackers: Vec<String> = text(get(&root, "announce")).into_iter().collect();
+    if let Some(Value::List(tiers)) = get(&root, "announce-list") {
+        for tier in tiers {
+            if let Value::List(urls) = tier {
+                trackers.extend(urls.iter().filter_map(|url| text(Some(url))));
+            }
+        }
+    }
+    let mut seen = Vec::new();
+    for tracker in trackers {
+        if !seen.contains(&tracker) {
+            magnet.push_str(&format!("&tr={}", encode(&tracker)));
+            seen.push(tracker);
+        }
+    }
+    Ok(magnet)
+}
+
+/// A torrent to add: a magnet link, or a `.torrent` file downloaded from a
+/// URL.
+pub enum Source {
+    Magnet(String),
+    /// The file's contents, and the magnet they amount to.
+    File {
+        data: Vec<u8>,
+        magnet: String,
+    },
+}
+
+impl Source {
+    /// `input` as a torrent to add: http(s) URLs are fetched, anything else
+    /// is taken to be a magnet link.
+    pub async fn resolve(client: &Client, input: &str) -> Result<Source> {
+        if is_url(input) {
+            fetch(client, input).await
+        } else {
+            Ok(Source::Magnet(input.to_string()))
+        }
+    }
+
+    /// The magnet link, which for a file is only as good as its trackers.
+    pub fn magnet(&self) -> &str {
+        match self {
+            Source::Magnet(magnet) | Source::File { magnet, .. } => magnet,
+        }
+    }
+
+    pub fn into_magnet(self) -> String {
+        match self {
+            Source::Magnet(magnet) | Source::File { magnet, .. } => magnet,
+        }
+    }
+
+    /// Submits the torrent to `provider`, uploading files, and returns its
+    /// torrent id.
+    pub async fn add(&self, provider: &dyn DebridProvider) -> Result<String> {
+        match self {
+            Source::Magnet(magnet) => provider.add_magnet(magnet).await,
+            Source::File { data, .. } => provider.add_torrent(data).await,
+        }
+    }
+}
+
+/// Whether `input` is an http(s) URL, to be fetched as a `.torrent` file.
+pub fn is_url(input: &str) -> bool {
+    let input = input.trim_start();
+    ["http://", "https://"].iter().any(|scheme| {
+        input
+            .get(..scheme.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// Downloads the `.torrent` file at `url`, which must be bencoded whatever
+/// the URL looks like.
+pub async fn fetch(client: &Client, url: &str) -> Result<Source> {
+    let context = "Failed to fetch torrent";
+    let resp = client
+        .get(url.trim())
+        .send()
+        .await
+        .map_err(Error::http(context))?;
+    let status = resp.status();
+    // Indexers and feeds often answer with a redirect to the magnet, which
+    // the client doesn't follow
+    if let Some(location) = resp
+        .headers()
+        .get(LOCATION)
+        .and_then(|l| l.to_str().ok())
+        .filter(|l| status.is_redirection() && l.starts_with("magnet:"))
+    {
+        return Ok(Source::Magnet(location.to_string()));
+    }
+    if !status.is_success() {
+        return Err(Error::Status(status));
+    }
+    let data = resp.bytes().await.map_err(Error::http(context))?.to_vec();
+    let magnet = to_magnet(&data)?;
+    Ok(Source::File { data, magnet })
+}
+
+/// The magnet link for the `.torrent` file at `url`.
+pub async fn magnet_from_url(client: &Client, url: &str) -> Result<String> {
+    Ok(fetch(client, url).await?.into_magnet())
+}
+
+/// The info hash a magnet link names, as lowercase hex. Base32 hashes are
+/// converted, so the same torrent always gets the same hash.
+///
+/// ```
+/// use lj_core::torrent::info_hash;
+///
+/// let hex = "magnet:?xt=urn:btih:C12FE1C06BBA254A9DC9F519B335AA7C1367A88A&dn=x";
+/// let base32 = "magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK";
+/// assert_eq!(info_hash(hex).as_deref(), Some("c12fe1c06bba254a9dc9f519b335aa7c1367a88a"));
+/// assert_eq!(info_hash(base32), info_hash(hex));
+/// ```
+pub fn info_hash(magnet: &str) -> Option<String> {
+    Magnet::parse(magnet).ok()?.info_hash
+}
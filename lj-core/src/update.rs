@@ -0,0 +1,183 @@
+//! Self-update from GitHub releases.
+//!
+//! A release carries one binary per platform, named `lj-<arch>-<os>` after
+//! [`std::env::consts`] (e.g. `lj-x86_64-linux`, `lj-aarch64-macos`,
+//! `lj-x86_64-windows.exe`), each with a `<name>.sha256` file next to it.
+//! The binary is only installed if its SHA-256 matches.
+
+use crate::error::{Error, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Repository whose releases are checked.
+pub const REPOSITORY: &str = "mat-lo/lj";
+
+const API_URL: &str = "https://api.github.com";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub html_url: String,
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// The tag without its `v` prefix.
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    /// The binary for this platform, if the release has one.
+    pub fn binary(&self) -> Option<&Asset> {
+        self.asset(&asset_name())
+    }
+
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+}
+
+/// Name of this platform's release binary.
+pub fn asset_name() -> String {
+    format!(
+        "lj-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+/// Whether `latest` is a newer version than `current`. Versions compare by
+/// their dot-separated numbers; anything after a `-` is ignored.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split('-')
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|n| n.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(latest) > parse(current)
+}
+
+/// Fetches the latest published release.
+pub async fn latest_release(client: &Client) -> Result<Release> {
+    let context = "Failed to check for updates";
+    let resp = client
+        .get(format!("{}/repos/{}/releases/latest", API_URL, REPOSITORY))
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .map_err(Error::http(context))?;
+    if !resp.status().is_success() {
+        return Err(Error::Api {
+            context,
+            status: resp.status(),
+            code: None,
+            message: resp.text().await.unwrap_or_default(),
+        });
+    }
+    resp.json().await.map_err(|e| Error::Parse {
+        context,
+        message: e.to_string(),
+    })
+}
+
+async fn fetch(client: &Client, asset: &Asset) -> Result<Vec<u8>> {
+    let context = "Failed to download update";
+    let resp = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await
+        .map_err(Error::http(context))?;
+    if !resp.status().is_success() {
+        return Err(Error::Status(resp.status()));
+    }
+    let bytes = resp.bytes().await.map_err(Error::http(context))?;
+    Ok(bytes.to_vec())
+}
+
+/// Downloads this platform's binary from `release`, checks it against its
+/// published SHA-256 and swaps it in for the running executable. Returns
+/// the path that was replaced.
+pub async fn install(client: &Client, release: &Release) -> Result<PathBuf> {
+    let name = asset_name();
+    let binary = release.binary().ok_or_else(|| {
+        Error::Other(format!(
+            "Release {} has no binary for this platform ({})",
+            release.tag_name, name
+        ))
+    })?;
+    let checksum = release.asset(&format!("{}.sha256", name)).ok_or_else(|| {
+        Error::Other(format!(
+            "Release {} has no checksum for {}",
+            release.tag_name, name
+        ))
+    })?;
+
+    let expected = String::from_utf8_lossy(&fetch(client, checksum).await?)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Other(format!(
+            "Malformed checksum file for {}",
+            name
+        )));
+    }
+    let data = fetch(client, binary).await?;
+    let actual: String = Sha256::digest(&data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if actual != expected {
+        return Err(Error::Other(format!(
+            "Checksum mismatch for {} (expected {}, got {})",
+            name, expected, actual
+        )));
+    }
+
+    let exe = env::current_exe()?.canonicalize()?;
+    replace_exe(&exe, &data)?;
+    Ok(exe)
+}
+
+/// Writes the new binary next to `exe` and renames it into place, so a
+/// failure never leaves a half-written executable.
+fn replace_exe(exe: &Path, data: &[u8]) -> Result<()> {
+    let new = exe.with_extension("new");
+    fs::write(&new, data)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Windows cannot overwrite a running executable, but it can rename it
+    let old = exe.with_extension("old");
+    if cfg!(windows) {
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old)?;
+    }
+
+    if let Err(e) = fs::rename(&new, exe) {
+        let _ = fs::remove_file(&new);
+        if cfg!(windows) {
+            let _ = fs::rename(&old, exe);
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
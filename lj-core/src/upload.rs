@@ -0,0 +1,161 @@
+//! Uploading finished downloads to cloud storage with rclone.
+//!
+//! With `[upload] remote` set, the [`hooks`](crate::hooks) step that ends a
+//! batch hands each completed file to `rclone moveto` (or `copyto`, with
+//! `keep_local`), after any [extraction](crate::extract) and `on_batch`,
+//! so both still see the local files. Files keep their path below
+//! `download_dir` on the remote, and the destination is recorded on the
+//! download as [`Download::remote`]. A remote that is an absolute local
+//! path, e.g. a mounted NAS share, is moved into without rclone.
+
+use crate::config::UploadConfig;
+use crate::error::{Error, Result};
+use crate::logging::{self, Level};
+use crate::platform;
+use crate::store::{self, Download, DownloadStatus};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::process::Command;
+
+/// rclone executable used when `program` is unset.
+pub const DEFAULT_PROGRAM: &str = "rclone";
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+    remote: String,
+    keep_local: bool,
+    program: String,
+    args: Vec<String>,
+    /// Paths below this keep their layout on the remote.
+    root: Option<PathBuf>,
+}
+
+impl Upload {
+    /// The uploads described by `config`, if it has a remote. Paths are
+    /// kept relative to `download_dir`.
+    pub fn from_config(config: &UploadConfig, download_dir: Option<&Path>) -> Option<Self> {
+        Some(Upload {
+            remote: config.remote.clone().filter(|r| !r.trim().is_empty())?,
+            keep_local: config.keep_local,
+            program: config
+                .program
+                .clone()
+                .unwrap_or_else(|| DEFAULT_PROGRAM.to_string()),
+            args: config.args.clone(),
+            root: download_dir.and_then(|dir| std::path::absolute(dir).ok()),
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        Path::new(&self.remote).is_absolute()
+    }
+
+    /// Where `dl` goes on the remote.
+    fn destination(&self, dl: &Download) -> String {
+        let path = dl.target_path();
+        let relative = self
+            .root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(Path::new(&dl.filename));
+        if self.is_local() {
+            return Path::new(&self.remote)
+                .join(relative)
+                .to_string_lossy()
+                .to_string();
+        }
+        let relative: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let remote = self.remote.trim_end_matches('/');
+        if remote.ends_with(':') {
+            format!("{}{}", remote, relative.join("/"))
+        } else {
+            format!("{}/{}", remote, relative.join("/"))
+        }
+    }
+
+    async fn move_locally(&self, from: &Path, to: &Path) -> Result<()> {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        if self.keep_local {
+            fs::copy(from, to).await?;
+            return Ok(());
+        }
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        tokio::task::spawn_blocking(move || platform::move_file(&from, &to, &mut |_, _| {}))
+            .await
+            .map_err(|e| Error::Other(e.to_string()))??;
+        Ok(())
+    }
+
+    async fn rclone(&self, from: &Path, to: &str) -> Result<()> {
+        let verb = if self.keep_local { "copyto" } else { "moveto" };
+        let output = Command::new(&self.program)
+            .arg(verb)
+            .args(&self.args)
+            .arg(from)
+            .arg(to)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .map_err(|e| Error::Other(format!("Failed to run {}: {}", self.program, e)))?;
+        if !output.status.success() {
+            let errors = String::from_utf8_lossy(&output.stderr);
+            let reason = errors
+                .lines()
+                .map(str::trim)
+                .rfind(|l| !l.is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{} exited with {}", self.program, output.status));
+            return Err(Error::Other(reason));
+        }
+        Ok(())
+    }
+
+    /// Uploads one completed download, recording and returning where it
+    /// went.
+    pub async fn upload(&self, dl: &Download) -> Result<String> {
+        let from = dl.target_path();
+        let to = self.destination(dl);
+        if self.is_local() {
+            self.move_locally(&from, Path::new(&to)).await?;
+        } else {
+            self.rclone(&from, &to).await?;
+        }
+        store::update_download(&dl.id, |dl| dl.remote = Some(to.clone()))?;
+        Ok(to)
+    }
+
+    /// Uploads each completed file among a finished batch's `downloads`
+    /// that is still on disk, logging how each went.
+    pub async fn upload_batch(&self, downloads: &[Download]) {
+        for dl in downloads {
+            // Archives may have been deleted after extraction
+            if dl.status != DownloadStatus::Completed
+                || dl.remote.is_some()
+                || !dl.target_path().exists()
+            {
+                continue;
+            }
+            let id = dl.id.as_str();
+            match self.upload(dl).await {
+                Ok(to) => logging::log(
+                    Level::Info,
+                    &format!("{}: Uploaded {} to {}", id, dl.filename, to),
+                    &[("LJ_DOWNLOAD_ID", id), ("LJ_EVENT", "uploaded")],
+                ),
+                Err(e) => logging::log(
+                    Level::Error,
+                    &format!("{}: Uploading {} failed: {}", id, dl.filename, e),
+                    &[("LJ_DOWNLOAD_ID", id), ("LJ_EVENT", "upload_failed")],
+                ),
+            }
+        }
+    }
+}
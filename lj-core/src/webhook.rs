@@ -0,0 +1,100 @@
+//! Generic webhook notifications.
+//!
+//! Each event is POSTed to the `[webhook]` section's `url` as JSON: the
+//! event's own fields, the download record and a Unix `timestamp`. With a
+//! `secret`, the body is signed with HMAC-SHA256 and the signature sent as
+//! `X-Lj-Signature: sha256=<hex>`.
+
+use crate::config::WebhookConfig;
+use crate::error::{Error, Result};
+use crate::events::DownloadEvent;
+use crate::notify::{self, Notifier};
+use crate::store;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Header carrying the body's HMAC-SHA256 signature.
+pub const SIGNATURE_HEADER: &str = "X-Lj-Signature";
+
+/// Events sent when the section lists none.
+const DEFAULT_EVENTS: &[&str] = &["started", "completed", "failed"];
+
+pub struct Webhook {
+    url: String,
+    secret: Option<String>,
+    events: Vec<String>,
+}
+
+impl Webhook {
+    /// The webhook described by `config`, if it has a URL.
+    pub fn from_config(config: &WebhookConfig) -> Option<Self> {
+        Some(Webhook {
+            url: config.url.clone()?,
+            secret: config.secret.clone(),
+            events: config.events.clone(),
+        })
+    }
+}
+
+/// Hex HMAC-SHA256 of `body` under `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn payload(event: &DownloadEvent) -> serde_json::Value {
+    let mut payload = serde_json::to_value(event).unwrap_or_default();
+    if let Some(fields) = payload.as_object_mut() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        fields.insert("timestamp".to_string(), timestamp.into());
+        fields.insert(
+            "download".to_string(),
+            serde_json::to_value(store::load_download(event.id())).unwrap_or_default(),
+        );
+    }
+    payload
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn wants(&self, event: &DownloadEvent) -> bool {
+        notify::selects(&self.events, DEFAULT_EVENTS, event)
+    }
+
+    async fn send(&self, client: &Client, event: &DownloadEvent) -> Result<()> {
+        let context = "Webhook request failed";
+        let body = payload(event).to_string();
+        let mut request = client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+        if let Some(secret) = &self.secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", sign(secret, body.as_bytes())),
+            );
+        }
+
+        let resp = request.send().await.map_err(Error::http(context))?;
+        if !resp.status().is_success() {
+            return Err(Error::Status(resp.status()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,890 @@
+//! Background download workers.
+//!
+//! Downloads run in detached processes so they survive the terminal
+//! closing, one per torrent rather than one per file. The host binary
+//! re-executes itself with [`WORKER_FLAG`] and the download ids, and must
+//! dispatch that to [`run_worker`].
+
+use crate::config::{self, Config};
+use crate::error::{Error, Result};
+use crate::events::{self, DownloadEvent};
+use crate::http;
+use crate::logging::{self, Level};
+use crate::notify;
+use crate::platform::{self, sanitize_filename};
+use crate::progress::{self, Snapshot};
+use crate::ratelimit::RateLimiter;
+use crate::sink::FileSink;
+use crate::speed::SpeedMeter;
+use crate::store::{self, Download, DownloadStatus};
+use futures_util::StreamExt;
+use reqwest::header;
+use reqwest::{Client, Response, StatusCode};
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Argument the host binary recognises as "run the worker for this id".
+pub const WORKER_FLAG: &str = "--bg-download";
+
+/// How many times [`supervise`] relaunches a download whose worker died
+/// before it is marked failed.
+pub const MAX_RESTARTS: u32 = 3;
+
+/// How long a transfer waits for the network to come back before failing.
+const NETWORK_WAIT: Duration = Duration::from_secs(30 * 60);
+
+/// How long a worker waits for another process to let go of its download,
+/// e.g. a cancelled worker that is still shutting down.
+const CLAIM_WAIT: Duration = Duration::from_secs(10);
+
+/// How many of its downloads a worker process runs at once.
+pub const FILES_AT_ONCE: usize = 4;
+
+/// Settings every transfer in a process shares, from `config.toml`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferOptions {
+    /// How long a transfer may go without new data (see [`run_download`]).
+    pub idle: Option<Duration>,
+    /// Whether to name files as the host's `Content-Disposition` does.
+    pub host_filenames: bool,
+    /// Whether to download untrusted links rather than refuse them.
+    pub insecure_links: bool,
+    /// Whether to write files past the page cache (see [`FileSink`]).
+    pub direct_io: bool,
+}
+
+impl TransferOptions {
+    pub fn from_config(config: &Config) -> Self {
+        TransferOptions {
+            idle: config.wait.download_timeout(),
+            host_filenames: config.host_filenames,
+            insecure_links: config.insecure_links,
+            direct_io: config.direct_io,
+        }
+    }
+}
+
+/// How much of a partial file a resumed transfer fetches again and compares
+/// with what is on disk, which catches a link that now serves another file
+/// and a partial file that was damaged.
+const RESUME_OVERLAP: u64 = 256 * 1024;
+
+/// How often a transfer saves its progress to the store, and notices it
+/// was cancelled, while it publishes it [live](progress).
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a transfer that stopped getting data was waiting for.
+const IDLE: &str = "data from the host";
+
+/// Exclusive hold on a download, so no two processes write its file at
+/// once. Taken before a transfer touches the record or the file, and let go
+/// when it is dropped or its process dies.
+pub struct Claim {
+    file: fs::File,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    path: PathBuf,
+}
+
+impl Claim {
+    /// Takes the hold on `download_id`, failing if another process has it.
+    pub fn acquire(download_id: &str) -> io::Result<Claim> {
+        let dir = config::state_dir().join("locks");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.lock", platform::sanitize_filename(download_id)));
+        loop {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            match file.try_lock() {
+                // The holder it was waiting on may have removed the file
+                // before letting go, and another process locked a new one
+                Ok(()) if !is_at(&file, &path)? => continue,
+                Ok(()) => return Ok(Claim { file, path }),
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        format!("{} is being downloaded by another process", download_id),
+                    ));
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// [`acquire`](Self::acquire), retrying for up to [`CLAIM_WAIT`].
+    async fn wait_for(download_id: &str) -> io::Result<Claim> {
+        let start = Instant::now();
+        loop {
+            match Claim::acquire(download_id) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && start.elapsed() < CLAIM_WAIT => {
+                    tokio::time::sleep(Duration::from_millis(500)).await
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        // Removed while still held, which `acquire` checks for. Windows
+        // doesn't say which file a handle is, so the lock file stays there.
+        #[cfg(unix)]
+        let _ = fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Whether `file` is still the one at `path`.
+#[cfg(unix)]
+fn is_at(file: &fs::File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let held = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(there) => Ok(held.dev() == there.dev() && held.ino() == there.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_at(_file: &fs::File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Starts one detached worker process for `downloads`, e.g. the files of a
+/// torrent, and records it as theirs. It downloads up to [`FILES_AT_ONCE`]
+/// of them at a time; the rest stay pending until their turn.
+pub fn spawn_worker(downloads: &[Download]) -> io::Result<u32> {
+    let exe = env::current_exe()?;
+
+    let mut cmd = Command::new(&exe);
+    cmd.arg(WORKER_FLAG)
+        .args(downloads.iter().map(|dl| &dl.id))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Some(profile) = config::profile() {
+        cmd.env(config::PROFILE_ENV, profile);
+    }
+    platform::detach(&mut cmd);
+    let child = cmd.spawn()?;
+
+    let pid = child.id();
+    for download in downloads {
+        // The worker may already have claimed the record; only fill in what's missing
+        let _ = store::update_download(&download.id, |dl| {
+            if dl.status == DownloadStatus::Pending {
+                dl.set_process(pid);
+            }
+        });
+    }
+    Ok(pid)
+}
+
+/// Worker process entry point: fetches each download to its target path,
+/// persisting progress as it goes. The transfers share one client.
+pub async fn run_worker(download_ids: &[String]) -> Result<()> {
+    logging::init();
+    // A broken [tls] section should fail the downloads rather than silently
+    // fall back to default certificate handling
+    let loaded = Config::load().and_then(|config| Ok((http::client(&config)?, config)));
+    let (client, options) = match loaded {
+        Ok((client, config)) => {
+            notify::start(&config, &client);
+            (client, TransferOptions::from_config(&config))
+        }
+        Err(e) => {
+            for id in download_ids {
+                let failed = store::update_download(id, |dl| {
+                    dl.status = DownloadStatus::Failed(e.to_string());
+                });
+                if let Ok(Some(dl)) = failed
+                    && let Some(event) = finished_event(&dl)
+                {
+                    events::emit(event);
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    let client = &client;
+    futures_util::stream::iter(download_ids)
+        .for_each_concurrent(FILES_AT_ONCE, |id| async move {
+            if let Err(e) = run_file(id, client, options).await {
+                logging::log(
+                    Level::Warning,
+                    &format!("{}: {}", id, e),
+                    &[("LJ_DOWNLOAD_ID", id), ("LJ_EVENT", "skipped")],
+                );
+            }
+        })
+        .await;
+    progress::close();
+    notify::finish().await;
+    Ok(())
+}
+
+/// Runs one of a worker's downloads, unless it was cancelled while it waited
+/// for its turn.
+async fn run_file(download_id: &str, client: &Client, options: TransferOptions) -> Result<()> {
+    // Spawned twice, e.g. retried while a cancelled worker lingers: the
+    // record is the other process's until it lets go
+    let claim = Claim::wait_for(download_id).await?;
+    let mut cancelled = false;
+    let download = store::update_download(download_id, |dl| {
+        if dl.status == DownloadStatus::Cancelled {
+            cancelled = true;
+            return;
+        }
+        dl.status = DownloadStatus::Downloading;
+        dl.set_process(std::process::id());
+    })?
+    .ok_or_else(|| Error::Other(format!("Download not found: {}", download_id)))?;
+    if cancelled {
+        events::emit(DownloadEvent::Cancelled { id: download.id });
+        return Ok(());
+    }
+    run_download(download, client, None, options, claim).await;
+    Ok(())
+}
+
+/// Runs a transfer in the current process and records the outcome.
+///
+/// Cancellation is picked up from the store, so this can be shared between
+/// worker processes and the daemon. `limiter` throttles the transfer against
+/// a bandwidth budget shared with other transfers. A record that already has
+/// progress continues from the end of its partial file if the host supports
+/// range requests, and so does a transfer the network dropped, once it is
+/// back (see [`wait_for_network`]). A connection that sends nothing for
+/// `options.idle` is dropped and made again, and the download fails once its
+/// file hasn't grown for that long across reconnects. The transfer holds
+/// `claim` until it ends.
+pub async fn run_download(
+    mut download: Download,
+    client: &Client,
+    limiter: Option<&RateLimiter>,
+    options: TransferOptions,
+    claim: Claim,
+) {
+    let download_id = download.id.clone();
+    let idle = options.idle;
+
+    let mut announce = true;
+    let mut meter = SpeedMeter::default();
+    let mut progress = (download.downloaded_bytes, Instant::now());
+    let result = loop {
+        let e = match transfer(
+            &mut download,
+            client,
+            limiter,
+            options,
+            &mut meter,
+            announce,
+        )
+        .await
+        {
+            Err(e) if is_network(&e) || matches!(e, Error::Timeout(IDLE)) => e,
+            result => break result,
+        };
+        let len = fs::metadata(download.target_path()).map_or(0, |meta| meta.len());
+        if len > progress.0 {
+            progress = (len, Instant::now());
+        } else if idle.is_some_and(|idle| progress.1.elapsed() >= idle) {
+            break Err(Error::Timeout(IDLE));
+        }
+        if matches!(e, Error::Timeout(_)) {
+            logging::log(
+                Level::Warning,
+                &format!("{}: no data from the host; reconnecting", download.id),
+                &[("LJ_DOWNLOAD_ID", &download.id), ("LJ_EVENT", "idle")],
+            );
+        } else if let Err(e) = wait_for_network(&download, client, e).await {
+            break Err(e);
+        }
+        download.downloaded_bytes = len;
+        announce = false;
+    };
+
+    if matches!(result, Err(Error::Cancelled)) {
+        let _ = std::fs::remove_file(download.target_path());
+    }
+
+    let finished = store::update_download(&download_id, |dl| {
+        match result {
+            Ok(downloaded) => {
+                dl.status = DownloadStatus::Completed;
+                dl.downloaded_bytes = downloaded;
+                dl.total_bytes = dl.total_bytes.max(downloaded);
+            }
+            Err(Error::Cancelled) => dl.status = DownloadStatus::Cancelled,
+            Err(e) => {
+                if dl.status != DownloadStatus::Cancelled {
+                    dl.status = DownloadStatus::Failed(e.to_string());
+                }
+            }
+        }
+        dl.speed = 0.0;
+        dl.pid = None;
+        dl.pid_started = None;
+    });
+    drop(claim);
+    progress::clear(&download_id);
+    if let Ok(Some(dl)) = finished
+        && let Some(event) = finished_event(&dl)
+    {
+        events::emit(event);
+    }
+}
+
+/// One attempt at the transfer, from the end of the partial file if the
+/// record has progress. Returns the bytes the file ends up with.
+async fn transfer(
+    download: &mut Download,
+    client: &Client,
+    limiter: Option<&RateLimiter>,
+    options: TransferOptions,
+    meter: &mut SpeedMeter,
+    announce: bool,
+) -> Result<u64> {
+    let download_id = download.id.clone();
+    let mut target_path = download.target_path();
+    // Links are fetched unattended, so one the service should never have
+    // handed out is not followed blindly
+    if let Some(reason) = http::untrusted_link(&download.url) {
+        if !options.insecure_links {
+            return Err(Error::Other(format!(
+                "Refusing to download: {}; set insecure_links to allow it",
+                reason
+            )));
+        }
+        if announce {
+            logging::log(
+                Level::Warning,
+                &format!("{}: {}; downloading anyway", download_id, reason),
+                &[
+                    ("LJ_DOWNLOAD_ID", &download_id),
+                    ("LJ_EVENT", "insecure_link"),
+                ],
+            );
+        }
+    }
+    // Recreating it would hide the partial file's loss, or fill the
+    // mount point of a drive that has gone
+    if download.downloaded_bytes > 0 && !Path::new(&download.target_dir).is_dir() {
+        return Err(Error::Other(format!(
+            "Folder {} is gone",
+            download.target_dir
+        )));
+    }
+    let resume_from = match tokio::fs::metadata(&target_path).await {
+        Ok(meta) if download.downloaded_bytes > 0 => meta.len(),
+        _ => 0,
+    };
+
+    // Ranges and byte counts refer to the bytes as sent, so they must be
+    // the file's own
+    let mut request = client
+        .get(&download.url)
+        .header(header::ACCEPT_ENCODING, "identity");
+    let overlap = resume_from.min(RESUME_OVERLAP);
+    let range_from = resume_from - overlap;
+    if resume_from > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", range_from));
+    }
+    let resp = request
+        .send()
+        .await
+        .map_err(Error::http("Request failed"))?;
+
+    if !resp.status().is_success() {
+        return Err(Error::Status(resp.status()));
+    }
+    if let Some(coding) = http::content_coding(&resp) {
+        return Err(Error::Other(format!(
+            "Host sent the file {}-encoded, not as it is",
+            coding
+        )));
+    }
+
+    // Hosts that ignore the range send the whole file with 200
+    let resumed = resume_from > 0 && resp.status() == StatusCode::PARTIAL_CONTENT;
+    if resumed && range_start(&resp) != Some(range_from) {
+        return start_over(download, client, limiter, options, meter).await;
+    }
+    let start = if resumed { range_from } else { 0 };
+    if resume_from == 0 && options.host_filenames {
+        adopt_host_filename(download, &resp);
+        target_path = download.target_path();
+    }
+    let total_size = resp
+        .content_length()
+        .map(|len| len + start)
+        .unwrap_or(download.total_bytes);
+
+    // A before_download hook may have moved the file somewhere new
+    if !resumed && let Some(dir) = target_path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut file = FileSink::open(&target_path, resumed, options.direct_io).await?;
+
+    if announce {
+        events::emit(DownloadEvent::Started {
+            id: download_id.clone(),
+            filename: download.filename.clone(),
+            total_bytes: total_size,
+        });
+    }
+
+    let mut expected = if resumed {
+        read_tail(&target_path, overlap).await?
+    } else {
+        Vec::new()
+    };
+    let downloaded = start + expected.len() as u64;
+    let mut report = Report::new(&download_id, downloaded, total_size, meter);
+    let mut stream = resp.bytes_stream();
+
+    // The sink writes out what it holds whatever ends the transfer, so a
+    // resumed one doesn't fetch it again
+    let result = async {
+        loop {
+            let chunk = match options.idle {
+                Some(idle) => tokio::time::timeout(idle, stream.next())
+                    .await
+                    .map_err(|_| Error::Timeout(IDLE))?,
+                None => stream.next().await,
+            };
+            let Some(chunk) = chunk else {
+                return Ok(true);
+            };
+            let mut chunk = chunk.map_err(Error::http("Download error"))?;
+
+            if let Some(limiter) = limiter {
+                limiter.acquire(chunk.len()).await;
+            }
+
+            // The overlap is compared with the end of the partial file, not
+            // written again
+            if !expected.is_empty() {
+                let n = chunk.len().min(expected.len());
+                if chunk[..n] != expected[..n] {
+                    return Ok(false);
+                }
+                expected.drain(..n);
+                chunk = chunk.slice(n..);
+            }
+            let len = chunk.len() as u64;
+            file.write(chunk).await?;
+            report.add(len)?;
+        }
+    }
+    .await;
+    let finished = file.finish().await;
+    match result {
+        Ok(true) => {
+            finished?;
+            Ok(report.downloaded)
+        }
+        Ok(false) => start_over(download, client, limiter, options, meter).await,
+        Err(e) => Err(e),
+    }
+}
+
+/// Keeps a transfer's progress: published [live](progress) twice a second,
+/// with events, and saved to the store every [`SAVE_INTERVAL`], which is
+/// also when the transfer learns it was cancelled.
+struct Report<'a> {
+    id: &'a str,
+    downloaded: u64,
+    total: u64,
+    meter: &'a mut SpeedMeter,
+    last_update: Instant,
+    last_saved: Instant,
+    last_bytes: u64,
+}
+
+impl<'a> Report<'a> {
+    fn new(id: &'a str, downloaded: u64, total: u64, meter: &'a mut SpeedMeter) -> Self {
+        Report {
+            id,
+            downloaded,
+            total,
+            meter,
+            last_update: Instant::now(),
+            last_saved: Instant::now(),
+            last_bytes: downloaded,
+        }
+    }
+
+    /// Counts `len` more bytes written, failing if the download was
+    /// cancelled.
+    fn add(&mut self, len: u64) -> Result<()> {
+        self.downloaded += len;
+        if self.last_update.elapsed() < Duration::from_millis(500) {
+            return Ok(());
+        }
+        let (downloaded, total_size) = (self.downloaded, self.total);
+        let speed = self
+            .meter
+            .sample(downloaded - self.last_bytes, self.last_update.elapsed());
+        let average_speed = self.meter.average();
+        let live = progress::publish(
+            self.id,
+            &Snapshot {
+                downloaded_bytes: downloaded,
+                total_bytes: total_size,
+                speed,
+                average_speed,
+            },
+        );
+
+        // Check for cancellation and record progress in one locked update
+        if !live || self.last_saved.elapsed() >= SAVE_INTERVAL {
+            let mut cancelled = false;
+            let _ = store::update_download(self.id, |dl| {
+                if dl.status == DownloadStatus::Cancelled {
+                    cancelled = true;
+                    return;
+                }
+                dl.downloaded_bytes = downloaded;
+                dl.total_bytes = total_size;
+                dl.speed = speed;
+                dl.average_speed = average_speed;
+            });
+            if cancelled {
+                return Err(Error::Cancelled);
+            }
+            self.last_saved = Instant::now();
+        }
+        events::emit(DownloadEvent::Progress {
+            id: self.id.to_string(),
+            downloaded_bytes: downloaded,
+            total_bytes: total_size,
+            speed,
+        });
+
+        self.last_update = Instant::now();
+        self.last_bytes = downloaded;
+        Ok(())
+    }
+}
+
+/// Starts `download` over from the first byte, after its partial file
+/// turned out not to match what the host sends now.
+fn start_over<'a>(
+    download: &'a mut Download,
+    client: &'a Client,
+    limiter: Option<&'a RateLimiter>,
+    options: TransferOptions,
+    meter: &'a mut SpeedMeter,
+) -> Pin<Box<dyn Future<Output = Result<u64>> + Send + 'a>> {
+    logging::log(
+        Level::Warning,
+        &format!(
+            "{}: partial file doesn't match the host's copy; starting over",
+            download.id
+        ),
+        &[
+            ("LJ_DOWNLOAD_ID", &download.id),
+            ("LJ_EVENT", "resume_mismatch"),
+        ],
+    );
+    download.downloaded_bytes = 0;
+    Box::pin(transfer(download, client, limiter, options, meter, false))
+}
+
+/// Where the body of a `206 Partial Content` response starts in the file,
+/// from its `Content-Range`.
+fn range_start(resp: &Response) -> Option<u64> {
+    resp.headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The last `len` bytes of the file at `path`.
+async fn read_tail(path: &Path, len: u64) -> io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(io::SeekFrom::End(-(len as i64))).await?;
+    let mut buf = vec![0; len as usize];
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Renames `download` to the file name in the `Content-Disposition` header
+/// of `resp`, if it gives a different one, keeping the service's name in
+/// `service_filename`.
+fn adopt_host_filename(download: &mut Download, resp: &Response) {
+    let Some(name) = resp
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(http::disposition_filename)
+    else {
+        return;
+    };
+    if name == sanitize_filename(&download.filename) {
+        return;
+    }
+    let renamed = store::update_download(&download.id, |dl| {
+        dl.service_filename
+            .get_or_insert_with(|| dl.filename.clone());
+        dl.filename = name.clone();
+    });
+    if let Ok(Some(dl)) = renamed {
+        download.service_filename = dl.service_filename;
+        download.filename = dl.filename;
+    }
+}
+
+/// Whether `e` is the connection failing rather than the host refusing.
+fn is_network(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Http { source, .. }
+            if source.is_connect() || source.is_timeout() || source.is_body()
+                || source.is_request()
+    )
+}
+
+/// Waits for the network to come back after `error` cut the transfer off,
+/// trying the host again with a growing delay. Fails with `error` if it
+/// isn't back within [`NETWORK_WAIT`], or if the download is cancelled
+/// meanwhile.
+async fn wait_for_network(download: &Download, client: &Client, error: Error) -> Result<()> {
+    logging::log(
+        Level::Warning,
+        &format!("{}: {}; waiting for the network", download.id, error),
+        &[
+            ("LJ_DOWNLOAD_ID", &download.id),
+            ("LJ_EVENT", "network_lost"),
+        ],
+    );
+    let start = Instant::now();
+    let mut delay = Duration::from_secs(5);
+    loop {
+        let mut cancelled = false;
+        let _ = store::update_download(&download.id, |dl| {
+            cancelled = dl.status == DownloadStatus::Cancelled;
+            dl.speed = 0.0;
+        });
+        if cancelled {
+            return Err(Error::Cancelled);
+        }
+        if start.elapsed() > NETWORK_WAIT {
+            return Err(error);
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(Duration::from_secs(60));
+
+        // Any answer will do, even a refusal of HEAD
+        if client.head(&download.url).send().await.is_ok() {
+            logging::log(
+                Level::Info,
+                &format!("{}: network is back, resuming", download.id),
+                &[("LJ_DOWNLOAD_ID", &download.id), ("LJ_EVENT", "resumed")],
+            );
+            return Ok(());
+        }
+    }
+}
+
+/// The event announcing how a finished download ended.
+pub(crate) fn finished_event(dl: &Download) -> Option<DownloadEvent> {
+    let id = dl.id.clone();
+    match &dl.status {
+        DownloadStatus::Completed => Some(DownloadEvent::Completed {
+            id,
+            bytes: dl.downloaded_bytes,
+        }),
+        DownloadStatus::Failed(error) => Some(DownloadEvent::Failed {
+            id,
+            error: error.clone(),
+        }),
+        DownloadStatus::Cancelled => Some(DownloadEvent::Cancelled { id }),
+        DownloadStatus::Pending | DownloadStatus::Downloading => None,
+    }
+}
+
+/// Settles downloads whose worker process has exited. A pid now held by an
+/// unrelated process counts as exited.
+///
+/// What is on disk decides, not the last progress the worker recorded: a
+/// file at its full size is marked completed. Unfinished ones with restarts
+/// left under [`MAX_RESTARTS`] go back to pending and are returned for the
+/// caller to relaunch, resuming from the partial file or starting over if
+/// there is none; the rest are marked failed. Pending records whose
+/// process died before starting them (see
+/// [`Staging`](crate::pipeline::Staging)) are returned as well.
+pub fn reap_dead_workers() -> Vec<Download> {
+    let mut relaunch = Vec::new();
+    for dl in store::load_downloads_with_status(&["downloading", "pending"]) {
+        if let Some(pid) = dl.pid
+            && !dl.process_alive()
+        {
+            let status = dl.status.clone();
+            let staged = status == DownloadStatus::Pending;
+            let leftover = Leftover::of(&dl);
+            let (mut reaped, mut restart) = (false, false);
+            let updated = store::update_download(&dl.id, |dl| {
+                // Re-check under the lock: the worker may have finished meanwhile
+                if dl.status != status || dl.pid != Some(pid) {
+                    return;
+                }
+                reaped = true;
+                if staged {
+                    restart = true;
+                } else if let Leftover::Complete(len) = leftover {
+                    dl.status = DownloadStatus::Completed;
+                    dl.downloaded_bytes = len;
+                } else if dl.restarts < MAX_RESTARTS {
+                    dl.downloaded_bytes = match leftover {
+                        Leftover::Partial(len) => len,
+                        _ => 0,
+                    };
+                    dl.status = DownloadStatus::Pending;
+                    dl.restarts += 1;
+                    restart = true;
+                } else {
+                    dl.status =
+                        DownloadStatus::Failed(format!("Process died ({} restarts)", dl.restarts));
+                }
+                dl.speed = 0.0;
+                dl.pid = None;
+                dl.pid_started = None;
+            });
+            match updated {
+                Ok(Some(dl)) if staged && restart => {
+                    logging::log(
+                        Level::Warning,
+                        &format!("{}: process {} died before starting it", dl.id, pid),
+                        &[("LJ_DOWNLOAD_ID", &dl.id), ("LJ_EVENT", "restarted")],
+                    );
+                    relaunch.push(dl);
+                }
+                Ok(Some(dl)) if restart => {
+                    logging::log(
+                        Level::Warning,
+                        &format!(
+                            "{}: worker {} died, restarting ({}/{})",
+                            dl.id, pid, dl.restarts, MAX_RESTARTS
+                        ),
+                        &[("LJ_DOWNLOAD_ID", &dl.id), ("LJ_EVENT", "restarted")],
+                    );
+                    relaunch.push(dl);
+                }
+                Ok(Some(dl)) if reaped => {
+                    if let Some(event) = finished_event(&dl) {
+                        events::emit(event);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    relaunch
+}
+
+/// What a dead worker left at a download's target path.
+enum Leftover {
+    /// The whole file, this many bytes.
+    Complete(u64),
+    /// Part of it, to resume from.
+    Partial(u64),
+    /// Nothing worth resuming.
+    Nothing,
+}
+
+impl Leftover {
+    fn of(dl: &Download) -> Self {
+        let len = fs::metadata(dl.target_path()).map_or(0, |meta| meta.len());
+        match len {
+            0 => Leftover::Nothing,
+            len if dl.total_bytes > 0 && len >= dl.total_bytes => Leftover::Complete(len),
+            len => Leftover::Partial(len),
+        }
+    }
+}
+
+/// Reaps dead workers and relaunches the ones that may be restarted in new
+/// worker processes, one per batch, resuming where they stopped.
+pub fn supervise() {
+    progress::sweep();
+    let mut batches: Vec<Vec<Download>> = Vec::new();
+    for dl in reap_dead_workers() {
+        match batches
+            .iter_mut()
+            .find(|batch| dl.batch.is_some() && batch[0].batch == dl.batch)
+        {
+            Some(batch) => batch.push(dl),
+            None => batches.push(vec![dl]),
+        }
+    }
+    for batch in batches {
+        let Err(e) = spawn_worker(&batch) else {
+            continue;
+        };
+        for dl in &batch {
+            let failed = store::update_download(&dl.id, |dl| {
+                dl.status = DownloadStatus::Failed(format!("Failed to restart: {}", e));
+            });
+            if let Ok(Some(dl)) = failed
+                && let Some(event) = finished_event(&dl)
+            {
+                events::emit(event);
+            }
+        }
+    }
+}
+
+/// Cancels a download that is running or waiting its turn in a worker.
+/// The worker is signalled unless it still has other downloads to run, which
+/// it keeps going with once it notices the cancellation, or its pid has
+/// since been reused by another process. Returns whether the download was
+/// running.
+pub fn cancel_download(id: &str) -> bool {
+    let mut worker_pid = None;
+    let mut was_running = false;
+    let _ = store::update_download(id, |dl| {
+        match dl.status {
+            DownloadStatus::Downloading => worker_pid = dl.take_process(),
+            DownloadStatus::Pending if dl.process_alive() => {
+                dl.pid = None;
+                dl.pid_started = None;
+            }
+            _ => return,
+        }
+        was_running = true;
+        dl.status = DownloadStatus::Cancelled;
+        dl.speed = 0.0;
+    });
+
+    if let Some(pid) = worker_pid {
+        let shared = store::load_downloads_with_status(&["downloading", "pending"])
+            .iter()
+            .any(|dl| dl.pid == Some(pid));
+        if !shared {
+            let _ = platform::terminate(pid);
+        }
+    }
+    was_running
+}
@@ -0,0 +1,85 @@
+//! The add/wait/select/unrestrict sequence against canned responses.
+
+use lj_core::config::SelectConfig;
+use lj_core::error::Error;
+use lj_core::mock::MockProvider;
+use lj_core::pipeline;
+
+const MAGNET: &str = "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=Show";
+
+fn provider() -> MockProvider {
+    MockProvider::new()
+        .with_file("Show/S01E01.mkv", 700_000_000)
+        .with_file("Show/S01E02.mkv", 700_000_000)
+        .with_file("Show/S01E02.nfo", 2_000)
+        .with_file("Show/sample.mkv", 20_000_000)
+        .with_download_base("http://127.0.0.1:9/files")
+}
+
+#[tokio::test]
+async fn fetches_the_selected_files() {
+    let provider = provider();
+    let client = reqwest::Client::new();
+
+    let fetched = pipeline::fetch_magnet(
+        &provider,
+        &client,
+        MAGNET,
+        &SelectConfig::default(),
+        None,
+        &mut |_| {},
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(fetched.name.as_deref(), Some("Show"));
+    let names: Vec<_> = fetched.links.iter().map(|l| l.filename.as_str()).collect();
+    assert_eq!(names, ["S01E01.mkv", "S01E02.mkv"]);
+    assert_eq!(fetched.links[0].url, "http://127.0.0.1:9/files/S01E01.mkv");
+    assert_eq!(fetched.links[0].size, 700_000_000);
+    assert_eq!(provider.added(), [MAGNET]);
+    assert_eq!(provider.selected(), [1, 2]);
+    assert!(provider.deleted());
+}
+
+#[tokio::test]
+async fn stops_when_the_key_is_rejected() {
+    let provider = provider().with_key_rejected_after(1);
+    let client = reqwest::Client::new();
+
+    let result = pipeline::fetch_magnet(
+        &provider,
+        &client,
+        MAGNET,
+        &SelectConfig::default(),
+        None,
+        &mut |_| {},
+    )
+    .await;
+
+    assert!(
+        result.as_ref().is_err_and(Error::is_auth),
+        "{:?}",
+        result.map(|f| f.links)
+    );
+    // The torrent isn't left behind on the account
+    assert!(provider.deleted());
+}
+
+#[tokio::test]
+async fn reports_a_failed_torrent() {
+    let provider = provider().with_failure("dead torrent");
+    let client = reqwest::Client::new();
+
+    let result = pipeline::fetch_magnet(
+        &provider,
+        &client,
+        MAGNET,
+        &SelectConfig::default(),
+        None,
+        &mut |_| {},
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::Torrent(message)) if message == "dead torrent"));
+}
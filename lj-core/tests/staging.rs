@@ -0,0 +1,36 @@
+//! Links staged in the store while they are resolved. A binary of its own,
+//! as it points the state directory somewhere else before anything runs.
+#![cfg(target_os = "linux")]
+
+use lj_core::mock::MockProvider;
+use lj_core::pipeline::{self, Staging};
+use lj_core::store;
+
+const MAGNET: &str = "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=Show";
+
+#[tokio::test]
+async fn discards_staged_links_when_the_key_is_rejected() {
+    let dir = std::env::temp_dir().join(format!("lj-staging-test-{}", std::process::id()));
+    // SAFETY: the only test in this binary, before anything reads them
+    unsafe {
+        std::env::set_var("XDG_STATE_HOME", dir.join("state"));
+        std::env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        std::env::set_var("XDG_RUNTIME_DIR", dir.join("run"));
+    }
+    let provider = MockProvider::new()
+        .with_file("Show/S01E01.mkv", 700_000_000)
+        .with_file("Show/S01E02.mkv", 700_000_000)
+        .with_file("Show/S01E03.mkv", 700_000_000)
+        .with_download_base("http://127.0.0.1:9/files")
+        .with_key_rejected_after(2);
+    let client = reqwest::Client::new();
+    let links = ["mock:1", "mock:2", "mock:3"].map(String::from);
+    let target = dir.join("downloads").to_string_lossy().to_string();
+    let staging = Staging::new(&target, Some("Show"), MAGNET);
+
+    let result = pipeline::resolve_links(&provider, &client, &links, Some(&staging)).await;
+
+    assert!(result.is_err_and(|e| e.is_auth()));
+    assert!(store::load_all_downloads().is_empty());
+    let _ = std::fs::remove_dir_all(dir);
+}
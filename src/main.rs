@@ -1,19 +1,51 @@
 use clap::{Parser, Subcommand};
-use console::{style, Term};
-use dialoguer::{theme::ColorfulTheme, Input, MultiSelect};
-use futures_util::StreamExt;
-use nix::sys::signal::{self, Signal};
-use nix::unistd::Pid;
+use console::{Term, style};
+use dialoguer::{Confirm, Input, MultiSelect, Password, Select, theme::ColorfulTheme};
+use lj_core::blackhole::Blackhole;
+use lj_core::config::{
+    self, Config, IndexerConfig, KeyStorage, LibraryConfig, SelectConfig, load_api_key,
+    save_api_key,
+};
+use lj_core::control::{self, cancel_download, list_downloads, start_download};
+use lj_core::crypt::{self, Encryption};
+#[cfg(unix)]
+use lj_core::daemon::{self, DaemonOptions};
+use lj_core::error::{Error, Result, exit};
+use lj_core::export::{self, Filter, Record};
+use lj_core::format::{
+    format_bytes, format_duration, format_size, format_speed, format_timestamp, parse_age,
+    parse_bytes, parse_timestamp,
+};
+use lj_core::handler;
+use lj_core::hooks;
+use lj_core::ipc::{self, Request};
+use lj_core::library::Library;
+use lj_core::logging::{self, Level};
+use lj_core::magnet::{self, Magnet};
+use lj_core::media::{self, Episodes};
+use lj_core::pipeline::{self, Fetched, Grouping, ResolvedLink, Staging};
+use lj_core::player;
+use lj_core::processing;
+use lj_core::provider::{self, DebridProvider, ProviderInfo, TorrentFile, TorrentStatus};
+use lj_core::rss::{Feed, Poller};
+use lj_core::search::{self, SearchResult};
+use lj_core::serve::{self, Companion, ServeOptions};
+use lj_core::state::{self, ImportOptions, StateArchive};
+use lj_core::store::{self, Download, DownloadStatus, Extraction, Processing, QueueStatus};
+#[cfg(unix)]
+use lj_core::systemd;
+use lj_core::telegram::Bot;
+use lj_core::torrent::{self, Source};
+use lj_core::update;
+use lj_core::worker;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
-use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-
-const RD_BASE_URL: &str = "https://api.real-debrid.com/rest/1.0";
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Parser)]
 #[command(name = "lj")]
@@ -22,666 +54,1799 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Magnet link to download
+    /// Magnet link, bare info hash or .torrent URL to download
     #[arg(value_name = "MAGNET")]
     magnet: Option<String>,
+
+    /// Debrid service to use (overrides config)
+    #[arg(long, global = true, value_name = "NAME")]
+    provider: Option<String>,
+
+    /// Profile with its own token, provider, download dir and state
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// API root for the provider, e.g. a caching proxy or a local mock
+    /// (overrides LJ_API_URL and config)
+    #[arg(long, global = true, value_name = "URL")]
+    api_url: Option<String>,
+
+    /// Sort movies and episodes into Movies/ and TV/ folders (see [organize])
+    #[arg(long)]
+    organize: bool,
+
+    /// Don't ask anything: download every selectable file, and fail instead
+    /// of prompting for a missing API key
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Download the torrent even if it is already downloading or was
+    /// downloaded before
+    #[arg(long)]
+    force: bool,
+
+    /// Don't wait for the service to download an uncached torrent: once the
+    /// files are picked, a process of its own waits and starts the
+    /// downloads (see `lj dl`)
+    #[arg(long)]
+    background: bool,
+
+    /// Wait for the downloads to finish, exiting with 8 if they all failed
+    /// and 9 if some did
+    #[arg(long, conflicts_with = "background")]
+    wait: bool,
+
+    /// Pick these episodes out of a season pack without asking, e.g.
+    /// S01E03-S01E06 or S01E01,S02
+    #[arg(long, value_name = "LIST", value_parser = Episodes::from_str)]
+    episodes: Option<Episodes>,
+
+    /// Seconds to wait for a torrent's file list, 0 for no limit
+    /// (overrides config; default 60)
+    #[arg(long, global = true, value_name = "SECS")]
+    files_timeout: Option<u64>,
+
+    /// Seconds to wait for the service to have the files ready, 0 for no
+    /// limit (overrides config; default 600)
+    #[arg(long, global = true, value_name = "SECS")]
+    processing_timeout: Option<u64>,
+
+    /// Seconds a torrent may sit at 0% with no seeders before it counts as
+    /// dead, 0 to never give up (overrides config; default 300)
+    #[arg(long, global = true, value_name = "SECS")]
+    stalled_timeout: Option<u64>,
+
+    /// Refuse to use an API key file that other users can read
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Where to save downloads (overrides config)
+    #[arg(long, value_name = "DIR")]
+    download_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Show downloads in progress
-    Dl,
-    /// Set or update API key
-    SetKey,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Download {
-    id: String,
-    filename: String,
-    url: String,
-    target_dir: String,
-    total_bytes: u64,
-    downloaded_bytes: u64,
-    speed: f64,
-    status: DownloadStatus,
-    started_at: u64,
-    pid: Option<u32>,
+    Dl {
+        /// Keep the list on screen, refreshed every second
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Change where a pending, failed or cancelled download is saved,
+    /// moving what it has downloaded so far
+    SetDir {
+        /// Download number, as listed by `lj dl`
+        #[arg(value_name = "N")]
+        n: usize,
+        /// Folder to save it in
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+    },
+    /// Set or update API key (for --provider and --profile)
+    SetKey {
+        /// Keep the key in a plaintext file instead of the system keyring
+        #[arg(long)]
+        file: bool,
+        /// Save the key without checking it with the provider first
+        #[arg(long)]
+        no_check: bool,
+        /// Keep the key in an encrypted file, for config directories synced
+        /// to cloud storage: with a passphrase, or a key kept in the system
+        /// keyring
+        #[arg(
+            long,
+            value_name = "WITH",
+            value_parser = ["passphrase", "keyring"],
+            conflicts_with = "file"
+        )]
+        encrypt: Option<String>,
+    },
+    /// Run a daemon that owns all transfers
+    Daemon {
+        /// Global download limit across all transfers (e.g. 500K, 10M)
+        #[arg(long, value_name = "RATE")]
+        limit: Option<String>,
+        /// Stop the running daemon
+        #[arg(long)]
+        stop: bool,
+        /// Write a systemd user unit that runs the daemon, then exit
+        #[arg(long)]
+        install_systemd: bool,
+    },
+    /// Update lj to the latest release
+    SelfUpdate {
+        /// Only report whether an update is available
+        #[arg(long)]
+        check: bool,
+    },
+    /// Serve an HTTP API for remote control
+    Serve {
+        /// Address to listen on (overrides config)
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<String>,
+    },
+    /// Search Torznab indexers and download a result
+    Search {
+        /// What to search for
+        #[arg(required = true, value_name = "QUERY")]
+        query: Vec<String>,
+    },
+    /// Export download history to CSV or JSON
+    Export {
+        /// Output format; from the output file's extension, or CSV
+        #[arg(long, value_parser = ["csv", "json"])]
+        format: Option<String>,
+        /// File to write instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Only downloads started since a date (2024-06-01) or within an
+        /// age (12h, 7d, 2w)
+        #[arg(long, value_name = "WHEN")]
+        since: Option<String>,
+        /// Only downloads with these statuses (comma-separated)
+        #[arg(
+            long,
+            value_name = "STATUS",
+            value_delimiter = ',',
+            value_parser = ["pending", "downloading", "completed", "failed", "cancelled"]
+        )]
+        status: Vec<String>,
+    },
+    /// Save config, download records and optionally API keys to one file
+    ExportState {
+        /// Archive to write
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+        /// Include the API key files
+        #[arg(long)]
+        with_keys: bool,
+    },
+    /// Restore an archive written by export-state on another machine
+    ImportState {
+        /// Archive to read
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+        /// Move download directories under OLD to NEW (repeatable)
+        #[arg(long = "map", value_name = "OLD=NEW")]
+        maps: Vec<String>,
+        /// Replace a config.toml or key file that differs from the archive's
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Stream a magnet's video into mpv or VLC
+    Play {
+        /// Magnet link, bare info hash or .torrent URL to play
+        #[arg(value_name = "MAGNET")]
+        magnet: String,
+        /// Also download the file while it plays
+        #[arg(long)]
+        download: bool,
+    },
+    /// Make lj the handler for magnet links clicked in the browser
+    RegisterHandler,
+    /// Remove the magnet link handler added by register-handler
+    UnregisterHandler,
+    /// Download new items from the feeds in [rss]
+    Rss {
+        #[command(subcommand)]
+        command: RssCommand,
+    },
+    /// Stash magnets now and download them later, e.g. overnight from cron
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-enum DownloadStatus {
-    Pending,
-    Downloading,
-    Completed,
-    Failed(String),
-    Cancelled,
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// Add magnets, bare info hashes or .torrent URLs without contacting
+    /// the provider
+    Add {
+        #[arg(value_name = "MAGNET", required = true)]
+        magnets: Vec<String>,
+    },
+    /// Show the queue and how each item went
+    List,
+    /// Download the pending items; one failing doesn't stop the others
+    Run {
+        /// Also retry failed items, and items a run was interrupted on
+        #[arg(long)]
+        retry: bool,
+    },
+    /// Remove items by number
+    Remove {
+        #[arg(value_name = "ID", required = true)]
+        ids: Vec<i64>,
+    },
+    /// Remove the items that are done
+    Clear,
 }
 
-#[derive(Debug, Deserialize)]
-struct AddMagnetResponse {
-    id: String,
-    #[allow(dead_code)]
-    uri: String,
+#[derive(Subcommand)]
+enum RssCommand {
+    /// Fetch the feeds and queue new matching items (run from cron, or set
+    /// [rss] interval for the daemon)
+    Poll {
+        /// Mark the current items seen without downloading them
+        #[arg(long)]
+        catch_up: bool,
+    },
+    /// Show the feeds' items and which ones would be downloaded
+    List,
 }
 
-#[derive(Debug, Deserialize)]
-struct TorrentInfo {
-    #[allow(dead_code)]
-    id: String,
-    status: String,
-    files: Option<Vec<TorrentFile>>,
-    links: Option<Vec<String>>,
-    progress: Option<f64>,
-    speed: Option<u64>,
-    seeders: Option<u32>,
+/// What to do with a magnet once the provider is set up.
+enum Job {
+    Download(String),
+    Search(String),
+    Play { magnet: String, download: bool },
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct TorrentFile {
-    id: u32,
-    path: String,
-    bytes: u64,
-    #[allow(dead_code)]
-    selected: u8,
-}
+/// Most search results offered for picking.
+const SEARCH_RESULTS: usize = 50;
 
-#[derive(Debug, Deserialize)]
-struct UnrestrictResponse {
-    filename: String,
-    download: String,
-    #[allow(dead_code)]
-    filesize: Option<u64>,
+/// Resolves the provider from `--provider`, then config, then the default.
+fn select_provider(flag: Option<&str>, config: &Config) -> Result<&'static ProviderInfo> {
+    let name = flag
+        .or(config.provider.as_deref())
+        .unwrap_or(provider::DEFAULT_PROVIDER);
+    provider::info(name)
 }
 
-fn get_config_dir() -> PathBuf {
-    dirs::config_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("lj")
-}
+async fn prompt_api_key(info: &ProviderInfo, config: &Config, client: &Client) -> Option<String> {
+    println!("Get your API key from: {}\n", info.token_url);
 
-fn get_downloads_dir() -> PathBuf {
-    get_config_dir().join("downloads")
-}
+    loop {
+        let key: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Enter your {} API key", info.display_name))
+            .allow_empty(true)
+            .interact_text()
+            .ok()?;
+
+        if key.is_empty() {
+            return None;
+        }
+        if let Err(e) = check_api_key(info, config, client, &key).await {
+            eprintln!(
+                "{} {}",
+                style("Error:").red(),
+                logging::redact(&e.to_string())
+            );
+            eprintln!(
+                "  {}",
+                style("Try again, or leave it empty to give up").dim()
+            );
+            continue;
+        }
 
-fn get_download_file(id: &str) -> PathBuf {
-    get_downloads_dir().join(format!("{}.json", id))
+        report_saved_key(save_api_key(info, &key, false));
+        return Some(key);
+    }
 }
 
-fn get_api_key_file() -> PathBuf {
-    get_config_dir().join("api_key")
+/// Asks the provider whose account `key` is, failing if it rejects it.
+async fn check_api_key(
+    info: &ProviderInfo,
+    config: &Config,
+    client: &Client,
+    key: &str,
+) -> Result<()> {
+    let provider = provider::from_config(info.name, key, &config.provider_config(info), client)?;
+    if let Some(account) = provider.account().await? {
+        let plan = match account.premium_until {
+            Some(until) => format!("premium until {}", &format_timestamp(until)[..10]),
+            None => "no premium".to_string(),
+        };
+        println!(
+            "{} {} ({})",
+            style("Key belongs to").green(),
+            account.username,
+            plan
+        );
+    }
+    Ok(())
 }
 
-fn load_api_key() -> Option<String> {
-    if let Ok(key) = env::var("RD_API_TOKEN") {
-        if !key.is_empty() {
-            return Some(key);
+/// Says where the key was saved, or why it wasn't. Returns whether it was.
+fn report_saved_key(result: std::result::Result<KeyStorage, impl std::fmt::Display>) -> bool {
+    match result {
+        Ok(KeyStorage::Keyring) => {
+            println!("{}", style("API key saved to the system keyring!").green())
         }
-    }
-
-    let key_file = get_api_key_file();
-    if key_file.exists() {
-        if let Ok(key) = fs::read_to_string(&key_file) {
-            let key = key.trim().to_string();
-            if !key.is_empty() {
-                return Some(key);
-            }
+        Ok(KeyStorage::File(path)) => {
+            println!("{} {}", style("API key saved to").green(), path.display())
+        }
+        Ok(KeyStorage::Encrypted(path)) => {
+            println!(
+                "{} {}",
+                style("API key encrypted and saved to").green(),
+                path.display()
+            )
+        }
+        Err(e) => {
+            eprintln!("{} Failed to save API key: {}", style("Error:").red(), e);
+            return false;
         }
     }
-    None
-}
-
-fn save_api_key(key: &str) -> io::Result<()> {
-    let config_dir = get_config_dir();
-    fs::create_dir_all(&config_dir)?;
-    fs::write(get_api_key_file(), key)?;
-    Ok(())
+    true
 }
 
-fn save_download(download: &Download) -> io::Result<()> {
-    let downloads_dir = get_downloads_dir();
-    fs::create_dir_all(&downloads_dir)?;
-    let data = serde_json::to_string_pretty(download)?;
-    fs::write(get_download_file(&download.id), data)?;
-    Ok(())
+fn print_progress(status: &TorrentStatus) {
+    let speed = status.speed as f64 / 1_000_000.0;
+    print!(
+        "\r{} {:.1}% @ {:.2} MB/s ({} seeders)    ",
+        style("Processing:").cyan(),
+        status.progress,
+        speed,
+        status.seeders
+    );
+    io::stdout().flush().ok();
 }
 
-fn load_download(id: &str) -> Option<Download> {
-    let path = get_download_file(id);
-    if path.exists() {
-        if let Ok(data) = fs::read_to_string(&path) {
-            return serde_json::from_str(&data).ok();
-        }
-    }
-    None
-}
+/// Adds `source` to `provider` and asks which of its files to download.
+/// Returns the torrent's id and the files picked, not yet selected.
+async fn choose_files(
+    provider: &dyn DebridProvider,
+    source: &Source,
+    select: &SelectConfig,
+    library: &LibraryConfig,
+    interactive: bool,
+) -> Result<(String, Vec<u32>)> {
+    let torrent_id = add_source(provider, source).await?;
 
-fn load_all_downloads() -> Vec<Download> {
-    let downloads_dir = get_downloads_dir();
-    let mut downloads = Vec::new();
+    println!("{} Waiting for file list...", style("[2/4]").dim());
+    let files = provider.wait_for_files(&torrent_id).await?;
 
-    if let Ok(entries) = fs::read_dir(&downloads_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().map(|e| e == "json").unwrap_or(false) {
-                if let Ok(data) = fs::read_to_string(&path) {
-                    if let Ok(dl) = serde_json::from_str::<Download>(&data) {
-                        downloads.push(dl);
-                    }
-                }
+    let mut valid_files = pipeline::selectable_files(&files, select);
+    if select.episodes.is_some() && valid_files.is_empty() {
+        let _ = provider.delete(&torrent_id).await;
+        println!(
+            "  {}",
+            style("None of the episodes are in this torrent").yellow()
+        );
+        return Err(Error::NothingSelected);
+    }
+    let mut defaults = vec![true; valid_files.len()];
+    let present = in_library(library, &valid_files);
+    if !library.warn_only && present.contains(&true) {
+        if interactive && valid_files.len() > 1 {
+            // Left for the user to pick, but unticked
+            defaults = present.iter().map(|present| !present).collect();
+        } else {
+            let mut present = present.into_iter();
+            valid_files.retain(|_| !present.next().unwrap_or(false));
+            defaults = vec![true; valid_files.len()];
+            if valid_files.is_empty() {
+                let _ = provider.delete(&torrent_id).await;
+                println!(
+                    "  {}",
+                    style("Every file is already in the library").green()
+                );
+                return Err(Error::NothingSelected);
             }
         }
     }
 
-    downloads.sort_by_key(|dl| dl.started_at);
-    downloads
-}
+    let selected_ids: Vec<u32> = if valid_files.len() == 1 {
+        println!(
+            "  {} {}",
+            style("Single file:").green(),
+            valid_files[0].name()
+        );
+        vec![valid_files[0].id]
+    } else if valid_files.is_empty() {
+        if files.is_empty() {
+            return Err(Error::NoFiles);
+        }
+        println!("  {}", style("Auto-selecting all files").yellow());
+        files.iter().map(|f| f.id).collect()
+    } else if select.episodes.is_some() {
+        println!(
+            "  {}",
+            style(format!("Selecting {} episode file(s)", valid_files.len())).green()
+        );
+        valid_files.iter().map(|f| f.id).collect()
+    } else if !interactive {
+        println!(
+            "  {}",
+            style(format!("Selecting all {} files", valid_files.len())).green()
+        );
+        valid_files.iter().map(|f| f.id).collect()
+    } else {
+        let selections = pick_files(&valid_files, &defaults)?;
+        if selections.is_empty() {
+            let _ = provider.delete(&torrent_id).await;
+            return Err(Error::NothingSelected);
+        }
 
-fn delete_download(id: &str) {
-    let path = get_download_file(id);
-    let _ = fs::remove_file(path);
+        selections.iter().map(|&i| valid_files[i].id).collect()
+    };
+    Ok((torrent_id, selected_ids))
 }
 
-fn format_bytes(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-
-    if bytes >= GB {
-        format!("{:.2} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.2} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.2} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} B", bytes)
+/// Files beyond which [`pick_files`] first offers to pick whole folders or
+/// extensions.
+const MANY_FILES: usize = 100;
+
+/// Files shown at once in the file picker; more are paged.
+const PAGE: usize = 20;
+
+/// Asks which of `files` to download, ticking those `defaults` says.
+/// Returns their indices.
+///
+/// A long list is first offered whole folders or extensions to pick, or
+/// the files one by one. Picking a group takes its files that are ticked
+/// by default, so those already in the library stay out.
+fn pick_files(files: &[TorrentFile], defaults: &[bool]) -> Result<Vec<usize>> {
+    let theme = ColorfulTheme::default();
+    if files.len() > MANY_FILES {
+        let ways = ["By folder", "By extension", "One by one", "All of them"];
+        let way = Select::with_theme(&theme)
+            .with_prompt(format!("{} files. Pick them", files.len()))
+            .items(&ways)
+            .default(0)
+            .interact()
+            .map_err(|e| Error::Other(format!("Selection cancelled: {}", e)))?;
+        let grouping = match way {
+            0 => Grouping::Folder,
+            1 => Grouping::Extension,
+            2 => return pick_each(&theme, files, defaults),
+            _ => return Ok((0..files.len()).filter(|&i| defaults[i]).collect()),
+        };
+        let groups = pipeline::group_files(files, grouping);
+        let items: Vec<String> = groups
+            .iter()
+            .map(|g| {
+                let label = match g.label.as_str() {
+                    "" => "(no extension)",
+                    label => label,
+                };
+                format!(
+                    "{} ({} files, {})",
+                    label,
+                    g.files.len(),
+                    format_bytes(g.bytes)
+                )
+            })
+            .collect();
+        let ticked: Vec<bool> = groups
+            .iter()
+            .map(|g| g.files.iter().any(|&i| defaults[i]))
+            .collect();
+        println!("\n{}", style("Select files to download:").cyan());
+        let selections = MultiSelect::with_theme(&theme)
+            .items(&items)
+            .defaults(&ticked)
+            .max_length(PAGE)
+            .interact()
+            .map_err(|e| Error::Other(format!("Selection cancelled: {}", e)))?;
+        let mut picked: Vec<usize> = selections
+            .iter()
+            .flat_map(|&g| groups[g].files.iter().copied())
+            .filter(|&i| defaults[i])
+            .collect();
+        picked.sort_unstable();
+        return Ok(picked);
     }
+    pick_each(&theme, files, defaults)
 }
 
-fn format_speed(bytes_per_sec: f64) -> String {
-    const KB: f64 = 1024.0;
-    const MB: f64 = KB * 1024.0;
-
-    if bytes_per_sec >= MB {
-        format!("{:.2} MB/s", bytes_per_sec / MB)
-    } else if bytes_per_sec >= KB {
-        format!("{:.2} KB/s", bytes_per_sec / KB)
-    } else {
-        format!("{:.0} B/s", bytes_per_sec)
-    }
+/// Asks about `files` one by one.
+fn pick_each(
+    theme: &ColorfulTheme,
+    files: &[TorrentFile],
+    defaults: &[bool],
+) -> Result<Vec<usize>> {
+    println!("\n{}", style("Select files to download:").cyan());
+    let items: Vec<String> = files
+        .iter()
+        .map(|f| format!("{} ({})", f.name(), format_bytes(f.bytes)))
+        .collect();
+    MultiSelect::with_theme(theme)
+        .items(&items)
+        .defaults(defaults)
+        .max_length(PAGE)
+        .interact()
+        .map_err(|e| Error::Other(format!("Selection cancelled: {}", e)))
 }
 
-async fn prompt_api_key() -> Option<String> {
-    println!("{}", style("Real-Debrid API key not found.").yellow());
-    println!("Get your API key from: https://real-debrid.com/apitoken\n");
-
-    let key: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Enter your Real-Debrid API key")
-        .interact_text()
-        .ok()?;
+/// Waits for the files of `torrent_id` picked by [`choose_files`] and gets
+/// their links, staged in the download directory.
+async fn process_magnet(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    source: &Source,
+    config: &Config,
+    torrent_id: String,
+    selected_ids: Vec<u32>,
+    interactive: bool,
+) -> Result<(Fetched, usize)> {
+    println!("{} Selecting files...", style("[3/4]").dim());
+    provider.select_files(&torrent_id, &selected_ids).await?;
+
+    let links = wait_for_links(provider, &torrent_id, interactive).await?;
+
+    let name = pipeline::torrent_name(provider, &torrent_id, source.magnet()).await;
+    let staging = Staging::new(&target_dir(config), name.as_deref(), source.magnet());
+    let resolved = pipeline::resolve_links(provider, client, &links, Some(&staging)).await;
+    let _ = provider.delete(&torrent_id).await;
+    let (download_links, errors) = resolved?;
+    for e in &errors {
+        eprintln!(
+            "{} {}",
+            style("Warning:").yellow(),
+            logging::redact(&e.to_string())
+        );
+    }
 
-    if key.is_empty() {
-        return None;
+    if download_links.is_empty() {
+        return Err(Error::NoLinks);
     }
 
-    if let Err(e) = save_api_key(&key) {
-        eprintln!("{} Failed to save API key: {}", style("Error:").red(), e);
-    } else {
-        println!("{}", style("API key saved!").green());
+    let links = pipeline::filter_links(download_links, &config.select);
+    if links.is_empty() {
+        return Err(Error::NothingSelected);
     }
 
-    Some(key)
+    Ok((Fetched { name, links }, errors.len()))
 }
 
-async fn add_magnet(client: &Client, api_key: &str, magnet: &str) -> Result<String, String> {
-    let resp = client
-        .post(format!("{}/torrents/addMagnet", RD_BASE_URL))
-        .bearer_auth(api_key)
-        .form(&[("magnet", magnet)])
-        .send()
-        .await
-        .map_err(|e| format!("Failed to add magnet: {}", e))?;
-
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Failed to add magnet: {} - {}", status, text));
+/// Waits for `provider` to have the selected files of `torrent_id` ready.
+/// If the torrent looks dead, asks whether to give up on it, deleting it
+/// from the service, or keep waiting; without asking, gives up.
+async fn wait_for_links(
+    provider: &dyn DebridProvider,
+    torrent_id: &str,
+    interactive: bool,
+) -> Result<Vec<String>> {
+    println!(
+        "{} Waiting for {} to process...",
+        style("[4/4]").dim(),
+        provider.display_name()
+    );
+    loop {
+        let e = match provider
+            .wait_for_download(torrent_id, &mut print_progress)
+            .await
+        {
+            Err(e @ Error::Stalled(_)) => e,
+            result => {
+                println!();
+                return result;
+            }
+        };
+        println!();
+        println!("{}", style(&e).yellow());
+        let keep_waiting = interactive
+            && Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!(
+                    "Keep waiting? (no deletes it from {})",
+                    provider.display_name()
+                ))
+                .default(false)
+                .interact()
+                .unwrap_or(false);
+        if !keep_waiting {
+            let _ = provider.delete(torrent_id).await;
+            return Err(e);
+        }
     }
+}
 
-    let data: AddMagnetResponse = resp
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+/// Which of `files` are already in the `[library]` directories, listing
+/// those that are. All false if there are no directories to look in.
+fn in_library(config: &LibraryConfig, files: &[TorrentFile]) -> Vec<bool> {
+    if config.dirs.is_empty() || files.is_empty() {
+        return vec![false; files.len()];
+    }
+    let library = Library::scan(&config.dirs);
+    files
+        .iter()
+        .map(|file| {
+            let Some(path) = library.find(file) else {
+                return false;
+            };
+            println!(
+                "  {} {} ({})",
+                style("Already in library:").yellow(),
+                file.name(),
+                path.display()
+            );
+            true
+        })
+        .collect()
+}
 
-    Ok(data.id)
+fn result_line(result: &SearchResult) -> String {
+    let mark = match result.cached {
+        Some(true) => style("cached").green().to_string(),
+        Some(false) => " ".repeat(6),
+        None => style("     ?").dim().to_string(),
+    };
+    let seeders = result
+        .seeders
+        .map_or("?".to_string(), |seeders| seeders.to_string());
+    format!(
+        "{} {} ({}, {} seeders) {}",
+        mark,
+        result.title,
+        format_bytes(result.size),
+        seeders,
+        style(format!("[{}]", result.indexer)).dim()
+    )
 }
 
-async fn get_torrent_info(
+/// Searches every indexer for `text` and lets the user pick a result.
+/// Returns its magnet, or `None` if nothing was found.
+async fn search_magnet(
+    provider: &dyn DebridProvider,
     client: &Client,
-    api_key: &str,
-    torrent_id: &str,
-) -> Result<TorrentInfo, String> {
-    let resp = client
-        .get(format!("{}/torrents/info/{}", RD_BASE_URL, torrent_id))
-        .bearer_auth(api_key)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to get torrent info: {}", e))?;
-
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Failed to get torrent info: {} - {}", status, text));
+    indexers: &BTreeMap<String, IndexerConfig>,
+    text: &str,
+) -> Result<Option<String>> {
+    println!(
+        "Searching {} indexer(s) for \"{}\"...",
+        indexers.len(),
+        text
+    );
+    let (mut results, failures) = search::search(client, indexers, text).await;
+    for (name, e) in failures {
+        eprintln!("{} {}: {}", style("Warning:").yellow(), name, e);
+    }
+    if results.is_empty() {
+        println!("{}", style("No results").yellow());
+        return Ok(None);
     }
+    results.truncate(SEARCH_RESULTS);
+    search::mark_cached(provider, &mut results).await;
+
+    let items: Vec<String> = results.iter().map(result_line).collect();
+    let picked = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Pick a result")
+        .items(&items)
+        .default(0)
+        .interact_opt()
+        .map_err(|e| Error::Other(format!("Selection cancelled: {}", e)))?
+        .ok_or(Error::Cancelled)?;
+
+    search::magnet(client, &results[picked]).await.map(Some)
+}
 
-    resp.json()
-        .await
-        .map_err(|e| format!("Failed to parse torrent info: {}", e))
+/// Asks which of `items` to take, offering the largest first.
+fn pick(prompt: &str, items: &[String], sizes: &[u64]) -> Result<usize> {
+    let largest = (0..sizes.len()).max_by_key(|&i| sizes[i]).unwrap_or(0);
+    Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(items)
+        .default(largest)
+        .interact_opt()
+        .map_err(|e| Error::Other(format!("Selection cancelled: {}", e)))?
+        .ok_or(Error::Cancelled)
 }
 
-async fn select_files(
+/// Gets a direct link to one playable file of `source`, asking which if
+/// there are several.
+async fn playable_link(
+    provider: &dyn DebridProvider,
     client: &Client,
-    api_key: &str,
-    torrent_id: &str,
-    file_ids: &[u32],
-) -> Result<(), String> {
-    let ids = file_ids
-        .iter()
-        .map(|id| id.to_string())
-        .collect::<Vec<_>>()
-        .join(",");
-
-    let resp = client
-        .post(format!("{}/torrents/selectFiles/{}", RD_BASE_URL, torrent_id))
-        .bearer_auth(api_key)
-        .form(&[("files", ids)])
-        .send()
-        .await
-        .map_err(|e| format!("Failed to select files: {}", e))?;
-
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Failed to select files: {} - {}", status, text));
+    source: &Source,
+    select: &SelectConfig,
+) -> Result<ResolvedLink> {
+    if let Some(hash) = torrent::info_hash(source.magnet())
+        && let Ok(Some(cached)) = provider.cached(std::slice::from_ref(&hash)).await
+        && !cached.contains(&hash)
+    {
+        println!(
+            "{}",
+            style(format!(
+                "Not cached on {}; playback starts once it has the file",
+                provider.display_name()
+            ))
+            .yellow()
+        );
     }
 
-    Ok(())
-}
+    let torrent_id = add_source(provider, source).await?;
 
-async fn unrestrict_link(
-    client: &Client,
-    api_key: &str,
-    link: &str,
-) -> Result<UnrestrictResponse, String> {
-    let resp = client
-        .post(format!("{}/unrestrict/link", RD_BASE_URL))
-        .bearer_auth(api_key)
-        .form(&[("link", link)])
-        .send()
-        .await
-        .map_err(|e| format!("Failed to unrestrict link: {}", e))?;
+    println!("{} Waiting for file list...", style("[2/4]").dim());
+    let files = provider.wait_for_files(&torrent_id).await?;
+    let playable: Vec<_> = pipeline::selectable_files(&files, select)
+        .into_iter()
+        .filter(|f| player::is_playable(f.name()))
+        .collect();
+    // Whole-torrent services list just the torrent itself until it's ready
+    let selected = match playable.len() {
+        0 if files.len() == 1 => vec![files[0].id],
+        0 => {
+            let _ = provider.delete(&torrent_id).await;
+            return Err(Error::NoFiles);
+        }
+        1 => vec![playable[0].id],
+        _ => {
+            let items: Vec<String> = playable
+                .iter()
+                .map(|f| format!("{} ({})", f.name(), format_bytes(f.bytes)))
+                .collect();
+            let sizes: Vec<u64> = playable.iter().map(|f| f.bytes).collect();
+            match pick("Play which file?", &items, &sizes) {
+                Ok(i) => vec![playable[i].id],
+                Err(e) => {
+                    let _ = provider.delete(&torrent_id).await;
+                    return Err(e);
+                }
+            }
+        }
+    };
 
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Failed to unrestrict link: {} - {}", status, text));
+    println!("{} Selecting file...", style("[3/4]").dim());
+    provider.select_files(&torrent_id, &selected).await?;
+
+    let links = wait_for_links(provider, &torrent_id, true).await?;
+
+    let resolved = pipeline::resolve_links(provider, client, &links, None).await;
+    let _ = provider.delete(&torrent_id).await;
+    let (resolved, errors) = resolved?;
+    for e in errors {
+        eprintln!(
+            "{} {}",
+            style("Warning:").yellow(),
+            logging::redact(&e.to_string())
+        );
     }
 
-    resp.json()
-        .await
-        .map_err(|e| format!("Failed to parse unrestrict response: {}", e))
+    let mut playable: Vec<ResolvedLink> = pipeline::filter_links(resolved, select)
+        .into_iter()
+        .filter(|link| player::is_playable(&link.filename))
+        .collect();
+    match playable.len() {
+        0 => Err(Error::NoLinks),
+        1 => Ok(playable.remove(0)),
+        _ => {
+            let items: Vec<String> = playable
+                .iter()
+                .map(|l| format!("{} ({})", l.filename, format_bytes(l.size)))
+                .collect();
+            let sizes: Vec<u64> = playable.iter().map(|l| l.size).collect();
+            let i = pick("Play which file?", &items, &sizes)?;
+            Ok(playable.remove(i))
+        }
+    }
 }
 
-async fn delete_torrent(client: &Client, api_key: &str, torrent_id: &str) -> Result<(), String> {
-    let resp = client
-        .delete(format!("{}/torrents/delete/{}", RD_BASE_URL, torrent_id))
-        .bearer_auth(api_key)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to delete torrent: {}", e))?;
-
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Failed to delete torrent: {} - {}", status, text));
+/// Streams a file of `source` into the player, optionally downloading it
+/// at the same time.
+async fn play_magnet(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    config: &Config,
+    source: &Source,
+    download: bool,
+) -> Result<()> {
+    let link = playable_link(provider, client, source, &config.select).await?;
+    if download {
+        println!();
+        let fetched = Fetched {
+            name: Magnet::parse(source.magnet())
+                .ok()
+                .and_then(|magnet| magnet.name),
+            links: vec![link.clone()],
+        };
+        // The stream doesn't need the download
+        if let Err(e) = start_downloads(config, source.magnet(), fetched) {
+            report_error(&e);
+        }
     }
+    println!();
+    println!("{} {}", style("Playing").green(), link.filename);
+    player::play(&config.player, &link.url)
+}
 
-    Ok(())
+/// Where downloads go: the download directory, or the current one.
+fn target_dir(config: &Config) -> String {
+    config
+        .download_dir
+        .clone()
+        .unwrap_or_else(|| env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+        .to_string_lossy()
+        .to_string()
 }
 
-async fn wait_for_files(
-    client: &Client,
-    api_key: &str,
-    torrent_id: &str,
-) -> Result<Vec<TorrentFile>, String> {
-    let start = Instant::now();
-    let timeout = Duration::from_secs(60);
+/// Progress of a torrent's files taken together.
+struct TorrentProgress {
+    name: String,
+    files: usize,
+    files_done: usize,
+    downloaded: u64,
+    total: u64,
+    speed: f64,
+}
 
-    loop {
-        if start.elapsed() > timeout {
-            return Err("Timeout waiting for file list".to_string());
+impl TorrentProgress {
+    fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
         }
+        self.downloaded as f64 / self.total as f64 * 100.0
+    }
 
-        let info = get_torrent_info(client, api_key, torrent_id).await?;
+    /// Seconds left at the files' combined smoothed speed.
+    fn eta(&self) -> Option<u64> {
+        (self.total > 0 && self.speed >= 1.0)
+            .then(|| (self.total.saturating_sub(self.downloaded) as f64 / self.speed) as u64)
+    }
+}
 
-        match info.status.as_str() {
-            "waiting_files_selection" => {
-                if let Some(files) = info.files {
-                    return Ok(files);
-                }
+/// Sums up the torrents with more than one file that are still being
+/// downloaded, in the order their files are listed.
+fn torrent_progress(downloads: &[Download]) -> Vec<TorrentProgress> {
+    let mut batches: Vec<(&str, TorrentProgress, bool)> = Vec::new();
+    for dl in downloads {
+        let Some(batch) = dl.batch.as_deref() else {
+            continue;
+        };
+        let index = match batches.iter().position(|(id, ..)| *id == batch) {
+            Some(index) => index,
+            None => {
+                let name = dl.torrent.clone().unwrap_or_else(|| batch.to_string());
+                let progress = TorrentProgress {
+                    name,
+                    files: 0,
+                    files_done: 0,
+                    downloaded: 0,
+                    total: 0,
+                    speed: 0.0,
+                };
+                batches.push((batch, progress, false));
+                batches.len() - 1
+            }
+        };
+        let (_, progress, active) = &mut batches[index];
+        progress.files += 1;
+        progress.total += dl.total_bytes;
+        progress.speed += dl.speed;
+        match dl.status {
+            DownloadStatus::Completed => {
+                progress.files_done += 1;
+                progress.downloaded += dl.total_bytes;
             }
-            "magnet_error" | "dead" | "error" => {
-                return Err(format!("Torrent error: {}", info.status));
+            DownloadStatus::Pending | DownloadStatus::Downloading => {
+                *active = true;
+                progress.downloaded += dl.downloaded_bytes;
             }
-            _ => {}
+            _ => progress.downloaded += dl.downloaded_bytes,
         }
-
-        tokio::time::sleep(Duration::from_secs(1)).await;
     }
+    batches
+        .into_iter()
+        .filter(|(_, progress, active)| *active && progress.files > 1)
+        .map(|(_, progress, _)| progress)
+        .collect()
 }
 
-async fn wait_for_download(
-    client: &Client,
-    api_key: &str,
-    torrent_id: &str,
-) -> Result<Vec<String>, String> {
-    let start = Instant::now();
-    let timeout = Duration::from_secs(600);
+/// Starts a background download for each fetched link, in the download
+/// directory (in a folder of its own for a torrent with several files) or
+/// wherever `[organize]` and the hooks put it. Fails before starting any if
+/// the download directory can't be written. Returns the ids of the
+/// downloads started and how many failed to start; those a hook vetoed are
+/// neither.
+fn start_downloads(
+    config: &Config,
+    magnet: &str,
+    fetched: Fetched,
+) -> Result<(Vec<String>, usize)> {
+    let target_dir = target_dir(config);
+    if let Err(e) = control::check_target_dir(&target_dir) {
+        fetched.discard();
+        return Err(e);
+    }
 
-    loop {
-        if start.elapsed() > timeout {
-            return Err("Timeout waiting for Real-Debrid to process".to_string());
+    println!(
+        "{} Starting {} download(s) in background...",
+        style("Success!").green(),
+        fetched.links.len()
+    );
+
+    let batch = store::new_batch_id();
+    let torrent = fetched.name;
+    let folder = pipeline::torrent_dir(&target_dir, torrent.as_deref(), fetched.links.len());
+    if folder != target_dir {
+        println!("  {} {}", style("Folder:").dim(), folder);
+    }
+    let mut downloads = Vec::new();
+    let mut names = Vec::new();
+    for link in fetched.links {
+        let mut download = link
+            .download(&target_dir)
+            .in_batch(&batch, torrent.as_deref())
+            .of_torrent(torrent::info_hash(magnet));
+
+        media::organize(&config.organize, &mut download);
+        // Files the library layout didn't place stay together
+        if download.target_dir == target_dir {
+            download.target_dir = folder.clone();
+        }
+        if let Err(e) = hooks::before_download(&config.hooks, &mut download) {
+            store::delete_download(&download.id);
+            println!("  {} {}", style("x").yellow(), e);
+            continue;
         }
+        downloads.push(download);
+        names.push(link.filename);
+    }
+    // One worker runs the whole torrent
+    if let Err(e) = control::start_downloads(&downloads) {
+        report_error(&e);
+        // Left to this process, they would be started later on their own
+        for download in &downloads {
+            if store::load_download(&download.id)
+                .is_some_and(|dl| dl.pid.is_none_or(|pid| pid == process::id()))
+            {
+                store::delete_download(&download.id);
+            }
+        }
+        return Ok((Vec::new(), downloads.len()));
+    }
+
+    for (download, name) in downloads.iter().zip(names) {
+        if download.target_dir == folder && download.filename == name {
+            println!("  {} {}", style("->").green(), name);
+        } else {
+            println!(
+                "  {} {}",
+                style("->").green(),
+                download.target_path().display()
+            );
+        }
+    }
+    Ok((downloads.into_iter().map(|dl| dl.id).collect(), 0))
+}
 
-        let info = get_torrent_info(client, api_key, torrent_id).await?;
+/// Fails with [`Error::Batch`] if `failed` of the `total` downloads did.
+fn batch_result(failed: usize, total: usize) -> Result<()> {
+    if failed == 0 {
+        return Ok(());
+    }
+    Err(Error::Batch {
+        failed,
+        total,
+        what: "download(s)",
+    })
+}
 
-        match info.status.as_str() {
-            "downloaded" => {
-                if let Some(links) = info.links {
-                    return Ok(links);
+/// Waits for the downloads `ids` to finish, printing each as it does. Fails
+/// if any of them failed, or any of the `failed` others that never started.
+async fn wait_for_downloads(ids: Vec<String>, mut failed: usize) -> Result<()> {
+    let total = ids.len() + failed;
+    let mut waiting = ids;
+    while !waiting.is_empty() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let downloads = list_downloads();
+        waiting.retain(|id| {
+            let Some(dl) = downloads.iter().find(|dl| &dl.id == id) else {
+                failed += 1;
+                return false;
+            };
+            match &dl.status {
+                DownloadStatus::Completed => {
+                    println!("  {} {}", style("done").green(), dl.filename);
+                }
+                DownloadStatus::Failed(e) => {
+                    failed += 1;
+                    println!("  {} {}: {}", style("x").red(), dl.filename, e);
+                }
+                DownloadStatus::Cancelled => {
+                    failed += 1;
+                    println!("  {} {} (cancelled)", style("x").yellow(), dl.filename);
+                }
+                DownloadStatus::Pending | DownloadStatus::Downloading => return true,
+            }
+            false
+        });
+    }
+    batch_result(failed, total)
+}
+
+#[cfg(unix)]
+async fn run_daemon(info: &ProviderInfo, rate_limit: Option<u64>, client: Client, config: Config) {
+    println!(
+        "{} listening on {}",
+        style("lj daemon").bold(),
+        ipc::socket_path().display()
+    );
+    let rss = match config.rss.interval {
+        Some(minutes) if !config.rss.feeds.is_empty() => {
+            match configured_provider(info, &config, &client)
+                .and_then(|provider| Poller::new(provider, client.clone(), &config))
+            {
+                Ok(poller) => {
+                    println!(
+                        "{} polling {} feed(s) every {} minute(s)",
+                        style("RSS").bold(),
+                        config.rss.feeds.len(),
+                        minutes
+                    );
+                    Some((
+                        std::sync::Arc::new(poller),
+                        std::time::Duration::from_secs(minutes.max(1) * 60),
+                    ))
+                }
+                Err(e) => {
+                    eprintln!("{} Not polling feeds: {}", style("Warning:").yellow(), e);
+                    None
                 }
-                return Err("No links available".to_string());
-            }
-            "magnet_error" | "dead" | "error" => {
-                return Err(format!("Torrent error: {}", info.status));
-            }
-            "downloading" | "queued" | "compressing" | "uploading" => {
-                let progress = info.progress.unwrap_or(0.0);
-                let speed = info.speed.unwrap_or(0) as f64 / 1_000_000.0;
-                let seeders = info.seeders.unwrap_or(0);
-                print!(
-                    "\r{} {:.1}% @ {:.2} MB/s ({} seeders)    ",
-                    style("RD Processing:").cyan(),
-                    progress,
-                    speed,
-                    seeders
-                );
-                io::stdout().flush().ok();
             }
-            _ => {}
         }
+        _ => None,
+    };
+    let opts = DaemonOptions {
+        rate_limit,
+        client,
+        config,
+        rss,
+    };
+    if let Err(e) = daemon::run_daemon(opts).await {
+        eprintln!(
+            "{} {}",
+            style("Error:").red(),
+            logging::redact(&e.to_string())
+        );
+        process::exit(exit::IO);
+    }
+}
 
-        tokio::time::sleep(Duration::from_secs(2)).await;
+#[cfg(unix)]
+fn install_systemd_unit(limit: Option<&str>) {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("{} {}", style("Error:").red(), e);
+            process::exit(exit::IO);
+        }
+    };
+    let args: Vec<String> = limit
+        .map(|l| vec!["--limit".to_string(), l.to_string()])
+        .unwrap_or_default();
+    let path = systemd::unit_path();
+    let unit = systemd::unit_file(&exe, &args);
+
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, unit));
+    if let Err(e) = written {
+        eprintln!(
+            "{} Failed to write {}: {}",
+            style("Error:").red(),
+            path.display(),
+            e
+        );
+        process::exit(exit::IO);
     }
+
+    let name = systemd::unit_name();
+    println!("{} Wrote {}", style("Success!").green(), path.display());
+    println!();
+    println!("Start it now and at every login with:");
+    println!("  systemctl --user daemon-reload");
+    println!("  systemctl --user enable --now {}.service", name);
+    println!();
+    println!("To keep it running after logout and start it at boot:");
+    println!("  loginctl enable-linger $USER");
 }
 
-async fn process_magnet(api_key: &str, magnet: &str) -> Result<Vec<(String, String, u64)>, String> {
-    let client = Client::new();
+#[cfg(not(unix))]
+fn install_systemd_unit(_limit: Option<&str>) {
+    eprintln!(
+        "{} systemd units are not supported on this platform",
+        style("Error:").red()
+    );
+}
 
-    println!("{} Adding magnet to Real-Debrid...", style("[1/4]").dim());
-    let torrent_id = add_magnet(&client, api_key, magnet).await?;
+#[cfg(not(unix))]
+async fn run_daemon(
+    _info: &ProviderInfo,
+    _rate_limit: Option<u64>,
+    _client: Client,
+    _config: Config,
+) {
+    eprintln!(
+        "{} The daemon is not supported on this platform",
+        style("Error:").red()
+    );
+    process::exit(exit::FAILURE);
+}
 
-    println!("{} Waiting for file list...", style("[2/4]").dim());
-    let files = wait_for_files(&client, api_key, &torrent_id).await?;
+/// Asks for the passphrase of the provider's encrypted key file, if it
+/// needs one that wasn't given through `LJ_PASSPHRASE` and `ask` allows.
+fn ask_passphrase(info: &ProviderInfo, ask: bool) -> Result<()> {
+    if !ask || !config::key_needs_passphrase(info) || !Term::stderr().is_term() {
+        return Ok(());
+    }
+    let passphrase = Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Passphrase for your {} API key", info.display_name))
+        .interact()
+        .map_err(|e| Error::Other(format!("Failed to read the passphrase: {}", e)))?;
+    config::set_passphrase(passphrase);
+    Ok(())
+}
 
-    let valid_files: Vec<_> = files
-        .iter()
-        .filter(|f| {
-            let path_lower = f.path.to_lowercase();
-            !path_lower.contains("sample") && f.bytes > 1_000_000
-        })
-        .cloned()
-        .collect();
+/// The passphrase to encrypt a key with: `LJ_PASSPHRASE`, or a new one
+/// typed twice.
+fn new_passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var(crypt::PASSPHRASE_ENV)
+        && !passphrase.is_empty()
+    {
+        return Ok(passphrase);
+    }
+    Password::with_theme(&ColorfulTheme::default())
+        .with_prompt("Passphrase")
+        .with_confirmation("Repeat passphrase", "The passphrases don't match")
+        .interact()
+        .map_err(|e| Error::Other(format!("Failed to read the passphrase: {}", e)))
+}
 
-    let selected_ids: Vec<u32> = if valid_files.len() == 1 {
+/// The provider from the saved key, for commands that can't prompt for one.
+fn configured_provider(
+    info: &ProviderInfo,
+    config: &Config,
+    client: &Client,
+) -> Result<Box<dyn DebridProvider>> {
+    ask_passphrase(info, true)?;
+    let api_key = load_api_key(info, config)?.ok_or_else(|| {
+        Error::Config(format!(
+            "no {} API key; run `lj set-key` first",
+            info.display_name
+        ))
+    })?;
+    provider::from_config(info.name, &api_key, &config.provider_config(info), client)
+}
+
+async fn run_server(
+    info: &ProviderInfo,
+    config: &Config,
+    client: Client,
+    bind: Option<String>,
+) -> Result<()> {
+    let bind = bind
+        .or_else(|| config.serve.bind.clone())
+        .unwrap_or_else(|| serve::DEFAULT_BIND.to_string());
+    let bind: std::net::SocketAddr = bind
+        .parse()
+        .map_err(|_| Error::Config(format!("invalid serve address '{}'", bind)))?;
+    let token = config.serve.token.clone().filter(|t| !t.trim().is_empty());
+
+    let provider = configured_provider(info, config, &client)?;
+    let target_dir = target_dir(config);
+    control::check_target_dir(&target_dir)?;
+
+    if token.is_none() && !bind.ip().is_loopback() {
+        eprintln!(
+            "{} No serve.token set; anyone who can reach {} can control lj",
+            style("Warning:").yellow(),
+            bind
+        );
+    }
+    let telegram = if config.telegram.accept_magnets {
+        let bot = Bot::from_config(&config.telegram).ok_or_else(|| {
+            Error::Config("telegram.accept_magnets needs a token and chat_id".to_string())
+        })?;
+        let username = bot.username(&client).await?;
         println!(
-            "  {} {}",
-            style("Single file:").green(),
-            valid_files[0].path.split('/').last().unwrap_or(&valid_files[0].path)
+            "{} taking magnets from chat {} via @{}",
+            style("Telegram").bold(),
+            bot.chat_id(),
+            username
         );
-        vec![valid_files[0].id]
-    } else if valid_files.is_empty() {
-        if files.is_empty() {
-            return Err("No files in torrent".to_string());
-        }
-        println!("  {}", style("Auto-selecting all files").yellow());
-        files.iter().map(|f| f.id).collect()
+        Some(bot)
     } else {
-        println!("\n{}", style("Select files to download:").cyan());
+        None
+    };
+    let blackhole = Blackhole::from_config(&config.blackhole);
+    if config.blackhole.watch.is_some() != config.blackhole.completed.is_some() {
+        return Err(Error::Config(
+            "blackhole needs both watch and completed".to_string(),
+        ));
+    }
+    if let Some(blackhole) = &blackhole {
+        println!(
+            "{} watching {}",
+            style("Blackhole").bold(),
+            blackhole.watch_dir().display()
+        );
+    }
+    let companion = Companion::from_config(&config.companion)?;
+    if let Some(companion) = &companion {
+        println!(
+            "{} listening on http://{}",
+            style("Companion").bold(),
+            companion.bind
+        );
+    }
 
-        let items: Vec<String> = valid_files
-            .iter()
-            .map(|f| {
-                let name = f.path.split('/').last().unwrap_or(&f.path);
-                format!("{} ({})", name, format_bytes(f.bytes))
-            })
-            .collect();
+    println!("{} listening on http://{}", style("lj serve").bold(), bind);
+    serve::run_server(ServeOptions {
+        bind,
+        token,
+        provider,
+        client,
+        target_dir,
+        select: config.select.clone(),
+        trackers: config.trackers.clone(),
+        telegram,
+        hooks: config.hooks.clone(),
+        organize: config.organize.clone(),
+        blackhole,
+        extract: config.extract.clone(),
+        qbittorrent: config.qbittorrent.clone(),
+        companion,
+    })
+    .await?;
+    Ok(())
+}
 
-        let selections = MultiSelect::with_theme(&ColorfulTheme::default())
-            .items(&items)
-            .defaults(&vec![true; items.len()])
-            .interact()
-            .map_err(|e| format!("Selection cancelled: {}", e))?;
+async fn self_update(client: &Client, check: bool) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = update::latest_release(client).await?;
+    if !update::is_newer(release.version(), current) {
+        println!(
+            "{} lj {} is the latest version",
+            style("Up to date:").green(),
+            current
+        );
+        return Ok(());
+    }
 
-        if selections.is_empty() {
-            let _ = delete_torrent(&client, api_key, &torrent_id).await;
-            return Err("No files selected".to_string());
-        }
+    println!(
+        "{} {} -> {} ({})",
+        style("Update available:").cyan(),
+        current,
+        release.version(),
+        release.html_url
+    );
+    if check {
+        return Ok(());
+    }
 
-        selections.iter().map(|&i| valid_files[i].id).collect()
-    };
+    println!("Downloading {}...", update::asset_name());
+    let path = update::install(client, &release).await?;
+    println!(
+        "{} Installed lj {} to {}",
+        style("Success!").green(),
+        release.version(),
+        path.display()
+    );
+    Ok(())
+}
 
-    println!("{} Selecting files...", style("[3/4]").dim());
-    select_files(&client, api_key, &torrent_id, &selected_ids).await?;
+/// Writes the download records that pass the filters to `output`, or to
+/// standard output.
+fn export_downloads(
+    format: Option<&str>,
+    output: Option<PathBuf>,
+    since: Option<&str>,
+    statuses: Vec<String>,
+) -> Result<()> {
+    let since = match since {
+        Some(when) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let since = parse_age(when)
+                .map(|age| now.saturating_sub(age))
+                .or_else(|| parse_timestamp(when))
+                .ok_or_else(|| Error::Other(format!("Invalid --since: {}", when)))?;
+            Some(since)
+        }
+        None => None,
+    };
+    let filter = Filter { since, statuses };
+    let records: Vec<Record> = store::load_all_downloads()
+        .iter()
+        .filter(|dl| filter.matches(dl))
+        .map(Record::from)
+        .collect();
 
-    println!("{} Waiting for Real-Debrid to process...", style("[4/4]").dim());
-    let links = wait_for_download(&client, api_key, &torrent_id).await?;
-    println!();
+    let json = match format {
+        Some(format) => format == "json",
+        None => output
+            .as_ref()
+            .and_then(|path| path.extension())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json")),
+    };
+    let text = if json {
+        export::to_json(&records)? + "\n"
+    } else {
+        export::to_csv(&records)
+    };
 
-    let mut download_links = Vec::new();
-    for link in links {
-        match unrestrict_link(&client, api_key, &link).await {
-            Ok(unrestricted) => {
-                let size = if let Ok(resp) = client.head(&unrestricted.download).send().await {
-                    resp.headers()
-                        .get("content-length")
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|v| v.parse().ok())
-                        .unwrap_or(0)
-                } else {
-                    0
-                };
-                download_links.push((unrestricted.filename, unrestricted.download, size));
-            }
-            Err(e) => {
-                eprintln!("{} {}", style("Warning:").yellow(), e);
-            }
+    match output {
+        Some(path) => {
+            std::fs::write(&path, text)?;
+            eprintln!(
+                "{} Exported {} download(s) to {}",
+                style("Success!").green(),
+                records.len(),
+                path.display()
+            );
         }
+        None => print!("{}", text),
     }
+    Ok(())
+}
 
-    let _ = delete_torrent(&client, api_key, &torrent_id).await;
+fn export_state(path: &Path, with_keys: bool) -> Result<()> {
+    let archive = StateArchive::collect(with_keys)?;
+    archive.write(path)?;
+    println!(
+        "{} Saved config, {} download record(s){} to {}",
+        style("Success!").green(),
+        archive.downloads.len(),
+        if archive.keys.is_empty() {
+            String::new()
+        } else {
+            format!(" and {} API key(s)", archive.keys.len())
+        },
+        path.display()
+    );
+    Ok(())
+}
 
-    if download_links.is_empty() {
-        return Err("No download links obtained".to_string());
+fn import_state(path: &Path, maps: Vec<String>, overwrite: bool) -> Result<()> {
+    let mut paths = BTreeMap::new();
+    for map in maps {
+        let (old, new) = map
+            .split_once('=')
+            .ok_or_else(|| Error::Other(format!("Invalid --map {}: expected OLD=NEW", map)))?;
+        paths.insert(old.to_string(), new.to_string());
     }
+    let archive = StateArchive::read(path)?;
+    let report = state::import(archive, &ImportOptions { paths, overwrite })?;
 
-    Ok(download_links)
+    for name in &report.written {
+        println!("  {} {}", style("->").green(), name);
+    }
+    for name in &report.kept {
+        println!(
+            "  {} {} differs from the archive's and was kept (--overwrite replaces it)",
+            style("x").yellow(),
+            name
+        );
+    }
+    if report.kept.iter().any(|name| name == "config.toml") {
+        println!(
+            "    {}",
+            style("The archive's config was saved as config.toml.imported").dim()
+        );
+    }
+    println!(
+        "{} Imported {} download record(s), {} under a new id; {} already present",
+        style("Success!").green(),
+        report.imported,
+        report.renamed,
+        report.skipped
+    );
+    Ok(())
 }
 
-fn spawn_background_download(download: &Download) {
-    let exe = env::current_exe().expect("Failed to get current executable path");
+fn register_handler(config: &Config) -> Result<()> {
+    let registration = handler::register(&env::current_exe()?, config)?;
+    println!(
+        "{} Magnet links now open lj ({})",
+        style("Success!").green(),
+        registration.location
+    );
+    if !registration.is_default {
+        println!(
+            "  {}",
+            style("xdg-mime was not available; choose lj for magnet links in your browser").dim()
+        );
+    }
+    println!(
+        "  {}",
+        style("Clicked magnets download every selectable file; check them with 'lj dl'").dim()
+    );
+    Ok(())
+}
 
-    let child = Command::new(&exe)
-        .arg("--bg-download")
-        .arg(&download.id)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
+async fn rss_poll(
+    info: &ProviderInfo,
+    config: &Config,
+    client: Client,
+    catch_up: bool,
+) -> Result<()> {
+    if config.rss.feeds.is_empty() {
+        return Err(Error::Config(
+            "No feeds configured; add an [rss.feeds.<name>] section".to_string(),
+        ));
+    }
+    let provider = configured_provider(info, config, &client)?;
+    let poller = Poller::new(provider, client, config)?;
+    let report = poller.poll(catch_up, &start_download).await;
 
-    match child {
-        Ok(child) => {
-            let mut dl = download.clone();
-            dl.pid = Some(child.id());
-            dl.status = DownloadStatus::Downloading;
-            let _ = save_download(&dl);
-        }
-        Err(e) => {
-            eprintln!("Failed to spawn download process: {}", e);
-        }
+    for (name, e) in &report.feed_errors {
+        eprintln!("{} {}: {}", style("Warning:").yellow(), name, e);
+    }
+    for title in &report.queued {
+        println!("  {} {}", style("->").green(), title);
+    }
+    for (title, e) in &report.failed {
+        println!("  {} {}: {}", style("x").red(), title, e);
+    }
+    if catch_up {
+        println!(
+            "{} Marked {} item(s) seen",
+            style("Success!").green(),
+            report.skipped
+        );
+    } else if report.queued.is_empty() && report.failed.is_empty() {
+        println!("{}", style("Nothing new").dim());
     }
+    Ok(())
 }
 
-async fn run_background_download(download_id: &str) {
-    let mut download = match load_download(download_id) {
-        Some(dl) => dl,
-        None => {
-            eprintln!("Download not found: {}", download_id);
-            return;
+async fn rss_list(config: &Config, client: &Client) -> Result<()> {
+    let feeds = Feed::all(config)?;
+    if feeds.is_empty() {
+        println!("{}", style("No feeds configured").yellow());
+        return Ok(());
+    }
+    for feed in feeds {
+        println!("{} {}", style(&feed.name).bold(), style(&feed.url).dim());
+        let items = match feed.fetch(client).await {
+            Ok(items) => items,
+            Err(e) => {
+                eprintln!("  {} {}", style("Error:").red(), e);
+                continue;
+            }
+        };
+        for item in items {
+            let mark = if !feed.matches(&item.title) {
+                style("  ").dim()
+            } else if feed.seen(&item) {
+                style("ok").dim()
+            } else {
+                style("->").green()
+            };
+            println!("  {} {}", mark, item.title);
         }
-    };
-
-    download.status = DownloadStatus::Downloading;
-    download.pid = Some(std::process::id());
-    let _ = save_download(&download);
-
-    let client = Client::new();
-    let target_path = PathBuf::from(&download.target_dir).join(&download.filename);
+    }
+    println!();
+    println!(
+        "{}",
+        style("-> will be downloaded by the next poll, ok was taken before").dim()
+    );
+    Ok(())
+}
 
-    let result = async {
-        let resp = client
-            .get(&download.url)
-            .send()
-            .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+/// What a queued magnet or URL is called in listings.
+fn queue_name(magnet: &str) -> String {
+    match Magnet::parse(magnet) {
+        Ok(magnet) => magnet.display_name().to_string(),
+        Err(_) => logging::redact(magnet),
+    }
+}
 
-        if !resp.status().is_success() {
-            return Err(format!("HTTP error: {}", resp.status()));
+fn queue_add(config: &Config, magnets: Vec<String>) -> Result<()> {
+    let mut invalid = 0;
+    for magnet in magnets {
+        let magnet = magnet_input(magnet, &config.trackers);
+        if !torrent::is_url(&magnet)
+            && let Err(e) = Magnet::parse(&magnet)
+        {
+            println!("  {} {}: {}", style("x").red(), magnet, e);
+            invalid += 1;
+            continue;
         }
+        match store::queue_add(magnet.trim())? {
+            Some(id) => println!(
+                "  {} {} {}",
+                style("->").green(),
+                style(format!("#{}", id)).dim(),
+                queue_name(&magnet)
+            ),
+            None => println!(
+                "  {} {} (already queued)",
+                style("ok").dim(),
+                queue_name(&magnet)
+            ),
+        }
+    }
+    if invalid > 0 {
+        return Err(Error::Other(format!("{} item(s) not queued", invalid)));
+    }
+    Ok(())
+}
 
-        let total_size = resp.content_length().unwrap_or(download.total_bytes);
-
-        let mut file = tokio::fs::File::create(&target_path)
-            .await
-            .map_err(|e| format!("Failed to create file: {}", e))?;
-
-        let mut stream = resp.bytes_stream();
-        let mut downloaded: u64 = 0;
-        let mut last_update = Instant::now();
-        let mut last_bytes: u64 = 0;
-
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| format!("Download error: {}", e))?;
-
-            tokio::io::AsyncWriteExt::write_all(&mut file, &chunk)
-                .await
-                .map_err(|e| format!("Write error: {}", e))?;
-
-            downloaded += chunk.len() as u64;
+fn queue_list() {
+    let items = store::queue_items();
+    if items.is_empty() {
+        println!("{}", style("The queue is empty").dim());
+        return;
+    }
+    for item in items {
+        let status = match &item.status {
+            QueueStatus::Pending => style("pending ").cyan(),
+            QueueStatus::Running => style("running ").yellow(),
+            QueueStatus::Done => style("done    ").green(),
+            QueueStatus::Failed(_) => style("failed  ").red(),
+        };
+        println!(
+            "{} {} {}",
+            style(format!("#{:<4}", item.id)).dim(),
+            status,
+            queue_name(&item.magnet)
+        );
+        if let QueueStatus::Failed(error) = &item.status {
+            println!("        {}", style(error).dim());
+        }
+    }
+}
 
-            if last_update.elapsed() >= Duration::from_millis(500) {
-                let elapsed = last_update.elapsed().as_secs_f64();
-                let speed = (downloaded - last_bytes) as f64 / elapsed;
+/// Downloads the pending items (and with `retry` the failed and interrupted
+/// ones), each on its own, so one failing is recorded and the rest still
+/// run. Fails at the end if any item did.
+async fn queue_run(
+    info: &ProviderInfo,
+    config: &Config,
+    client: Client,
+    retry: bool,
+) -> Result<()> {
+    let kinds: &[&str] = if retry {
+        &["pending", "failed", "running"]
+    } else {
+        &["pending"]
+    };
+    let items: Vec<_> = store::queue_items()
+        .into_iter()
+        .filter(|item| kinds.contains(&item.status.kind()))
+        .collect();
+    if items.is_empty() {
+        println!("{}", style("Nothing queued").dim());
+        return Ok(());
+    }
+    let provider = configured_provider(info, config, &client)?;
 
-                // Reload to check for cancellation
-                if let Some(dl) = load_download(download_id) {
-                    if dl.status == DownloadStatus::Cancelled {
-                        return Err("Cancelled".to_string());
-                    }
+    let mut failed = 0;
+    for item in &items {
+        // Another run got there first
+        if !store::claim_queue_item(item.id, kinds)? {
+            continue;
+        }
+        println!(
+            "{} {}",
+            style(format!("#{}", item.id)).dim(),
+            queue_name(&item.magnet)
+        );
+        match run_queued(provider.as_ref(), &client, config, &item.magnet).await {
+            Ok(()) => store::finish_queue_item(item.id, None)?,
+            Err(e) => {
+                let message = logging::redact(&e.to_string());
+                println!("  {} {}", style("x").red(), message);
+                store::finish_queue_item(item.id, Some(&message))?;
+                // The others would be refused the same way
+                if e.is_auth() {
+                    return Err(e);
                 }
-
-                // Update progress
-                download.downloaded_bytes = downloaded;
-                download.total_bytes = total_size;
-                download.speed = speed;
-                let _ = save_download(&download);
-
-                last_update = Instant::now();
-                last_bytes = downloaded;
+                failed += 1;
             }
         }
+        println!();
+    }
+    if failed > 0 {
+        return Err(Error::Batch {
+            failed,
+            total: items.len(),
+            what: "queued item(s)",
+        });
+    }
+    Ok(())
+}
+
+/// Downloads one queued magnet or `.torrent` URL without asking anything.
+async fn run_queued(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    config: &Config,
+    magnet: &str,
+) -> Result<()> {
+    let source = Source::resolve(client, magnet).await?;
+    let fetched = pipeline::fetch_source(
+        provider,
+        client,
+        &source,
+        &config.select,
+        Some(&target_dir(config)),
+        &mut |_| {},
+    )
+    .await?;
+    let (started, failed) = start_downloads(config, source.magnet(), fetched)?;
+    batch_result(failed, started.len() + failed)
+}
 
-        Ok(())
+/// Points download #`n` of `lj dl` at `dir`.
+fn set_download_dir(n: usize, dir: &Path) -> Result<()> {
+    let downloads = list_downloads();
+    let download = n
+        .checked_sub(1)
+        .and_then(|i| downloads.get(i))
+        .ok_or_else(|| Error::Other(format!("No download #{}; see `lj dl`", n)))?;
+    let dir = env::current_dir().unwrap_or_default().join(dir);
+    let mut copying = false;
+    let moved = control::set_download_dir(
+        &download.id,
+        &dir.to_string_lossy(),
+        &mut |copied, total| {
+            copying = true;
+            print!(
+                "\r{} {} / {}    ",
+                style("Copying:").cyan(),
+                format_bytes(copied),
+                format_bytes(total)
+            );
+            io::stdout().flush().ok();
+        },
+    )?;
+    if copying {
+        println!();
     }
-    .await;
+    println!(
+        "{} {}",
+        style("Will download to").green(),
+        moved.target_path().display()
+    );
+    Ok(())
+}
 
-    match result {
-        Ok(()) => {
-            download.status = DownloadStatus::Completed;
-            download.downloaded_bytes = download.total_bytes;
-            download.speed = 0.0;
-            download.pid = None;
-        }
-        Err(e) => {
-            if e == "Cancelled" {
-                download.status = DownloadStatus::Cancelled;
-                let _ = std::fs::remove_file(&target_path);
-            } else {
-                download.status = DownloadStatus::Failed(e);
-            }
-            download.speed = 0.0;
-            download.pid = None;
-        }
-    }
-    let _ = save_download(&download);
+/// `text` cut down to what fits on one line of `term` next to `used`
+/// columns, by display width so wide characters count double. Left whole
+/// when `term` isn't a terminal.
+fn fit(text: &str, term: &Term, used: usize) -> String {
+    let Some((_, columns)) = term.size_checked() else {
+        return text.to_string();
+    };
+    let width = (columns as usize).saturating_sub(used).max(20);
+    console::truncate_str(text, width, "…").into_owned()
 }
 
-fn show_downloads() {
-    let term = Term::stdout();
-    let mut downloads = load_all_downloads();
-
-    // Clean up dead processes
-    for dl in &mut downloads {
-        if dl.status == DownloadStatus::Downloading {
-            if let Some(pid) = dl.pid {
-                if signal::kill(Pid::from_raw(pid as i32), None).is_err() {
-                    if dl.downloaded_bytes >= dl.total_bytes && dl.total_bytes > 0 {
-                        dl.status = DownloadStatus::Completed;
-                    } else {
-                        dl.status = DownloadStatus::Failed("Process died".to_string());
-                    }
-                    dl.pid = None;
-                    let _ = save_download(dl);
-                }
-            }
+/// Prints the downloads and the torrents still processing, returning them
+/// unless there are none.
+fn print_downloads(term: &Term) -> Option<(Vec<Download>, Vec<Processing>)> {
+    let downloads = list_downloads();
+
+    let quarantined = store::quarantined_records();
+    if !quarantined.is_empty() {
+        eprintln!(
+            "{} {} unreadable record(s) moved to quarantine in {}:",
+            style("Warning:").yellow(),
+            quarantined.len(),
+            store::db_path().display()
+        );
+        for record in &quarantined {
+            eprintln!("  {} {}", record.id, style(&record.error).dim());
         }
+        println!();
     }
 
-    // Reload after cleanup
-    let downloads = load_all_downloads();
-
-    if downloads.is_empty() {
+    let jobs = processing::jobs();
+    if downloads.is_empty() && jobs.is_empty() {
         println!("{}", style("No downloads").dim());
-        return;
+        return None;
     }
 
-    println!("{}", style("Downloads:").bold());
-    println!();
+    if !jobs.is_empty() {
+        println!("{}", style("Processing:").bold());
+        println!();
+    }
+    for (i, job) in jobs.iter().enumerate() {
+        let service =
+            provider::info(&job.provider).map_or(job.provider.as_str(), |info| info.display_name);
+        let status_str = match &job.error {
+            Some(e) => format!("{} {}", style("FAILED").red(), e),
+            None => format!(
+                "{} {:.1}% @ {} ({} seeders)",
+                style(format!("{} PROCESSING", service)).magenta(),
+                job.progress,
+                format_speed(job.speed as f64),
+                job.seeders
+            ),
+        };
+        let number = format!("[p{}]", i + 1);
+        println!(
+            "{} {}",
+            style(&number).dim(),
+            fit(
+                job.name.as_deref().unwrap_or(&job.torrent_id),
+                term,
+                number.len() + 1
+            )
+        );
+        println!(
+            "    {} {}",
+            status_str,
+            style(format!("-> {}", job.target_dir)).dim()
+        );
+        println!();
+    }
+
+    let torrents = torrent_progress(&downloads);
+    if !torrents.is_empty() {
+        println!("{}", style("Torrents:").bold());
+        println!();
+    }
+    for torrent in &torrents {
+        println!("  {}", fit(&torrent.name, term, 2));
+        let eta = torrent
+            .eta()
+            .map(|secs| format!(", {} left", format_duration(secs)))
+            .unwrap_or_default();
+        println!(
+            "    {} {}/{} files, {} of {} @ {}{}",
+            style(format!("{:.1}%", torrent.percent())).cyan(),
+            torrent.files_done,
+            torrent.files,
+            format_bytes(torrent.downloaded),
+            format_size(torrent.total),
+            format_speed(torrent.speed),
+            eta
+        );
+        println!();
+    }
+
+    if !downloads.is_empty() {
+        let summary = store::status_counts()
+            .iter()
+            .map(|(kind, n)| format!("{} {}", n, kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("{} {}", style("Downloads:").bold(), style(summary).dim());
+        println!();
+    }
 
     for (i, dl) in downloads.iter().enumerate() {
         let status_str = match &dl.status {
             DownloadStatus::Pending => style("PENDING").yellow().to_string(),
+            DownloadStatus::Downloading if !dl.size_known() => format!(
+                "{} {} @ {}",
+                style("DOWNLOADING").cyan(),
+                format_bytes(dl.downloaded_bytes),
+                format_speed(dl.speed)
+            ),
             DownloadStatus::Downloading => {
-                let pct = if dl.total_bytes > 0 {
-                    (dl.downloaded_bytes as f64 / dl.total_bytes as f64 * 100.0) as u8
-                } else {
-                    0
-                };
+                let pct = (dl.downloaded_bytes as f64 / dl.total_bytes as f64 * 100.0) as u8;
+                let eta = dl
+                    .eta()
+                    .map(|secs| format!(", {} left", format_duration(secs)))
+                    .unwrap_or_default();
                 format!(
-                    "{} {}% @ {}",
+                    "{} {}% @ {}{} {}",
                     style("DOWNLOADING").cyan(),
                     pct,
-                    format_speed(dl.speed)
+                    format_speed(dl.speed),
+                    eta,
+                    style(format!("(avg {})", format_speed(dl.average_speed))).dim()
                 )
             }
             DownloadStatus::Completed => style("COMPLETED").green().to_string(),
@@ -689,15 +1854,46 @@ fn show_downloads() {
             DownloadStatus::Cancelled => style("CANCELLED").dim().to_string(),
         };
 
+        let number = format!("[{}]", i + 1);
+        let size = format!("({})", format_size(dl.total_bytes));
         println!(
             "{} {} {}",
-            style(format!("[{}]", i + 1)).dim(),
-            &dl.filename,
-            style(format!("({})", format_bytes(dl.total_bytes))).dim()
+            style(&number).dim(),
+            fit(&dl.filename, term, number.len() + size.len() + 2),
+            style(size).dim()
+        );
+        println!(
+            "    {} {}",
+            status_str,
+            style(format!("-> {}", dl.target_dir)).dim()
         );
-        println!("    {} {}", status_str, style(format!("-> {}", dl.target_dir)).dim());
 
-        if dl.status == DownloadStatus::Downloading && dl.total_bytes > 0 {
+        if dl.target_missing() {
+            println!(
+                "    {} {}",
+                style("FOLDER MISSING").red(),
+                style(format!("move it with m {} <dir>", i + 1)).dim()
+            );
+        }
+        match &dl.extraction {
+            Some(Extraction::Extracting { progress }) => {
+                println!("    {} {}%", style("EXTRACTING").cyan(), progress)
+            }
+            Some(Extraction::Extracted) => println!("    {}", style("EXTRACTED").green()),
+            Some(Extraction::Failed(e)) => {
+                println!("    {} {}", style("EXTRACTION FAILED").red(), e)
+            }
+            None => {}
+        }
+        if let Some(remote) = &dl.remote {
+            println!(
+                "    {} {}",
+                style("UPLOADED").green(),
+                style(format!("-> {}", remote)).dim()
+            );
+        }
+
+        if dl.status == DownloadStatus::Downloading && dl.size_known() {
             let pct = dl.downloaded_bytes as f64 / dl.total_bytes as f64;
             let width = 40;
             let filled = (pct * width as f64) as usize;
@@ -710,11 +1906,36 @@ fn show_downloads() {
         }
         println!();
     }
+    Some((downloads, jobs))
+}
+
+/// Redraws the downloads every second until interrupted.
+fn watch_downloads() {
+    let term = Term::stdout();
+    loop {
+        let _ = term.clear_screen();
+        print_downloads(&term);
+        println!("{}", style("Refreshing every second; Ctrl-C to stop").dim());
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+fn show_downloads() {
+    let term = Term::stdout();
+    let Some((downloads, jobs)) = print_downloads(&term) else {
+        return;
+    };
 
     println!("{}", style("Actions:").bold());
     println!("  [c]ancel <n>  - Cancel download #n");
     println!("  [r]emove <n>  - Remove completed/failed #n");
     println!("  [C]lear       - Clear all completed/failed/cancelled");
+    if !jobs.is_empty() {
+        println!("  [p] <n>       - Cancel (or remove if failed) processing #pn");
+    }
+    if downloads.iter().any(Download::target_missing) {
+        println!("  [m]ove <n> <dir> - Start #n over in another folder");
+    }
     println!("  [q]uit        - Exit");
     println!();
 
@@ -738,43 +1959,65 @@ fn show_downloads() {
             Some('q') | Some('Q') => break,
             Some('C') => {
                 for dl in &downloads {
-                    if matches!(
-                        dl.status,
-                        DownloadStatus::Completed | DownloadStatus::Failed(_) | DownloadStatus::Cancelled
-                    ) {
-                        delete_download(&dl.id);
+                    if dl.status.is_finished() {
+                        store::delete_download(&dl.id);
                     }
                 }
                 let _ = term.clear_screen();
                 show_downloads();
                 return;
             }
+            Some('p') => {
+                let Some(job) = input[1..]
+                    .trim()
+                    .trim_start_matches('p')
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| jobs.get(n.checked_sub(1)?))
+                else {
+                    println!("{}", style("Usage: p <n>").red());
+                    continue;
+                };
+                if processing::cancel(job.id) {
+                    println!("{}", style("Cancelled").yellow());
+                } else {
+                    println!("{}", style("Removed").green());
+                }
+            }
+            Some('m') => {
+                let args = input[1..].trim().split_once(char::is_whitespace);
+                let Some((n, dir)) = args.and_then(|(n, dir)| {
+                    let n = n
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|n| (1..=download_ids.len()).contains(n))?;
+                    Some((n, dir.trim()))
+                }) else {
+                    println!("{}", style("Usage: m <n> <dir>").red());
+                    continue;
+                };
+                let dir = env::current_dir().unwrap_or_default().join(dir);
+                match control::move_download(&download_ids[n - 1], &dir.to_string_lossy()) {
+                    Ok(_) => println!("{} {}", style("Moved to").green(), dir.display()),
+                    Err(e) => report_error(&e),
+                }
+            }
             Some('c') | Some('r') => {
                 let is_cancel = input.starts_with('c');
                 let num_str = input[1..].trim();
-                if let Ok(n) = num_str.parse::<usize>() {
-                    if n > 0 && n <= download_ids.len() {
-                        let id = &download_ids[n - 1];
-
-                        if is_cancel {
-                            if let Some(mut dl) = load_download(id) {
-                                if dl.status == DownloadStatus::Downloading {
-                                    dl.status = DownloadStatus::Cancelled;
-                                    if let Some(pid) = dl.pid {
-                                        let _ = signal::kill(
-                                            Pid::from_raw(pid as i32),
-                                            Signal::SIGTERM,
-                                        );
-                                    }
-                                    dl.pid = None;
-                                    let _ = save_download(&dl);
-                                    println!("{}", style("Cancelled").yellow());
-                                }
-                            }
-                        } else {
-                            delete_download(id);
-                            println!("{}", style("Removed").green());
+                if let Ok(n) = num_str.parse::<usize>()
+                    && n > 0
+                    && n <= download_ids.len()
+                {
+                    let id = &download_ids[n - 1];
+
+                    if is_cancel {
+                        if cancel_download(id) {
+                            println!("{}", style("Cancelled").yellow());
                         }
+                    } else {
+                        store::delete_download(id);
+                        println!("{}", style("Removed").green());
                     }
                 }
             }
@@ -787,117 +2030,683 @@ fn show_downloads() {
 
 #[tokio::main]
 async fn main() {
+    logging::redact_panics();
     let args: Vec<String> = env::args().collect();
-    if args.len() >= 3 && args[1] == "--bg-download" {
-        run_background_download(&args[2]).await;
+    if args.len() >= 3 && args[1] == worker::WORKER_FLAG {
+        let profile = env::var(config::PROFILE_ENV).ok();
+        if let Err(e) = config::set_profile(profile.as_deref()) {
+            eprintln!("{}", e);
+            process::exit(e.exit_code());
+        }
+        if let Err(e) = worker::run_worker(&args[2..]).await {
+            eprintln!("{}", logging::redact(&e.to_string()));
+            process::exit(e.exit_code());
+        }
+        return;
+    }
+    if args.len() >= 3 && args[1] == processing::PROCESSOR_FLAG {
+        let profile = env::var(config::PROFILE_ENV).ok();
+        if let Err(e) = config::set_profile(profile.as_deref()) {
+            eprintln!("{}", e);
+            process::exit(e.exit_code());
+        }
+        if let Err(e) = processing::run(&args[2]).await {
+            eprintln!("{}", logging::redact(&e.to_string()));
+            process::exit(e.exit_code());
+        }
         return;
     }
 
     let cli = Cli::parse();
 
-    match cli.command {
-        Some(Commands::Dl) => {
-            show_downloads();
+    let profile = cli
+        .profile
+        .clone()
+        .or_else(|| env::var(config::PROFILE_ENV).ok());
+    if let Err(e) = config::set_profile(profile.as_deref()) {
+        report_error(&e);
+        process::exit(e.exit_code());
+    }
+
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(&e);
+            process::exit(e.exit_code());
+        }
+    };
+    if let Ok(cwd) = env::current_dir()
+        && let Err(e) = config.apply_local(&cwd)
+    {
+        report_error(&e);
+        process::exit(e.exit_code());
+    }
+    let client = match lj_core::http::client(&config) {
+        Ok(client) => client,
+        Err(e) => {
+            report_error(&e);
+            process::exit(e.exit_code());
+        }
+    };
+    if config.tls.insecure_skip_verify {
+        eprintln!(
+            "{} TLS certificate verification is disabled (tls.insecure_skip_verify)",
+            style("Warning:").yellow()
+        );
+    }
+
+    let info = match select_provider(cli.provider.as_deref(), &config) {
+        Ok(info) => info,
+        Err(e) => {
+            report_error(&e);
+            process::exit(e.exit_code());
+        }
+    };
+    // set-key rewrites the file, so it can't be refused
+    if let Some(problem) = config::key_file_exposure(info)
+        && !matches!(cli.command, Some(Commands::SetKey { .. }))
+    {
+        if cli.strict {
+            let e = Error::Config(problem);
+            report_error(&e);
+            process::exit(e.exit_code());
+        }
+        eprintln!("{} {}", style("Warning:").yellow(), problem);
+    }
+    let api_url = cli
+        .api_url
+        .clone()
+        .or_else(|| env::var(config::API_URL_ENV).ok())
+        .filter(|url| !url.is_empty());
+    if let Some(url) = api_url {
+        config.set_api_url(info, &url);
+    }
+    if cli.organize {
+        config.organize.enabled = true;
+    }
+    if let Some(dir) = cli.download_dir {
+        config.download_dir = Some(dir);
+    }
+    config.select.episodes = cli.episodes;
+    if cli.files_timeout.is_some() {
+        config.wait.files = cli.files_timeout;
+    }
+    if cli.processing_timeout.is_some() {
+        config.wait.processing = cli.processing_timeout;
+    }
+    if cli.stalled_timeout.is_some() {
+        config.wait.stalled = cli.stalled_timeout;
+    }
+    // Run by a browser, there is no terminal to report errors to
+    if cli.yes {
+        logging::init();
+    }
+
+    let job = match cli.command {
+        Some(Commands::Dl { watch }) => {
+            if watch {
+                watch_downloads();
+            } else {
+                show_downloads();
+            }
+            return;
+        }
+        Some(Commands::SetDir { n, dir }) => {
+            if let Err(e) = set_download_dir(n, &dir) {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::Export {
+            format,
+            output,
+            since,
+            status,
+        }) => {
+            if let Err(e) = export_downloads(format.as_deref(), output, since.as_deref(), status) {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::ExportState { path, with_keys }) => {
+            if let Err(e) = export_state(&path, with_keys) {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::ImportState {
+            path,
+            maps,
+            overwrite,
+        }) => {
+            if let Err(e) = import_state(&path, maps, overwrite) {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
             return;
         }
-        Some(Commands::SetKey) => {
+        Some(Commands::SetKey {
+            file,
+            no_check,
+            encrypt,
+        }) => {
             let key: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Enter your Real-Debrid API key")
+                .with_prompt(format!("Enter your {} API key", info.display_name))
                 .interact_text()
                 .expect("Failed to read input");
 
-            if let Err(e) = save_api_key(&key) {
-                eprintln!("{} Failed to save API key: {}", style("Error:").red(), e);
-            } else {
-                println!("{}", style("API key saved!").green());
+            if !no_check && let Err(e) = check_api_key(info, &config, &client, &key).await {
+                eprintln!(
+                    "{} {}",
+                    style("Error:").red(),
+                    logging::redact(&e.to_string())
+                );
+                eprintln!(
+                    "  {}",
+                    style("The key was not saved; pass --no-check to save it anyway").dim()
+                );
+                process::exit(e.exit_code());
+            }
+
+            let encryption = match encrypt.as_deref() {
+                Some("passphrase") => match new_passphrase() {
+                    Ok(passphrase) => Some(Encryption::Passphrase(passphrase)),
+                    Err(e) => {
+                        report_error(&e);
+                        process::exit(e.exit_code());
+                    }
+                },
+                Some(_) => Some(Encryption::Keyring),
+                None => None,
+            };
+            let saved = match encryption {
+                Some(encryption) => {
+                    report_saved_key(config::save_encrypted_api_key(info, &key, &encryption))
+                }
+                None => report_saved_key(save_api_key(info, &key, file)),
+            };
+            if !saved {
+                process::exit(exit::IO);
             }
             return;
         }
-        None => {}
-    }
+        Some(Commands::Daemon {
+            limit,
+            stop,
+            install_systemd,
+        }) => {
+            if stop {
+                match ipc::request(&Request::Shutdown) {
+                    Ok(_) => println!("{}", style("Daemon stopped").green()),
+                    Err(_) => {
+                        eprintln!("{} No daemon running", style("Error:").red());
+                        process::exit(exit::FAILURE);
+                    }
+                }
+                return;
+            }
 
-    let magnet = match cli.magnet {
-        Some(m) => m,
-        None => {
-            println!("Usage: lj <magnet>    - Download from magnet link");
-            println!("       lj dl          - Show downloads in progress");
-            println!("       lj set-key     - Set Real-Debrid API key");
+            let rate_limit = match limit.as_deref().map(parse_bytes) {
+                Some(None) => {
+                    eprintln!("{} Invalid rate limit", style("Error:").red());
+                    process::exit(exit::INPUT);
+                }
+                Some(rate) => rate,
+                None => None,
+            };
+            if install_systemd {
+                install_systemd_unit(limit.as_deref());
+                return;
+            }
+            run_daemon(info, rate_limit, client, config).await;
+            return;
+        }
+        Some(Commands::SelfUpdate { check }) => {
+            if let Err(e) = self_update(&client, check).await {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::Serve { bind }) => {
+            if let Err(e) = run_server(info, &config, client, bind).await {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::RegisterHandler) => {
+            if let Err(e) = register_handler(&config) {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::UnregisterHandler) => {
+            match handler::unregister() {
+                Ok(Some(location)) => {
+                    println!("{} Removed {}", style("Success!").green(), location)
+                }
+                Ok(None) => println!("{}", style("No magnet handler registered").yellow()),
+                Err(e) => {
+                    report_error(&e);
+                    process::exit(e.exit_code());
+                }
+            }
+            return;
+        }
+        Some(Commands::Rss { command }) => {
+            let result = match command {
+                RssCommand::Poll { catch_up } => rss_poll(info, &config, client, catch_up).await,
+                RssCommand::List => rss_list(&config, &client).await,
+            };
+            if let Err(e) = result {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
             return;
         }
+        Some(Commands::Queue { command }) => {
+            let result =
+                match command {
+                    QueueCommand::Add { magnets } => queue_add(&config, magnets),
+                    QueueCommand::List => {
+                        queue_list();
+                        Ok(())
+                    }
+                    QueueCommand::Run { retry } => queue_run(info, &config, client, retry).await,
+                    QueueCommand::Remove { ids } => store::remove_queue_items(&ids)
+                        .map_err(Error::from)
+                        .map(|removed| {
+                            println!("{} Removed {} item(s)", style("Success!").green(), removed)
+                        }),
+                    QueueCommand::Clear => store::clear_done_queue_items()
+                        .map_err(Error::from)
+                        .map(|removed| {
+                            println!("{} Removed {} item(s)", style("Success!").green(), removed)
+                        }),
+                };
+            if let Err(e) = result {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            return;
+        }
+        Some(Commands::Search { query }) => Job::Search(query.join(" ")),
+        Some(Commands::Play { magnet, download }) => Job::Play {
+            magnet: magnet_input(magnet, &config.trackers),
+            download,
+        },
+        None => match cli.magnet {
+            Some(magnet) => Job::Download(magnet_input(magnet, &config.trackers)),
+            None => {
+                println!("Usage: lj <magnet>      - Download from magnet link");
+                println!("       lj search <text> - Search indexers and download a result");
+                println!("       lj play <magnet> - Stream a magnet into mpv or VLC");
+                println!("       lj dl            - Show downloads in progress");
+                println!("       lj rss poll      - Download new items from RSS feeds");
+                println!("       lj queue add     - Stash magnets for `lj queue run` later");
+                println!("       lj export        - Export download history to CSV or JSON");
+                println!("       lj set-key       - Set debrid API key");
+                println!("       lj daemon        - Run the transfer daemon");
+                println!("       lj serve         - Serve the HTTP API");
+                println!("       lj self-update   - Update to the latest release");
+                return;
+            }
+        },
     };
 
-    if !magnet.starts_with("magnet:") {
-        eprintln!("{} Not a valid magnet link", style("Error:").red());
-        return;
+    match &job {
+        Job::Search(_) if config.indexers.is_empty() => {
+            let e = Error::Config(
+                "No indexers configured; add an [indexers.<name>] section".to_string(),
+            );
+            report_error(&e);
+            process::exit(e.exit_code());
+        }
+        // URLs are checked once fetched
+        Job::Download(magnet) | Job::Play { magnet, .. } if !torrent::is_url(magnet) => {
+            if let Err(e) = Magnet::parse(magnet) {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+        }
+        _ => {}
     }
 
-    let api_key = match load_api_key() {
-        Some(key) => key,
-        None => match prompt_api_key().await {
-            Some(key) => key,
-            None => {
-                eprintln!("{} API key is required", style("Error:").red());
-                return;
+    let mut api_key =
+        match ask_passphrase(info, !cli.yes).and_then(|()| load_api_key(info, &config)) {
+            Ok(Some(key)) => key,
+            Err(e) => {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            Ok(None) if cli.yes => {
+                let e = Error::Config(format!(
+                    "no {} API key; run `lj set-key` first",
+                    info.display_name
+                ));
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+            Ok(None) => {
+                println!(
+                    "{}",
+                    style(format!("{} API key not found.", info.display_name)).yellow()
+                );
+                match prompt_api_key(info, &config, &client).await {
+                    Some(key) => key,
+                    None => {
+                        eprintln!("{} API key is required", style("Error:").red());
+                        process::exit(exit::AUTH);
+                    }
+                }
+            }
+        };
+
+    let mut job = job;
+    loop {
+        let provider = match provider::from_config(
+            info.name,
+            &api_key,
+            &config.provider_config(info),
+            &client,
+        ) {
+            Ok(provider) => provider,
+            Err(e) => {
+                report_error(&e);
+                process::exit(e.exit_code());
+            }
+        };
+        let e = match run_job(
+            provider.as_ref(),
+            &client,
+            &config,
+            &mut job,
+            !cli.yes,
+            cli.force,
+            if cli.background {
+                Handoff::Background(&api_key)
+            } else if cli.wait {
+                Handoff::Wait
+            } else {
+                Handoff::Start
+            },
+        )
+        .await
+        {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        if !e.is_auth() || cli.yes || !Term::stderr().is_term() {
+            report_error(&e);
+            process::exit(e.exit_code());
+        }
+        match renew_api_key(info, &config, &client, &e).await {
+            Some(key) => api_key = key,
+            None => process::exit(e.exit_code()),
+        }
+    }
+}
+
+/// Runs `job` through `provider`. A search becomes a download of the
+/// picked result, so running it again doesn't search again. Unless
+/// `force`, a torrent that is already downloaded is only downloaded again
+/// if the user says so. `handoff` says what happens once the files are
+/// picked.
+async fn run_job(
+    provider: &dyn DebridProvider,
+    client: &Client,
+    config: &Config,
+    job: &mut Job,
+    interactive: bool,
+    force: bool,
+    handoff: Handoff<'_>,
+) -> Result<()> {
+    let magnet = match job {
+        Job::Download(magnet) => magnet.clone(),
+        Job::Search(text) => match search_magnet(provider, client, &config.indexers, text).await? {
+            Some(magnet) => {
+                let magnet = magnet_input(magnet, &config.trackers);
+                *job = Job::Download(magnet.clone());
+                magnet
             }
+            None => return Ok(()),
         },
+        Job::Play { magnet, download } => {
+            let source = Source::resolve(client, magnet).await?;
+            println!();
+            print_magnet(&Magnet::parse(source.magnet())?);
+            return play_magnet(provider, client, config, &source, *download).await;
+        }
     };
 
+    let source = Source::resolve(client, &magnet).await?;
+    println!();
+    print_magnet(&Magnet::parse(source.magnet())?);
+    if !force && !download_again(source.magnet(), interactive) {
+        return Ok(());
+    }
+    let (torrent_id, selected_ids) = choose_files(
+        provider,
+        &source,
+        &config.select,
+        &config.library,
+        interactive,
+    )
+    .await?;
+    if let Handoff::Background(api_key) = handoff {
+        return process_in_background(
+            provider,
+            config,
+            &source,
+            &torrent_id,
+            selected_ids,
+            api_key,
+        );
+    }
+    let (fetched, unresolved) = process_magnet(
+        provider,
+        client,
+        &source,
+        config,
+        torrent_id,
+        selected_ids,
+        interactive,
+    )
+    .await?;
     println!();
-    match process_magnet(&api_key, &magnet).await {
-        Ok(links) => {
-            let current_dir = env::current_dir()
-                .unwrap_or_else(|_| PathBuf::from("."))
-                .to_string_lossy()
-                .to_string();
+    let (started, failed) = start_downloads(config, source.magnet(), fetched)?;
+    let failed = failed + unresolved;
 
-            println!();
-            println!(
-                "{} Starting {} download(s) in background...",
-                style("Success!").green(),
-                links.len()
-            );
+    println!();
+    if let Handoff::Wait = handoff {
+        println!("{}", style("Waiting for the downloads to finish...").dim());
+        return wait_for_downloads(started, failed).await;
+    }
+    println!(
+        "{}",
+        style("Downloads running in background. Use 'lj dl' to check progress.").dim()
+    );
+    batch_result(failed, started.len() + failed)
+}
 
-            for (filename, url, size) in links {
-                let id = format!(
-                    "{}-{}",
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis(),
-                    &filename[..filename.len().min(10)]
-                );
+/// What `lj <magnet>` does once the files are picked.
+#[derive(Clone, Copy)]
+enum Handoff<'a> {
+    /// Start the downloads in the background and return.
+    Start,
+    /// Start the downloads and wait for them to finish.
+    Wait,
+    /// Leave the torrent with the service for a process of its own to wait
+    /// on, given this API key.
+    Background(&'a str),
+}
 
-                let download = Download {
-                    id: id.clone(),
-                    filename: filename.clone(),
-                    url,
-                    target_dir: current_dir.clone(),
-                    total_bytes: size,
-                    downloaded_bytes: 0,
-                    speed: 0.0,
-                    status: DownloadStatus::Pending,
-                    started_at: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                    pid: None,
-                };
+/// Hands `torrent_id` to a process that selects `file_ids`, waits for the
+/// service to have them and starts their downloads, so the terminal isn't
+/// held up meanwhile.
+fn process_in_background(
+    provider: &dyn DebridProvider,
+    config: &Config,
+    source: &Source,
+    torrent_id: &str,
+    file_ids: Vec<u32>,
+    api_key: &str,
+) -> Result<()> {
+    let target_dir = target_dir(config);
+    control::check_target_dir(&target_dir)?;
+    let info = provider::info(provider.name())?;
+
+    let mut job = Processing::new(
+        info.name,
+        torrent_id,
+        source.magnet(),
+        file_ids,
+        &target_dir,
+    );
+    job.api_url = config.provider_config(info).api_url;
+    job.name = Magnet::parse(source.magnet())
+        .ok()
+        .and_then(|magnet| magnet.name);
+    job.organize = config.organize.enabled;
+    processing::start(&job, api_key)?;
 
-                // Save download first, then spawn
-                let _ = save_download(&download);
-                spawn_background_download(&download);
+    println!();
+    println!(
+        "{} {} is processing it; the downloads start once it's done",
+        style("Handed over!").green(),
+        provider.display_name()
+    );
+    println!("{}", style("Use 'lj dl' to check progress.").dim());
+    Ok(())
+}
 
-                println!("  {} {}", style("->").green(), filename);
-            }
+/// Warns if the torrent is already downloading or was downloaded before,
+/// and returns whether to download it anyway, which only the user can say.
+fn download_again(magnet: &str, interactive: bool) -> bool {
+    let Some(hash) = torrent::info_hash(magnet) else {
+        return true;
+    };
+    let downloads = store::downloads_of_torrent(&hash);
+    if downloads.is_empty() {
+        return true;
+    }
+    println!();
+    println!(
+        "{}",
+        style("This torrent is already downloading or was downloaded before:").yellow()
+    );
+    for dl in &downloads {
+        let state = match dl.status {
+            DownloadStatus::Completed => "downloaded",
+            _ => "downloading",
+        };
+        println!(
+            "  {} {} ({})",
+            style("-").dim(),
+            dl.target_path().display(),
+            state
+        );
+    }
+    if interactive
+        && Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Download it again?")
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    {
+        return true;
+    }
+    println!(
+        "{}",
+        style("Skipped; use --force to download it anyway").dim()
+    );
+    false
+}
 
-            println!();
-            println!(
-                "{}",
-                style("Downloads running in background. Use 'lj dl' to check progress.").dim()
-            );
-        }
-        Err(e) => {
-            eprintln!("{} {}", style("Error:").red(), e);
-        }
+/// Submits `source` to `provider`, uploading it if it's a `.torrent` file.
+async fn add_source(provider: &dyn DebridProvider, source: &Source) -> Result<String> {
+    let what = match source {
+        Source::Magnet(_) => "magnet",
+        Source::File { .. } => "torrent",
+    };
+    println!(
+        "{} Adding {} to {}...",
+        style("[1/4]").dim(),
+        what,
+        provider.display_name()
+    );
+    source.add(provider).await
+}
+
+/// `input` as a magnet link, made up if it is a bare info hash, with the
+/// configured trackers if it names none.
+fn magnet_input(input: String, trackers: &[String]) -> String {
+    match Magnet::from_info_hash(&input, trackers) {
+        Some(magnet) => magnet.to_string(),
+        None => magnet::with_trackers(&input, trackers),
+    }
+}
+
+/// What a magnet is about to add.
+fn print_magnet(magnet: &Magnet) {
+    println!("{} {}", style("Torrent:").cyan(), magnet.display_name());
+    if let Some(hash) = magnet
+        .info_hash
+        .as_deref()
+        .or(magnet.info_hash_v2.as_deref())
+    {
+        println!("  {} {}", style("Info hash:").dim(), hash);
+    }
+    if !magnet.trackers.is_empty() {
+        println!("  {} {}", style("Trackers:").dim(), magnet.trackers.len());
+    }
+}
+
+/// Tells the user the provider rejected the API key, and offers to enter a
+/// new one, which is checked and saved like `lj set-key` does.
+async fn renew_api_key(
+    info: &ProviderInfo,
+    config: &Config,
+    client: &Client,
+    error: &Error,
+) -> Option<String> {
+    eprintln!(
+        "{} {}",
+        style("Error:").red(),
+        logging::redact(&error.to_string())
+    );
+    eprintln!(
+        "  {}",
+        style(format!(
+            "Your {} API key was rejected; it has probably expired or been revoked",
+            info.display_name
+        ))
+        .dim()
+    );
+    let renew = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter a new API key now?")
+        .default(true)
+        .interact()
+        .ok()?;
+    if !renew {
+        return None;
+    }
+    prompt_api_key(info, config, client).await
+}
+
+fn report_error(e: &Error) {
+    logging::log(Level::Error, &e.to_string(), &[("LJ_EVENT", "cli_failed")]);
+    eprintln!(
+        "{} {}",
+        style("Error:").red(),
+        logging::redact(&e.to_string())
+    );
+    if let Some(hint) = e.hint() {
+        eprintln!("  {}", style(hint).dim());
     }
 }